@@ -0,0 +1,122 @@
+// budget.rs
+use crate::{plain_text_excerpt, FeedData};
+use serde::Deserialize;
+use std::error::Error;
+
+// Characters kept of a description shortened to fit a file size budget
+const TRUNCATED_DESCRIPTION_CHARS: usize = 280;
+
+/// What gives way first when an archived feed exceeds `max_file_bytes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetStrategy {
+    /// Drop the oldest items
+    #[default]
+    DropOldest,
+    /// Shorten the descriptions (and drop the full content) of the oldest items first,
+    /// then drop the oldest items if that is not enough
+    TruncateDescriptions,
+}
+
+// What was given up to fit a feed into its budget
+#[derive(Debug, Default)]
+pub struct Trimmed {
+    pub items_dropped: usize,
+    pub items_truncated: usize,
+}
+
+impl Trimmed {
+    pub fn is_empty(&self) -> bool {
+        self.items_dropped == 0 && self.items_truncated == 0
+    }
+}
+
+/// Renders `feed_data` with `render` and, if the result is larger than `max_bytes`,
+/// trims the feed until it fits, and returns the trimmed feed with its rendering.
+///
+/// Items are trimmed oldest first (ties broken by guid), and the smallest amount of
+/// trimming that fits is found by bisection, so the same feed always produces the same
+/// file. A feed that doesn't fit even without items is returned without items.
+pub fn fit_to_budget<F>(
+    feed_data: &FeedData,
+    max_bytes: u64,
+    strategy: BudgetStrategy,
+    render: F,
+) -> Result<(FeedData, String, Trimmed), Box<dyn Error + Send + Sync>>
+where
+    F: Fn(&FeedData) -> Result<String, Box<dyn Error + Send + Sync>>,
+{
+    let rendered = render(feed_data)?;
+    if max_bytes == 0 || rendered.len() as u64 <= max_bytes {
+        return Ok((feed_data.clone(), rendered, Trimmed::default()));
+    }
+
+    // Item indices, oldest first
+    let mut oldest_first: Vec<usize> = (0..feed_data.items.len()).collect();
+    oldest_first.sort_by(|&a, &b| {
+        let (a, b) = (&feed_data.items[a], &feed_data.items[b]);
+        a.pub_date.cmp(&b.pub_date).then_with(|| a.guid.cmp(&b.guid))
+    });
+
+    let trimmed_feed = |truncate: usize, drop: usize| {
+        let mut trimmed = feed_data.clone();
+        for &index in &oldest_first[..truncate] {
+            let item = &mut trimmed.items[index];
+            item.description = item.description.as_deref().map(truncate_description);
+            item.content = None;
+        }
+        let mut keep = vec![true; trimmed.items.len()];
+        for &index in &oldest_first[..drop] {
+            keep[index] = false;
+        }
+        let mut keep = keep.into_iter();
+        trimmed.items.retain(|_| keep.next().unwrap_or(true));
+        trimmed
+    };
+    let fits = |truncate: usize, drop: usize| -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let rendered = render(&trimmed_feed(truncate, drop))?;
+        Ok((rendered.len() as u64 <= max_bytes).then_some(rendered))
+    };
+
+    let items = feed_data.items.len();
+    if strategy == BudgetStrategy::TruncateDescriptions {
+        if let Some((truncated, rendered)) = bisect(items, |truncate| fits(truncate, 0))? {
+            return Ok((trimmed_feed(truncated, 0), rendered, Trimmed { items_dropped: 0, items_truncated: truncated }));
+        }
+    }
+    let truncate = if strategy == BudgetStrategy::TruncateDescriptions { items } else { 0 };
+    let (dropped, rendered) = match bisect(items, |drop| fits(truncate, drop))? {
+        Some(fitting) => fitting,
+        None => (items, render(&trimmed_feed(truncate, items))?),
+    };
+    let trimmed = Trimmed { items_dropped: dropped, items_truncated: truncate.saturating_sub(dropped) };
+    Ok((trimmed_feed(truncate, dropped), rendered, trimmed))
+}
+
+/// Finds the smallest `n` in `1..=max` for which `attempt` returns a rendering, assuming
+/// that larger values only make the output smaller.
+fn bisect<F>(max: usize, attempt: F) -> Result<Option<(usize, String)>, Box<dyn Error + Send + Sync>>
+where
+    F: Fn(usize) -> Result<Option<String>, Box<dyn Error + Send + Sync>>,
+{
+    let Some(mut best) = attempt(max)?.map(|rendered| (max, rendered)) else {
+        return Ok(None);
+    };
+    let (mut low, mut high) = (1, max);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match attempt(middle)? {
+            Some(rendered) => {
+                best = (middle, rendered);
+                high = middle;
+            }
+            None => low = middle + 1,
+        }
+    }
+    Ok(Some(best))
+}
+
+/// Shortens a description to its first words as plain text.
+fn truncate_description(description: &str) -> String {
+    plain_text_excerpt(description, TRUNCATED_DESCRIPTION_CHARS)
+}
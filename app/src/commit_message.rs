@@ -0,0 +1,107 @@
+// commit_message.rs
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Where the summary of a run is written when `commit_message` is enabled.
+pub const COMMIT_MESSAGE_PATH: &str = "feeds/.commit-message.txt";
+
+// Subjects longer than this are replaced by a summary of the counts
+const MAX_SUBJECT_LENGTH: usize = 72;
+
+// What a run changed in the archive, keyed by feed title so that the message doesn't
+// depend on the order feeds were fetched in
+#[derive(Debug, Default)]
+pub struct RunChanges {
+    // Items not seen before in feeds that were archived before
+    pub new_items: BTreeMap<String, usize>,
+    // Feeds archived for the first time, with their number of items
+    pub added_feeds: BTreeMap<String, usize>,
+    // Feeds that could not be fetched, with the error
+    pub failed_feeds: BTreeMap<String, String>,
+    // Archived files removed because their feed is no longer listed
+    pub removed_files: BTreeSet<String>,
+}
+
+impl RunChanges {
+    /// Records the items of a feed not seen before. `added` is set on the first fetch of a feed.
+    pub fn record_new_items(&mut self, title: &str, count: usize, added: bool) {
+        if added {
+            *self.added_feeds.entry(title.to_string()).or_default() += count;
+        } else if count > 0 {
+            *self.new_items.entry(title.to_string()).or_default() += count;
+        }
+    }
+
+    /// Formats the changes as a commit message: a one-line subject, a blank line and
+    /// the details. The same changes always give the same message.
+    pub fn commit_message(&self) -> String {
+        // Feeds with the most new items first
+        let mut new_items: Vec<(&String, &usize)> = self.new_items.iter().collect();
+        new_items.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        let total_new: usize = new_items.iter().map(|(_, count)| **count).sum();
+
+        let mut parts = Vec::new();
+        if let Some(((first_title, first_count), rest)) = new_items.split_first() {
+            let mut new_part = format!("{} new {} in {}", first_count, items(**first_count), first_title);
+            for (title, count) in rest {
+                new_part.push_str(&format!(", {} in {}", count, title));
+            }
+            parts.push(new_part);
+        }
+        parts.extend(self.added_feeds.keys().map(|title| format!("added {}", title)));
+        parts.extend(self.failed_feeds.keys().map(|title| format!("{} failed", title)));
+        parts.extend(self.removed_files.iter().map(|file| format!("removed {}", file)));
+
+        let mut subject = parts.join("; ");
+        if subject.is_empty() {
+            subject = "Update feeds, no new items".to_string();
+        } else if subject.chars().count() > MAX_SUBJECT_LENGTH {
+            let mut counts = Vec::new();
+            if total_new > 0 {
+                let feeds = if new_items.len() == 1 { "feed" } else { "feeds" };
+                counts.push(format!("{} new {} in {} {}", total_new, items(total_new), new_items.len(), feeds));
+            }
+            if !self.added_feeds.is_empty() {
+                counts.push(format!("{} added", self.added_feeds.len()));
+            }
+            if !self.failed_feeds.is_empty() {
+                counts.push(format!("{} failed", self.failed_feeds.len()));
+            }
+            if !self.removed_files.is_empty() {
+                counts.push(format!("{} removed", self.removed_files.len()));
+            }
+            subject = counts.join(", ");
+        }
+        // Feed titles and URLs are kept as they are, only our own words are capitalized
+        for (word, capitalized) in [("added ", "Added "), ("removed ", "Removed ")] {
+            if let Some(rest) = subject.strip_prefix(word) {
+                subject = format!("{}{}", capitalized, rest);
+            }
+        }
+        let mut message = format!("{}\n", subject);
+
+        let mut section = |heading: &str, lines: Vec<String>| {
+            if !lines.is_empty() {
+                message.push_str(&format!("\n{}:\n", heading));
+                for line in lines {
+                    message.push_str(&format!("- {}\n", line));
+                }
+            }
+        };
+        section("New items", new_items.iter().map(|(title, count)| format!("{}: {}", title, count)).collect());
+        section(
+            "Added feeds",
+            self.added_feeds.iter().map(|(title, count)| format!("{} ({} {})", title, count, items(*count))).collect(),
+        );
+        section("Failed feeds", self.failed_feeds.iter().map(|(title, error)| format!("{}: {}", title, error)).collect());
+        section("Removed files", self.removed_files.iter().cloned().collect());
+        message
+    }
+}
+
+fn items(count: usize) -> &'static str {
+    if count == 1 {
+        "item"
+    } else {
+        "items"
+    }
+}
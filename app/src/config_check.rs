@@ -0,0 +1,127 @@
+// config_check.rs
+use crate::config_schema::validate_config_with_schema;
+use crate::crawler::{coalesce_duplicate_specs, parse_feed_list_with_errors};
+use crate::filters::{AuthorFilter, KeywordFilter};
+use crate::storage::StorageConfig;
+use crate::{content_store, is_absolute_http_url, validate_feed_lists, Config, FeedList};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Environment variables the S3 storage backend reads its credentials from
+const S3_CREDENTIAL_VARS: &[&str] = &["AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"];
+
+/// Checks config.toml, every feed list and the output directories without any network
+/// access, returning every problem found rather than stopping at the first one.
+pub fn check_config() -> Vec<String> {
+    let mut errors = Vec::new();
+    let contents = match fs::read_to_string("config.toml") {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return vec![format!("Could not read config.toml: {}", e)],
+    };
+    let config: Config = match contents {
+        Some(contents) => {
+            if let Err(schema_errors) = validate_config_with_schema(&contents) {
+                errors.extend(schema_errors.into_iter().map(|e| format!("config.toml: {}", e)));
+            }
+            // An unknown key still leaves a config to check the feed lists with, a value of
+            // the wrong type doesn't
+            match toml::from_str(&contents) {
+                Ok(config) => config,
+                Err(_) if !errors.is_empty() => return errors,
+                Err(e) => return vec![format!("config.toml: {}", e)],
+            }
+        }
+        None => Config::default(),
+    };
+
+    let lists = config.feed_lists();
+    if let Err(e) = validate_feed_lists(&lists) {
+        errors.push(format!("config.toml: {}", e));
+    }
+    if config.content_addressed.unwrap_or(false) && lists.iter().any(|list| list.feed_dir() == content_store::ITEMS_DIR) {
+        errors.push(format!("config.toml: {} holds the item content with content_addressed enabled and can't be a list's output_dir", content_store::ITEMS_DIR));
+    }
+    if let Some(hub) = config.websub_hub.as_deref().filter(|hub| !is_absolute_http_url(hub)) {
+        errors.push(format!("config.toml: websub_hub {:?} is not an absolute http(s) URL", hub));
+    }
+    let filters_valid = match KeywordFilter::for_feed(config.filters.as_ref(), None, None) {
+        Ok(_) => true,
+        Err(e) => {
+            errors.push(format!("config.toml [filters]: {}", e));
+            false
+        }
+    };
+
+    for list in &lists {
+        errors.extend(check_feed_list(&config, list, filters_valid));
+    }
+
+    match &config.storage {
+        None => errors.extend(check_output_dirs(Path::new("."), &lists)),
+        Some(StorageConfig::Local { root }) => errors.extend(check_output_dirs(Path::new(root.as_deref().unwrap_or(".")), &lists)),
+        Some(StorageConfig::S3 { .. }) => {
+            for var in S3_CREDENTIAL_VARS {
+                if std::env::var(var).map_or(true, |value| value.trim().is_empty()) {
+                    errors.push(format!("config.toml [storage]: S3 credentials need the {} environment variable, which is not set", var));
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// Checks the syntax and the options of every line of a feed list. The filters of its
+/// feeds are only checked if the `[filters]` section is valid, as they all include it.
+fn check_feed_list(config: &Config, list: &FeedList, filters_valid: bool) -> Vec<String> {
+    let contents = match fs::read_to_string(&list.source) {
+        Ok(contents) => contents,
+        Err(e) => return vec![format!("Could not read {}: {}", list.source, e)],
+    };
+    let (specs, errors) = parse_feed_list_with_errors(&contents);
+    let mut errors: Vec<String> = errors.into_iter().map(|e| format!("{}: {}", list.source, e)).collect();
+    // Lines listing the same feed twice are merged when archiving, with a warning
+    for spec in coalesce_duplicate_specs(specs, &list.source) {
+        if let Some(base_url) = spec.base_url.as_deref().filter(|base_url| !is_absolute_http_url(base_url)) {
+            errors.push(format!("{}: {}: base_url {:?} is not an absolute http(s) URL", list.source, spec.url, base_url));
+        }
+        if !filters_valid {
+            continue;
+        }
+        if let Err(e) = KeywordFilter::for_feed(config.filters.as_ref(), spec.include.as_deref(), spec.exclude.as_deref()) {
+            errors.push(format!("{}: {}: {}", list.source, spec.url, e));
+        }
+        if let Err(e) = AuthorFilter::for_feed(config.filters.as_ref(), spec.include_authors.as_deref(), spec.exclude_authors.as_deref(), false) {
+            errors.push(format!("{}: {}: {}", list.source, spec.url, e));
+        }
+    }
+    errors
+}
+
+/// Checks that the output directories under `root` can be written to. A missing directory
+/// is fine as long as it can be created, as every run creates them.
+fn check_output_dirs(root: &Path, lists: &[FeedList]) -> Vec<String> {
+    let mut dirs: Vec<String> = std::iter::once("feeds".to_string()).chain(lists.iter().map(FeedList::feed_dir)).collect();
+    dirs.sort();
+    dirs.dedup();
+    let mut errors = Vec::new();
+    for dir in dirs {
+        let path = root.join(&dir);
+        let existing = path.ancestors().find(|ancestor| ancestor.exists()).map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+        if !existing.is_dir() {
+            errors.push(format!("Output directory {}: {} is not a directory", dir, existing.display()));
+            continue;
+        }
+        // Writing a file is the only reliable test, permissions alone don't tell about ACLs
+        // or read-only mounts
+        let probe = existing.join(format!(".config-check-{}", std::process::id()));
+        match fs::write(&probe, b"") {
+            Ok(()) => {
+                let _ = fs::remove_file(&probe);
+            }
+            Err(e) if existing == path => errors.push(format!("Output directory {} is not writable: {}", dir, e)),
+            Err(e) => errors.push(format!("Output directory {} does not exist and can't be created in {}: {}", dir, existing.display(), e)),
+        }
+    }
+    errors
+}
@@ -0,0 +1,35 @@
+// config_schema.rs
+use jsonschema::error::ValidationErrorKind;
+use serde_json::Value;
+
+// JSON Schema of config.toml, catching misspelled keys and out of range values
+const CONFIG_SCHEMA: &str = include_str!("config.schema.json");
+
+/// Validates the contents of config.toml against the embedded schema. Each error names
+/// the offending field by its JSON pointer, such as `/lists/0/source`.
+pub fn validate_config_with_schema(raw_toml: &str) -> Result<(), Vec<String>> {
+    let table: toml::Value = toml::from_str(raw_toml).map_err(|e| vec![e.to_string()])?;
+    let instance = serde_json::to_value(&table).map_err(|e| vec![e.to_string()])?;
+    let schema: Value = serde_json::from_str(CONFIG_SCHEMA).expect("config.schema.json is valid JSON");
+    let validator = jsonschema::validator_for(&schema).expect("config.schema.json is a valid schema");
+
+    let errors: Vec<String> = validator
+        .iter_errors(&instance)
+        .flat_map(|error| {
+            let pointer = error.instance_path().to_string();
+            match error.kind() {
+                // Report each unknown key at its own path rather than at its parent
+                ValidationErrorKind::AdditionalProperties { unexpected } => unexpected
+                    .iter()
+                    .map(|key| format!("{}/{}: unknown key", pointer, key))
+                    .collect::<Vec<_>>(),
+                _ => vec![format!("{}: {}", if pointer.is_empty() { "/" } else { &pointer }, error)],
+            }
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,113 @@
+// content_store.rs
+use crate::storage::Storage;
+use crate::{FeedData, FeedItem};
+use regex::Regex;
+use rss::Item;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::error::Error;
+
+/// Directory holding the content of items when `content_addressed` is enabled.
+pub const ITEMS_DIR: &str = "feeds/items";
+
+// The content of an item as stored in feeds/items/{sha256}.json
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredContent {
+    pub title: String,
+    pub description: Option<String>,
+    pub content: Option<String>,
+}
+
+impl StoredContent {
+    pub fn of(item: &FeedItem) -> StoredContent {
+        StoredContent {
+            title: item.title.clone(),
+            description: item.description.clone(),
+            content: item.content.clone(),
+        }
+    }
+
+    /// Hex encoded SHA-256 of the serialized content, which names its file.
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        Sha256::digest(&json).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Path of the file holding the content with the given hash.
+pub fn content_path(hash: &str) -> String {
+    format!("{}/{}.json", ITEMS_DIR, hash)
+}
+
+/// Replaces the description and full content of an RSS item with a link to its content
+/// file at `reference_url`. The title stays inline so that readers can list the item.
+pub fn build_content_store_reference_item(item: &Item, reference_url: &str) -> Item {
+    let mut reference_item = item.clone();
+    reference_item.set_description(format!(
+        "<p><a href=\"{}\">Archived content</a></p>",
+        quick_xml::escape::escape(reference_url)
+    ));
+    reference_item.set_content(None);
+    reference_item
+}
+
+/// Writes the content files of `items` that are not in `stored` yet, recording them in
+/// it. Returns the number of files written.
+pub async fn store_items(
+    storage: &dyn Storage,
+    items: &[FeedItem],
+    stored: &mut HashSet<String>,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let mut written = 0;
+    for item in items {
+        let content = StoredContent::of(item);
+        let hash = content.hash();
+        if stored.contains(&hash) {
+            continue;
+        }
+        storage.write(&content_path(&hash), &serde_json::to_vec_pretty(&content)?).await?;
+        stored.insert(hash);
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Hashes of the content files already stored.
+pub async fn stored_hashes(storage: &dyn Storage) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+    Ok(storage
+        .list(ITEMS_DIR)
+        .await?
+        .into_iter()
+        .filter_map(|name| name.strip_suffix(".json").map(str::to_string))
+        .collect())
+}
+
+/// Restores the description and content of items of an archived feed that were replaced
+/// by a reference to their content file. Items whose content file can't be read keep
+/// the reference.
+pub async fn resolve_references(storage: &dyn Storage, feed_data: &mut FeedData) {
+    let reference_re = Regex::new(r#"^<p><a href="[^"]*/feeds/items/([0-9a-f]{64})\.json">Archived content</a></p>$"#).unwrap();
+    for item in &mut feed_data.items {
+        let Some(hash) = item
+            .description
+            .as_deref()
+            .filter(|_| item.content.is_none())
+            .and_then(|description| reference_re.captures(description))
+            .map(|caps| caps[1].to_string())
+        else {
+            continue;
+        };
+        let stored = storage
+            .read(&content_path(&hash))
+            .await
+            .and_then(|bytes| Ok(serde_json::from_slice::<StoredContent>(&bytes)?));
+        match stored {
+            Ok(stored) => {
+                item.description = stored.description;
+                item.content = stored.content;
+            }
+            Err(e) => eprintln!("Warning: Could not read archived item content {}: {}", content_path(&hash), e),
+        }
+    }
+}
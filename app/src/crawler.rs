@@ -0,0 +1,2834 @@
+// crawler.rs
+use crate::dates::parse_fallback_date;
+use crate::duplicates::{find_duplicate_feeds, DuplicateFeed, FeedIdentity};
+use crate::filters::{AuthorFilter, KeywordFilter};
+use crate::fingerprint::{content_hash, has_feed_changed, FeedFingerprint, FingerprintStore};
+use crate::guid::{GuidStrategy, ItemIdentity};
+use crate::output::OutputFormat;
+use crate::pseudo_feed::{build_pseudo_feed, is_sitemap_index, PseudoFeed, PseudoFeedKind, ScrapeSelectors, DEFAULT_SITEMAP_ENTRIES, DEFAULT_SITEMAP_TITLE_FETCHES};
+use crate::rejects::{retain_items, RejectedItem};
+use crate::republish::RepublishDetection;
+use crate::{estimate_read_time, html_to_plain_text, rss_extension_value, Config, Enclosure, FeedData, FeedItem, DCTERMS_NS, DCTERMS_NS_PREFIX, SLASH_NS, SLASH_NS_PREFIX, WFW_NS, WFW_NS_PREFIX};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, Utc};
+use feed_rs::model::FeedType;
+use feed_rs::parser;
+use regex::Regex;
+use rss::Channel;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, Semaphore};
+
+/// Accept header sent unless configured otherwise
+pub const DEFAULT_ACCEPT: &str =
+    "application/rss+xml, application/atom+xml, application/feed+json;q=0.9, application/xml;q=0.8, text/xml;q=0.8, */*;q=0.5";
+
+/// Maximum number of feeds fetched at the same time
+const MAX_CONCURRENT_FETCHES: usize = 16;
+
+// Default maximum number of feeds fetched from the same host at the same time
+const DEFAULT_MAX_CONCURRENT_PER_HOST: usize = 2;
+
+// How often a broken off download is resumed with a Range request before giving up
+const MAX_RESUMES: usize = 3;
+
+// Largest size a body sent compressed without a Content-Encoding header is decompressed to
+const MAX_DECOMPRESSED_BYTES: u64 = 64 * 1024 * 1024;
+
+// Items clamped for this many days are forgotten, and clamped anew if still dated in the future
+const CLAMPED_DATE_DAYS: i64 = 365;
+
+// How long connecting to a feed server may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How long a feed server may stay silent, before sending its response headers or between
+// parts of the body. Large feeds on slow connections may take longer as a whole.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long in-flight fetches may still take once a shutdown was requested
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+// Longest a response is taken as fresh, whatever its cache headers say, so that a server
+// sending a huge max-age or a far Expires date doesn't stop a feed from being fetched
+const MAX_FRESHNESS_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+
+/// A feed to crawl, as listed in a feed list file. The same URL may be listed
+/// several times with different slugs and filters; it is only fetched once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedSpec {
+    pub url: String,
+    /// Replaces the generated archive filename
+    pub slug: Option<String>,
+    /// Keep only items with an audio/video enclosure
+    pub require_enclosure: bool,
+    /// Drop items with an audio/video enclosure
+    pub exclude_enclosures: bool,
+    /// How republished items are recognized, see `RepublishDetection`
+    pub republish_detection: Option<RepublishDetection>,
+    /// Overrides the configured Accept header
+    pub accept: Option<String>,
+    /// Items of higher priority feeds are kept longest when a combined output is truncated,
+    /// 0 if not set
+    pub priority: Option<i32>,
+    /// Overrides the configured output format
+    pub format: Option<OutputFormat>,
+    /// Name of the combined feed this URL is merged into
+    pub combined: Option<String>,
+    /// Overrides the configured `follow_redirects`
+    pub follow_redirects: Option<bool>,
+    /// Size budget of the archived file, overriding the configured `max_file_bytes`
+    pub max_file_bytes: Option<u64>,
+    /// Link items to their comments page instead of the article
+    pub prefer_comments_link: bool,
+    /// Replaces the configured `base_url` in the links to the archived feed
+    pub base_url: Option<String>,
+    /// Primary categories of the archived feed
+    pub categories: Vec<String>,
+    /// Format the feed is expected in, to notice when it changes
+    pub feed_type: Option<FeedTypeHint>,
+    /// Replaces the configured `include_keywords`
+    pub include: Option<Vec<String>>,
+    /// Replaces the configured `exclude_keywords`
+    pub exclude: Option<Vec<String>>,
+    /// Keep only items by one of these authors
+    pub include_authors: Option<Vec<String>>,
+    /// Drop items by one of these authors
+    pub exclude_authors: Option<Vec<String>>,
+    /// Drop items without an author
+    pub drop_unattributed: Option<bool>,
+    /// Seconds between fetches of the feed, overriding the configured `feed_interval_secs`
+    pub interval_secs: Option<u64>,
+    /// Of the items published in several languages, keep the copy in the first of these
+    pub keep_languages: Option<Vec<String>>,
+    /// Builds the feed from a sitemap or an HTML listing page instead of a feed
+    pub pseudo_feed: Option<PseudoFeedKind>,
+    /// Number of sitemap entries taken as items
+    pub sitemap_entries: Option<usize>,
+    /// Where the items are found on a scraped listing page
+    pub scrape_selectors: ScrapeSelectors,
+}
+
+impl FeedSpec {
+    /// Applies a single `key=value` option from a feed list line.
+    fn apply_option(&mut self, option: &str) -> Result<(), String> {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, found {:?}", option))?;
+        match key {
+            "slug" => self.slug = Some(value.to_string()),
+            "require_enclosure" => self.require_enclosure = parse_bool_option(key, value)?,
+            "exclude_enclosures" => self.exclude_enclosures = parse_bool_option(key, value)?,
+            "republish_detection" => self.republish_detection = Some(value.parse()?),
+            "accept" => self.accept = Some(value.to_string()),
+            "format" => self.format = Some(value.parse()?),
+            "follow_redirects" => self.follow_redirects = Some(parse_bool_option(key, value)?),
+            "prefer_comments_link" => self.prefer_comments_link = parse_bool_option(key, value)?,
+            "base_url" => self.base_url = Some(value.trim_end_matches('/').to_string()),
+            "category" => self.add_categories(parse_list_option(value)),
+            "type" => match PseudoFeedKind::from_type(value) {
+                Some(kind) => self.pseudo_feed = Some(kind),
+                None => self.feed_type = Some(value.parse()?),
+            },
+            "entries" => {
+                self.sitemap_entries = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid entries {:?}, expected a number of sitemap entries", value))?,
+                )
+            }
+            "item_selector" => self.scrape_selectors.item = Some(value.parse()?),
+            "link_selector" => self.scrape_selectors.link = Some(value.parse()?),
+            "title_selector" => self.scrape_selectors.title = Some(value.parse()?),
+            "date_selector" => self.scrape_selectors.date = Some(value.parse()?),
+            "include" => self.include = Some(parse_list_option(value)),
+            "exclude" => self.exclude = Some(parse_list_option(value)),
+            "include_authors" => self.include_authors = Some(parse_list_option(value)),
+            "exclude_authors" => self.exclude_authors = Some(parse_list_option(value)),
+            "drop_unattributed" => self.drop_unattributed = Some(parse_bool_option(key, value)?),
+            "keep_languages" => {
+                let languages: Vec<String> = parse_list_option(value).iter().map(|language| language.to_lowercase()).collect();
+                if let Some(invalid) = languages.iter().find(|language| isolang::Language::from_639_1(language).is_none()) {
+                    return Err(format!("invalid language {:?} in keep_languages, expected ISO 639-1 codes such as en", invalid));
+                }
+                self.keep_languages = Some(languages).filter(|languages| !languages.is_empty());
+            }
+            "interval" => {
+                self.interval_secs = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid interval {:?}, expected a number of seconds", value))?,
+                )
+            }
+            "max_file_bytes" => {
+                self.max_file_bytes = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid max_file_bytes {:?}, expected a number of bytes", value))?,
+                )
+            }
+            "priority" => {
+                self.priority = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid priority {:?}, expected an integer", value))?,
+                )
+            }
+            _ => return Err(format!("unknown option {:?}", key)),
+        }
+        Ok(())
+    }
+
+    /// Fills in the options of `other`, a later line for the same URL, slug and combined
+    /// feed, that this spec doesn't set and adds its categories.
+    fn merge(&mut self, other: FeedSpec) {
+        // Every field is listed, so that a new option can't be forgotten here
+        let FeedSpec {
+            url: _,
+            slug: _,
+            require_enclosure,
+            exclude_enclosures,
+            republish_detection,
+            accept,
+            priority,
+            format,
+            combined: _,
+            follow_redirects,
+            max_file_bytes,
+            prefer_comments_link,
+            base_url,
+            categories,
+            feed_type,
+            include,
+            exclude,
+            include_authors,
+            exclude_authors,
+            drop_unattributed,
+            interval_secs,
+            keep_languages,
+            pseudo_feed,
+            sitemap_entries,
+            scrape_selectors,
+        } = other;
+        self.republish_detection = self.republish_detection.or(republish_detection);
+        self.accept = self.accept.take().or(accept);
+        self.priority = self.priority.or(priority);
+        self.format = self.format.or(format);
+        self.follow_redirects = self.follow_redirects.or(follow_redirects);
+        self.max_file_bytes = self.max_file_bytes.or(max_file_bytes);
+        self.interval_secs = self.interval_secs.or(interval_secs);
+        self.base_url = self.base_url.take().or(base_url);
+        self.feed_type = self.feed_type.or(feed_type);
+        if self.pseudo_feed.is_none() {
+            self.pseudo_feed = pseudo_feed;
+            self.sitemap_entries = sitemap_entries;
+            self.scrape_selectors = scrape_selectors;
+        }
+        self.include = self.include.take().or(include);
+        self.exclude = self.exclude.take().or(exclude);
+        self.include_authors = self.include_authors.take().or(include_authors);
+        self.exclude_authors = self.exclude_authors.take().or(exclude_authors);
+        self.keep_languages = self.keep_languages.take().or(keep_languages);
+        self.drop_unattributed = self.drop_unattributed.or(drop_unattributed);
+        self.prefer_comments_link |= prefer_comments_link;
+        if !self.require_enclosure && !self.exclude_enclosures {
+            self.require_enclosure = require_enclosure;
+            self.exclude_enclosures = exclude_enclosures;
+        }
+        self.add_categories(categories);
+    }
+
+    /// Checks that the sitemap and scrape options are only set along the matching `type`,
+    /// and that scraped pages have an item selector.
+    fn check_pseudo_feed_options(&self) -> Result<(), String> {
+        if self.pseudo_feed.is_some() && self.feed_type.is_some() {
+            return Err("type can only be set once".to_string());
+        }
+        if self.sitemap_entries.is_some() && self.pseudo_feed != Some(PseudoFeedKind::Sitemap) {
+            return Err("entries is only used with type=sitemap".to_string());
+        }
+        match self.pseudo_feed {
+            Some(PseudoFeedKind::Scrape) if self.scrape_selectors.item.is_none() => Err("type=scrape needs an item_selector".to_string()),
+            Some(PseudoFeedKind::Scrape) => Ok(()),
+            _ if !self.scrape_selectors.is_empty() => Err("the *_selector options are only used with type=scrape".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn add_categories(&mut self, categories: Vec<String>) {
+        for category in categories {
+            if !self.categories.contains(&category) {
+                self.categories.push(category);
+            }
+        }
+    }
+}
+
+/// Source format a feed is expected in, from the `type` option of its feed list line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedTypeHint {
+    /// Any RSS version
+    Rss,
+    Rss0,
+    Rss1,
+    Rss2,
+    Atom,
+    Json,
+}
+
+impl FromStr for FeedTypeHint {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "rss" => Ok(FeedTypeHint::Rss),
+            "rss0" => Ok(FeedTypeHint::Rss0),
+            "rss1" => Ok(FeedTypeHint::Rss1),
+            "rss2" => Ok(FeedTypeHint::Rss2),
+            "atom" => Ok(FeedTypeHint::Atom),
+            "json" => Ok(FeedTypeHint::Json),
+            _ => Err(format!("invalid type {:?}, expected rss, rss0, rss1, rss2, atom, json, sitemap or scrape", value)),
+        }
+    }
+}
+
+impl FeedTypeHint {
+    /// The value of the `type` option naming this format.
+    fn name(self) -> &'static str {
+        match self {
+            FeedTypeHint::Rss => "rss",
+            FeedTypeHint::Rss0 => "rss0",
+            FeedTypeHint::Rss1 => "rss1",
+            FeedTypeHint::Rss2 => "rss2",
+            FeedTypeHint::Atom => "atom",
+            FeedTypeHint::Json => "json",
+        }
+    }
+
+    fn matches(self, feed_type: &FeedType) -> bool {
+        match self {
+            FeedTypeHint::Rss => matches!(feed_type, FeedType::RSS0 | FeedType::RSS1 | FeedType::RSS2),
+            FeedTypeHint::Rss0 => *feed_type == FeedType::RSS0,
+            FeedTypeHint::Rss1 => *feed_type == FeedType::RSS1,
+            FeedTypeHint::Rss2 => *feed_type == FeedType::RSS2,
+            FeedTypeHint::Atom => *feed_type == FeedType::Atom,
+            FeedTypeHint::Json => *feed_type == FeedType::JSON,
+        }
+    }
+}
+
+/// Human readable name of a feed format detected by feed_rs.
+fn feed_type_name(feed_type: &FeedType) -> &'static str {
+    match feed_type {
+        FeedType::Atom => "Atom",
+        FeedType::JSON => "JSON Feed",
+        FeedType::RSS0 => "RSS 0.9x",
+        FeedType::RSS1 => "RSS 1.0",
+        FeedType::RSS2 => "RSS 2.0",
+    }
+}
+
+/// Reads a feed list into feed specs. Each line holds a URL, optionally followed by
+/// whitespace-separated `key=value` options. Blank lines and lines starting with `#`
+/// are ignored.
+///
+/// A line `@combined=Name` followed by options starts a combined feed: the indented
+/// lines after it list the URLs merged into it, and inherit its options.
+///
+/// Lines whose URL is not an absolute http(s) URL are left out and returned with the
+/// feeds, to be reported, unless `strict` makes them errors like any other invalid line.
+pub fn parse_feed_list(contents: &str, strict: bool) -> Result<(Vec<FeedSpec>, Vec<String>), String> {
+    let (specs, mut errors, invalid_urls) = parse_feed_lines(contents);
+    if strict {
+        errors.extend(invalid_urls.iter().cloned());
+    }
+    if errors.is_empty() {
+        Ok((specs, invalid_urls))
+    } else {
+        Err(errors.swap_remove(0))
+    }
+}
+
+/// Parses a feed list like `parse_feed_list`, but skips invalid lines instead of stopping
+/// at the first one, returning the feeds of the valid lines and every error.
+pub fn parse_feed_list_with_errors(contents: &str) -> (Vec<FeedSpec>, Vec<String>) {
+    let (specs, mut errors, invalid_urls) = parse_feed_lines(contents);
+    errors.extend(invalid_urls);
+    (specs, errors)
+}
+
+/// Reads the valid lines of a feed list, returning their feeds, the errors of the other
+/// lines and, apart from them, those of the lines with an invalid URL.
+fn parse_feed_lines(contents: &str) -> (Vec<FeedSpec>, Vec<String>, Vec<String>) {
+    let mut specs = Vec::new();
+    let mut errors = Vec::new();
+    let mut invalid_urls = Vec::new();
+    // The combined feed whose members are being read, and how many it has so far
+    let mut group: Option<(FeedSpec, usize)> = None;
+    for (index, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_error = |e: String| format!("line {}: {}", index + 1, e);
+
+        let parts = match split_options(line) {
+            Ok(parts) => parts,
+            Err(e) => {
+                errors.push(line_error(e));
+                continue;
+            }
+        };
+        let mut parts = parts.iter();
+        let first = parts.next().cloned().unwrap_or_default();
+
+        let is_member = raw_line.starts_with(char::is_whitespace) && group.is_some();
+        if !is_member {
+            if let Some((template, 0)) = group.take() {
+                errors.push(line_error(format!("combined feed {:?} lists no feeds", template.combined.unwrap_or_default())));
+            }
+        }
+
+        if let Some(name) = first.strip_prefix("@combined=") {
+            if name.is_empty() {
+                errors.push(line_error("combined feed needs a name".to_string()));
+            }
+            let mut template = FeedSpec {
+                combined: Some(name.to_string()),
+                slug: Some(name.to_string()),
+                ..Default::default()
+            };
+            // The members of a combined feed with an invalid option are still read as members
+            for option in parts {
+                if let Err(e) = template.apply_option(option) {
+                    errors.push(line_error(e));
+                }
+            }
+            group = Some((template, 0));
+            continue;
+        }
+
+        let mut spec = match &group {
+            Some((template, _)) if is_member => FeedSpec { url: first, ..template.clone() },
+            _ => FeedSpec { url: first, ..Default::default() },
+        };
+        if let Err(e) = check_feed_url(&spec.url) {
+            invalid_urls.push(line_error(e));
+            continue;
+        }
+        // A combined feed whose members all have an invalid URL lists no feeds
+        if let Some((_, members)) = group.as_mut().filter(|_| is_member) {
+            *members += 1;
+        }
+        let mut valid = true;
+        for option in parts {
+            if let Err(e) = spec.apply_option(option) {
+                errors.push(line_error(e));
+                valid = false;
+            }
+        }
+        if spec.require_enclosure && spec.exclude_enclosures {
+            errors.push(line_error("require_enclosure and exclude_enclosures cannot both be set".to_string()));
+            valid = false;
+        }
+        if let Err(e) = spec.check_pseudo_feed_options() {
+            errors.push(line_error(e));
+            valid = false;
+        }
+        if valid {
+            specs.push(spec);
+        }
+    }
+    if let Some((template, 0)) = group {
+        errors.push(format!("combined feed {:?} lists no feeds", template.combined.unwrap_or_default()));
+    }
+    (specs, errors, invalid_urls)
+}
+
+/// Checks that a feed list URL is an absolute http(s) URL, so that typos such as
+/// `htps://` or a missing scheme are reported with their line rather than at fetch time.
+fn check_feed_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid URL {:?}: {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("invalid URL {:?}: scheme {:?} is not http or https", url, parsed.scheme()));
+    }
+    Ok(())
+}
+
+/// Merges lines listing the same URL into the same archived feed (same slug and combined
+/// feed), which would otherwise write the same file twice. Options set by an earlier line
+/// win and categories are merged. Lines with different slugs are variants and are kept.
+pub fn coalesce_duplicate_specs(specs: Vec<FeedSpec>, source: &str) -> Vec<FeedSpec> {
+    coalesce_specs(specs, |spec| eprintln!("Warning: {} lists {} more than once, merging the lines", source, spec.url))
+}
+
+/// Points the lines listing `from` at `to` instead, merging them into the lines already
+/// listing `to` the way `coalesce_duplicate_specs` does, without a warning.
+pub fn redirect_specs(specs: Vec<FeedSpec>, from: &str, to: &str) -> Vec<FeedSpec> {
+    let redirected = specs
+        .into_iter()
+        .map(|spec| if spec.url == from { FeedSpec { url: to.to_string(), ..spec } } else { spec })
+        .collect();
+    coalesce_specs(redirected, |_| {})
+}
+
+fn coalesce_specs(specs: Vec<FeedSpec>, mut on_merge: impl FnMut(&FeedSpec)) -> Vec<FeedSpec> {
+    let mut coalesced: Vec<FeedSpec> = Vec::new();
+    for spec in specs {
+        let duplicate_of = coalesced
+            .iter_mut()
+            .find(|known| known.url == spec.url && known.slug == spec.slug && known.combined == spec.combined);
+        match duplicate_of {
+            Some(known) => {
+                on_merge(&spec);
+                known.merge(spec);
+            }
+            None => coalesced.push(spec),
+        }
+    }
+    coalesced
+}
+
+/// Splits a feed list line at whitespace. Double quotes allow option values with
+/// spaces, e.g. `accept="application/rss+xml, */*;q=0.5"`.
+fn split_options(line: &str) -> Result<Vec<String>, String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    for c in line.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    parts.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("unterminated quote".to_string());
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    Ok(parts)
+}
+
+fn parse_bool_option(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("invalid value {:?} for {}, expected true or false", value, key)),
+    }
+}
+
+/// Splits a comma-separated option value, e.g. `exclude=sponsored,advertisement`. A
+/// comma escaped as `\,` belongs to the value, e.g. `exclude=v[0-9]{2\,3}`.
+fn parse_list_option(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => {
+                chars.next();
+                current.push(',');
+            }
+            ',' => items.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    items.push(current);
+    items
+        .iter()
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Why a feed could not be crawled.
+#[derive(Debug)]
+pub enum FeedError {
+    /// The request could not be sent or the body could not be read
+    Http(String),
+    /// The server answered with a non-success status
+    Status(u16),
+    /// The server redirected to this location and redirects are not followed
+    Redirect(String),
+    /// The body is not a feed we can parse
+    Parse(String),
+    /// The fetch task panicked or was cancelled
+    Task(String),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::Http(e) => write!(f, "request failed: {}", e),
+            FeedError::Status(status) => write!(f, "server returned HTTP {}", status),
+            FeedError::Redirect(location) => write!(f, "redirected to {}, which is not followed", location),
+            FeedError::Parse(e) => write!(f, "could not parse feed: {}", e),
+            FeedError::Task(e) => write!(f, "fetch task failed: {}", e),
+        }
+    }
+}
+
+impl Error for FeedError {}
+
+/// A downloaded feed body.
+#[derive(Debug, Clone)]
+pub struct FetchResponse {
+    pub body: Vec<u8>,
+    /// Time until the response headers arrived, if the client measures it
+    pub headers_after: Option<Duration>,
+    /// How long the response may be reused, from its Cache-Control or Expires header. Zero
+    /// if it must be fetched again every time, None if the server didn't say.
+    pub freshness: Option<Duration>,
+    /// URL the body was served from after redirects, if the client knows it
+    pub final_url: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// The server answered a conditional request with 304 Not Modified; the body is empty
+    pub not_modified: bool,
+}
+
+/// A feed download to perform.
+#[derive(Debug, Clone)]
+pub struct FetchRequest {
+    pub url: String,
+    /// Value of the Accept header, used by some servers to choose the format
+    pub accept: String,
+    /// Whether redirects are followed, if the client supports choosing per request
+    pub follow_redirects: bool,
+    /// ETag of the previous response, sent as If-None-Match
+    pub if_none_match: Option<String>,
+    /// Last-Modified of the previous response, sent as If-Modified-Since
+    pub if_modified_since: Option<String>,
+}
+
+/// Downloads feed bodies. Implemented for `reqwest::Client`; tests and embedding
+/// applications can provide their own implementation.
+#[async_trait]
+pub trait HttpClient: Send + Sync {
+    async fn fetch(&self, request: &FetchRequest) -> Result<FetchResponse, FeedError>;
+}
+
+#[async_trait]
+impl HttpClient for reqwest::Client {
+    async fn fetch(&self, request: &FetchRequest) -> Result<FetchResponse, FeedError> {
+        let started = Instant::now();
+        let mut builder = self.get(&request.url).header(reqwest::header::ACCEPT, &request.accept);
+        if let Some(etag) = &request.if_none_match {
+            builder = builder.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &request.if_modified_since {
+            builder = builder.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let mut response = builder.send().await.map_err(|e| FeedError::Http(error_with_causes(&e)))?;
+        let header = |response: &reqwest::Response, name| {
+            response.headers().get(name).and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok()).map(str::to_string)
+        };
+        let conditional = request.if_none_match.is_some() || request.if_modified_since.is_some();
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED && conditional {
+            return Ok(FetchResponse {
+                body: Vec::new(),
+                headers_after: Some(started.elapsed()),
+                freshness: freshness_lifetime(response.headers(), Utc::now()),
+                final_url: Some(response.url().to_string()),
+                // A 304 need not repeat the validators, which still hold then
+                etag: header(&response, reqwest::header::ETAG).or_else(|| request.if_none_match.clone()),
+                last_modified: header(&response, reqwest::header::LAST_MODIFIED).or_else(|| request.if_modified_since.clone()),
+                not_modified: true,
+            });
+        }
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .unwrap_or_default();
+            return Err(FeedError::Redirect(location.to_string()));
+        }
+        if !response.status().is_success() {
+            return Err(FeedError::Status(response.status().as_u16()));
+        }
+        // reqwest doesn't tell DNS, connect and server time apart, so they are measured together
+        let headers_after = started.elapsed();
+        let freshness = freshness_lifetime(response.headers(), Utc::now());
+        let final_url = response.url().to_string();
+        let etag = header(&response, reqwest::header::ETAG);
+        let last_modified = header(&response, reqwest::header::LAST_MODIFIED);
+
+        let validator = resume_validator(&response);
+        let mut body = Vec::new();
+        let mut resumes = 0;
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+                Ok(None) => break,
+                Err(e) => {
+                    let Some(validator) = validator.as_deref().filter(|_| resumes < MAX_RESUMES && !body.is_empty()) else {
+                        return Err(FeedError::Http(e.to_string()));
+                    };
+                    resumes += 1;
+                    eprintln!("Warning: Download of {} broke off after {} bytes, resuming: {}", request.url, body.len(), e);
+                    response = self
+                        .get(&request.url)
+                        .header(reqwest::header::ACCEPT, &request.accept)
+                        .header(reqwest::header::RANGE, format!("bytes={}-", body.len()))
+                        .header(reqwest::header::IF_RANGE, validator)
+                        .send()
+                        .await
+                        .map_err(|e| FeedError::Http(e.to_string()))?;
+                    match response.status() {
+                        reqwest::StatusCode::PARTIAL_CONTENT => {}
+                        // The feed changed in the meantime or ranges are not supported after all
+                        reqwest::StatusCode::OK => body.clear(),
+                        status => return Err(FeedError::Status(status.as_u16())),
+                    }
+                }
+            }
+        }
+        Ok(FetchResponse {
+            body,
+            headers_after: Some(headers_after),
+            freshness,
+            final_url: Some(final_url),
+            etag,
+            last_modified,
+            not_modified: false,
+        })
+    }
+}
+
+/// A shared client, e.g. to keep its connections across several crawls.
+#[async_trait]
+impl HttpClient for Arc<dyn HttpClient> {
+    async fn fetch(&self, request: &FetchRequest) -> Result<FetchResponse, FeedError> {
+        self.as_ref().fetch(request).await
+    }
+}
+
+/// Returns the validator to send in If-Range when resuming the download of `response`,
+/// if it can be resumed: the server accepts byte ranges, the body is not being
+/// decompressed on the fly (ranges count encoded bytes) and it has a strong ETag or a
+/// Last-Modified date.
+fn resume_validator(response: &reqwest::Response) -> Option<String> {
+    let headers = response.headers();
+    let header = |name| headers.get(name).and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok());
+    let accepts_ranges = header(reqwest::header::ACCEPT_RANGES).is_some_and(|ranges| ranges.eq_ignore_ascii_case("bytes"));
+    // reqwest drops Content-Length and Content-Encoding when it decompresses a body
+    let identity = header(reqwest::header::CONTENT_LENGTH).is_some() && header(reqwest::header::CONTENT_ENCODING).is_none();
+    if !accepts_ranges || !identity {
+        return None;
+    }
+    header(reqwest::header::ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(reqwest::header::LAST_MODIFIED))
+        .map(str::to_string)
+}
+
+/// Computes how long a response stays fresh, as an HTTP cache would: `no-store` and
+/// `no-cache` make it stale right away, `max-age` (less the `Age` the response already has)
+/// wins over `Expires`, which is relative to the response's `Date`. An invalid `Expires`
+/// means the response is already stale. The lifetime is capped at `MAX_FRESHNESS_LIFETIME`.
+fn freshness_lifetime(headers: &reqwest::header::HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let header = |name| headers.get(name).and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok());
+    let http_date = |value: &str| DateTime::parse_from_rfc2822(value.trim()).ok().map(|date| date.with_timezone(&Utc));
+
+    if let Some(cache_control) = header(reqwest::header::CACHE_CONTROL) {
+        let mut max_age = None;
+        for directive in cache_control.split(',') {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" | "no-cache" => return Some(Duration::ZERO),
+                "max-age" => max_age = value.trim().trim_matches('"').parse::<u64>().ok(),
+                _ => {}
+            }
+        }
+        if let Some(max_age) = max_age {
+            let age = header(reqwest::header::AGE).and_then(|age| age.trim().parse::<u64>().ok()).unwrap_or(0);
+            return Some(Duration::from_secs(max_age.saturating_sub(age)).min(MAX_FRESHNESS_LIFETIME));
+        }
+    }
+    let expires = header(reqwest::header::EXPIRES)?;
+    let Some(expires) = http_date(expires) else {
+        return Some(Duration::ZERO);
+    };
+    let date = header(reqwest::header::DATE).and_then(http_date).unwrap_or(now);
+    Some((expires - date).to_std().unwrap_or(Duration::ZERO).min(MAX_FRESHNESS_LIFETIME))
+}
+
+/// A pair of reqwest clients, one following up to `max_redirects` redirects and one not
+/// following any, since reqwest only sets the redirect policy per client. Each request
+/// uses the client matching its `follow_redirects`.
+pub struct RedirectPolicyClient {
+    following: reqwest::Client,
+    not_following: reqwest::Client,
+}
+
+impl RedirectPolicyClient {
+    pub fn new(max_redirects: usize, ip_family: IpFamily) -> Result<RedirectPolicyClient, reqwest::Error> {
+        Ok(RedirectPolicyClient {
+            following: ip_family
+                .client_builder()
+                .redirect(reqwest::redirect::Policy::limited(max_redirects))
+                .build()?,
+            not_following: ip_family.client_builder().redirect(reqwest::redirect::Policy::none()).build()?,
+        })
+    }
+}
+
+/// An error followed by its causes, such as the DNS or connection error behind a failed
+/// request, which reqwest leaves out of its message.
+fn error_with_causes(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        if !message.contains(&cause_message) {
+            message.push_str(&format!(": {}", cause_message));
+        }
+        source = cause.source();
+    }
+    message
+}
+
+/// Address family feed servers are connected over, from the `ip_family` config option.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    /// Every address the system resolver returns, as the system orders them
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl IpFamily {
+    /// A reqwest client builder that only connects over this family, with the connect and
+    /// read timeouts of the crawler. Hosts given as an IP address in the URL aren't
+    /// resolved and are connected to as they are.
+    pub fn client_builder(self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder().connect_timeout(CONNECT_TIMEOUT).read_timeout(READ_TIMEOUT);
+        match self {
+            IpFamily::Any => builder,
+            family => builder.dns_resolver(Arc::new(FamilyResolver(family))),
+        }
+    }
+
+    fn allows(self, address: &SocketAddr) -> bool {
+        match self {
+            IpFamily::Any => true,
+            IpFamily::Ipv4 => address.is_ipv4(),
+            IpFamily::Ipv6 => address.is_ipv6(),
+        }
+    }
+}
+
+// Resolves host names with the system resolver, keeping the addresses of one family
+struct FamilyResolver(IpFamily);
+
+impl reqwest::dns::Resolve for FamilyResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let family = self.0;
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.filter(|address| family.allows(address)).collect();
+            if addresses.is_empty() {
+                let family = if family == IpFamily::Ipv4 { "IPv4" } else { "IPv6" };
+                return Err(format!("{} has no {} address", host, family).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+#[async_trait]
+impl HttpClient for RedirectPolicyClient {
+    async fn fetch(&self, request: &FetchRequest) -> Result<FetchResponse, FeedError> {
+        let client = if request.follow_redirects { &self.following } else { &self.not_following };
+        client.fetch(request).await
+    }
+}
+
+/// How long fetching and parsing a feed took.
+#[derive(Debug, Clone, Copy)]
+pub struct FetchTiming {
+    /// Time until the response headers arrived, covering DNS, connect and the server's
+    /// response time, if the client measures it
+    pub headers: Option<Duration>,
+    /// Time of the whole download
+    pub fetch: Duration,
+    pub parse: Duration,
+}
+
+/// Outcome of crawling a set of feeds.
+#[derive(Debug)]
+pub struct CrawlResult {
+    /// Timing of the successfully crawled feeds, keyed by URL
+    pub timings: HashMap<String, FetchTiming>,
+    /// Freshness lifetime of the successfully crawled feeds' responses, keyed by URL, for
+    /// the feeds whose server declared one
+    pub freshness: HashMap<String, Duration>,
+    /// Feeds whose response is the one of their fingerprint, and so were not parsed
+    pub unchanged: Vec<FeedSpec>,
+    /// Fingerprints of the responses of the successfully crawled and unchanged feeds, keyed by URL
+    pub fingerprints: HashMap<String, FeedFingerprint>,
+    /// Listed URLs found to serve the same feed. With `merge_duplicates`, only the
+    /// canonical URL of each is sent on.
+    pub duplicates: Vec<DuplicateFeed>,
+    /// Items a feed was sent on with that turned out to be cross-posts of a feed listed
+    /// before it, which was fetched later, by the URL of the feed. They must be removed from it.
+    pub given_up: HashMap<String, HashSet<String>>,
+    pub failures: Vec<(FeedSpec, FeedError)>,
+    /// Feeds that were not crawled (or not finished) because of a shutdown request
+    pub interrupted: Vec<FeedSpec>,
+    pub stats: CrawlStats,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CrawlStats {
+    pub attempted: usize,
+    pub succeeded: usize,
+    // Included in succeeded
+    pub unchanged: usize,
+    pub failed: usize,
+    pub interrupted: usize,
+    pub items: usize,
+    pub duplicates_removed: usize,
+    pub excluded_by_link_domain: usize,
+    pub excluded_by_keyword: usize,
+    pub excluded_by_author: usize,
+    pub excluded_by_blocklist: usize,
+    pub elapsed: Duration,
+}
+
+/// Fetches, parses and deduplicates feeds.
+///
+/// ```ignore
+/// let mut crawler = FeedCrawler::new()
+///     .with_config(config)
+///     .with_client(reqwest::Client::new())
+///     .with_dedup_cache(cache)
+///     .with_shutdown(shutdown_receiver);
+/// let result = crawler.crawl_all(&specs, ready_sender).await;
+/// ```
+pub struct FeedCrawler {
+    config: Config,
+    client: Arc<dyn HttpClient>,
+    dedup_cache: Option<DedupCache>,
+    removed_items: RemovedItems,
+    clamped_dates: ClampedDates,
+    // Items dropped by the filters, collected with `generate_rejects_feed`
+    rejected_items: Vec<RejectedItem>,
+    shutdown: Option<watch::Receiver<bool>>,
+    progress: Option<Arc<Mutex<BTreeSet<String>>>>,
+    fingerprints: Arc<FingerprintStore>,
+    // Titles of the entries of sitemaps archived by earlier runs, by feed URL and entry URL
+    known_titles: HashMap<String, Arc<HashMap<String, String>>>,
+    // Print debug messages, see `with_verbose`
+    verbose: bool,
+    // Print progress messages to stderr, see `with_progress_to_stderr`
+    progress_to_stderr: bool,
+    // Feeds already warned about because their items carry no author for the author filter to match
+    unattributed_feeds: HashSet<String>,
+}
+
+// What a fetch task hands back for a feed it fetched
+struct FetchedFeed {
+    // None if the response is the one of the feed's fingerprint, and so was not parsed
+    feed_data: Option<FeedData>,
+    fingerprint: FeedFingerprint,
+    timing: FetchTiming,
+    freshness: Option<Duration>,
+    final_url: Option<String>,
+}
+
+// The keyword and author filters of the feeds of a crawl, compiled once per feed URL. Feeds
+// without filters, or whose filters fail to compile, have none.
+#[derive(Debug, Default)]
+struct FeedFilters {
+    keywords: HashMap<String, KeywordFilter>,
+    authors: HashMap<String, AuthorFilter>,
+}
+
+impl FeedFilters {
+    /// Compiles the filters of `specs`, the first spec of a URL setting them. Invalid
+    /// patterns are rejected at startup, so a feed whose filter fails to compile here is
+    /// left unfiltered.
+    fn compile(config: &Config, specs: &[FeedSpec]) -> FeedFilters {
+        let mut filters = FeedFilters::default();
+        let mut compiled = HashSet::new();
+        for spec in specs.iter().filter(|spec| compiled.insert(spec.url.as_str())) {
+            match KeywordFilter::for_feed(config.filters.as_ref(), spec.include.as_deref(), spec.exclude.as_deref()) {
+                Ok(filter) if !filter.is_empty() => {
+                    filters.keywords.insert(spec.url.clone(), filter);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Warning: Not filtering {} by keyword: {}", spec.url, e),
+            }
+            let filter = AuthorFilter::for_feed(
+                config.filters.as_ref(),
+                spec.include_authors.as_deref(),
+                spec.exclude_authors.as_deref(),
+                spec.drop_unattributed.unwrap_or(false),
+            );
+            match filter {
+                Ok(filter) if !filter.is_empty() => {
+                    filters.authors.insert(spec.url.clone(), filter);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Warning: Not filtering {} by author: {}", spec.url, e),
+            }
+        }
+        filters
+    }
+}
+
+// The items first seen in this crawl, so that cross-posts stay in the first listed feed
+// having them whatever order the feeds are fetched in
+#[derive(Debug, Default)]
+struct CrossPosts {
+    // The position in the specs and the URL of the feed keeping each item, by guid
+    kept_by: HashMap<String, (usize, String)>,
+    // Items taken back from a feed after it was sent on, by the URL of the feed
+    given_up: HashMap<String, HashSet<String>>,
+}
+
+impl Default for FeedCrawler {
+    fn default() -> Self {
+        FeedCrawler::new()
+    }
+}
+
+impl FeedCrawler {
+    /// Creates a crawler with the default configuration, a plain reqwest client
+    /// and no cross-feed deduplication.
+    pub fn new() -> FeedCrawler {
+        FeedCrawler {
+            config: Config::default(),
+            client: Arc::new(reqwest::Client::new()),
+            dedup_cache: None,
+            removed_items: RemovedItems::default(),
+            clamped_dates: ClampedDates::default(),
+            rejected_items: Vec::new(),
+            shutdown: None,
+            progress: None,
+            fingerprints: Arc::default(),
+            known_titles: HashMap::new(),
+            verbose: false,
+            progress_to_stderr: false,
+            unattributed_feeds: HashSet::new(),
+        }
+    }
+
+    pub fn with_config(mut self, config: Config) -> FeedCrawler {
+        self.config = config;
+        self
+    }
+
+    pub fn with_client(mut self, client: impl HttpClient + 'static) -> FeedCrawler {
+        self.client = Arc::new(client);
+        self
+    }
+
+    /// Enables cross-feed deduplication backed by `cache`.
+    pub fn with_dedup_cache(mut self, cache: DedupCache) -> FeedCrawler {
+        self.dedup_cache = Some(cache);
+        self
+    }
+
+    /// Keeps the items recorded in `removed` out of the feeds, and records the items
+    /// removed with `blocked_items` in it.
+    pub fn with_removed_items(mut self, removed: RemovedItems) -> FeedCrawler {
+        self.removed_items = removed;
+        self
+    }
+
+    /// Clamps the items dated in the future that were clamped before to the date recorded
+    /// in `clamped`, and records the items clamped first in it.
+    pub fn with_clamped_dates(mut self, clamped: ClampedDates) -> FeedCrawler {
+        self.clamped_dates = clamped;
+        self
+    }
+
+    /// Stops crawling once `true` is sent on the channel: fetches that haven't started
+    /// are skipped and in-flight ones get a short grace period to finish.
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> FeedCrawler {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Adds the URL of every feed whose response matches its fingerprint to `progress` as
+    /// soon as its fetch completes, as its archived copies are already up to date. Feeds
+    /// parsed again are only archived once written, which is up to the caller.
+    pub fn with_progress(mut self, progress: Arc<Mutex<BTreeSet<String>>>) -> FeedCrawler {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Skips parsing the feeds whose response matches their fingerprint in `store`: the
+    /// server answers 304 Not Modified to the validators or sends the same body again.
+    pub fn with_fingerprints(mut self, store: FingerprintStore) -> FeedCrawler {
+        self.fingerprints = Arc::new(store);
+        self
+    }
+
+    /// Titles the entries of `type=sitemap` feeds had in earlier runs, by feed URL and
+    /// entry URL. Their pages aren't fetched again for their title.
+    pub fn with_known_titles(mut self, titles: HashMap<String, HashMap<String, String>>) -> FeedCrawler {
+        self.known_titles = titles.into_iter().map(|(url, titles)| (url, Arc::new(titles))).collect();
+        self
+    }
+
+    /// Prints how each feed was fetched and parsed to stderr.
+    pub fn with_verbose(mut self, verbose: bool) -> FeedCrawler {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Prints progress messages to stderr instead of stdout, for when stdout carries
+    /// machine-readable output.
+    pub fn with_progress_to_stderr(mut self, progress_to_stderr: bool) -> FeedCrawler {
+        self.progress_to_stderr = progress_to_stderr;
+        self
+    }
+
+    /// Takes the removed items, including those removed by the crawls so far.
+    pub fn take_removed_items(&mut self) -> RemovedItems {
+        std::mem::take(&mut self.removed_items)
+    }
+
+    /// Takes the dates of the clamped items, including those clamped by the crawls so far.
+    pub fn take_clamped_dates(&mut self) -> ClampedDates {
+        std::mem::take(&mut self.clamped_dates)
+    }
+
+    /// Takes the items dropped by the filters in the crawls so far, collected only with
+    /// `generate_rejects_feed`.
+    pub fn take_rejected_items(&mut self) -> Vec<RejectedItem> {
+        std::mem::take(&mut self.rejected_items)
+    }
+
+    /// Returns the deduplication cache, updated by the crawls so far.
+    pub fn into_dedup_cache(self) -> Option<DedupCache> {
+        self.dedup_cache
+    }
+
+    /// Fetches and parses all feeds concurrently, removes the items filtered out by keyword,
+    /// already seen in another feed or linking to an excluded domain, and sends each feed to
+    /// `ready` as soon as it is done, so that it can be archived while others are still being
+    /// fetched. While `ready` is full, no new fetches start. With `merge_duplicates`, the
+    /// feeds are only sent once all are fetched, as duplicates are only recognized then.
+    pub async fn crawl_all(&mut self, specs: &[FeedSpec], ready: mpsc::Sender<FeedData>) -> CrawlResult {
+        let started = Instant::now();
+        let merge_duplicates = self.config.merge_duplicates.unwrap_or(false);
+        let now = Utc::now();
+        self.evict_seen_items(now);
+        let filters = FeedFilters::compile(&self.config, specs);
+
+        // A fetch task keeps its permit until it hands over its result, so that fetching
+        // pauses while the feeds fetched so far wait to be archived
+        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_FETCHES));
+        let (done_sender, mut done) = mpsc::channel(MAX_CONCURRENT_FETCHES);
+        let title_fetches = Arc::new(AtomicUsize::new(self.config.sitemap_title_fetches.unwrap_or(DEFAULT_SITEMAP_TITLE_FETCHES)));
+        // Feeds of the same host are fetched a few at a time, so that one provider isn't
+        // hammered by a list with many of its feeds
+        let per_host = self.config.max_concurrent_per_host.unwrap_or(DEFAULT_MAX_CONCURRENT_PER_HOST).max(1);
+        let mut host_semaphores: HashMap<String, Arc<Semaphore>> = HashMap::new();
+        let mut handles = Vec::new();
+        for (index, spec) in specs.iter().enumerate() {
+            let client = Arc::clone(&self.client);
+            let semaphore = Arc::clone(&semaphore);
+            let host_semaphore = Arc::clone(
+                host_semaphores
+                    .entry(link_host(&spec.url).unwrap_or_default())
+                    .or_insert_with(|| Arc::new(Semaphore::new(per_host))),
+            );
+            let shutdown = self.shutdown.clone();
+            let fingerprints = Arc::clone(&self.fingerprints);
+            let known = fingerprints.feeds.get(&spec.url);
+            let request = FetchRequest {
+                url: spec.url.clone(),
+                accept: spec
+                    .accept
+                    .clone()
+                    .or_else(|| spec.pseudo_feed.map(|kind| kind.accept().to_string()))
+                    .or_else(|| self.config.accept.clone())
+                    .unwrap_or_else(|| DEFAULT_ACCEPT.to_string()),
+                follow_redirects: spec
+                    .follow_redirects
+                    .or(self.config.follow_redirects)
+                    .unwrap_or(true),
+                if_none_match: known.and_then(|known| known.etag.clone()),
+                if_modified_since: known.and_then(|known| known.last_modified.clone()),
+            };
+            let format = match spec.pseudo_feed {
+                Some(PseudoFeedKind::Sitemap) => SourceFormat::Pseudo(PseudoFeed::Sitemap {
+                    entries: spec.sitemap_entries.unwrap_or(DEFAULT_SITEMAP_ENTRIES),
+                    title_fetches: Arc::clone(&title_fetches),
+                    known_titles: self.known_titles.get(&spec.url).cloned().unwrap_or_default(),
+                }),
+                Some(PseudoFeedKind::Scrape) => SourceFormat::Pseudo(PseudoFeed::Scrape(spec.scrape_selectors.clone())),
+                None => SourceFormat::Feed(spec.feed_type),
+            };
+            let options = ParseOptions {
+                guid_strategy: self.config.guid_strategy.unwrap_or_default(),
+                verbose: self.verbose,
+                progress_to_stderr: self.progress_to_stderr,
+            };
+            let progress = self.progress.clone();
+            let done_sender = done_sender.clone();
+            handles.push(tokio::spawn(async move {
+                // The host's permit is taken first, so that feeds waiting for their host
+                // don't hold global permits that feeds of other hosts could use. The
+                // semaphores are never closed.
+                let Ok(host_permit) = host_semaphore.acquire_owned().await else {
+                    return;
+                };
+                let Ok(_permit) = semaphore.acquire_owned().await else {
+                    return;
+                };
+                let result = fetch_feed(client.as_ref(), &request, &fingerprints, shutdown, &format, options, progress).await;
+                // The host is free again once the fetch is done, even while the result waits
+                drop(host_permit);
+                let _ = done_sender.send((index, result)).await;
+            }));
+        }
+        drop(done_sender);
+
+        // Held back until all are fetched when merging duplicates, by position in the specs
+        let mut held: Vec<Option<FeedData>> = specs.iter().map(|_| None).collect();
+        let mut finished = vec![false; specs.len()];
+        let mut identities = Vec::new();
+        let mut cross_posts = CrossPosts::default();
+        let mut stats = CrawlStats::default();
+        let mut timings = HashMap::new();
+        let mut freshness = HashMap::new();
+        let mut unchanged = Vec::new();
+        let mut fingerprints = HashMap::new();
+        let mut failures = Vec::new();
+        let mut interrupted = Vec::new();
+        let mut shutdown = self.shutdown.clone();
+        let mut deadline: Option<tokio::time::Instant> = None;
+        loop {
+            let received = match deadline {
+                // Past the grace period, only the results already handed over are taken
+                Some(deadline) => tokio::time::timeout_at(deadline, done.recv()).await.ok().flatten(),
+                None => tokio::select! {
+                    received = done.recv() => received,
+                    _ = shutdown_requested(&mut shutdown) => {
+                        deadline = Some(tokio::time::Instant::now() + SHUTDOWN_GRACE_PERIOD);
+                        continue;
+                    }
+                },
+            };
+            let Some((index, result)) = received else {
+                break;
+            };
+            finished[index] = true;
+            let spec = &specs[index];
+            match result {
+                Ok(Some(fetched)) => {
+                    timings.insert(spec.url.clone(), fetched.timing);
+                    if let Some(lifetime) = fetched.freshness {
+                        freshness.insert(spec.url.clone(), lifetime);
+                    }
+                    let mut fingerprint = fetched.fingerprint;
+                    let Some(feed_data) = fetched.feed_data else {
+                        // Unchanged feeds are told apart from their duplicates and keep their
+                        // items from cross-posts as they were when last parsed
+                        identities.extend(fingerprint.identity.clone().map(|identity| (index, identity)));
+                        self.claim_unchanged_items(index, &spec.url, &fingerprint.guids, &mut cross_posts, now);
+                        fingerprints.insert(spec.url.clone(), fingerprint);
+                        unchanged.push((index, spec.clone()));
+                        continue;
+                    };
+                    let final_url = fetched.final_url.filter(|final_url| *final_url != spec.url);
+                    let identity = FeedIdentity::new(&feed_data, final_url.as_deref());
+                    fingerprint.identity = Some(identity.clone());
+                    identities.push((index, identity));
+                    if merge_duplicates {
+                        fingerprints.insert(spec.url.clone(), fingerprint);
+                        held[index] = Some(feed_data);
+                    } else {
+                        let feed_data = self.process_feed(&filters, index, feed_data, &mut cross_posts, &mut stats, now);
+                        fingerprint.guids = feed_data.items.iter().map(|item| item.guid.clone()).collect();
+                        fingerprints.insert(spec.url.clone(), fingerprint);
+                        stats.items += feed_data.items.len();
+                        let _ = ready.send(feed_data).await;
+                    }
+                }
+                Ok(None) => interrupted.push((index, spec.clone())),
+                Err(e) => failures.push((index, spec.clone(), e)),
+            }
+        }
+        // Tasks that panicked handed over nothing, and those still running are stopped
+        for (index, handle) in handles.into_iter().enumerate().filter(|(index, _)| !finished[*index]) {
+            let spec = specs[index].clone();
+            if !handle.is_finished() {
+                handle.abort();
+                interrupted.push((index, spec));
+                continue;
+            }
+            match handle.await {
+                Err(e) => failures.push((index, spec, FeedError::Task(e.to_string()))),
+                Ok(()) => interrupted.push((index, spec)),
+            }
+        }
+        interrupted.sort_by_key(|(index, _)| *index);
+        failures.sort_by_key(|(index, _, _)| *index);
+        unchanged.sort_by_key(|(index, _)| *index);
+        identities.sort_by_key(|(index, _)| *index);
+
+        // Duplicates are recognized by their items as published, and merged before the
+        // cross-post removal would empty all but the first of them
+        let identities: Vec<FeedIdentity> = identities.into_iter().map(|(_, identity)| identity).collect();
+        let duplicates = find_duplicate_feeds(&identities);
+        if merge_duplicates {
+            let merged: HashSet<&str> = duplicates.iter().flat_map(|duplicate| duplicate.duplicates.iter().map(String::as_str)).collect();
+            // Items first seen in a merged feed belong to its canonical feed from now on
+            if let Some(cache) = self.dedup_cache.as_mut() {
+                for duplicate in &duplicates {
+                    for url in &duplicate.duplicates {
+                        cache.move_feed(url, &duplicate.canonical);
+                    }
+                }
+            }
+            for (index, feed_data) in held.into_iter().enumerate() {
+                let Some(feed_data) = feed_data.filter(|feed_data| !merged.contains(feed_data.url.as_str())) else {
+                    continue;
+                };
+                let feed_data = self.process_feed(&filters, index, feed_data, &mut cross_posts, &mut stats, now);
+                if let Some(fingerprint) = fingerprints.get_mut(&feed_data.url) {
+                    fingerprint.guids = feed_data.items.iter().map(|item| item.guid.clone()).collect();
+                }
+                stats.items += feed_data.items.len();
+                let _ = ready.send(feed_data).await;
+            }
+        }
+        let mut given_up: Vec<(String, HashSet<String>)> = cross_posts.given_up.into_iter().collect();
+        given_up.sort_by(|a, b| a.0.cmp(&b.0));
+        for (url, guids) in &given_up {
+            progress!(self.progress_to_stderr, "Removed {} duplicate items from {}, which a feed listed before it has as well", guids.len(), url);
+            stats.duplicates_removed += guids.len();
+            stats.items -= guids.len();
+        }
+
+        stats.attempted = specs.len();
+        stats.succeeded = fingerprints.len();
+        stats.unchanged = unchanged.len();
+        stats.failed = failures.len();
+        stats.interrupted = interrupted.len();
+        stats.elapsed = started.elapsed();
+        CrawlResult {
+            timings,
+            freshness,
+            unchanged: unchanged.into_iter().map(|(_, spec)| spec).collect(),
+            fingerprints,
+            duplicates,
+            given_up: given_up.into_iter().collect(),
+            failures: failures.into_iter().map(|(_, spec, e)| (spec, e)).collect(),
+            interrupted: interrupted.into_iter().map(|(_, spec)| spec).collect(),
+            stats,
+        }
+    }
+
+    /// Cleans up the titles and dates of the feed listed at `index` in `specs`, and removes
+    /// its blocked items and those filtered out by keyword or author, already seen in
+    /// another feed or linking to an excluded domain, counting them in `stats`.
+    fn process_feed(&mut self, filters: &FeedFilters, index: usize, mut feed_data: FeedData, cross_posts: &mut CrossPosts, stats: &mut CrawlStats, now: DateTime<Utc>) -> FeedData {
+        let feeds = std::slice::from_mut(&mut feed_data);
+        self.normalize_titles(feeds);
+        self.shorten_item_titles(feeds);
+        self.handle_future_dates(feeds);
+        stats.excluded_by_blocklist += self.filter_blocked(feeds, now);
+        stats.excluded_by_keyword += self.filter_keywords(filters, feeds);
+        stats.excluded_by_author += self.filter_authors(filters, feeds);
+        stats.duplicates_removed += self.remove_duplicates(&mut feeds[0], index, cross_posts, now);
+        stats.excluded_by_link_domain += self.filter_link_domains(feeds);
+        feed_data
+    }
+
+    /// Cleans up the feed and item titles, unless `normalize_titles` is disabled. This runs
+    /// before anything uses the titles, so filenames, OPML entries and items agree.
+    fn normalize_titles(&self, feeds: &mut [FeedData]) {
+        if !self.config.normalize_titles.unwrap_or(true) {
+            return;
+        }
+        let max_length = self.config.max_title_length.unwrap_or(0);
+        for feed_data in feeds.iter_mut() {
+            feed_data.title = normalize_title(&feed_data.title, max_length);
+            for item in &mut feed_data.items {
+                item.title = normalize_title(&item.title, max_length);
+            }
+        }
+    }
+
+    /// Shortens item titles longer than `max_title_chars` at a word boundary. Feed titles,
+    /// which the archive filenames are built from, are left alone.
+    fn shorten_item_titles(&self, feeds: &mut [FeedData]) {
+        let max_chars = self.config.max_title_chars.unwrap_or(0);
+        if max_chars == 0 {
+            return;
+        }
+        for item in feeds.iter_mut().flat_map(|feed_data| feed_data.items.iter_mut()) {
+            item.title = shorten_title(&item.title, max_chars, true);
+        }
+    }
+
+    /// Clamps the publication date of items dated further in the future than the configured
+    /// tolerance to the time of the run that first saw them so dated, or drops them with
+    /// `drop_future_dates`, so that they don't stay on top of the feed. Clamped items keep
+    /// the date of their first clamping until the source fixes their date.
+    fn handle_future_dates(&mut self, feeds: &mut [FeedData]) {
+        if !self.config.clamp_future_dates.unwrap_or(true) {
+            return;
+        }
+        let drop = self.config.drop_future_dates.unwrap_or(false);
+        let now = Utc::now();
+        // A tolerance too large for a date means nothing is too far in the future
+        let Some(latest_allowed) = chrono::TimeDelta::try_minutes(self.config.future_date_tolerance_minutes.unwrap_or(60) as i64)
+            .and_then(|tolerance| now.checked_add_signed(tolerance))
+        else {
+            return;
+        };
+
+        let mut rejects = self.config.generate_rejects_feed.unwrap_or(false).then_some(&mut self.rejected_items);
+        let clamped_dates = &mut self.clamped_dates.entries;
+        for feed_data in feeds.iter_mut() {
+            let mut clamped = 0;
+            let dropped = retain_items(feed_data, rejects.as_deref_mut(), "drop_future_dates", |item| {
+                if item.pub_date <= latest_allowed {
+                    clamped_dates.remove(&item.guid);
+                    return true;
+                }
+                if !drop {
+                    item.pub_date = clamped_dates.entry(item.guid.clone()).or_insert(now).fixed_offset();
+                    clamped += 1;
+                }
+                !drop
+            });
+            if clamped > 0 {
+                progress!(self.progress_to_stderr, "Clamped the future publication date of {} items in {}", clamped, feed_data.url);
+            }
+            if dropped > 0 {
+                progress!(self.progress_to_stderr, "Dropped {} future-dated items from {}", dropped, feed_data.url);
+            }
+        }
+        if let Some(cutoff) = now.checked_sub_signed(chrono::TimeDelta::days(CLAMPED_DATE_DAYS)) {
+            clamped_dates.retain(|_, first_clamped| *first_clamped >= cutoff);
+        }
+    }
+
+    /// Forgets the items seen in other feeds that fell out of the deduplication window, or
+    /// all of them without a window.
+    fn evict_seen_items(&mut self, now: DateTime<Utc>) {
+        let window = self.dedup_window();
+        let Some(cache) = self.dedup_cache.as_mut() else {
+            return;
+        };
+        match window {
+            Some(window) => cache.evict_expired(now, window),
+            None => cache.entries.clear(),
+        }
+    }
+
+    /// The cross-feed deduplication window, None if `dedup_window_days` is 0. A window too
+    /// large to be represented keeps every item seen.
+    fn dedup_window(&self) -> Option<chrono::TimeDelta> {
+        match self.config.dedup_window_days.unwrap_or(30) {
+            0 => None,
+            days => Some(i64::try_from(days).ok().and_then(chrono::TimeDelta::try_days).unwrap_or(chrono::TimeDelta::MAX)),
+        }
+    }
+
+    /// Drops items cross-posted to several feeds, keeping them in the first listed feed that
+    /// has them. `index` is the position of the feed in the specs. Feeds are processed as
+    /// they are fetched, so an item first kept by a feed listed later is taken back from it
+    /// and recorded in `cross_posts`.
+    fn remove_duplicates(&mut self, feed_data: &mut FeedData, index: usize, cross_posts: &mut CrossPosts, now: DateTime<Utc>) -> usize {
+        let (Some(window), Some(cache)) = (self.dedup_window(), self.dedup_cache.as_mut()) else {
+            return 0;
+        };
+
+        let before = feed_data.items.len();
+        let url = feed_data.url.clone();
+        feed_data.items.retain(|item| {
+            match cross_posts.kept_by.get(&item.guid) {
+                Some((_, kept_by)) if *kept_by == url => true,
+                Some((kept_at, kept_by)) if *kept_at > index => {
+                    cross_posts.given_up.entry(kept_by.clone()).or_default().insert(item.guid.clone());
+                    if let Some(seen) = cache.entries.get_mut(&item.guid) {
+                        seen.feed_url = url.clone();
+                    }
+                    cross_posts.kept_by.insert(item.guid.clone(), (index, url.clone()));
+                    true
+                }
+                Some(_) => false,
+                None => {
+                    let first_seen = !cache.entries.contains_key(&item.guid);
+                    let duplicate = cache.is_duplicate(&item.guid, &url, now, window);
+                    if first_seen {
+                        cross_posts.kept_by.insert(item.guid.clone(), (index, url.clone()));
+                    }
+                    !duplicate
+                }
+            }
+        });
+        let removed = before - feed_data.items.len();
+        if removed > 0 {
+            progress!(self.progress_to_stderr, "Removed {} duplicate items from {}", removed, url);
+        }
+        removed
+    }
+
+    /// Records the items `guids` an unchanged feed listed at `index` kept when last parsed
+    /// as kept by it, as `remove_duplicates` would if it were parsed: feeds listed after it
+    /// give them up. The items stay in its archived copy either way.
+    fn claim_unchanged_items(&mut self, index: usize, url: &str, guids: &[String], cross_posts: &mut CrossPosts, now: DateTime<Utc>) {
+        let (Some(window), Some(cache)) = (self.dedup_window(), self.dedup_cache.as_mut()) else {
+            return;
+        };
+        for guid in guids {
+            match cross_posts.kept_by.get(guid) {
+                Some((kept_at, kept_by)) if *kept_at > index && kept_by != url => {
+                    cross_posts.given_up.entry(kept_by.clone()).or_default().insert(guid.clone());
+                    if let Some(seen) = cache.entries.get_mut(guid) {
+                        seen.feed_url = url.to_string();
+                    }
+                }
+                Some(_) => continue,
+                // Items first seen in another feed within the window belong to that feed
+                None if cache.is_duplicate(guid, url, now, window) => continue,
+                None => {}
+            }
+            cross_posts.kept_by.insert(guid.clone(), (index, url.to_string()));
+        }
+    }
+
+    /// Drops items matching the feed's exclude keywords and, if it has include keywords,
+    /// items matching none of them.
+    fn filter_keywords(&mut self, filters: &FeedFilters, feeds: &mut [FeedData]) -> usize {
+        let mut rejects = self.config.generate_rejects_feed.unwrap_or(false).then_some(&mut self.rejected_items);
+        let mut total_removed = 0;
+        for feed_data in feeds.iter_mut() {
+            let Some(filter) = filters.keywords.get(&feed_data.url) else {
+                continue;
+            };
+            let removed = retain_items(feed_data, rejects.as_deref_mut(), "keyword filters", |item| filter.keeps(item));
+            if removed > 0 {
+                progress!(self.progress_to_stderr, "Excluded {} items from {} by keyword", removed, feed_data.url);
+            }
+            total_removed += removed;
+        }
+        total_removed
+    }
+
+    /// Removes items by the authors excluded (or not included) by the feed's options. A feed
+    /// whose items carry no authors at all is reported once per crawler, as its author
+    /// filter has no effect, or drops everything with `drop_unattributed`.
+    fn filter_authors(&mut self, filters: &FeedFilters, feeds: &mut [FeedData]) -> usize {
+        let mut rejects = self.config.generate_rejects_feed.unwrap_or(false).then_some(&mut self.rejected_items);
+        let mut total_removed = 0;
+        for feed_data in feeds.iter_mut() {
+            let Some(filter) = filters.authors.get(&feed_data.url) else {
+                continue;
+            };
+            let unattributed = !feed_data.items.is_empty() && feed_data.items.iter().all(|item| item.authors.is_empty());
+            if unattributed && self.unattributed_feeds.insert(feed_data.url.clone()) {
+                let effect = if filter.drops_unattributed() { "drop_unattributed drops all of them" } else { "its author filter has no effect" };
+                eprintln!("Warning: None of the items of {} has an author, so {}", feed_data.url, effect);
+            }
+
+            let removed = retain_items(feed_data, rejects.as_deref_mut(), "author filters", |item| filter.keeps(item));
+            if removed > 0 {
+                progress!(self.progress_to_stderr, "Excluded {} items from {} by author", removed, feed_data.url);
+            }
+            total_removed += removed;
+        }
+        total_removed
+    }
+
+    /// Drops the items listed in `blocked_items`, by guid or link, and those removed that way
+    /// before, recording the guids of newly blocked items. These stay removed when their
+    /// entry is dropped from the blocklist or the feed changes their link.
+    fn filter_blocked(&mut self, feeds: &mut [FeedData], now: DateTime<Utc>) -> usize {
+        let blocked = self.config.blocked_items.as_deref().unwrap_or_default();
+        let removed = &mut self.removed_items;
+        if blocked.is_empty() && removed.entries.is_empty() {
+            return 0;
+        }
+
+        let mut rejects = self.config.generate_rejects_feed.unwrap_or(false).then_some(&mut self.rejected_items);
+        let mut total_removed = 0;
+        for feed_data in feeds.iter_mut() {
+            let count = retain_items(feed_data, rejects.as_deref_mut(), "blocked_items", |item| {
+                if removed.entries.contains_key(&item.guid) {
+                    return false;
+                }
+                if !blocked.iter().any(|entry| *entry == item.guid || *entry == item.link) {
+                    return true;
+                }
+                removed.entries.insert(item.guid.clone(), now);
+                false
+            });
+            if count > 0 {
+                progress!(self.progress_to_stderr, "Removed {} blocked items from {}", count, feed_data.url);
+            }
+            total_removed += count;
+        }
+        total_removed
+    }
+
+    /// Drops items linking to a host in `exclude_link_domains` and, if `include_link_domains`
+    /// is not empty, items not linking to one of those hosts.
+    fn filter_link_domains(&mut self, feeds: &mut [FeedData]) -> usize {
+        let exclude = self.config.exclude_link_domains.as_deref().unwrap_or_default();
+        let include = self.config.include_link_domains.as_deref().unwrap_or_default();
+        if exclude.is_empty() && include.is_empty() {
+            return 0;
+        }
+
+        let mut rejects = self.config.generate_rejects_feed.unwrap_or(false).then_some(&mut self.rejected_items);
+        let mut total_removed = 0;
+        for feed_data in feeds.iter_mut() {
+            let removed = retain_items(feed_data, rejects.as_deref_mut(), "link domain filters", |item| {
+                let host = link_host(&item.link);
+                let matches_any = |domains: &[String]| {
+                    host.as_deref().is_some_and(|host| domains.iter().any(|domain| host_matches_domain(host, domain)))
+                };
+                !matches_any(exclude) && (include.is_empty() || matches_any(include))
+            });
+            if removed > 0 {
+                progress!(self.progress_to_stderr, "Excluded {} items from {} by link domain", removed, feed_data.url);
+            }
+            total_removed += removed;
+        }
+        total_removed
+    }
+}
+
+/// Collapses runs of whitespace (including line breaks) into single spaces, decodes
+/// entities that survived parsing because the feed encoded them twice, and shortens
+/// titles longer than `max_length` characters (0 means unlimited) with an ellipsis.
+fn normalize_title(title: &str, max_length: usize) -> String {
+    static ENTITY_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&(amp|lt|gt|quot|apos|#[0-9]+|#[xX][0-9a-fA-F]+);").unwrap());
+    let decoded = ENTITY_RE.replace_all(title, |caps: &regex::Captures| {
+        let entity = &caps[1];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity[1..]
+                .strip_prefix(['x', 'X'])
+                .map_or_else(|| entity[1..].parse().ok(), |hex| u32::from_str_radix(hex, 16).ok())
+                .and_then(char::from_u32),
+        };
+        decoded.map_or_else(|| caps[0].to_string(), String::from)
+    });
+    let title = decoded.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if max_length > 0 {
+        shorten_title(&title, max_length, false)
+    } else {
+        title
+    }
+}
+
+/// Shortens `text` if it is longer than `max_chars` characters, to at most that many, the
+/// ellipsis included. With `at_word`, the cut falls after the last whole word that fits, or
+/// mid-word if the first word alone is too long, and punctuation left dangling at the cut is
+/// dropped. Words are separated by whitespace only, so "Don't" or "e.g." are never split.
+fn shorten_title(text: &str, max_chars: usize, at_word: bool) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    if !at_word {
+        return format!("{}…", kept.trim_end());
+    }
+    // The character after the kept part tells whether the cut fell at the end of a word
+    let cut_between_words = text.chars().nth(kept.chars().count()).is_some_and(char::is_whitespace);
+    let shortened = match kept.rfind(char::is_whitespace) {
+        _ if cut_between_words => kept.as_str(),
+        Some(boundary) if boundary > 0 => &kept[..boundary],
+        _ => kept.as_str(),
+    };
+    let shortened = shortened.trim_end_matches(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | ':' | '-' | '–' | '—'));
+    format!("{}…", shortened)
+}
+
+/// Decompresses a body that starts with gzip or zlib magic bytes, returning the name
+/// of the detected encoding and the decompressed body. Bodies decompressing to more than
+/// MAX_DECOMPRESSED_BYTES are left alone, so that a compression bomb can't exhaust memory.
+pub fn decompress_sniffed(body: &[u8]) -> Option<(&'static str, Vec<u8>)> {
+    let (encoding, decoder): (_, Box<dyn Read + '_>) = match body {
+        [0x1f, 0x8b, ..] => ("gzip", Box::new(flate2::read::MultiGzDecoder::new(body))),
+        // zlib header: deflate method, window size and a checksum making it a multiple of 31
+        [first, second, ..] if first & 0x0f == 8 && (u16::from(*first) << 8 | u16::from(*second)) % 31 == 0 => {
+            ("zlib", Box::new(flate2::read::ZlibDecoder::new(body)))
+        }
+        _ => return None,
+    };
+    let mut decompressed = Vec::new();
+    decoder.take(MAX_DECOMPRESSED_BYTES + 1).read_to_end(&mut decompressed).ok()?;
+    if decompressed.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        eprintln!("Warning: Not decompressing a {} body larger than {} bytes", encoding, MAX_DECOMPRESSED_BYTES);
+        return None;
+    }
+    Some((encoding, decompressed))
+}
+
+/// Returns the lowercase host of a link, if it is a valid absolute URL.
+fn link_host(link: &str) -> Option<String> {
+    let url = reqwest::Url::parse(link).ok()?;
+    url.host_str().map(|host| host.to_ascii_lowercase())
+}
+
+/// Returns true if `host` is `domain` or one of its subdomains.
+fn host_matches_domain(host: &str, domain: &str) -> bool {
+    let domain = domain.trim().trim_matches('.').to_ascii_lowercase();
+    !domain.is_empty() && (host == domain || host.strip_suffix(domain.as_str()).is_some_and(|rest| rest.ends_with('.')))
+}
+
+/// Fetches and parses a feed, unless a shutdown was requested. A response that is the one
+/// of the feed's fingerprint in `fingerprints` is not parsed.
+async fn fetch_feed(
+    client: &dyn HttpClient,
+    request: &FetchRequest,
+    fingerprints: &FingerprintStore,
+    shutdown: Option<watch::Receiver<bool>>,
+    format: &SourceFormat,
+    options: ParseOptions,
+    progress: Option<Arc<Mutex<BTreeSet<String>>>>,
+) -> Result<Option<FetchedFeed>, FeedError> {
+    // Don't start new fetches once a shutdown was requested
+    if shutdown.is_some_and(|shutdown| *shutdown.borrow()) {
+        return Ok(None);
+    }
+    let fetch_started = Instant::now();
+    let response = client.fetch(request).await?;
+    let fetch = fetch_started.elapsed();
+    let hash = if response.not_modified { None } else { Some(content_hash(&response.body)) };
+    let known = fingerprints.feeds.get(&request.url);
+    let mut fingerprint = FeedFingerprint {
+        etag: response.etag.clone(),
+        last_modified: response.last_modified.clone(),
+        content_hash: hash.clone().unwrap_or_else(|| known.map(|known| known.content_hash.clone()).unwrap_or_default()),
+        ..Default::default()
+    };
+    // The sitemaps of an unchanged sitemap index may have changed, so it is always read,
+    // and without validators, so that it is never answered with 304 Not Modified
+    let sitemap_index = matches!(format, SourceFormat::Pseudo(PseudoFeed::Sitemap { .. })) && is_sitemap_index(&response.body);
+    if sitemap_index {
+        fingerprint = FeedFingerprint::default();
+    }
+    let unchanged = !sitemap_index && hash.as_deref().is_none_or(|hash| !has_feed_changed(&request.url, hash, fingerprints));
+    if unchanged {
+        // Still recognized by what it was when last parsed
+        if let Some(known) = known {
+            fingerprint.identity = known.identity.clone();
+            fingerprint.guids = known.guids.clone();
+        }
+        debug!(options.verbose, "{} is unchanged since the last run, not parsing it", request.url);
+        if let Some(progress) = progress {
+            progress.lock().unwrap().insert(request.url.clone());
+        }
+        return Ok(Some(FetchedFeed {
+            feed_data: None,
+            fingerprint,
+            timing: FetchTiming { headers: response.headers_after, fetch, parse: Duration::ZERO },
+            freshness: response.freshness,
+            final_url: response.final_url,
+        }));
+    }
+    let parse_started = Instant::now();
+    let (mut feed_data, feed_type) = match format {
+        SourceFormat::Feed(_) => match parse_typed_feed(&request.url, &response.body, options) {
+            Ok((feed_data, feed_type)) => (feed_data, Some(feed_type)),
+            Err(e) => {
+                // Some servers compress the body without saying so in Content-Encoding
+                let Some((encoding, body)) = decompress_sniffed(&response.body) else {
+                    return Err(e);
+                };
+                let (feed_data, feed_type) = parse_typed_feed(&request.url, &body, options)?;
+                eprintln!(
+                    "Warning: {} sent a {} compressed body without a matching Content-Encoding header",
+                    request.url, encoding
+                );
+                (feed_data, Some(feed_type))
+            }
+        },
+        SourceFormat::Pseudo(pseudo_feed) => {
+            let page_url = response.final_url.as_deref().unwrap_or(&request.url);
+            (build_pseudo_feed(client, request, page_url, &response.body, pseudo_feed, options.verbose).await?, None)
+        }
+    };
+    let timing = FetchTiming {
+        headers: response.headers_after,
+        fetch,
+        parse: parse_started.elapsed(),
+    };
+    if let Some(final_url) = response.final_url.as_ref().filter(|final_url| **final_url != request.url) {
+        progress!(options.progress_to_stderr, "{} redirected to {}", request.url, final_url);
+        feed_data.resolved_url = final_url.clone();
+    }
+    debug!(
+        options.verbose,
+        "Parsed {} as {} (fetched in {} ms{}, parsed in {} ms)",
+        request.url,
+        match (&feed_type, format) {
+            (Some(feed_type), _) => feed_type_name(feed_type),
+            (None, SourceFormat::Pseudo(pseudo_feed)) => pseudo_feed.name(),
+            (None, SourceFormat::Feed(_)) => "a feed",
+        },
+        timing.fetch.as_millis(),
+        timing
+            .headers
+            .map(|headers| format!(", headers after {} ms", headers.as_millis()))
+            .unwrap_or_default(),
+        timing.parse.as_millis()
+    );
+    if let (SourceFormat::Feed(Some(expected)), Some(feed_type)) = (format, &feed_type) {
+        if !expected.matches(feed_type) {
+            eprintln!(
+                "Warning: {} is listed with type={} but was parsed as {}; the feed may have changed format",
+                request.url,
+                expected.name(),
+                feed_type_name(feed_type)
+            );
+        }
+    }
+    Ok(Some(FetchedFeed {
+        feed_data: Some(feed_data),
+        fingerprint,
+        timing,
+        freshness: response.freshness,
+        final_url: response.final_url,
+    }))
+}
+
+// How the body fetched for a feed list line is read
+enum SourceFormat {
+    // A feed, expected in the format of its type option if it has one
+    Feed(Option<FeedTypeHint>),
+    // A sitemap or listing page, see `PseudoFeed`
+    Pseudo(PseudoFeed),
+}
+
+/// Resolves once `true` was sent on the shutdown channel. Never resolves without one.
+async fn shutdown_requested(shutdown: &mut Option<watch::Receiver<bool>>) {
+    if let Some(shutdown) = shutdown {
+        if shutdown.wait_for(|&requested| requested).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending::<()>().await
+}
+
+// Remembers when each item guid was first seen and in which feed, so that items
+// cross-posted to several feeds are only archived once
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DedupCache {
+    pub entries: BTreeMap<String, SeenItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeenItem {
+    first_seen: DateTime<Utc>,
+    feed_url: String,
+}
+
+impl DedupCache {
+    /// Records the items first seen in the feed `from` as first seen in `to`.
+    pub fn move_feed(&mut self, from: &str, to: &str) {
+        for seen in self.entries.values_mut().filter(|seen| seen.feed_url == from) {
+            seen.feed_url = to.to_string();
+        }
+    }
+
+    /// Returns true if `guid` was first seen in another feed within `window`.
+    /// Otherwise records it as seen in `feed_url`. Entries older than the window
+    /// are treated as if they didn't exist, so genuinely republished content
+    /// can reappear.
+    pub fn is_duplicate(&mut self, guid: &str, feed_url: &str, now: DateTime<Utc>, window: chrono::Duration) -> bool {
+        match self.entries.get(guid) {
+            Some(seen) if now - seen.first_seen <= window => seen.feed_url != feed_url,
+            _ => {
+                self.entries.insert(guid.to_string(), SeenItem {
+                    first_seen: now,
+                    feed_url: feed_url.to_string(),
+                });
+                false
+            }
+        }
+    }
+
+    /// Forgets entries that fell out of the deduplication window.
+    pub fn evict_expired(&mut self, now: DateTime<Utc>, window: chrono::Duration) {
+        self.entries.retain(|_, seen| now - seen.first_seen <= window);
+    }
+}
+
+// Guids of the items removed with `blocked_items`, with when they were first removed, so
+// that they don't come back from the live feed in later runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RemovedItems {
+    pub entries: BTreeMap<String, DateTime<Utc>>,
+}
+
+// When the items dated in the future were first clamped to the time of the run, by guid, so
+// that they keep that date in later runs instead of being clamped to each run's time
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ClampedDates {
+    pub entries: BTreeMap<String, DateTime<Utc>>,
+}
+
+// How downloaded feed bodies are parsed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    // How a guid is made up for items without an id
+    pub guid_strategy: GuidStrategy,
+    // Print the dates that needed the fallback parser to stderr
+    pub verbose: bool,
+    // Print progress messages to stderr instead of stdout
+    pub progress_to_stderr: bool,
+}
+
+/// Parses a downloaded feed body into its items and metadata.
+pub fn parse_feed(url: &str, bytes: &[u8], options: ParseOptions) -> Result<FeedData, FeedError> {
+    parse_typed_feed(url, bytes, options).map(|(feed_data, _)| feed_data)
+}
+
+/// Like `parse_feed`, also returning the format feed_rs detected.
+fn parse_typed_feed(url: &str, bytes: &[u8], options: ParseOptions) -> Result<(FeedData, FeedType), FeedError> {
+    // Missing ids are left empty, to be made up below instead of by feed_rs, which falls
+    // back to random ones for entries without a link
+    let feed = parser::Builder::new()
+        .id_generator(|_, _, _| String::new())
+        .build()
+        .parse(bytes)
+        .map_err(|e| FeedError::Parse(e.to_string()))?;
+    let feed_type = feed.feed_type.clone();
+
+    // feed_rs does not expose skipHours/skipDays, item comments, iTunes episodes or dates it
+    // can't parse, so read them from the RSS channel directly
+    let (skip_hours, skip_days, serial, rss_extras) = match Channel::read_from(bytes) {
+        Ok(channel) => (
+            channel.skip_hours.iter().filter_map(|h| h.trim().parse().ok()).collect(),
+            channel.skip_days.iter().map(|d| d.trim().to_string()).collect(),
+            channel
+                .itunes_ext
+                .as_ref()
+                .and_then(|itunes| itunes.r#type.as_deref())
+                .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("serial")),
+            channel
+                .items
+                .iter()
+                .map(|item| RssItemExtras {
+                    date: item
+                        .pub_date
+                        .clone()
+                        .or_else(|| item.dublin_core_ext.as_ref().and_then(|dc| dc.dates.first().cloned())),
+                    modified: rss_extension_value(&channel, item, DCTERMS_NS, DCTERMS_NS_PREFIX, "modified").map(str::to_string),
+                    comments: item.comments.clone(),
+                    source: item.source.clone(),
+                    comment_count: rss_extension_value(&channel, item, SLASH_NS, SLASH_NS_PREFIX, "comments")
+                        .and_then(|count| count.trim().parse().ok()),
+                    comments_feed: rss_extension_value(&channel, item, WFW_NS, WFW_NS_PREFIX, "commentRss").map(|url| url.trim().to_string()),
+                    episode: item
+                        .itunes_ext
+                        .as_ref()
+                        .and_then(|itunes| itunes.episode.as_deref())
+                        .and_then(|episode| episode.trim().parse().ok()),
+                })
+                .collect(),
+        ),
+        Err(_) => (Vec::new(), Vec::new(), false, Vec::new()),
+    };
+    // Both parsers read the items in document order; only trust the pairing if they agree on the count
+    let rss_extras: Vec<RssItemExtras> = if rss_extras.len() == feed.entries.len() { rss_extras } else { Vec::new() };
+
+    // Extract feed title
+    let feed_title = feed.title
+        .map(|t| t.content)
+        .unwrap_or_else(|| url.to_string());
+    // feed_rs reads the Atom <subtitle>, the RSS <description> and the JSON Feed description
+    // into the same field; it is kept as plain text
+    let subtitle = feed
+        .description
+        .map(|text| html_to_plain_text(&text.content).split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|subtitle| !subtitle.is_empty());
+
+    // feed_rs reads the RSS <image>, the Atom <logo> and the JSON Feed icon into the logo,
+    // falling back to the smaller Atom <icon> and JSON Feed favicon
+    let icon = feed.logo.or(feed.icon).map(|image| image.uri).filter(|uri| !uri.trim().is_empty());
+    let copyright = feed
+        .rights
+        .map(|text| text.content.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|copyright| !copyright.is_empty());
+
+    // Create a FixedOffset with zero offset.
+    let offset = FixedOffset::east_opt(0).unwrap();
+
+    let mut items = Vec::new();
+    for (index, entry) in feed.entries.into_iter().enumerate() {
+        let extras = rss_extras.get(index).cloned().unwrap_or_default();
+
+        // Convert published/updated dates to DateTime<FixedOffset>, parsing the dates
+        // feed_rs gave up on ourselves before falling back to the current time
+        let pub_date = entry
+            .published
+            .map(|d| d.with_timezone(&offset))
+            .or(entry.updated.map(|d| d.with_timezone(&offset)))
+            .or_else(|| {
+                let raw = extras.date.as_deref()?;
+                let parsed = parse_fallback_date(raw);
+                match parsed {
+                    Some(date) => debug!(options.verbose, "Parsed the date {:?} of an item of {} as {}", raw, url, date.to_rfc3339()),
+                    None => debug!(options.verbose, "Could not parse the date {:?} of an item of {}", raw, url),
+                }
+                parsed
+            })
+            .unwrap_or_else(|| Utc::now().with_timezone::<FixedOffset>(&offset));
+        // feed_rs copies the publication date of RSS items to their update date, so only
+        // the dcterms:modified of RSS items tells them apart
+        let updated_date = extras
+            .modified
+            .as_deref()
+            .and_then(parse_fallback_date)
+            .or_else(|| entry.updated.map(|d| d.with_timezone(&offset)))
+            .filter(|updated| *updated != pub_date);
+
+        // Use the first available link (if any)
+        let link = if !entry.links.is_empty() {
+            entry.links[0].href.clone()
+        } else {
+            String::new()
+        };
+
+        // RSS has a comments element, Atom a rel="replies" link (RFC 4685), preferably to an HTML page
+        let replies = || entry.links.iter().filter(|link| link.rel.as_deref() == Some("replies"));
+        let comments = extras
+            .comments
+            .or_else(|| replies().find(|link| link.media_type.as_deref() == Some("text/html")).map(|link| link.href.clone()))
+            .or_else(|| replies().next().map(|link| link.href.clone()))
+            .filter(|comments| !comments.is_empty() && *comments != link);
+
+        let enclosure = extract_enclosure(&entry);
+        let mut authors: Vec<String> = Vec::new();
+        for name in entry.authors.iter().map(author_name) {
+            if !name.is_empty() && !authors.contains(&name) {
+                authors.push(name);
+            }
+        }
+        let mut categories: Vec<String> = Vec::new();
+        for category in &entry.categories {
+            let name = category.term.trim();
+            if !name.is_empty() && !categories.iter().any(|known| known == name) {
+                categories.push(name.to_string());
+            }
+        }
+        let thumbnail = entry
+            .media
+            .iter()
+            .flat_map(|media| media.thumbnails.iter())
+            .map(|thumbnail| thumbnail.image.uri.clone())
+            .next();
+
+        let description = entry.summary.map(|s| s.content);
+        let content = entry.content.and_then(|c| c.body);
+
+        let guid_synthesized = entry.id.is_empty();
+        let guid = if guid_synthesized {
+            options.guid_strategy.synthesize(&ItemIdentity {
+                links: &entry.links,
+                title: &entry.title,
+                description: description.as_deref(),
+                content: content.as_deref(),
+            })
+        } else {
+            entry.id
+        };
+
+        let title = entry
+            .title
+            .map(|t| t.content)
+            .unwrap_or_else(|| String::from("No title"));
+
+        // Estimate the reading time from the full content, falling back to the summary
+        let read_time_minutes = content
+            .as_deref()
+            .or(description.as_deref())
+            .map(estimate_read_time);
+
+        items.push(FeedItem {
+            guid,
+            guid_synthesized,
+            title,
+            link,
+            description,
+            content,
+            pub_date,
+            updated_date,
+            read_time_minutes,
+            language: entry.language,
+            enclosure,
+            thumbnail,
+            comments,
+            comment_count: extras.comment_count,
+            comments_feed: extras.comments_feed,
+            episode_number: extras.episode,
+            // Items republished from another feed keep the <source> they name
+            original_feed_url: Some(extras.source.as_ref().map_or_else(|| url.to_string(), |source| source.url.clone())),
+            original_feed_title: match &extras.source {
+                Some(source) => source.title.clone(),
+                None => Some(feed_title.clone()),
+            },
+            categories,
+            authors,
+        });
+    }
+
+    number_episodes(&mut items, serial);
+
+    let feed_data = FeedData {
+        title: feed_title,
+        subtitle,
+        url: url.to_string(),
+        resolved_url: url.to_string(),
+        items,
+        language: feed.language,
+        icon,
+        copyright,
+        slug: None,
+        priority: 0,
+        max_file_bytes: None,
+        base_url: None,
+        categories: Vec::new(),
+        display_title: None,
+        format: OutputFormat::default(),
+        filtered_count: 0,
+        ttl: feed.ttl,
+        skip_hours,
+        skip_days,
+    };
+    Ok((feed_data, feed_type))
+}
+
+/// Numbers the items of podcasts and serialized content. A feed is episodic if one of its
+/// items has an `<itunes:episode>`, its channel an `<itunes:type>` of serial, or if the
+/// titles of all its items (at least two) start with a number. Items of an episodic feed
+/// without an `<itunes:episode>` get the number their title starts with, if any; others,
+/// such as trailers and bonus items, stay unnumbered rather than get a made-up number.
+fn number_episodes(items: &mut [FeedItem], serial: bool) {
+    static TITLE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*(\d+)[.:)\s]").unwrap());
+    let title_number = |title: &str| TITLE_RE.captures(title).and_then(|caps| caps[1].parse::<u32>().ok());
+    let episodic = serial
+        || items.iter().any(|item| item.episode_number.is_some())
+        || (items.len() >= 2 && items.iter().all(|item| title_number(&item.title).is_some()));
+    if !episodic {
+        return;
+    }
+    for item in items.iter_mut().filter(|item| item.episode_number.is_none()) {
+        item.episode_number = title_number(&item.title);
+    }
+}
+
+// What feed_rs doesn't expose of an RSS item: its date as written (pubDate or dc:date),
+// its <comments> page, <source>, slash:comments, wfw:commentRss and itunes:episode
+#[derive(Debug, Clone, Default)]
+struct RssItemExtras {
+    episode: Option<u32>,
+    date: Option<String>,
+    // The dcterms:modified of the item, which feed_rs doesn't read
+    modified: Option<String>,
+    comments: Option<String>,
+    source: Option<rss::Source>,
+    comment_count: Option<u64>,
+    comments_feed: Option<String>,
+}
+
+/// Name of an item author. feed_rs names RSS `<author>` elements "author" and keeps their
+/// text, an email address optionally followed by the name in parentheses, as the email.
+fn author_name(person: &feed_rs::model::Person) -> String {
+    match person.email.as_deref().filter(|_| person.name == "author") {
+        Some(email) => email
+            .split_once('(')
+            .and_then(|(_, name)| name.trim_end().strip_suffix(')'))
+            .unwrap_or(email)
+            .trim()
+            .to_string(),
+        None => person.name.trim().to_string(),
+    }
+}
+
+/// Finds the audio/video enclosure of an entry. RSS `<enclosure>` elements are
+/// exposed by feed_rs as media content, Atom enclosures as `rel="enclosure"` links.
+fn extract_enclosure(entry: &feed_rs::model::Entry) -> Option<Enclosure> {
+    let media = entry
+        .media
+        .iter()
+        .flat_map(|media| media.content.iter())
+        .find_map(|content| {
+            let url = content.url.as_ref()?;
+            let mime_type = content.content_type.as_ref()?.to_string();
+            is_audio_or_video(&mime_type).then(|| Enclosure {
+                url: url.to_string(),
+                mime_type,
+                length: content.size.unwrap_or(0),
+            })
+        });
+
+    media.or_else(|| {
+        entry
+            .links
+            .iter()
+            .filter(|link| link.rel.as_deref() == Some("enclosure"))
+            .find(|link| link.media_type.as_deref().is_some_and(is_audio_or_video))
+            .map(|link| Enclosure {
+                url: link.href.clone(),
+                mime_type: link.media_type.clone().unwrap_or_default(),
+                length: link.length.unwrap_or(0),
+            })
+    })
+}
+
+fn is_audio_or_video(mime_type: &str) -> bool {
+    mime_type.starts_with("audio/") || mime_type.starts_with("video/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, rss, serve_once, MockClient};
+
+    /// Crawls `specs` with `config` and `client`, returning the feeds sent on.
+    async fn crawl(config: Config, client: &Arc<MockClient>, specs: &[FeedSpec]) -> (Vec<FeedData>, CrawlResult) {
+        let mut crawler = FeedCrawler::new().with_config(config).with_client(Arc::clone(client) as Arc<dyn HttpClient>);
+        let (sender, mut receiver) = mpsc::channel(specs.len().max(1));
+        let result = crawler.crawl_all(specs, sender).await;
+        let mut feeds = Vec::new();
+        while let Some(feed_data) = receiver.recv().await {
+            feeds.push(feed_data);
+        }
+        (feeds, result)
+    }
+
+    fn spec(url: &str) -> FeedSpec {
+        FeedSpec {
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn episodes(titles_and_episodes: &[(&str, Option<u32>)], serial: bool) -> Vec<Option<u32>> {
+        let mut items: Vec<FeedItem> = titles_and_episodes
+            .iter()
+            .map(|(title, episode)| FeedItem {
+                title: title.to_string(),
+                episode_number: *episode,
+                ..item(title, "2024-01-01T00:00:00Z")
+            })
+            .collect();
+        number_episodes(&mut items, serial);
+        items.iter().map(|item| item.episode_number).collect()
+    }
+
+    #[test]
+    fn only_itunes_episodes_and_title_numbers_number_items() {
+        // Trailers and bonus items of a podcast stay unnumbered instead of taking a position
+        assert_eq!(episodes(&[("Trailer", None), ("Pilot", Some(1)), ("Bonus", None), ("7) Seven", None)], false), [None, Some(1), None, Some(7)]);
+        assert_eq!(episodes(&[("Prologue", None), ("2: Two", None)], true), [None, Some(2)]);
+        assert_eq!(episodes(&[("12. Twelve", None), ("3 Three", None)], false), [Some(12), Some(3)]);
+        // Titles starting with a number don't make a feed episodic on their own unless all do
+        assert_eq!(episodes(&[("10 tips", None), ("News", None)], false), [None, None]);
+    }
+
+    #[test]
+    fn itunes_episodes_are_read_from_rss() {
+        let feed = r#"<?xml version="1.0"?>
+            <rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd"><channel>
+                <title>Podcast</title><link>https://example.com/</link><description>Podcast</description>
+                <item><title>Trailer</title><guid>trailer</guid><pubDate>Mon, 01 Jan 2024 00:00:00 +0000</pubDate></item>
+                <item><title>Pilot</title><guid>pilot</guid><itunes:episode> 1 </itunes:episode><pubDate>Tue, 02 Jan 2024 00:00:00 +0000</pubDate></item>
+            </channel></rss>"#;
+        let feed_data = parse_feed("https://example.com/podcast.xml", feed.as_bytes(), ParseOptions::default()).unwrap();
+        let numbers: Vec<Option<u32>> = feed_data.items.iter().map(|item| item.episode_number).collect();
+        assert_eq!(numbers, [None, Some(1)]);
+    }
+
+    #[test]
+    fn feed_urls_must_be_absolute_http_urls() {
+        assert!(check_feed_url("https://example.com/feed").is_ok());
+        assert!(check_feed_url("http://127.0.0.1:8080/feed.xml").is_ok());
+        for url in ["htps://example.com/feed", "example.com/feed", "ftp://example.com/feed", "https://", ""] {
+            assert!(check_feed_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn lines_with_an_invalid_url_are_skipped_unless_strict() {
+        let list = "https://a.example/feed\nexample.com/feed slug=typo\nhttps://b.example/feed\n";
+        let (specs, invalid) = parse_feed_list(list, false).unwrap();
+        let urls: Vec<&str> = specs.iter().map(|spec| spec.url.as_str()).collect();
+        assert_eq!(urls, ["https://a.example/feed", "https://b.example/feed"]);
+        assert_eq!(invalid.len(), 1);
+        assert!(invalid[0].starts_with("line 2: invalid URL \"example.com/feed\""), "{}", invalid[0]);
+
+        let error = parse_feed_list(list, true).unwrap_err();
+        assert!(error.starts_with("line 2: "), "{}", error);
+        assert_eq!(parse_feed_list_with_errors(list).1, invalid);
+    }
+
+    #[test]
+    fn combined_feeds_need_a_member_with_a_valid_url() {
+        let list = "@combined=News\n  htps://a.example/feed\n  example.com/feed\nhttps://c.example/feed\n";
+        let error = parse_feed_list(list, false).unwrap_err();
+        assert_eq!(error, "line 4: combined feed \"News\" lists no feeds");
+
+        let list = "@combined=News\n  htps://a.example/feed\n  https://b.example/feed\n";
+        let (specs, invalid) = parse_feed_list(list, false).unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].combined.as_deref(), Some("News"));
+        assert_eq!(invalid.len(), 1);
+    }
+
+    #[test]
+    fn first_line_setting_an_option_wins_even_with_the_default_value() {
+        let list = "https://a.example/feed priority=0 category=news\n\
+                    https://a.example/feed priority=2 accept=text/xml category=tech\n\
+                    https://b.example/feed\n\
+                    https://b.example/feed priority=-1\n";
+        let (specs, invalid) = parse_feed_list(list, true).unwrap();
+        assert!(invalid.is_empty());
+        let specs = coalesce_duplicate_specs(specs, "feeds.txt");
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].priority, Some(0));
+        assert_eq!(specs[0].accept.as_deref(), Some("text/xml"));
+        assert_eq!(specs[0].categories, ["news", "tech"]);
+        assert_eq!(specs[1].priority, Some(-1));
+    }
+
+    #[tokio::test]
+    async fn drop_unattributed_is_taken_from_the_first_line_setting_it() {
+        let list = "https://a.example/feed drop_unattributed=false\nhttps://a.example/feed drop_unattributed=true exclude_authors=Spam\n";
+        let specs = coalesce_duplicate_specs(parse_feed_list(list, true).unwrap().0, "feeds.txt");
+        assert_eq!(specs[0].drop_unattributed, Some(false));
+
+        let client = Arc::new(MockClient::new(&[("https://a.example/feed", rss("Feed", &[("a", "2024-01-01T00:00:00Z")]))]));
+        let (feeds, _) = crawl(Config::default(), &client, &specs).await;
+        assert_eq!(feeds[0].items.len(), 1);
+    }
+
+    #[test]
+    fn comment_counts_are_read_whatever_the_namespace_prefix() {
+        let body = r#"<?xml version="1.0"?>
+            <rss version="2.0" xmlns:s="http://purl.org/rss/1.0/modules/slash/" xmlns:cr="http://wellformedweb.org/CommentAPI/">
+            <channel><title>Feed</title><link>https://example.com/</link><description>Test feed</description>
+            <item><title>A</title><link>https://example.com/a</link><guid>a</guid>
+                <s:comments> 42 </s:comments><cr:commentRss>https://example.com/a/comments.xml</cr:commentRss></item>
+            <item><title>B</title><link>https://example.com/b</link><guid>b</guid><s:comments>many</s:comments></item>
+            </channel></rss>"#;
+        let feed_data = parse_feed("https://example.com/feed.xml", body.as_bytes(), ParseOptions::default()).unwrap();
+        assert_eq!(feed_data.items[0].comment_count, Some(42));
+        assert_eq!(feed_data.items[0].comments_feed.as_deref(), Some("https://example.com/a/comments.xml"));
+        assert_eq!(feed_data.items[1].comment_count, None);
+        assert_eq!(feed_data.items[1].comments_feed, None);
+    }
+
+    #[test]
+    fn long_titles_are_shortened_at_a_word_boundary() {
+        let title = "Don't miss the e.g. release notes: what changed in version 2.0";
+        assert_eq!(shorten_title(title, 62, true), title);
+        assert_eq!(shorten_title(title, 8, true), "Don't…");
+        assert_eq!(shorten_title(title, 18, true), "Don't miss the…");
+        assert_eq!(shorten_title(title, 20, true), "Don't miss the e.g.…");
+        // Dangling punctuation goes, periods and apostrophes within words stay
+        assert_eq!(shorten_title(title, 35, true), "Don't miss the e.g. release notes…");
+        assert_eq!(shorten_title(title, 61, true), "Don't miss the e.g. release notes: what changed in version…");
+        assert!(shorten_title(title, 35, true).chars().count() <= 35);
+        // A first word too long to fit is cut mid-word
+        assert_eq!(shorten_title("Supercalifragilistic", 6, true), "Super…");
+        // max_title_length cuts wherever the limit falls
+        assert_eq!(shorten_title(title, 8, false), "Don't m…");
+    }
+
+    #[test]
+    fn escaped_commas_stay_in_list_values() {
+        assert_eq!(parse_list_option("rust, cargo,,"), ["rust", "cargo"]);
+        assert_eq!(parse_list_option(r"v[0-9]{2\,3},beta"), [r"v[0-9]{2,3}", "beta"]);
+        assert_eq!(parse_list_option(r"a\b"), [r"a\b"]);
+    }
+
+    #[test]
+    fn items_without_an_id_get_a_guid_from_the_configured_strategy() {
+        let body = "<?xml version=\"1.0\"?><rss version=\"2.0\"><channel><title>Feed</title><link>https://example.com/</link><description>Test</description>\
+                    <item><title>Linked</title><link>https://example.com/linked</link></item>\
+                    <item><title>Unlinked</title><description>Text</description></item>\
+                    <item><title>Identified</title><link>https://example.com/id</link><guid>id-1</guid></item></channel></rss>";
+        let guids = |guid_strategy| {
+            let feed_data = parse_feed("https://example.com/feed.xml", body.as_bytes(), ParseOptions { guid_strategy, ..ParseOptions::default() }).unwrap();
+            feed_data.items.into_iter().map(|item| (item.guid, item.guid_synthesized)).collect::<Vec<_>>()
+        };
+
+        let by_link = guids(GuidStrategy::Link);
+        assert_eq!(by_link[0], ("https://example.com/linked".to_string(), true));
+        assert_eq!(by_link[2], ("id-1".to_string(), false));
+        let by_content = guids(GuidStrategy::ContentHash);
+        assert_ne!(by_content[0].0, by_link[0].0);
+        // Unlinked items are hashed whatever the strategy, and the same on every parse
+        assert!(by_link[1].1);
+        assert_eq!(by_link[1], by_content[1]);
+        assert_eq!(guids(GuidStrategy::LinkAndTitle)[1], by_link[1]);
+        assert_eq!(guids(GuidStrategy::ContentHash), by_content);
+    }
+
+    /// Crawls `specs` like a run following one that recorded `fingerprints`, returning the
+    /// feeds sent on and the fingerprints to record.
+    async fn crawl_again(client: &Arc<MockClient>, specs: &[FeedSpec], fingerprints: &HashMap<String, FeedFingerprint>) -> (Vec<FeedData>, CrawlResult) {
+        let store = FingerprintStore {
+            settings_hash: String::new(),
+            feeds: fingerprints.clone().into_iter().collect(),
+        };
+        let mut crawler = FeedCrawler::new()
+            .with_client(Arc::clone(client) as Arc<dyn HttpClient>)
+            .with_dedup_cache(DedupCache::default())
+            .with_fingerprints(store);
+        let (sender, mut receiver) = mpsc::channel(specs.len());
+        let result = crawler.crawl_all(specs, sender).await;
+        let mut feeds = Vec::new();
+        while let Some(feed_data) = receiver.recv().await {
+            feeds.push(feed_data);
+        }
+        (feeds, result)
+    }
+
+    // Client answering the requests for `slow_url` only after a while
+    struct DelayingClient {
+        client: MockClient,
+        slow_url: &'static str,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for DelayingClient {
+        async fn fetch(&self, request: &FetchRequest) -> Result<FetchResponse, FeedError> {
+            if request.url == self.slow_url {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            self.client.fetch(request).await
+        }
+    }
+
+    // Client recording how many requests per host it answers at once at most
+    #[derive(Default)]
+    struct CountingClient {
+        client: MockClient,
+        in_flight: Mutex<HashMap<String, usize>>,
+        most_in_flight: Mutex<HashMap<String, usize>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for CountingClient {
+        async fn fetch(&self, request: &FetchRequest) -> Result<FetchResponse, FeedError> {
+            let host = link_host(&request.url).unwrap_or_default();
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                let count = in_flight.entry(host.clone()).or_default();
+                *count += 1;
+                let mut most_in_flight = self.most_in_flight.lock().unwrap();
+                let most = most_in_flight.entry(host.clone()).or_default();
+                *most = (*most).max(*count);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            *self.in_flight.lock().unwrap().get_mut(&host).unwrap() -= 1;
+            self.client.fetch(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn feeds_of_one_host_are_not_all_fetched_at_once() {
+        let urls: Vec<String> = (0..6)
+            .map(|n| format!("https://busy.example/feed{}", n))
+            .chain((0..2).map(|n| format!("https://quiet.example/feed{}", n)))
+            .collect();
+        let feeds: Vec<(&str, String)> = urls.iter().map(|url| (url.as_str(), rss(url, &[(url, "2024-01-01T00:00:00Z")]))).collect();
+        let client = Arc::new(CountingClient { client: MockClient::new(&feeds), ..Default::default() });
+        let config = Config { max_concurrent_per_host: Some(2), ..Default::default() };
+        let mut crawler = FeedCrawler::new()
+            .with_config(config)
+            .with_client(Arc::clone(&client) as Arc<dyn HttpClient>)
+            .with_dedup_cache(DedupCache::default());
+        let specs: Vec<FeedSpec> = urls.iter().map(|url| spec(url)).collect();
+        let (sender, mut receiver) = mpsc::channel(specs.len());
+        crawler.crawl_all(&specs, sender).await;
+        let mut fetched = 0;
+        while receiver.recv().await.is_some() {
+            fetched += 1;
+        }
+        assert_eq!(fetched, urls.len());
+
+        let most_in_flight = client.most_in_flight.lock().unwrap();
+        assert_eq!(most_in_flight["busy.example"], 2);
+        assert_eq!(most_in_flight["quiet.example"], 2);
+    }
+
+    #[tokio::test]
+    async fn feeds_are_sent_on_as_they_are_fetched_and_listing_order_decides_cross_posts() {
+        let client = Arc::new(DelayingClient {
+            client: MockClient::new(&[
+                ("https://slow.example/feed", rss("Slow", &[("slow", "2024-01-01T00:00:00Z"), ("shared", "2024-01-02T00:00:00Z")])),
+                ("https://fast.example/feed", rss("Fast", &[("fast", "2024-01-01T00:00:00Z"), ("shared", "2024-01-02T00:00:00Z")])),
+            ]),
+            slow_url: "https://slow.example/feed",
+        });
+        let mut crawler = FeedCrawler::new().with_client(client as Arc<dyn HttpClient>).with_dedup_cache(DedupCache::default());
+        let (sender, mut receiver) = mpsc::channel(1);
+        let specs = [spec("https://slow.example/feed"), spec("https://fast.example/feed")];
+        let crawl = crawler.crawl_all(&specs, sender);
+        let receive = async {
+            let mut urls = Vec::new();
+            while let Some(feed_data) = receiver.recv().await {
+                urls.push(feed_data.url);
+            }
+            urls
+        };
+        let (result, urls) = tokio::join!(crawl, receive);
+
+        // The fast feed is written first, then gives up the item of the first-listed feed
+        assert_eq!(urls, ["https://fast.example/feed", "https://slow.example/feed"]);
+        assert_eq!(result.given_up["https://fast.example/feed"], HashSet::from(["shared".to_string()]));
+        assert_eq!(result.stats.succeeded, 2);
+    }
+
+    #[tokio::test]
+    async fn huge_dedup_windows_keep_every_item_seen() {
+        let seen_long_ago = |url: &str| {
+            let mut cache = DedupCache::default();
+            cache.entries.insert("shared".to_string(), SeenItem { first_seen: Utc::now() - chrono::Duration::days(3650), feed_url: url.to_string() });
+            cache
+        };
+        let client = Arc::new(MockClient::new(&[("https://b.example/feed", rss("B", &[("shared", "2024-01-01T00:00:00Z"), ("own", "2024-01-02T00:00:00Z")]))]));
+        for (window_days, kept) in [(u64::MAX, vec!["own"]), (i64::MAX as u64, vec!["own"]), (30, vec!["own", "shared"]), (0, vec!["own", "shared"])] {
+            let config = Config { dedup_window_days: Some(window_days), ..Default::default() };
+            let mut crawler = FeedCrawler::new()
+                .with_config(config)
+                .with_client(Arc::clone(&client) as Arc<dyn HttpClient>)
+                .with_dedup_cache(seen_long_ago("https://a.example/feed"));
+            let (sender, mut receiver) = mpsc::channel(1);
+            crawler.crawl_all(&[spec("https://b.example/feed")], sender).await;
+            let mut guids: Vec<String> = receiver.recv().await.unwrap().items.into_iter().map(|item| item.guid).collect();
+            guids.sort();
+            assert_eq!(guids, kept, "dedup_window_days = {}", window_days);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocked_items_are_not_re_added_in_later_runs() {
+        let url = "https://example.com/feed.xml";
+        let items = [("a", "2024-01-03T00:00:00Z"), ("b", "2024-01-02T00:00:00Z"), ("c", "2024-01-01T00:00:00Z")];
+        let client = Arc::new(MockClient::new(&[(url, rss("Feed", &items))]));
+        let guids = |feed_data: FeedData| feed_data.items.iter().map(|item| item.guid.clone()).collect::<Vec<_>>();
+
+        let config = Config { blocked_items: Some(vec!["b".to_string(), "https://example.com/c".to_string()]), ..Default::default() };
+        let mut crawler = FeedCrawler::new().with_config(config).with_client(Arc::clone(&client) as Arc<dyn HttpClient>);
+        let (sender, mut receiver) = mpsc::channel(1);
+        crawler.crawl_all(&[spec(url)], sender).await;
+        assert_eq!(guids(receiver.recv().await.unwrap()), ["a"]);
+
+        // Persisted with the state, then run again with the entries gone from the blocklist
+        let removed = serde_json::to_string(&crawler.take_removed_items()).unwrap();
+        let removed: RemovedItems = serde_json::from_str(&removed).unwrap();
+        assert_eq!(removed.entries.keys().collect::<Vec<_>>(), ["b", "c"]);
+        let mut crawler = FeedCrawler::new()
+            .with_client(Arc::clone(&client) as Arc<dyn HttpClient>)
+            .with_dedup_cache(DedupCache::default())
+            .with_removed_items(removed);
+        let (sender, mut receiver) = mpsc::channel(1);
+        crawler.crawl_all(&[spec(url)], sender).await;
+        assert_eq!(guids(receiver.recv().await.unwrap()), ["a"]);
+        assert_eq!(crawler.take_removed_items().entries.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn identical_bodies_are_not_parsed_again() {
+        let body = rss("Feed", &[("a", "2024-01-01T00:00:00Z")]);
+        let client = Arc::new(MockClient::new(&[("https://example.com/feed", body.clone())]));
+        let specs = [spec("https://example.com/feed")];
+        let (feeds, first) = crawl_again(&client, &specs, &HashMap::new()).await;
+        assert_eq!(feeds.len(), 1);
+        assert!(first.unchanged.is_empty());
+        assert_eq!(first.fingerprints["https://example.com/feed"].content_hash, content_hash(body.as_bytes()));
+
+        let (feeds, second) = crawl_again(&client, &specs, &first.fingerprints).await;
+        assert!(feeds.is_empty());
+        assert_eq!(second.unchanged.iter().map(|spec| spec.url.as_str()).collect::<Vec<_>>(), ["https://example.com/feed"]);
+        assert_eq!(second.fingerprints["https://example.com/feed"].content_hash, content_hash(body.as_bytes()));
+        assert_eq!(second.fingerprints["https://example.com/feed"].guids, ["a"]);
+    }
+
+    #[tokio::test]
+    async fn unchanged_sitemap_indexes_are_read_again() {
+        let index = "<sitemapindex><sitemap><loc>https://example.com/sitemap-1.xml</loc></sitemap></sitemapindex>".to_string();
+        let client = Arc::new(MockClient::new(&[
+            ("https://example.com/sitemap.xml", index),
+            ("https://example.com/sitemap-1.xml", "<urlset><url><loc>https://example.com/a</loc></url></urlset>".to_string()),
+            ("https://example.com/urlset.xml", "<urlset><url><loc>https://example.com/b</loc></url></urlset>".to_string()),
+        ]));
+        let sitemap = |url: &str| FeedSpec {
+            pseudo_feed: Some(PseudoFeedKind::Sitemap),
+            ..spec(url)
+        };
+        let specs = [sitemap("https://example.com/sitemap.xml"), sitemap("https://example.com/urlset.xml")];
+        let (_, first) = crawl_again(&client, &specs, &HashMap::new()).await;
+        let fingerprint = &first.fingerprints["https://example.com/sitemap.xml"];
+        assert_eq!((fingerprint.etag.as_deref(), fingerprint.content_hash.as_str()), (None, ""));
+
+        let (feeds, second) = crawl_again(&client, &specs, &first.fingerprints).await;
+        assert_eq!(feeds.iter().map(|feed_data| feed_data.url.as_str()).collect::<Vec<_>>(), ["https://example.com/sitemap.xml"]);
+        assert_eq!(second.unchanged.iter().map(|spec| spec.url.as_str()).collect::<Vec<_>>(), ["https://example.com/urlset.xml"]);
+    }
+
+    #[tokio::test]
+    async fn unchanged_feeds_are_still_told_apart_from_their_duplicates() {
+        let body = rss("Feed", &[("a", "2024-01-01T00:00:00Z")]);
+        let client = Arc::new(MockClient::new(&[("https://example.com/feed", body.clone()), ("http://www.example.com/feed", body)]));
+        let specs = [spec("https://example.com/feed"), spec("http://www.example.com/feed")];
+        let (_, first) = crawl_again(&client, &specs, &HashMap::new()).await;
+        assert_eq!(first.duplicates.len(), 1);
+
+        let (feeds, second) = crawl_again(&client, &specs, &first.fingerprints).await;
+        assert!(feeds.is_empty());
+        assert_eq!(second.stats.unchanged, 2);
+        assert_eq!(second.stats.succeeded, 2);
+        assert_eq!(second.duplicates.len(), 1);
+        assert_eq!(second.duplicates[0].canonical, "https://example.com/feed");
+        assert_eq!(second.duplicates[0].duplicates, ["http://www.example.com/feed"]);
+    }
+
+    #[tokio::test]
+    async fn unchanged_feeds_keep_their_items_from_cross_posts() {
+        let first = rss("First", &[("shared", "2024-01-01T00:00:00Z")]);
+        let specs = [spec("https://a.example/feed"), spec("https://b.example/feed")];
+        let client = Arc::new(MockClient::new(&[
+            ("https://a.example/feed", first.clone()),
+            ("https://b.example/feed", rss("Second", &[("own", "2024-01-01T00:00:00Z")])),
+        ]));
+        let (_, run) = crawl_again(&client, &specs, &HashMap::new()).await;
+        assert_eq!(run.fingerprints["https://a.example/feed"].guids, ["shared"]);
+
+        // The second feed picks up the item the unchanged first feed has
+        let client = Arc::new(MockClient::new(&[
+            ("https://a.example/feed", first),
+            ("https://b.example/feed", rss("Second", &[("own", "2024-01-01T00:00:00Z"), ("shared", "2024-01-02T00:00:00Z")])),
+        ]));
+        let (feeds, run) = crawl_again(&client, &specs, &run.fingerprints).await;
+        assert_eq!(run.stats.unchanged, 1);
+        // Removed before the second feed is sent on, or given up after if it came in first
+        let given_up = run.given_up.get("https://b.example/feed").cloned().unwrap_or_default();
+        let kept: Vec<Vec<&str>> = feeds
+            .iter()
+            .map(|feed_data| feed_data.items.iter().map(|item| item.guid.as_str()).filter(|guid| !given_up.contains(*guid)).collect())
+            .collect();
+        assert_eq!(kept, [["own"]]);
+        assert_eq!(run.fingerprints["https://a.example/feed"].guids, ["shared"]);
+    }
+
+    #[tokio::test]
+    async fn progress_only_records_feeds_whose_archive_is_up_to_date() {
+        let unchanged = rss("Unchanged", &[("a", "2024-01-01T00:00:00Z")]);
+        let updated = rss("Updated", &[("b", "2024-01-01T00:00:00Z")]);
+        let client = Arc::new(MockClient::new(&[("https://a.example/feed", unchanged.clone()), ("https://b.example/feed", updated)]));
+        let mut store = FingerprintStore::default();
+        store.feeds.insert(
+            "https://a.example/feed".to_string(),
+            FeedFingerprint { content_hash: content_hash(unchanged.as_bytes()), ..Default::default() },
+        );
+        let progress: Arc<Mutex<BTreeSet<String>>> = Arc::default();
+        let mut crawler = FeedCrawler::new()
+            .with_client(Arc::clone(&client) as Arc<dyn HttpClient>)
+            .with_fingerprints(store)
+            .with_progress(Arc::clone(&progress));
+        let (sender, mut receiver) = mpsc::channel(2);
+        crawler.crawl_all(&[spec("https://a.example/feed"), spec("https://b.example/feed")], sender).await;
+
+        // The updated feed is sent on to be written, and recorded by whoever writes it
+        assert_eq!(receiver.recv().await.map(|feed_data| feed_data.url).as_deref(), Some("https://b.example/feed"));
+        assert_eq!(*progress.lock().unwrap(), BTreeSet::from(["https://a.example/feed".to_string()]));
+    }
+
+    #[test]
+    fn freshness_lifetime_is_capped() {
+        use reqwest::header::{AGE, CACHE_CONTROL, DATE, EXPIRES};
+        let now = Utc::now();
+        let lifetime = |headers: &[(reqwest::header::HeaderName, &str)]| {
+            let mut map = reqwest::header::HeaderMap::new();
+            for (name, value) in headers {
+                map.insert(name.clone(), value.parse().unwrap());
+            }
+            freshness_lifetime(&map, now)
+        };
+        assert_eq!(lifetime(&[(CACHE_CONTROL, "public, max-age=600"), (AGE, "100")]), Some(Duration::from_secs(500)));
+        assert_eq!(lifetime(&[(CACHE_CONTROL, "max-age=600, no-cache")]), Some(Duration::ZERO));
+        assert_eq!(lifetime(&[(CACHE_CONTROL, "max-age=18446744073709551615")]), Some(MAX_FRESHNESS_LIFETIME));
+        assert_eq!(
+            lifetime(&[(DATE, "Mon, 01 Jan 2024 00:00:00 GMT"), (EXPIRES, "Fri, 31 Dec 9999 23:59:59 GMT")]),
+            Some(MAX_FRESHNESS_LIFETIME)
+        );
+        assert_eq!(lifetime(&[(EXPIRES, "0")]), Some(Duration::ZERO));
+        assert_eq!(lifetime(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn accept_header_is_configured_globally_and_per_feed() {
+        let body = rss("Feed", &[("a", "2024-01-01T00:00:00Z")]);
+        let client = Arc::new(MockClient::new(&[("https://a.example/feed", body.clone()), ("https://b.example/feed", body)]));
+        let specs = [
+            spec("https://a.example/feed"),
+            FeedSpec {
+                accept: Some("application/atom+xml".to_string()),
+                ..spec("https://b.example/feed")
+            },
+        ];
+        crawl(Config::default(), &client, &specs).await;
+        let config = Config {
+            accept: Some("application/rss+xml".to_string()),
+            ..Default::default()
+        };
+        crawl(config, &client, &specs).await;
+
+        let mut sent: Vec<(String, String)> = client.requests.lock().unwrap().iter().map(|request| (request.url.clone(), request.accept.clone())).collect();
+        sent.sort();
+        assert_eq!(
+            sent,
+            [
+                ("https://a.example/feed".to_string(), "application/rss+xml".to_string()),
+                ("https://a.example/feed".to_string(), DEFAULT_ACCEPT.to_string()),
+                ("https://b.example/feed".to_string(), "application/atom+xml".to_string()),
+                ("https://b.example/feed".to_string(), "application/atom+xml".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reqwest_client_sends_the_accept_header() {
+        let (url, server) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await;
+        let request = FetchRequest {
+            url,
+            accept: "application/feed+json".to_string(),
+            follow_redirects: true,
+            if_none_match: None,
+            if_modified_since: None,
+        };
+        let response = reqwest::Client::new().fetch(&request).await.unwrap();
+        assert_eq!(response.body, b"ok");
+        let received = server.await.unwrap().to_ascii_lowercase();
+        assert!(received.contains("\r\naccept: application/feed+json\r\n"), "{}", received);
+    }
+
+    #[tokio::test]
+    async fn ip_family_limits_the_addresses_connected_to() {
+        // The server only listens on 127.0.0.1, which localhost resolves to over IPv4
+        let (url, server) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await;
+        let request = |url: &str| FetchRequest {
+            url: url.replace("127.0.0.1", "localhost"),
+            accept: DEFAULT_ACCEPT.to_string(),
+            follow_redirects: true,
+            if_none_match: None,
+            if_modified_since: None,
+        };
+        let ipv6 = RedirectPolicyClient::new(10, IpFamily::Ipv6).unwrap();
+        assert!(ipv6.fetch(&request(&url)).await.is_err());
+        let ipv4 = RedirectPolicyClient::new(10, IpFamily::Ipv4).unwrap();
+        assert_eq!(ipv4.fetch(&request(&url)).await.unwrap().body, b"ok");
+        server.await.unwrap();
+
+        let config: Config = toml::from_str("ip_family = \"ipv6\"").unwrap();
+        assert_eq!(config.ip_family, Some(IpFamily::Ipv6));
+    }
+
+    #[test]
+    fn filters_are_compiled_once_per_url_from_its_first_spec() {
+        let specs = [
+            FeedSpec {
+                exclude: Some(vec!["sponsored".to_string()]),
+                ..spec("https://a.example/feed")
+            },
+            FeedSpec {
+                include: Some(vec!["rust".to_string()]),
+                ..spec("https://a.example/feed")
+            },
+            FeedSpec {
+                exclude_authors: Some(vec!["Bot".to_string()]),
+                ..spec("https://b.example/feed")
+            },
+            spec("https://c.example/feed"),
+        ];
+        let filters = FeedFilters::compile(&Config::default(), &specs);
+        let mut sponsored = crate::test_support::item("a", "2024-01-01T00:00:00Z");
+        sponsored.title = "Sponsored post".to_string();
+        let plain = crate::test_support::item("b", "2024-01-01T00:00:00Z");
+
+        let keywords = &filters.keywords["https://a.example/feed"];
+        assert!(!keywords.keeps(&sponsored));
+        assert!(keywords.keeps(&plain));
+        assert_eq!(filters.keywords.len(), 1);
+        assert_eq!(filters.authors.keys().collect::<Vec<_>>(), ["https://b.example/feed"]);
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        std::io::Write::write_all(&mut encoder, bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn sniffed_bodies_are_decompressed_up_to_a_limit() {
+        let feed = rss("Feed", &[("a", "2024-01-01T00:00:00Z")]);
+        assert_eq!(decompress_sniffed(&gzip(feed.as_bytes())), Some(("gzip", feed.into_bytes())));
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut zlib, b"<rss/>").unwrap();
+        assert_eq!(decompress_sniffed(&zlib.finish().unwrap()), Some(("zlib", b"<rss/>".to_vec())));
+        assert_eq!(decompress_sniffed(b"<rss/>"), None);
+
+        let bomb = gzip(&vec![b' '; MAX_DECOMPRESSED_BYTES as usize + 1]);
+        assert!(bomb.len() < 1024 * 1024);
+        assert_eq!(decompress_sniffed(&bomb), None);
+    }
+
+    #[tokio::test]
+    async fn future_dated_items_keep_the_date_of_their_first_clamping() {
+        let body = rss("Feed", &[("future", "2999-01-01T00:00:00Z"), ("past", "2024-01-01T00:00:00Z")]);
+        let client: Arc<dyn HttpClient> = Arc::new(MockClient::new(&[("https://a.example/feed", body)]));
+        let specs = [spec("https://a.example/feed")];
+        let mut clamped = ClampedDates::default();
+        let mut dates = Vec::new();
+        for _ in 0..2 {
+            let mut crawler = FeedCrawler::new().with_client(Arc::clone(&client)).with_clamped_dates(clamped);
+            let (sender, mut receiver) = mpsc::channel(1);
+            crawler.crawl_all(&specs, sender).await;
+            let feed_data = receiver.recv().await.unwrap();
+            let item = feed_data.items.iter().find(|item| item.guid == "future").unwrap();
+            assert!(item.pub_date <= Utc::now());
+            dates.push(item.pub_date);
+            clamped = crawler.take_clamped_dates();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(dates[0], dates[1]);
+        assert_eq!(clamped.entries.keys().collect::<Vec<_>>(), ["future"]);
+    }
+}
@@ -0,0 +1,223 @@
+// dates.rs
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+// Zone names found in feeds in place of numeric offsets: the obsolete RFC 822 ones and a
+// few common non-standard ones. Ambiguous names such as IST are left out.
+const ZONE_OFFSETS: &[(&str, &str)] = &[
+    ("UT", "+0000"),
+    ("UTC", "+0000"),
+    ("GMT", "+0000"),
+    ("Z", "+0000"),
+    ("EST", "-0500"),
+    ("EDT", "-0400"),
+    ("CST", "-0600"),
+    ("CDT", "-0500"),
+    ("MST", "-0700"),
+    ("MDT", "-0600"),
+    ("PST", "-0800"),
+    ("PDT", "-0700"),
+    ("AKST", "-0900"),
+    ("AKDT", "-0800"),
+    ("HST", "-1000"),
+    ("WET", "+0000"),
+    ("WEST", "+0100"),
+    ("BST", "+0100"),
+    ("CET", "+0100"),
+    ("CEST", "+0200"),
+    ("MET", "+0100"),
+    ("MEST", "+0200"),
+    ("EET", "+0200"),
+    ("EEST", "+0300"),
+    ("MSK", "+0300"),
+    ("JST", "+0900"),
+    ("KST", "+0900"),
+    ("AEST", "+1000"),
+    ("AEDT", "+1100"),
+    ("NZST", "+1200"),
+    ("NZDT", "+1300"),
+];
+
+// Formats of dates with an offset, tried after RFC 2822 and RFC 3339. Weekdays are removed
+// before, as feeds often get them wrong. %B also accepts abbreviated month names.
+const OFFSET_FORMATS: &[&str] = &[
+    "%d %B %Y %H:%M:%S %z",
+    "%d %B %Y %H:%M %z",
+    "%d %B %y %H:%M:%S %z",
+    "%d %B %y %H:%M %z",
+    "%d %B, %Y %H:%M:%S %z",
+    "%B %d %Y %H:%M:%S %z",
+    "%B %d, %Y %H:%M:%S %z",
+    "%B %d %H:%M:%S %z %Y",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+    "%Y-%m-%dT%H:%M%z",
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%d %H:%M:%S%.f %z",
+    "%Y-%m-%d %H:%M%z",
+    "%Y/%m/%d %H:%M:%S %z",
+];
+
+// Formats of dates without an offset, taken as UTC
+const NAIVE_FORMATS: &[&str] = &[
+    "%d %B %Y %H:%M:%S",
+    "%d %B %Y %H:%M",
+    "%d %B %y %H:%M:%S",
+    "%B %d %Y %H:%M:%S",
+    "%B %d, %Y %H:%M:%S",
+    "%B %d %H:%M:%S %Y",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M:%S",
+    "%Y/%m/%d %H:%M",
+];
+
+// Formats of dates without a time, taken as midnight UTC
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%d %B %Y", "%d %B %y", "%B %d, %Y", "%B %d %Y", "%Y%m%d"];
+
+/// Parses a date that feed_rs could not, trying RFC 2822 with obsolete or non-standard
+/// zone names, ISO 8601 variants and common sloppy formats. Dates without an offset are
+/// taken as UTC, dates without a time as midnight.
+pub fn parse_fallback_date(raw: &str) -> Option<DateTime<FixedOffset>> {
+    let collapsed = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.is_empty() {
+        return None;
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(&collapsed).or_else(|_| DateTime::parse_from_rfc3339(&collapsed)) {
+        return Some(date);
+    }
+
+    let text = replace_zone_name(&strip_weekday(&collapsed));
+    let text = text.as_str();
+    if let Ok(date) = DateTime::parse_from_rfc2822(text).or_else(|_| DateTime::parse_from_rfc3339(text)) {
+        return Some(date);
+    }
+    OFFSET_FORMATS
+        .iter()
+        .find_map(|format| DateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            NAIVE_FORMATS
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+                .or_else(|| {
+                    DATE_FORMATS
+                        .iter()
+                        .find_map(|format| NaiveDate::parse_from_str(text, format).ok())
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                })
+                .map(|naive| Utc.from_utc_datetime(&naive).fixed_offset())
+        })
+}
+
+/// Removes a leading weekday, abbreviated or not and with or without a comma.
+fn strip_weekday(text: &str) -> String {
+    let Some((first, rest)) = text.split_once(' ') else {
+        return text.to_string();
+    };
+    let word = first.trim_end_matches(',').trim_end_matches('.').to_lowercase();
+    let weekdays = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+    let is_weekday = word.len() >= 3 && weekdays.iter().any(|day| day.starts_with(&word));
+    if is_weekday {
+        rest.to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Replaces a zone name, as a separate word or appended to an ISO 8601 time, with its
+/// numeric offset, and drops the parenthesized zone comments some feeds add.
+fn replace_zone_name(text: &str) -> String {
+    let text = match text.find(" (") {
+        Some(comment) if text.ends_with(')') => &text[..comment],
+        _ => text,
+    };
+    text.split(' ')
+        .map(|word| {
+            let upper = word.to_uppercase();
+            // A suffix glued to a time, as in 2024-01-05T10:00:00Z or 10:00:00GMT
+            for (zone, offset) in ZONE_OFFSETS {
+                if upper == *zone {
+                    return offset.to_string();
+                }
+                if let Some(time) = upper.strip_suffix(zone).filter(|time| word.is_ascii() && time.ends_with(|c: char| c.is_ascii_digit()) && time.contains(':')) {
+                    return format!("{}{}", &word[..time.len()], offset);
+                }
+            }
+            // GMT+2, UTC-05:00
+            for prefix in ["GMT", "UTC", "UT"] {
+                if let Some(offset) = upper.strip_prefix(prefix).filter(|offset| offset.starts_with(['+', '-'])) {
+                    return numeric_offset(offset).unwrap_or_else(|| word.to_string());
+                }
+            }
+            word.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Turns `+2`, `-5`, `+05:30` or `+0530` into `+0200`, `-0500` or `+0530`.
+fn numeric_offset(offset: &str) -> Option<String> {
+    let (sign, digits) = offset.split_at(1);
+    let digits = digits.replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.parse::<u32>().ok()?, 0),
+        3 | 4 => {
+            let (hours, minutes) = digits.split_at(digits.len() - 2);
+            (hours.parse().ok()?, minutes.parse().ok()?)
+        }
+        _ => return None,
+    };
+    (hours <= 14 && minutes < 60).then(|| format!("{}{:02}{:02}", sign, hours, minutes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(raw: &str) -> String {
+        parse_fallback_date(raw).unwrap_or_else(|| panic!("{} was not parsed", raw)).to_rfc3339()
+    }
+
+    #[test]
+    fn obsolete_and_non_standard_zone_names_are_offsets() {
+        assert_eq!(parsed("Tue, 05 Mar 2024 10:00:00 EST"), "2024-03-05T10:00:00-05:00");
+        assert_eq!(parsed("Tue, 05 Mar 24 10:00:00 PDT"), "2024-03-05T10:00:00-07:00");
+        assert_eq!(parsed("Tue, 05 Mar 2024 10:00:00 CEST"), "2024-03-05T10:00:00+02:00");
+        assert_eq!(parsed("05 Mar 2024 10:00:00 GMT+2"), "2024-03-05T10:00:00+02:00");
+        assert_eq!(parsed("05 Mar 2024 10:00:00 UTC-05:30"), "2024-03-05T10:00:00-05:30");
+        assert_eq!(parsed("Tue, 05 Mar 2024 10:00:00 +0100 (CET)"), "2024-03-05T10:00:00+01:00");
+    }
+
+    #[test]
+    fn wrong_weekdays_and_sloppy_spacing_are_ignored() {
+        assert_eq!(parsed("Monday, 05 Mar 2024 10:00:00 GMT"), "2024-03-05T10:00:00+00:00");
+        assert_eq!(parsed("  Tue,  5   March 2024   10:00 +0000 "), "2024-03-05T10:00:00+00:00");
+        assert_eq!(parsed("Thurs. March 7, 2024 08:15:00 +0000"), "2024-03-07T08:15:00+00:00");
+    }
+
+    #[test]
+    fn iso_8601_variants_are_parsed() {
+        assert_eq!(parsed("2024-03-05T10:00:00.123Z"), "2024-03-05T10:00:00.123+00:00");
+        assert_eq!(parsed("2024-03-05T10:00:00EST"), "2024-03-05T10:00:00-05:00");
+        assert_eq!(parsed("2024-03-05 10:00:00+0100"), "2024-03-05T10:00:00+01:00");
+        assert_eq!(parsed("2024-03-05T10:00"), "2024-03-05T10:00:00+00:00");
+    }
+
+    #[test]
+    fn dates_without_offset_or_time_are_utc_midnight() {
+        assert_eq!(parsed("2024/03/05 10:00:00"), "2024-03-05T10:00:00+00:00");
+        assert_eq!(parsed("March 5, 2024"), "2024-03-05T00:00:00+00:00");
+        assert_eq!(parsed("20240305"), "2024-03-05T00:00:00+00:00");
+    }
+
+    #[test]
+    fn garbage_and_impossible_dates_are_rejected() {
+        assert_eq!(parse_fallback_date(""), None);
+        assert_eq!(parse_fallback_date("   "), None);
+        assert_eq!(parse_fallback_date("yesterday"), None);
+        assert_eq!(parse_fallback_date("2024-02-30"), None);
+        assert_eq!(parse_fallback_date("05 Mar 2024 10:00:00 GMT+25"), None);
+        // IST is ambiguous and left unparsed rather than guessed
+        assert_eq!(parse_fallback_date("Tue, 05 Mar 2024 10:00:00 IST"), None);
+    }
+}
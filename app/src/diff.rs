@@ -0,0 +1,189 @@
+// diff.rs
+use crate::{FeedData, FeedItem};
+use rss::{Channel, Item};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Where the changes of the archived feeds are written when `changes_report` is enabled.
+pub const CHANGES_REPORT_PATH: &str = "feeds/changes.json";
+
+// Items added to and removed from an archived feed
+#[derive(Debug, Default, Serialize)]
+pub struct FeedDiff {
+    pub added: Vec<DiffItem>,
+    pub removed: Vec<DiffItem>,
+    // Items kept with a new title, link, date, description or content
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub modified: Vec<DiffItem>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffItem {
+    pub title: String,
+    pub link: String,
+}
+
+impl FeedDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// Adds the changes of a second write of the same file, `later` being the changes
+    /// since the first write. Items added by the first write and removed by the second
+    /// were never there.
+    pub fn then(&mut self, later: FeedDiff) {
+        for item in later.removed {
+            match self.added.iter().position(|added| added.title == item.title && added.link == item.link) {
+                Some(index) => {
+                    self.added.remove(index);
+                }
+                None => self.removed.push(item),
+            }
+        }
+        self.added.extend(later.added);
+        self.modified.extend(later.modified);
+    }
+}
+
+// The changes of an archived feed since the previous run, as listed in feeds/changes.json
+#[derive(Debug, Serialize)]
+pub struct FeedChanges {
+    pub path: String,
+    pub title: String,
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    // Titles and links of the added items, with changes_report_titles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added_items: Option<Vec<DiffItem>>,
+}
+
+impl FeedChanges {
+    pub fn new(path: String, title: String, diff: FeedDiff, with_titles: bool) -> FeedChanges {
+        FeedChanges {
+            path,
+            title,
+            added: diff.added.len(),
+            removed: diff.removed.len(),
+            modified: diff.modified.len(),
+            added_items: with_titles.then_some(diff.added),
+        }
+    }
+}
+
+/// Compares the items of a previously written feed file with a freshly built channel.
+/// Items are identified by their guid, falling back to their link. An unparseable or
+/// empty `old_xml` is treated as a feed without items.
+pub fn diff_feed_items(old_xml: &str, new_channel: &Channel) -> FeedDiff {
+    let old_items = Channel::read_from(old_xml.as_bytes())
+        .map(|channel| channel.items)
+        .unwrap_or_default();
+
+    let old_keys: HashSet<String> = old_items.iter().map(item_key).collect();
+    let new_keys: HashSet<String> = new_channel.items.iter().map(item_key).collect();
+
+    FeedDiff {
+        added: new_channel
+            .items
+            .iter()
+            .filter(|item| !old_keys.contains(&item_key(item)))
+            .map(DiffItem::from)
+            .collect(),
+        removed: old_items
+            .iter()
+            .filter(|item| !new_keys.contains(&item_key(item)))
+            .map(DiffItem::from)
+            .collect(),
+        // The render options the file was written with are unknown, so content isn't compared
+        modified: Vec::new(),
+    }
+}
+
+/// Compares the items of a previously archived feed with the items replacing them.
+/// Items are identified by their guid. Without an archived copy, every item is added.
+pub fn diff_feed_data(old: Option<&FeedData>, new: &FeedData) -> FeedDiff {
+    let old_items: HashMap<&str, &FeedItem> = old
+        .map(|old| old.items.iter().map(|item| (item.guid.as_str(), item)).collect())
+        .unwrap_or_default();
+    let new_guids: HashSet<&str> = new.items.iter().map(|item| item.guid.as_str()).collect();
+
+    let mut diff = FeedDiff::default();
+    for item in &new.items {
+        match old_items.get(item.guid.as_str()) {
+            None => diff.added.push(DiffItem::from(item)),
+            Some(old_item) if is_modified(old_item, item) => diff.modified.push(DiffItem::from(item)),
+            Some(_) => {}
+        }
+    }
+    if let Some(old) = old {
+        diff.removed = old
+            .items
+            .iter()
+            .filter(|item| !new_guids.contains(item.guid.as_str()))
+            .map(DiffItem::from)
+            .collect();
+    }
+    diff
+}
+
+/// Whether an item kept its guid but changed what readers see of it.
+fn is_modified(old: &FeedItem, new: &FeedItem) -> bool {
+    let text = |value: &Option<String>| value.as_deref().unwrap_or_default().trim().to_string();
+    old.title.trim() != new.title.trim()
+        || old.link != new.link
+        // Archived dates are written without their fractional seconds
+        || old.pub_date.timestamp() != new.pub_date.timestamp()
+        || old.updated_date.map(|date| date.timestamp()) != new.updated_date.map(|date| date.timestamp())
+        || text(&old.description) != text(&new.description)
+        || text(&old.content) != text(&new.content)
+}
+
+fn item_key(item: &Item) -> String {
+    item.guid
+        .as_ref()
+        .map(|guid| guid.value.clone())
+        .or_else(|| item.link.clone())
+        .unwrap_or_default()
+}
+
+impl From<&Item> for DiffItem {
+    fn from(item: &Item) -> DiffItem {
+        DiffItem {
+            title: item.title.clone().unwrap_or_default(),
+            link: item.link.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&FeedItem> for DiffItem {
+    fn from(item: &FeedItem) -> DiffItem {
+        DiffItem {
+            title: item.title.clone(),
+            link: item.link.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{feed, item};
+
+    fn titles(items: &[DiffItem]) -> Vec<&str> {
+        items.iter().map(|item| item.title.as_str()).collect()
+    }
+
+    #[test]
+    fn a_file_written_twice_in_a_run_changes_by_both_writes() {
+        let archived = feed("https://example.com/feed.xml", "Example", vec![item("a", "2024-01-01T00:00:00Z"), item("b", "2024-01-01T00:00:00Z")]);
+        let first = feed("https://example.com/feed.xml", "Example", vec![item("b", "2024-01-01T00:00:00Z"), item("c", "2024-01-02T00:00:00Z")]);
+        // Written again at the end of the run, having given up c to another feed
+        let second = feed("https://example.com/feed.xml", "Example", vec![item("b", "2024-01-01T00:00:00Z"), item("d", "2024-01-03T00:00:00Z")]);
+
+        let mut diff = diff_feed_data(Some(&archived), &first);
+        diff.then(diff_feed_data(Some(&first), &second));
+        assert_eq!(titles(&diff.added), ["Item d"]);
+        assert_eq!(titles(&diff.removed), ["Item a"]);
+        assert!(diff.modified.is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+// duplicates.rs
+use crate::FeedData;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+// How listed URLs were found to serve the same feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateReason {
+    // They end up at the same URL after redirects, ignoring http/https and www.
+    SameUrl,
+    // They have the same title and the same item guids
+    SameContent,
+}
+
+impl fmt::Display for DuplicateReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DuplicateReason::SameUrl => write!(f, "same URL after redirects"),
+            DuplicateReason::SameContent => write!(f, "same title and items"),
+        }
+    }
+}
+
+// Listed URLs serving the same feed
+#[derive(Debug, Clone)]
+pub struct DuplicateFeed {
+    // The URL kept when duplicates are merged: https before http, without www. before
+    // with it, not redirected before redirected, then the first listed
+    pub canonical: String,
+    // The other URLs, in the order they are listed
+    pub duplicates: Vec<String>,
+    pub reason: DuplicateReason,
+}
+
+// What a crawled feed is recognized by, taken as it was parsed. Kept with the fingerprint of
+// the feed, so that it is still recognized while it is unchanged and not parsed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedIdentity {
+    url: String,
+    // Its final URL reduced by url_identity
+    location: String,
+    redirected: bool,
+    // See content_fingerprint
+    fingerprint: Option<(String, BTreeSet<String>)>,
+}
+
+impl FeedIdentity {
+    /// The identity of a feed listed as `feed.url` and ending up at `final_url` after
+    /// redirects, if it was redirected.
+    pub fn new(feed: &FeedData, final_url: Option<&str>) -> FeedIdentity {
+        FeedIdentity {
+            url: feed.url.clone(),
+            location: url_identity(final_url.unwrap_or(&feed.url)),
+            redirected: final_url.is_some(),
+            fingerprint: content_fingerprint(feed),
+        }
+    }
+}
+
+/// Groups the crawled feeds that are the same feed listed under several URLs, e.g.
+/// `http://example.com/feed` and `https://www.example.com/feed`. Feeds match when their
+/// final URLs only differ in scheme and `www.`, or when they have items and the same title
+/// and item guids. `feeds` is in listing order.
+pub fn find_duplicate_feeds(feeds: &[FeedIdentity]) -> Vec<DuplicateFeed> {
+    // Each feed points at a feed of its group, the first listed one pointing at itself
+    let mut parent: Vec<usize> = (0..feeds.len()).collect();
+    let mut by_identity: HashMap<&str, usize> = HashMap::new();
+    let mut by_fingerprint: HashMap<&(String, BTreeSet<String>), usize> = HashMap::new();
+    for (index, feed) in feeds.iter().enumerate() {
+        let mut matches = Vec::new();
+        matches.extend(by_identity.insert(feed.location.as_str(), index));
+        if let Some(fingerprint) = &feed.fingerprint {
+            matches.extend(by_fingerprint.insert(fingerprint, index));
+        }
+        for other in matches {
+            let (a, b) = (find_root(&mut parent, other), find_root(&mut parent, index));
+            parent[b.max(a)] = a.min(b);
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+    for index in 0..feeds.len() {
+        let group_root = find_root(&mut parent, index);
+        let group = *group_of_root.entry(group_root).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(index);
+    }
+
+    groups
+        .into_iter()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let canonical = *members
+                .iter()
+                .min_by_key(|&&index| {
+                    let url = &feeds[index].url;
+                    (!url.starts_with("https://"), url_has_www(url), feeds[index].redirected, index)
+                })
+                .unwrap_or(&members[0]);
+            let distinct_identities: BTreeSet<&str> = members.iter().map(|&index| feeds[index].location.as_str()).collect();
+            let reason = if distinct_identities.len() < members.len() {
+                DuplicateReason::SameUrl
+            } else {
+                DuplicateReason::SameContent
+            };
+            DuplicateFeed {
+                canonical: feeds[canonical].url.clone(),
+                duplicates: members.iter().filter(|&&index| index != canonical).map(|&index| feeds[index].url.clone()).collect(),
+                reason,
+            }
+        })
+        .collect()
+}
+
+/// Follows the `parent` links from a feed to the first listed feed of its group.
+fn find_root(parent: &mut [usize], mut index: usize) -> usize {
+    while parent[index] != index {
+        parent[index] = parent[parent[index]];
+        index = parent[index];
+    }
+    index
+}
+
+/// Reduces a URL to what identifies the feed: the host without `www.` and its port, the
+/// path without a trailing slash and the query. The scheme is left out, as most sites
+/// serve the same feed over http and https.
+pub fn url_identity(url: &str) -> String {
+    let Ok(parsed) = reqwest::Url::parse(url.trim()) else {
+        return url.trim().to_string();
+    };
+    let host = parsed.host_str().unwrap_or_default();
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let port = parsed.port().map(|port| format!(":{}", port)).unwrap_or_default();
+    let query = parsed.query().map(|query| format!("?{}", query)).unwrap_or_default();
+    format!("{}{}{}{}", host, port, parsed.path().trim_end_matches('/'), query)
+}
+
+fn url_has_www(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|parsed| parsed.host_str().is_some_and(|host| host.starts_with("www.")))
+}
+
+/// The title, ignoring case and whitespace, and the guids of a feed's items. Feeds
+/// without items have no fingerprint, as empty feeds with the same title are common.
+fn content_fingerprint(feed: &FeedData) -> Option<(String, BTreeSet<String>)> {
+    if feed.items.is_empty() {
+        return None;
+    }
+    let title = feed.title.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    Some((title, feed.items.iter().map(|item| item.guid.clone()).collect()))
+}
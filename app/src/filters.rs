@@ -0,0 +1,171 @@
+// filters.rs
+use crate::FeedItem;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// The `[filters]` section of config.toml.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FilterConfig {
+    /// When not empty, only items matching one of these are kept
+    pub include_keywords: Option<Vec<String>>,
+    /// Items matching one of these are dropped
+    pub exclude_keywords: Option<Vec<String>>,
+    /// Treat keywords as regular expressions instead of plain text
+    pub use_regex: Option<bool>,
+}
+
+// A keyword, matched case-insensitively against item titles and descriptions
+#[derive(Debug)]
+enum Keyword {
+    Text(String),
+    Pattern(Regex),
+}
+
+impl Keyword {
+    fn new(keyword: &str, use_regex: bool) -> Result<Keyword, String> {
+        if use_regex {
+            RegexBuilder::new(keyword)
+                .case_insensitive(true)
+                .build()
+                .map(Keyword::Pattern)
+                .map_err(|e| format!("invalid keyword pattern {:?}: {}", keyword, e))
+        } else {
+            Ok(Keyword::Text(keyword.to_lowercase()))
+        }
+    }
+
+    fn matches(&self, text: &str, lowercase_text: &str) -> bool {
+        match self {
+            Keyword::Text(keyword) => lowercase_text.contains(keyword.as_str()),
+            Keyword::Pattern(pattern) => pattern.is_match(text),
+        }
+    }
+
+    /// Matches a name: plain text has to match all of it, a pattern any part of it.
+    fn matches_name(&self, name: &str) -> bool {
+        match self {
+            Keyword::Text(keyword) => name.trim().to_lowercase() == *keyword,
+            Keyword::Pattern(pattern) => pattern.is_match(name),
+        }
+    }
+}
+
+/// Compiles a keyword list, skipping empty entries.
+fn compile(keywords: &[String], use_regex: bool) -> Result<Vec<Keyword>, String> {
+    keywords
+        .iter()
+        .filter(|keyword| !keyword.is_empty())
+        .map(|keyword| Keyword::new(keyword, use_regex))
+        .collect()
+}
+
+/// Compiled include and exclude keywords.
+#[derive(Debug, Default)]
+pub struct KeywordFilter {
+    include: Vec<Keyword>,
+    exclude: Vec<Keyword>,
+}
+
+impl KeywordFilter {
+    /// Compiles keyword lists, failing on invalid patterns when `use_regex` is set.
+    pub fn new(include: &[String], exclude: &[String], use_regex: bool) -> Result<KeywordFilter, String> {
+        Ok(KeywordFilter {
+            include: compile(include, use_regex)?,
+            exclude: compile(exclude, use_regex)?,
+        })
+    }
+
+    /// Builds the filter of a feed from the `[filters]` section; the feed's own `include`
+    /// and `exclude` lists, when set, replace the configured ones.
+    pub fn for_feed(
+        config: Option<&FilterConfig>,
+        include: Option<&[String]>,
+        exclude: Option<&[String]>,
+    ) -> Result<KeywordFilter, String> {
+        let config = config.cloned().unwrap_or_default();
+        KeywordFilter::new(
+            include.or(config.include_keywords.as_deref()).unwrap_or_default(),
+            exclude.or(config.exclude_keywords.as_deref()).unwrap_or_default(),
+            config.use_regex.unwrap_or(false),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Returns true if `item` should be archived.
+    pub fn keeps(&self, item: &FeedItem) -> bool {
+        let text = format!("{}\n{}", item.title, item.description.as_deref().unwrap_or_default());
+        let lowercase_text = text.to_lowercase();
+        let matches_any = |keywords: &[Keyword]| keywords.iter().any(|keyword| keyword.matches(&text, &lowercase_text));
+        !matches_any(&self.exclude) && (self.include.is_empty() || matches_any(&self.include))
+    }
+}
+
+/// Compiled include and exclude lists of item authors.
+#[derive(Debug, Default)]
+pub struct AuthorFilter {
+    include: Vec<Keyword>,
+    exclude: Vec<Keyword>,
+    drop_unattributed: bool,
+}
+
+impl AuthorFilter {
+    /// Builds the author filter of a feed from its `include_authors`, `exclude_authors` and
+    /// `drop_unattributed` options; `use_regex` of the `[filters]` section applies.
+    pub fn for_feed(
+        config: Option<&FilterConfig>,
+        include: Option<&[String]>,
+        exclude: Option<&[String]>,
+        drop_unattributed: bool,
+    ) -> Result<AuthorFilter, String> {
+        let use_regex = config.and_then(|config| config.use_regex).unwrap_or(false);
+        Ok(AuthorFilter {
+            include: compile(include.unwrap_or_default(), use_regex)?,
+            exclude: compile(exclude.unwrap_or_default(), use_regex)?,
+            drop_unattributed,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && !self.drop_unattributed
+    }
+
+    pub fn drops_unattributed(&self) -> bool {
+        self.drop_unattributed
+    }
+
+    /// Returns true if `item` should be archived. Items without an author are kept unless
+    /// `drop_unattributed` is set.
+    pub fn keeps(&self, item: &FeedItem) -> bool {
+        if item.authors.is_empty() {
+            return !self.drop_unattributed;
+        }
+        let matches_any =
+            |keywords: &[Keyword]| item.authors.iter().any(|author| keywords.iter().any(|keyword| keyword.matches_name(author)));
+        !matches_any(&self.exclude) && (self.include.is_empty() || matches_any(&self.include))
+    }
+}
+
+/// A query of the `search` subcommand: a case-insensitive substring or, with `use_regex`,
+/// a regular expression.
+#[derive(Debug)]
+pub struct SearchQuery(Keyword);
+
+impl SearchQuery {
+    pub fn new(query: &str, use_regex: bool) -> Result<SearchQuery, String> {
+        if query.is_empty() {
+            return Err("the search query is empty".to_string());
+        }
+        Keyword::new(query, use_regex).map(SearchQuery)
+    }
+
+    /// Returns true if the title, description or content of `item` matches.
+    pub fn matches(&self, item: &FeedItem) -> bool {
+        [Some(item.title.as_str()), item.description.as_deref(), item.content.as_deref()]
+            .into_iter()
+            .flatten()
+            .any(|text| self.0.matches(text, &text.to_lowercase()))
+    }
+}
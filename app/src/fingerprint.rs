@@ -0,0 +1,88 @@
+// fingerprint.rs
+use crate::duplicates::FeedIdentity;
+use crate::storage::Storage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Where the fingerprints of the last fetched responses are kept between runs.
+pub const FINGERPRINTS_PATH: &str = "feeds/.fingerprints.json";
+
+// What identifies the last response of a feed: its validators, if the server sent any,
+// and a hash of the body for servers that don't
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedFingerprint {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // Hex encoded SHA-256 of the response body
+    pub content_hash: String,
+    // What the feed was recognized by when last parsed, for finding duplicates of it
+    // while it is unchanged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<FeedIdentity>,
+    // Guids of the items the feed kept when last parsed, for removing cross-posts of them
+    // while it is unchanged
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub guids: Vec<String>,
+}
+
+// Fingerprints keyed by feed URL. They only hold for archived copies built with the same
+// settings, so the settings they were recorded with are remembered as well.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FingerprintStore {
+    #[serde(default)]
+    pub settings_hash: String,
+    #[serde(default)]
+    pub feeds: BTreeMap<String, FeedFingerprint>,
+}
+
+impl FingerprintStore {
+    /// Loads the fingerprint file, starting from an empty store if it is missing or
+    /// unreadable, or if it was written with other settings than `settings_hash`.
+    pub async fn load(storage: &dyn Storage, path: &str, settings_hash: &str) -> FingerprintStore {
+        let store = match storage.read(path).await {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                eprintln!("Warning: Ignoring unreadable feed fingerprints {}: {}", path, e);
+                FingerprintStore::default()
+            }),
+            Err(_) => FingerprintStore::default(),
+        };
+        if store.settings_hash == settings_hash {
+            store
+        } else {
+            FingerprintStore {
+                settings_hash: settings_hash.to_string(),
+                feeds: BTreeMap::new(),
+            }
+        }
+    }
+
+    /// Writes the fingerprint file.
+    pub async fn save(&self, storage: &dyn Storage, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        storage.write(path, serde_json::to_string_pretty(self)?.as_bytes()).await
+    }
+}
+
+/// Returns true unless `new_hash` is the content hash stored for `url`.
+pub fn has_feed_changed(url: &str, new_hash: &str, store: &FingerprintStore) -> bool {
+    store.feeds.get(url).is_none_or(|fingerprint| fingerprint.content_hash != new_hash)
+}
+
+/// Hex encoded SHA-256 of a response body.
+pub fn content_hash(body: &[u8]) -> String {
+    Sha256::digest(body).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hash of the settings the archived copies are built from: the archiver's version and
+/// the contents of config.toml and the feed lists. Any change to them invalidates the
+/// fingerprints, so that the feeds are processed again with the new settings.
+pub fn settings_hash(version: &str, files: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(version.as_bytes());
+    for contents in files {
+        hasher.update([0]);
+        hasher.update(contents.as_bytes());
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,121 @@
+// guid.rs
+use feed_rs::model::{Link, Text};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// How a guid is made up for items published without an id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuidStrategy {
+    /// The item link itself
+    Link,
+    /// A hash of the link and the title, the ids feed_rs makes up, so that archives written
+    /// before the strategy could be chosen keep their guids
+    #[default]
+    LinkAndTitle,
+    /// A hash of the title, description and content, for feeds whose links are missing or
+    /// change between runs. An edited item becomes a new item.
+    ContentHash,
+}
+
+// What a guid is made up from, as parsed by feed_rs
+pub struct ItemIdentity<'a> {
+    pub links: &'a [Link],
+    pub title: &'a Option<Text>,
+    pub description: Option<&'a str>,
+    pub content: Option<&'a str>,
+}
+
+impl GuidStrategy {
+    /// Makes up the guid of an item without an id. The same item always gets the same
+    /// guid. Items without a link get a content hash whatever the strategy.
+    pub fn synthesize(self, item: &ItemIdentity) -> String {
+        let Some(link) = item.links.first().filter(|link| !link.href.is_empty()) else {
+            return content_hash(item);
+        };
+        match self {
+            GuidStrategy::Link => link.href.clone(),
+            GuidStrategy::LinkAndTitle => feed_rs::parser::generate_id_from_link_and_title(link, item.title),
+            GuidStrategy::ContentHash => content_hash(item),
+        }
+    }
+}
+
+/// Hex encoded SHA-256 of the title, description and content of an item, the same for an
+/// item published in several feeds.
+fn content_hash(item: &ItemIdentity) -> String {
+    let mut hasher = Sha256::new();
+    let title = item.title.as_ref().map(|title| title.content.as_str());
+    for part in [title, item.description, item.content] {
+        hasher.update(part.unwrap_or_default().as_bytes());
+        hasher.update([0]);
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(href: &str) -> Link {
+        Link {
+            href: href.to_string(),
+            rel: None,
+            media_type: None,
+            href_lang: None,
+            title: None,
+            length: None,
+        }
+    }
+
+    fn title(content: &str) -> Option<Text> {
+        // The media type has no public constructor outside feed_rs
+        Some(serde_json::from_value(serde_json::json!({"content_type": "text/plain", "src": null, "content": content})).unwrap())
+    }
+
+    #[test]
+    fn each_strategy_makes_up_stable_guids() {
+        let links = [link("https://example.com/post")];
+        let title = title("Post");
+        let item = ItemIdentity { links: &links, title: &title, description: Some("Text"), content: None };
+
+        assert_eq!(GuidStrategy::Link.synthesize(&item), "https://example.com/post");
+        assert_eq!(
+            GuidStrategy::LinkAndTitle.synthesize(&item),
+            feed_rs::parser::generate_id_from_link_and_title(&links[0], &title)
+        );
+        let hash = GuidStrategy::ContentHash.synthesize(&item);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(GuidStrategy::ContentHash.synthesize(&item), hash);
+        for strategy in [GuidStrategy::Link, GuidStrategy::LinkAndTitle, GuidStrategy::ContentHash] {
+            assert_eq!(strategy.synthesize(&item), strategy.synthesize(&item));
+        }
+    }
+
+    #[test]
+    fn content_hash_ignores_the_link_but_not_edits() {
+        let title = title("Post");
+        let first = [link("https://example.com/post?utm=1")];
+        let second = [link("https://example.com/post?utm=2")];
+        let item = |links| ItemIdentity { links, title: &title, description: Some("Text"), content: None };
+        assert_eq!(GuidStrategy::ContentHash.synthesize(&item(&first)), GuidStrategy::ContentHash.synthesize(&item(&second)));
+
+        let edited = ItemIdentity { description: Some("Edited text"), ..item(&first) };
+        assert_ne!(GuidStrategy::ContentHash.synthesize(&edited), GuidStrategy::ContentHash.synthesize(&item(&first)));
+        // The parts are separated, so moving text from one to the other changes the hash
+        let moved = ItemIdentity { description: None, content: Some("Text"), ..item(&first) };
+        assert_ne!(GuidStrategy::ContentHash.synthesize(&moved), GuidStrategy::ContentHash.synthesize(&item(&first)));
+    }
+
+    #[test]
+    fn items_without_a_link_get_a_content_hash_whatever_the_strategy() {
+        let title = title("Post");
+        let empty = [link("")];
+        for links in [&[][..], &empty[..]] {
+            let item = ItemIdentity { links, title: &title, description: None, content: None };
+            let hash = GuidStrategy::ContentHash.synthesize(&item);
+            assert_eq!(GuidStrategy::Link.synthesize(&item), hash);
+            assert_eq!(GuidStrategy::LinkAndTitle.synthesize(&item), hash);
+        }
+    }
+}
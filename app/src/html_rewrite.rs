@@ -0,0 +1,492 @@
+// html_rewrite.rs
+use crate::FeedData;
+use serde::Deserialize;
+
+// srcset candidates with data: URIs longer than this are dropped by default
+const DEFAULT_MAX_SRCSET_DATA_URI_BYTES: usize = 1024;
+
+// Attributes holding a single URL
+const URL_ATTRIBUTES: &[&str] = &["src", "href", "poster"];
+
+// Elements whose content is text up to their end tag rather than markup
+pub const RAW_TEXT_ELEMENTS: &[&str] = &["iframe", "noembed", "noframes", "script", "style", "textarea", "title", "xmp"];
+
+/// What happens to the `<iframe>` elements of item HTML.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IframePolicy {
+    /// Keep them, upgrading their URL like any other
+    #[default]
+    Keep,
+    /// Remove them along with their fallback content
+    Strip,
+    /// Replace them with a link to the embedded page
+    Link,
+}
+
+/// The `[html_rewrite]` section of config.toml. Item HTML is only rewritten when it is present.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HtmlRewriteConfig {
+    /// Hosts whose http:// URLs are upgraded to https://, subdomains included
+    pub upgrade_hosts: Option<Vec<String>>,
+    /// Upgrade the http:// URLs of every host
+    pub upgrade_all_hosts: Option<bool>,
+    pub iframes: Option<IframePolicy>,
+    /// srcset candidates with data: URIs longer than this are dropped
+    pub max_srcset_data_uri_bytes: Option<usize>,
+}
+
+/// Rewrites the HTML of item descriptions and content so that it can be shown on an https
+/// page: http:// resources are upgraded to https://, iframes are kept, stripped or
+/// replaced by links and large inline images are dropped from srcset attributes. The HTML
+/// is tokenized rather than matched with patterns, and tags that don't need a change are
+/// copied as they are.
+#[derive(Debug)]
+pub struct HtmlRewriter {
+    upgrade_hosts: Vec<String>,
+    upgrade_all_hosts: bool,
+    iframes: IframePolicy,
+    max_data_uri_bytes: usize,
+}
+
+impl HtmlRewriter {
+    pub fn new(config: &HtmlRewriteConfig) -> HtmlRewriter {
+        HtmlRewriter {
+            upgrade_hosts: config
+                .upgrade_hosts
+                .iter()
+                .flatten()
+                .map(|host| host.trim().trim_matches('.').to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            upgrade_all_hosts: config.upgrade_all_hosts.unwrap_or(false),
+            iframes: config.iframes.unwrap_or_default(),
+            max_data_uri_bytes: config.max_srcset_data_uri_bytes.unwrap_or(DEFAULT_MAX_SRCSET_DATA_URI_BYTES),
+        }
+    }
+
+    /// Rewrites the description and content of every item of a feed, returning the
+    /// number of items changed.
+    pub fn rewrite_feed(&self, feed_data: &mut FeedData) -> usize {
+        let mut rewritten = 0;
+        for item in feed_data.items.iter_mut() {
+            let mut changed = false;
+            for html in [&mut item.description, &mut item.content].into_iter().flatten() {
+                let new_html = self.rewrite(html);
+                if new_html != *html {
+                    *html = new_html;
+                    changed = true;
+                }
+            }
+            rewritten += changed as usize;
+        }
+        rewritten
+    }
+
+    /// Rewrites an HTML fragment. Text, comments and the content of elements such as
+    /// `<script>` are copied as they are.
+    pub fn rewrite(&self, html: &str) -> String {
+        let mut output = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                let end = comment.find("-->").map_or(rest.len(), |end| end + "<!---->".len());
+                output.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            // A < that doesn't start a tag is text
+            let Some(tag) = Tag::parse(rest) else {
+                output.push('<');
+                rest = &rest[1..];
+                continue;
+            };
+            let raw_tag = &rest[..tag.len];
+            rest = &rest[tag.len..];
+
+            let name = tag.name.to_ascii_lowercase();
+            let raw_text = !tag.end && RAW_TEXT_ELEMENTS.contains(&name.as_str());
+            // Self-closing iframes, as written in XHTML, have no content. Neither do
+            // elements whose end tag is missing, so that the rest of the HTML is kept.
+            let (content, end_tag) = if raw_text && !(tag.self_closing && name == "iframe") {
+                split_raw_text(rest, &name)
+            } else {
+                ("", "")
+            };
+            rest = &rest[content.len() + end_tag.len()..];
+
+            if name == "iframe" && !tag.end && self.iframes != IframePolicy::Keep {
+                if self.iframes == IframePolicy::Link {
+                    output.push_str(&self.iframe_link(&tag));
+                }
+                continue;
+            }
+            match self.rewrite_tag(&tag) {
+                Some(rewritten) => output.push_str(&rewritten),
+                None => output.push_str(raw_tag),
+            }
+            output.push_str(content);
+            output.push_str(end_tag);
+        }
+        output.push_str(rest);
+        output
+    }
+
+    /// Rebuilds a tag with its URLs upgraded and its srcset filtered, or returns None if
+    /// neither changes anything.
+    fn rewrite_tag(&self, tag: &Tag) -> Option<String> {
+        let mut changed = false;
+        let mut attributes = Vec::new();
+        for attribute in &tag.attributes {
+            let name = attribute.name.to_ascii_lowercase();
+            let value = attribute.value.unwrap_or_default();
+            let new_value = if URL_ATTRIBUTES.contains(&name.as_str()) {
+                self.upgrade_url(value)
+            } else if name == "srcset" {
+                self.rewrite_srcset(value)
+            } else {
+                None
+            };
+            match new_value {
+                // A srcset without candidates left is dropped
+                Some(new_value) if new_value.is_empty() && name == "srcset" => changed = true,
+                Some(new_value) => {
+                    changed = true;
+                    attributes.push(format!("{}=\"{}\"", attribute.name, new_value.replace('"', "&quot;")));
+                }
+                None => attributes.push(attribute.raw.to_string()),
+            }
+        }
+        if !changed {
+            return None;
+        }
+        let mut rebuilt = format!("<{}{}", if tag.end { "/" } else { "" }, tag.name);
+        for attribute in attributes {
+            rebuilt.push(' ');
+            rebuilt.push_str(&attribute);
+        }
+        rebuilt.push_str(if tag.self_closing { " />" } else { ">" });
+        Some(rebuilt)
+    }
+
+    /// A link to the page embedded by an iframe, labelled with its title or else its URL.
+    /// Iframes without a URL leave nothing behind.
+    fn iframe_link(&self, tag: &Tag) -> String {
+        let attribute = |name: &str| {
+            tag.attributes
+                .iter()
+                .find(|attribute| attribute.name.eq_ignore_ascii_case(name))
+                .and_then(|attribute| attribute.value)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let Some(src) = attribute("src") else {
+            return String::new();
+        };
+        let src = self.upgrade_url(src).unwrap_or_else(|| src.to_string()).replace('"', "&quot;");
+        let label = attribute("title").map_or_else(|| src.clone(), |title| title.replace('<', "&lt;"));
+        format!("<a href=\"{}\">{}</a>", src, label)
+    }
+
+    /// Returns the https:// URL for an http:// URL of a host to upgrade. URLs with an
+    /// explicit port are left alone, as https is served on another one.
+    fn upgrade_url(&self, url: &str) -> Option<String> {
+        let url = url.trim();
+        let rest = url.get(.."http://".len()).filter(|scheme| scheme.eq_ignore_ascii_case("http://")).map(|_| &url["http://".len()..])?;
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+        if host.is_empty() || host.contains(':') {
+            return None;
+        }
+        let host = host.to_lowercase();
+        let upgrade = self.upgrade_all_hosts
+            || self
+                .upgrade_hosts
+                .iter()
+                .any(|allowed| host == *allowed || host.strip_suffix(allowed.as_str()).is_some_and(|sub| sub.ends_with('.')));
+        upgrade.then(|| format!("https://{}", rest))
+    }
+
+    /// Drops the candidates of a srcset whose data: URI is over the size limit and
+    /// upgrades the URLs of the others. Returns None if nothing changes.
+    fn rewrite_srcset(&self, srcset: &str) -> Option<String> {
+        let mut changed = false;
+        let mut candidates = Vec::new();
+        for (url, descriptor) in srcset_candidates(srcset) {
+            let is_data = url.get(.."data:".len()).is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"));
+            if is_data && url.len() > self.max_data_uri_bytes {
+                changed = true;
+                continue;
+            }
+            let url = match self.upgrade_url(url) {
+                Some(upgraded) => {
+                    changed = true;
+                    upgraded
+                }
+                None => url.to_string(),
+            };
+            candidates.push(if descriptor.is_empty() { url } else { format!("{} {}", url, descriptor) });
+        }
+        changed.then(|| candidates.join(", "))
+    }
+}
+
+// A start or end tag as written
+pub struct Tag<'a> {
+    pub name: &'a str,
+    pub end: bool,
+    pub attributes: Vec<Attribute<'a>>,
+    pub self_closing: bool,
+    // Length of the tag in the HTML, from < to >
+    pub len: usize,
+}
+
+pub struct Attribute<'a> {
+    pub name: &'a str,
+    // Without quotes and with its character references as written
+    pub value: Option<&'a str>,
+    // The whole attribute as written, to copy it unchanged
+    pub raw: &'a str,
+}
+
+impl<'a> Tag<'a> {
+    /// Parses the tag at the start of `html`, which starts with <. Returns None if it is
+    /// not a tag, e.g. a < in text, or if the tag is not closed.
+    pub fn parse(html: &'a str) -> Option<Tag<'a>> {
+        let bytes = html.as_bytes();
+        let mut index = 1;
+        let end = bytes.get(index) == Some(&b'/');
+        if end {
+            index += 1;
+        }
+        if !bytes.get(index)?.is_ascii_alphabetic() {
+            return None;
+        }
+        let name_start = index;
+        while index < bytes.len() && !bytes[index].is_ascii_whitespace() && !matches!(bytes[index], b'/' | b'>') {
+            index += 1;
+        }
+        let name = &html[name_start..index];
+
+        let mut attributes = Vec::new();
+        let mut self_closing = false;
+        loop {
+            while index < bytes.len() && (bytes[index].is_ascii_whitespace() || bytes[index] == b'/') {
+                self_closing = bytes[index] == b'/' && bytes.get(index + 1) == Some(&b'>');
+                index += 1;
+            }
+            match bytes.get(index)? {
+                b'>' => break,
+                _ => self_closing = false,
+            }
+            let attribute_start = index;
+            // A name can start with =, only the following ones end it
+            index += 1;
+            while index < bytes.len() && !bytes[index].is_ascii_whitespace() && !matches!(bytes[index], b'/' | b'>' | b'=') {
+                index += 1;
+            }
+            let attribute_name = &html[attribute_start..index];
+            let mut after_name = index;
+            while after_name < bytes.len() && bytes[after_name].is_ascii_whitespace() {
+                after_name += 1;
+            }
+            let mut value = None;
+            if bytes.get(after_name) == Some(&b'=') {
+                index = after_name + 1;
+                while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+                    index += 1;
+                }
+                match bytes.get(index)? {
+                    &quote @ (b'"' | b'\'') => {
+                        let value_end = index + 1 + html[index + 1..].find(quote as char)?;
+                        value = Some(&html[index + 1..value_end]);
+                        index = value_end + 1;
+                    }
+                    _ => {
+                        let value_start = index;
+                        while index < bytes.len() && !bytes[index].is_ascii_whitespace() && bytes[index] != b'>' {
+                            index += 1;
+                        }
+                        value = Some(&html[value_start..index]);
+                    }
+                }
+            }
+            attributes.push(Attribute {
+                name: attribute_name,
+                value,
+                raw: &html[attribute_start..index],
+            });
+        }
+        Some(Tag {
+            name,
+            end,
+            attributes,
+            self_closing,
+            len: index + 1,
+        })
+    }
+}
+
+/// Splits the text following the start tag of a raw text element into its content and
+/// its end tag. Without an end tag, both are empty.
+pub fn split_raw_text<'a>(html: &'a str, name: &str) -> (&'a str, &'a str) {
+    let lowercase = html.to_ascii_lowercase();
+    let end_tag = format!("</{}", name);
+    let mut from = 0;
+    while let Some(found) = lowercase[from..].find(&end_tag).map(|found| from + found) {
+        let after = found + end_tag.len();
+        // </iframe> and </iframe > end the element, </iframes> doesn't
+        if lowercase.as_bytes().get(after).is_some_and(|&byte| byte.is_ascii_whitespace() || matches!(byte, b'/' | b'>')) {
+            let Some(close) = lowercase[after..].find('>') else {
+                break;
+            };
+            return (&html[..found], &html[found..after + close + 1]);
+        }
+        from = after;
+    }
+    ("", "")
+}
+
+/// Splits a srcset attribute into its image candidates, each a URL and its (possibly
+/// empty) width or density descriptor. URLs can contain commas, as data: URIs do.
+fn srcset_candidates(srcset: &str) -> Vec<(&str, &str)> {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            return candidates;
+        }
+        let url_end = rest.find(|c: char| c.is_ascii_whitespace()).unwrap_or(rest.len());
+        let url = &rest[..url_end];
+        rest = &rest[url_end..];
+        // A URL ending in a comma has no descriptor
+        if url.ends_with(',') {
+            candidates.push((url.trim_end_matches(','), ""));
+            continue;
+        }
+        // The descriptor runs to the next comma outside of parentheses
+        let mut depth = 0usize;
+        let descriptor_end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth = depth.saturating_sub(1),
+                    ',' => return depth == 0,
+                    _ => {}
+                }
+                false
+            })
+            .map_or(rest.len(), |(index, _)| index);
+        candidates.push((url, rest[..descriptor_end].trim()));
+        rest = &rest[descriptor_end..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewriter(iframes: IframePolicy, max_srcset_data_uri_bytes: usize) -> HtmlRewriter {
+        HtmlRewriter::new(&HtmlRewriteConfig {
+            upgrade_hosts: Some(vec!["example.com".to_string()]),
+            upgrade_all_hosts: None,
+            iframes: Some(iframes),
+            max_srcset_data_uri_bytes: Some(max_srcset_data_uri_bytes),
+        })
+    }
+
+    #[test]
+    fn tags_are_parsed_with_quoted_and_unquoted_attributes() {
+        let html = r#"<img src="http://a.example/x.png" alt='a "b"' width=10 data-x = "y" hidden/>rest"#;
+        let tag = Tag::parse(html).unwrap();
+        assert_eq!(tag.name, "img");
+        assert!(!tag.end);
+        assert!(tag.self_closing);
+        assert_eq!(&html[tag.len..], "rest");
+        let attributes: Vec<(&str, Option<&str>, &str)> = tag.attributes.iter().map(|attribute| (attribute.name, attribute.value, attribute.raw)).collect();
+        assert_eq!(
+            attributes,
+            [
+                ("src", Some("http://a.example/x.png"), r#"src="http://a.example/x.png""#),
+                ("alt", Some(r#"a "b""#), r#"alt='a "b"'"#),
+                ("width", Some("10"), "width=10"),
+                ("data-x", Some("y"), r#"data-x = "y""#),
+                ("hidden", None, "hidden"),
+            ]
+        );
+
+        let end = Tag::parse("</DIV >").unwrap();
+        assert!(end.end);
+        assert_eq!(end.name, "DIV");
+        assert!(Tag::parse("< a>").is_none());
+        assert!(Tag::parse("<3 apples").is_none());
+        assert!(Tag::parse(r#"<a href="unclosed>"#).is_none());
+        assert!(Tag::parse("<a href=x").is_none());
+    }
+
+    #[test]
+    fn only_urls_of_upgraded_hosts_are_rewritten() {
+        let rewriter = rewriter(IframePolicy::Keep, DEFAULT_MAX_SRCSET_DATA_URI_BYTES);
+        assert_eq!(
+            rewriter.rewrite(r#"<p>1 < 2 <a href=http://www.example.com/page title="x">link</a> <img src="http://other.org/i.png"></p>"#),
+            r#"<p>1 < 2 <a href="https://www.example.com/page" title="x">link</a> <img src="http://other.org/i.png"></p>"#
+        );
+        // An explicit port is left alone, as https is served on another one
+        let html = r#"<a href="http://example.com:8080/">x</a><a href="http://notexample.com/">y</a>"#;
+        assert_eq!(rewriter.rewrite(html), html);
+    }
+
+    #[test]
+    fn comments_and_raw_text_are_copied_as_they_are() {
+        let rewriter = rewriter(IframePolicy::Strip, DEFAULT_MAX_SRCSET_DATA_URI_BYTES);
+        let html = r#"<!-- <a href="http://example.com/"><iframe> --><script>var a = "<iframe src='http://example.com/'></iframe>";</SCRIPT ><style>a[href="http://example.com/"]{}</style>"#;
+        assert_eq!(rewriter.rewrite(html), html);
+        assert_eq!(
+            rewriter.rewrite(r#"<a href="http://example.com/">x</a><!-- unclosed <a href="http://example.com/">"#),
+            r#"<a href="https://example.com/">x</a><!-- unclosed <a href="http://example.com/">"#
+        );
+        // </scripts> doesn't end a script, and without an end tag the rest is still markup
+        assert_eq!(split_raw_text("a</scripts>b</script>c", "script"), ("a</scripts>b", "</script>"));
+        assert_eq!(
+            rewriter.rewrite(r#"<textarea><a href="http://example.com/">"#),
+            r#"<textarea><a href="https://example.com/">"#
+        );
+    }
+
+    #[test]
+    fn iframes_are_kept_stripped_or_linked() {
+        let html = r#"<p><iframe src="http://example.com/embed" title="Video">fallback <b>x</b></iframe></p>"#;
+        assert_eq!(
+            rewriter(IframePolicy::Keep, DEFAULT_MAX_SRCSET_DATA_URI_BYTES).rewrite(html),
+            r#"<p><iframe src="https://example.com/embed" title="Video">fallback <b>x</b></iframe></p>"#
+        );
+        let strip = rewriter(IframePolicy::Strip, DEFAULT_MAX_SRCSET_DATA_URI_BYTES);
+        assert_eq!(strip.rewrite(html), "<p></p>");
+        assert_eq!(strip.rewrite(r#"<iframe src="http://example.com/e"/>after"#), "after");
+        let link = rewriter(IframePolicy::Link, DEFAULT_MAX_SRCSET_DATA_URI_BYTES);
+        assert_eq!(link.rewrite(html), r#"<p><a href="https://example.com/embed">Video</a></p>"#);
+        assert_eq!(link.rewrite(r#"<iframe src="https://other.org/e"></iframe>"#), r#"<a href="https://other.org/e">https://other.org/e</a>"#);
+        assert_eq!(link.rewrite("<iframe></iframe>after"), "after");
+    }
+
+    #[test]
+    fn large_srcset_data_uris_are_dropped() {
+        assert_eq!(
+            srcset_candidates("data:a,b 1x, img.png 2x,plain.png, last.png"),
+            [("data:a,b", "1x"), ("img.png", "2x"), ("plain.png", ""), ("last.png", "")]
+        );
+        let rewriter = rewriter(IframePolicy::Keep, 16);
+        assert_eq!(
+            rewriter.rewrite(r#"<img srcset="data:image/png;base64,AAAA,BBBB 1x, http://example.com/big.png 2x" alt="x">"#),
+            r#"<img srcset="https://example.com/big.png 2x" alt="x">"#
+        );
+        // Small ones stay, and a srcset left without candidates goes
+        let small = r#"<img srcset="data:a,b 1x, https://other.org/a.png 2x">"#;
+        assert_eq!(rewriter.rewrite(small), small);
+        assert_eq!(rewriter.rewrite(r#"<img srcset="data:image/png;base64,AAAAAAAA 1x" />"#), "<img />");
+    }
+}
@@ -0,0 +1,381 @@
+// import.rs
+use crate::crawler::{parse_feed_list_with_errors, IpFamily};
+use crate::duplicates::url_identity;
+use crate::verify::check_reachable;
+use crate::FeedList;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+// Keys naming the feed URL, title and folder in the JSON exports of the common readers
+const JSON_URL_KEYS: &[&str] = &["feed_url", "feed_address", "xmlUrl", "xml_url", "url"];
+const JSON_TITLE_KEYS: &[&str] = &["title", "feed_title", "name"];
+const JSON_CATEGORY_KEYS: &[&str] = &["category", "categories", "folder", "folders", "tags"];
+
+// A subscription read from a reader export
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedFeed {
+    pub url: String,
+    pub title: Option<String>,
+    // Folders the subscription is filed in, nested folders joined with "/"
+    pub categories: Vec<String>,
+}
+
+/// Reads the subscriptions of an OPML export (Feedly, Inoreader, Miniflux...) or of a JSON
+/// dump (NewsBlur, Miniflux, Feedbin or a plain list of feeds), telling them apart by
+/// their first character.
+pub fn parse_subscriptions(contents: &str) -> Result<Vec<ImportedFeed>, Box<dyn Error + Send + Sync>> {
+    let contents = contents.trim_start_matches('\u{feff}').trim_start();
+    if contents.starts_with('{') || contents.starts_with('[') {
+        parse_json_subscriptions(&serde_json::from_str(contents)?)
+    } else {
+        parse_opml_subscriptions(contents)
+    }
+}
+
+/// Reads every outline with an `xmlUrl`, filing it under the outlines it is nested in
+/// and the folders of its `category` attribute.
+fn parse_opml_subscriptions(opml: &str) -> Result<Vec<ImportedFeed>, Box<dyn Error + Send + Sync>> {
+    let mut reader = Reader::from_str(opml);
+    reader.config_mut().trim_text(true);
+    // Names of the folders the reader is in, None for feed outlines with children
+    let mut folders: Vec<Option<String>> = Vec::new();
+    let mut feeds = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"outline" => {
+                let outline = OpmlOutline::read(&e)?;
+                match outline.xml_url {
+                    Some(_) => {
+                        feeds.extend(outline.into_feed(&folders));
+                        folders.push(None);
+                    }
+                    None => folders.push(outline.title),
+                }
+            }
+            Event::Empty(e) if e.name().as_ref() == b"outline" => {
+                feeds.extend(OpmlOutline::read(&e)?.into_feed(&folders));
+            }
+            Event::End(e) if e.name().as_ref() == b"outline" => {
+                folders.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(feeds)
+}
+
+// The attributes of an OPML outline that matter for importing it
+struct OpmlOutline {
+    title: Option<String>,
+    xml_url: Option<String>,
+    category: Option<String>,
+}
+
+impl OpmlOutline {
+    fn read(element: &BytesStart) -> Result<OpmlOutline, Box<dyn Error + Send + Sync>> {
+        let mut text = None;
+        let mut title = None;
+        let mut xml_url = None;
+        let mut category = None;
+        for attr in element.attributes() {
+            let attr = attr?;
+            let value = attr.unescape_value()?.trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+            match attr.key.as_ref() {
+                b"text" => text = Some(value),
+                b"title" => title = Some(value),
+                b"xmlUrl" => xml_url = Some(value),
+                b"category" => category = Some(value),
+                _ => {}
+            }
+        }
+        Ok(OpmlOutline { title: title.or(text), xml_url, category })
+    }
+
+    /// The subscription of a feed outline nested in `folders`, None for a folder.
+    fn into_feed(self, folders: &[Option<String>]) -> Option<ImportedFeed> {
+        let mut categories = Vec::new();
+        let path: Vec<&str> = folders.iter().flatten().map(String::as_str).collect();
+        if !path.is_empty() {
+            categories.push(path.join("/"));
+        }
+        // The OPML category attribute is a comma-separated list of slash-delimited paths
+        for category in self.category.iter().flat_map(|category| category.split(',')) {
+            let category = category.trim().trim_matches('/');
+            if !category.is_empty() && !categories.iter().any(|known| known == category) {
+                categories.push(category.to_string());
+            }
+        }
+        Some(ImportedFeed {
+            url: self.xml_url?,
+            title: self.title,
+            categories,
+        })
+    }
+}
+
+/// Reads a NewsBlur export, with its `feeds` by id and their `folders`, or a list of
+/// feeds, given as the document or under a `feeds` or `subscriptions` key.
+fn parse_json_subscriptions(json: &Value) -> Result<Vec<ImportedFeed>, Box<dyn Error + Send + Sync>> {
+    if let Some(by_id) = json.get("feeds").and_then(Value::as_object) {
+        let mut folder_of = HashMap::new();
+        if let Some(folders) = json.get("folders") {
+            newsblur_folders(folders, &mut Vec::new(), &mut folder_of);
+        }
+        let mut ids: Vec<&String> = by_id.keys().collect();
+        ids.sort_by_key(|id| (id.parse::<u64>().unwrap_or(u64::MAX), id.as_str()));
+        return Ok(ids
+            .into_iter()
+            .filter_map(|id| {
+                let mut feed = json_feed(&by_id[id])?;
+                if let Some(folder) = folder_of.get(id.as_str()).filter(|folder| !feed.categories.contains(folder)) {
+                    feed.categories.insert(0, folder.clone());
+                }
+                Some(feed)
+            })
+            .collect());
+    }
+    let list = [json, &json["feeds"], &json["subscriptions"]]
+        .into_iter()
+        .find_map(Value::as_array)
+        .ok_or("expected a list of feeds, or a NewsBlur export with feeds by id")?;
+    Ok(list.iter().filter_map(json_feed).collect())
+}
+
+/// Records the folder of every feed id in NewsBlur's `folders`, a list of feed ids and
+/// objects mapping folder names to such lists.
+fn newsblur_folders(folders: &Value, path: &mut Vec<String>, folder_of: &mut HashMap<String, String>) {
+    for entry in folders.as_array().into_iter().flatten() {
+        match entry {
+            Value::Object(named) => {
+                for (name, contents) in named {
+                    path.push(name.clone());
+                    newsblur_folders(contents, path, folder_of);
+                    path.pop();
+                }
+            }
+            id if !path.is_empty() => {
+                let id = id.as_str().map_or_else(|| id.to_string(), str::to_string);
+                folder_of.entry(id).or_insert_with(|| path.join("/"));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A subscription of a JSON export, None if it names no feed URL.
+fn json_feed(value: &Value) -> Option<ImportedFeed> {
+    let text = |keys: &[&str]| keys.iter().find_map(|key| value.get(key)?.as_str()).map(str::trim).filter(|text| !text.is_empty()).map(str::to_string);
+    let url = text(JSON_URL_KEYS)?;
+    let mut categories = Vec::new();
+    for key in JSON_CATEGORY_KEYS {
+        let values = match value.get(key) {
+            Some(Value::Array(values)) => values.iter().collect(),
+            Some(value) => vec![value],
+            None => Vec::new(),
+        };
+        // Categories are given as names or as objects with a title or label
+        let names = values.into_iter().filter_map(|value| match value {
+            Value::String(name) => Some(name.as_str()),
+            Value::Object(_) => ["title", "label", "name"].iter().find_map(|key| value.get(key)?.as_str()),
+            _ => None,
+        });
+        for name in names.map(|name| name.trim().trim_matches('/')).filter(|name| !name.is_empty()) {
+            if !categories.iter().any(|known| known == name) {
+                categories.push(name.to_string());
+            }
+        }
+    }
+    Some(ImportedFeed {
+        url,
+        title: text(JSON_TITLE_KEYS),
+        categories,
+    })
+}
+
+/// The feed list lines of an imported feed: its title as a comment and its URL with its
+/// folders as `category` option.
+fn feed_list_lines(feed: &ImportedFeed) -> String {
+    let mut lines = String::new();
+    if let Some(title) = feed.title.as_deref().filter(|title| *title != feed.url) {
+        lines.push_str(&format!("# {}\n", title.split_whitespace().collect::<Vec<_>>().join(" ")));
+    }
+    lines.push_str(&feed.url);
+    // Feed list options can't hold quotes, and commas separate categories
+    let categories: Vec<String> = feed
+        .categories
+        .iter()
+        .map(|category| category.replace(['"', ','], " ").split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|category| !category.is_empty())
+        .collect();
+    if !categories.is_empty() {
+        lines.push_str(&format!(" category=\"{}\"", categories.join(",")));
+    }
+    lines.push('\n');
+    lines
+}
+
+/// Imports the subscriptions exported to `file` into the first feed list. Feeds already
+/// listed in one of `lists`, listed twice or without an http(s) URL are skipped with a
+/// note. With `verify`, feeds that don't answer a HEAD request over `ip_family` are added
+/// commented out. The lines are printed unless `write` is set, in which case they are
+/// appended to the feed list.
+pub async fn import_subscriptions(file: &str, lists: &[FeedList], verify: bool, write: bool, ip_family: IpFamily) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let target = &lists.first().ok_or("No feed list to import into")?.source;
+    let contents = fs::read_to_string(file).map_err(|e| format!("Could not read {}: {}", file, e))?;
+    let imported = parse_subscriptions(&contents).map_err(|e| format!("Could not read the subscriptions in {}: {}", file, e))?;
+
+    let mut listed: HashMap<String, String> = HashMap::new();
+    for list in lists {
+        // A feed list that doesn't exist yet lists nothing
+        let Ok(feed_list) = fs::read_to_string(&list.source) else {
+            continue;
+        };
+        for spec in parse_feed_list_with_errors(&feed_list).0 {
+            listed.entry(url_identity(&spec.url)).or_insert_with(|| list.source.clone());
+        }
+    }
+    // Position of every feed to add, by URL identity
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut feeds: Vec<ImportedFeed> = Vec::new();
+    for feed in imported {
+        let identity = url_identity(&feed.url);
+        if let Some(source) = listed.get(&identity) {
+            eprintln!("Skipping {}: already listed in {}", feed.url, source);
+        } else if !feed.url.starts_with("http://") && !feed.url.starts_with("https://") {
+            eprintln!("Skipping {}: not an http or https URL", feed.url);
+        } else if let Some(&position) = positions.get(&identity) {
+            // A feed filed in several folders is listed once for each of them
+            eprintln!("Skipping {}: listed twice in {}, keeping its folders", feed.url, file);
+            for category in feed.categories {
+                if !feeds[position].categories.contains(&category) {
+                    feeds[position].categories.push(category);
+                }
+            }
+        } else {
+            positions.insert(identity, feeds.len());
+            feeds.push(feed);
+        }
+    }
+
+    let unreachable: HashMap<String, String> = if verify {
+        let urls: Vec<String> = feeds.iter().map(|feed| feed.url.clone()).collect();
+        check_reachable(&urls, ip_family).await?.into_iter().collect()
+    } else {
+        HashMap::new()
+    };
+    let mut additions = String::new();
+    for feed in &feeds {
+        match unreachable.get(&feed.url) {
+            Some(reason) => {
+                eprintln!("Warning: {} could not be reached ({}), adding it commented out", feed.url, reason);
+                additions.push_str(&format!("# Unreachable when imported: {}\n", reason));
+                for line in feed_list_lines(feed).lines() {
+                    additions.push_str(&format!("# {}\n", line.trim_start_matches("# ")));
+                }
+            }
+            None => additions.push_str(&feed_list_lines(feed)),
+        }
+    }
+
+    if feeds.is_empty() {
+        eprintln!("No new feeds to import into {}", target);
+    } else if write {
+        let mut feed_list = fs::read_to_string(target).unwrap_or_default();
+        if !feed_list.is_empty() && !feed_list.ends_with('\n') {
+            feed_list.push('\n');
+        }
+        feed_list.push_str(&additions);
+        fs::write(target, feed_list).map_err(|e| format!("Could not write {}: {}", target, e))?;
+        eprintln!("Added {} feeds to {}", feeds.len(), target);
+    } else {
+        print!("{}", additions);
+        eprintln!("{} feeds to add to {}; run again with --write to append them", feeds.len(), target);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn imported(url: &str, title: &str, categories: &[&str]) -> ImportedFeed {
+        ImportedFeed {
+            url: url.to_string(),
+            title: Some(title.to_string()),
+            categories: categories.iter().map(|category| category.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn opml_folders_become_categories() {
+        let opml = r#"<?xml version="1.0"?><opml version="2.0"><head><title>Export</title></head><body>
+            <outline text="Tech" title="Tech">
+                <outline text="Rust">
+                    <outline text="Rust Blog" xmlUrl="https://blog.rust-lang.org/feed.xml"/>
+                </outline>
+                <outline text="LWN" xmlUrl="https://lwn.net/headlines/rss" category="/News/Linux,Tech"></outline>
+            </outline>
+            <outline title="Podcast" text="Ignored" xmlUrl="https://example.com/podcast.xml">
+                <outline text="Episode" xmlUrl="https://example.com/episode.xml"/>
+            </outline>
+            <outline text="Empty folder"/>
+        </body></opml>"#;
+        assert_eq!(
+            parse_subscriptions(&format!("\u{feff}{}", opml)).unwrap(),
+            [
+                imported("https://blog.rust-lang.org/feed.xml", "Rust Blog", &["Tech/Rust"]),
+                imported("https://lwn.net/headlines/rss", "LWN", &["Tech", "News/Linux"]),
+                imported("https://example.com/podcast.xml", "Podcast", &[]),
+                imported("https://example.com/episode.xml", "Episode", &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn flat_opml_has_no_categories() {
+        let opml = r#"<opml version="1.0"><body>
+            <outline type="rss" text="One" xmlUrl=" https://one.example/feed "/>
+            <outline type="rss" xmlUrl="https://two.example/feed"/>
+        </body></opml>"#;
+        let two = ImportedFeed { url: "https://two.example/feed".to_string(), title: None, categories: Vec::new() };
+        assert_eq!(parse_subscriptions(opml).unwrap(), [imported("https://one.example/feed", "One", &[]), two]);
+        assert!(parse_subscriptions("<opml><body><outline text=\"x\" xmlUrl=\"a\"></body>").is_err());
+    }
+
+    #[test]
+    fn json_exports() {
+        let newsblur = r#"{"feeds": {"12": {"feed_address": "https://b.example/feed", "feed_title": "B"},
+                                     "3": {"feed_address": "https://a.example/feed", "feed_title": "A"}},
+                           "folders": [3, {"News": [{"World": ["12"]}]}]}"#;
+        assert_eq!(
+            parse_subscriptions(newsblur).unwrap(),
+            [imported("https://a.example/feed", "A", &[]), imported("https://b.example/feed", "B", &["News/World"])]
+        );
+        let list = r#"[{"feed_url": "https://a.example/feed", "title": "A", "category": {"title": "Tech"}}, {"name": "No URL"}]"#;
+        assert_eq!(parse_subscriptions(list).unwrap(), [imported("https://a.example/feed", "A", &["Tech"])]);
+        assert!(parse_subscriptions(r#"{"unknown": 1}"#).is_err());
+    }
+
+    #[test]
+    fn feed_list_lines_hold_the_title_and_categories() {
+        let feed = imported("https://a.example/feed", "A  feed", &["Tech, \"Rust\"", "News"]);
+        assert_eq!(feed_list_lines(&feed), "# A feed\nhttps://a.example/feed category=\"Tech Rust,News\"\n");
+    }
+
+    #[tokio::test]
+    async fn importing_needs_a_feed_list() {
+        let file = std::env::temp_dir().join(format!("import-test-{}.opml", std::process::id()));
+        fs::write(&file, "<opml><body></body></opml>").unwrap();
+        let result = import_subscriptions(file.to_str().unwrap(), &[], false, false, IpFamily::default()).await;
+        fs::remove_file(&file).unwrap();
+        assert_eq!(result.unwrap_err().to_string(), "No feed list to import into");
+    }
+}
@@ -1,376 +1,5198 @@
 // main.rs
-use feed_rs::parser;
-use reqwest;
-use rss::{Channel, ChannelBuilder, Item, ItemBuilder};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+/// Prints a progress message, like `println!`, or to stderr if `to_stderr`, which is set
+/// when stdout carries machine-readable output (`--json`).
+macro_rules! progress {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Prints a diagnostic message to stderr if `verbose`, which is set by `--verbose`.
+macro_rules! debug {
+    ($verbose:expr, $($arg:tt)*) => {
+        if $verbose {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod budget;
+mod commit_message;
+mod config_check;
+mod config_schema;
+mod content_store;
+mod crawler;
+mod dates;
+mod duplicates;
+mod diff;
+mod filters;
+mod fingerprint;
+mod guid;
+mod html_rewrite;
+mod import;
+mod opml;
+mod output;
+mod pseudo_feed;
+mod rejects;
+mod republish;
+mod resume;
+mod rss_validation;
+mod search;
+mod since_commit;
+mod stale;
+mod stats;
+mod storage;
+mod translations;
+mod verify;
+#[cfg(test)]
+mod test_support;
+
+use rss::{Category, Channel, ChannelBuilder, Guid, Image, Item, ItemBuilder};
+use rss::extension::dublincore::DublinCoreExtension;
+use rss::extension::itunes::ITunesItemExtension;
+use atom_syndication::LinkBuilder;
+use rss::extension::atom::AtomExtension;
+use rss::extension::syndication::{SyndicationExtension, UpdatePeriod, NAMESPACE as SYNDICATION_NS};
+use rss::extension::{ExtensionBuilder, ExtensionMap};
 use std::error::Error;
 use std::fs;
-use std::collections::{HashMap, HashSet};
-use chrono::{DateTime, FixedOffset, Utc};
-use tokio;
-use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use chrono::{DateTime, Datelike, FixedOffset, Timelike, Utc};
+use serde::{Deserialize, Serialize};
 use regex::Regex;
-use quick_xml::Writer;
+use sha2::{Digest, Sha256};
+use deunicode::deunicode;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use quick_xml::{Reader, Writer};
 use quick_xml::events::{Event, BytesEnd, BytesStart, BytesText};
-use std::io::Cursor;
+use std::io::Write;
+use tokio::sync::{mpsc, watch};
+use budget::{fit_to_budget, BudgetStrategy, Trimmed};
+use commit_message::{RunChanges, COMMIT_MESSAGE_PATH};
+use content_store::{build_content_store_reference_item, content_path, resolve_references, store_items, stored_hashes, StoredContent};
+use crawler::{
+    coalesce_duplicate_specs, parse_feed, parse_feed_list, redirect_specs, ClampedDates, CrawlResult, CrawlStats, DedupCache, FeedCrawler, RemovedItems, FeedSpec, FetchTiming,
+    HttpClient, IpFamily, ParseOptions, RedirectPolicyClient,
+};
+use diff::{diff_feed_data, diff_feed_items, FeedChanges, FeedDiff, CHANGES_REPORT_PATH};
+use filters::{AuthorFilter, FilterConfig, KeywordFilter};
+use fingerprint::{settings_hash, FingerprintStore, FINGERPRINTS_PATH};
+use guid::GuidStrategy;
+use html_rewrite::{HtmlRewriteConfig, HtmlRewriter};
+use opml::write_and_validate_opml;
+use output::{build_atom_feed, build_json_feed, listing_order, OutputFormat};
+use pseudo_feed::PseudoFeedKind;
+use rejects::{rejects_feed, RejectedItem, REJECTS_SLUG};
+use republish::{ItemHistory, RepublishDetection};
+use resume::ResumeState;
+use rss_validation::{validate_rss, validate_rss_file};
+use since_commit::added_feed_urls;
+use search::{build_search_index, merge_search_entries, read_archived_feed, search_archive, write_search_index, SearchEntry, SEARCH_INDEX_PATH};
+use stale::{StaleDetector, StaleFeed, LAST_ITEM_DATES_PATH};
+use stats::FeedStats;
+use storage::{is_not_found, LocalFs, Storage, StorageConfig};
+use translations::{keep_languages, TranslationGrouping};
+use verify::{check_reachable, sample_evenly, verify_archive, ArchiveSource};
+
+// Namespace for the archiver's own RSS extension elements
+const AGGREGATOR_NS_PREFIX: &str = "rssAggregator";
+const AGGREGATOR_NS: &str = "https://github.com/xavwe/rss-aggregator";
+
+// Media RSS namespace, used for item thumbnails
+const MEDIA_NS_PREFIX: &str = "media";
+const MEDIA_NS: &str = "http://search.yahoo.com/mrss/";
+
+// Slash namespace, used for item comment counts
+const SLASH_NS_PREFIX: &str = "slash";
+const SLASH_NS: &str = "http://purl.org/rss/1.0/modules/slash/";
+
+// Well-Formed Web namespace, used for the comment feeds of items
+const WFW_NS_PREFIX: &str = "wfw";
+const WFW_NS: &str = "http://wellformedweb.org/CommentAPI/";
+
+// DCMI Metadata Terms namespace, used for the dates items were last updated
+const DCTERMS_NS_PREFIX: &str = "dcterms";
+const DCTERMS_NS: &str = "http://purl.org/dc/terms/";
+
+// Syndication namespace, used for the update schedule of the archived feeds
+const SYNDICATION_NS_PREFIX: &str = "sy";
+
+// Per-feed state persisted between runs
+const STATE_PATH: &str = "feeds/.state.json";
+
+// Fetch timings kept per feed in the state file, to follow their trend
+const TIMING_HISTORY: usize = 30;
+
+// Default seconds between runs with --daemon
+const DEFAULT_INTERVAL_SECS: u64 = 3600;
+
+// Share of the interval, in percent, randomly added to the wait between daemon runs
+const DAEMON_JITTER_PERCENT: u64 = 10;
+
+// Largest share of a feed's polling interval, in percent, by which it is due early
+const FEED_INTERVAL_STAGGER_PERCENT: u64 = 10;
+
+// Slowest feeds listed in the run summary
+const SLOWEST_FEEDS_LISTED: usize = 10;
+
+// Fetched feeds waiting to be archived before fetching pauses
+const READY_FEEDS_BUFFER: usize = 8;
+
+// Publishing activity of every feed
+const FEED_STATS_PATH: &str = "feeds/.stats.json";
+
+// Summary of the last run
+const STATS_PATH: &str = "feeds/stats.json";
+
+// Average reading speed used for read time estimates
+const WORDS_PER_MINUTE: usize = 250;
+
+// Default maximum length of the name part of archive filenames, before the URL hash suffix
+const DEFAULT_MAX_FILENAME_LENGTH: usize = 50;
+
+// Upper bound of the name part of archive filenames whatever `max_filename_length` is, so
+// that the name, URL hash and extension stay within the 255 bytes filesystems allow
+const FILENAME_LENGTH_LIMIT: usize = 200;
+
+// Files the archiver writes next to the archived feeds under a feed's extension, which
+// feed files of that format must not take
+const RESERVED_FILES: [&str; 3] = ["stats.json", "search-index.json", "changes.json"];
+
+// Identifies the archiver in the generator element of the archived feeds
+const GENERATOR_NAME: &str = "rss-aggregator";
+const GENERATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 // Config struct for deserializing config.toml
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 struct Config {
     max_items: Option<usize>,
     repo_name: Option<String>,
+    // Further repositories each getting their own copy of the OPML and archived feed files
+    repo_targets: Option<Vec<RepoTarget>>,
+    lists: Option<Vec<FeedList>>,
+    respect_feed_hints: Option<bool>,
+    detect_language: Option<bool>,
+    language_confidence: Option<f64>,
+    // Translations of a post published further apart than this are not recognized as such
+    translation_window_minutes: Option<u64>,
+    // Share of path segments the links of translations have in common (1.0 means the same path)
+    translation_link_similarity: Option<f64>,
+    dedup_window_days: Option<u64>,
+    base_url: Option<String>,
+    storage: Option<StorageConfig>,
+    exclude_link_domains: Option<Vec<String>>,
+    include_link_domains: Option<Vec<String>>,
+    // Guids or links of items to remove from the archive for good
+    blocked_items: Option<Vec<String>>,
+    // Collect the items dropped by filters into feeds/rejected.xml
+    generate_rejects_feed: Option<bool>,
+    rejects_feed_in_opml: Option<bool>,
+    min_items_per_week: Option<f64>,
+    stale_feed_threshold_days: Option<u64>,
+    accept: Option<String>,
+    extract_images: Option<bool>,
+    output_format: Option<OutputFormat>,
+    verify_max_age_days: Option<u64>,
+    force_refresh: Option<bool>,
+    normalize_titles: Option<bool>,
+    max_title_length: Option<usize>,
+    // Item titles longer than this are shortened at a word boundary (0 means unlimited)
+    max_title_chars: Option<usize>,
+    max_feeds: Option<usize>,
+    clamp_future_dates: Option<bool>,
+    drop_future_dates: Option<bool>,
+    future_date_tolerance_minutes: Option<u64>,
+    quarantine_after_failures: Option<u32>,
+    quarantine_after_days: Option<u64>,
+    quarantine_retry_hours: Option<u64>,
+    exclude_quarantined: Option<bool>,
+    follow_redirects: Option<bool>,
+    max_redirects: Option<usize>,
+    ip_family: Option<IpFamily>,
+    // Maximum number of feeds fetched from the same host at the same time
+    max_concurrent_per_host: Option<usize>,
+    // Pages fetched per run for the titles of sitemap entries
+    sitemap_title_fetches: Option<usize>,
+    stable_item_order: Option<bool>,
+    filters: Option<FilterConfig>,
+    max_file_bytes: Option<u64>,
+    budget_strategy: Option<BudgetStrategy>,
+    source_annotation: Option<SourceAnnotationSetting>,
+    content_addressed: Option<bool>,
+    self_links: Option<bool>,
+    websub_hub: Option<String>,
+    max_filename_length: Option<usize>,
+    output_dir_structure: Option<OutputDirStructure>,
+    channel_categories: Option<usize>,
+    // Copyright notices of archived RSS channels by domain suffix of the feed URL, see CopyrightRules
+    copyright_rules: Option<BTreeMap<String, String>>,
+    default_copyright_template: Option<String>,
+    // Stylesheet referenced by an xml-stylesheet processing instruction in archived RSS and Atom files
+    stylesheet_href: Option<String>,
+    // Seconds between runs with --daemon
+    interval_secs: Option<u64>,
+    // Minutes between the runs updating the archive, announced to readers in the archived feeds
+    update_interval_minutes: Option<u64>,
+    // Seconds between fetches of a feed without its own interval option (0 means every run)
+    feed_interval_secs: Option<u64>,
+    // How guids are made up for items published without an id
+    guid_strategy: Option<GuidStrategy>,
+    // File recording the feeds fetched by an interrupted run, e.g. feeds/.resume.json
+    resume_file: Option<String>,
+    // Resume files written longer ago than this are ignored
+    resume_max_age_minutes: Option<u64>,
+    // Keep the comment counts and comment feeds of items in archived RSS feeds
+    comment_counts: Option<bool>,
+    // Add a dc:date in ISO 8601 next to the RFC 2822 pubDate of items in archived RSS feeds
+    dc_dates: Option<bool>,
+    // Write feeds/search-index.json, listing the archived items for client-side search
+    search_index: Option<bool>,
+    // Most recent items listed in the search index (0 means unlimited)
+    search_index_max_items: Option<usize>,
+    // Write feeds/.commit-message.txt, summarizing what the run changed
+    commit_message: Option<bool>,
+    // Write feeds/changes.json, listing the items added, removed and modified per feed
+    changes_report: Option<bool>,
+    // List the titles and links of the added items in the changes report
+    changes_report_titles: Option<bool>,
+    // Archived URLs requested by --check-published (0 means all of them)
+    published_check_sample: Option<usize>,
+    // Archive a feed listed under several URLs (http and https, with and without www.) once
+    merge_duplicates: Option<bool>,
+    // Keep the archived copies of feeds whose response is the same as last time, without parsing it
+    skip_unchanged: Option<bool>,
+    // Rewrite the item HTML for https readers; left untouched without this section
+    html_rewrite: Option<HtmlRewriteConfig>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Read configuration from config.toml
-    let config: Config = fs::read_to_string("config.toml")
-        .ok()
-        .and_then(|contents| toml::from_str(&contents).ok())
-        .unwrap_or(Config { max_items: None, repo_name: None });
-    let max_items = config.max_items.unwrap_or(300);
-    let repo_name = config.repo_name.unwrap_or_else(|| 
-        "xavwe/rss-aggregator".to_string()
-    );
-    println!("Using max_items = {}", max_items);
-
-    // Read feed URLs from "feeds.txt" (one URL per line)
-    let feeds_content = fs::read_to_string("feeds.txt")?;
-    let feed_urls: Vec<String> = feeds_content
-        .lines()
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty())
-        .map(|s| s.to_string())
-        .collect();
-
-    if feed_urls.is_empty() {
-        eprintln!("No feed URLs found in feeds.txt");
-        return Ok(());
+impl Config {
+    /// The configured feed lists. Without configured lists, feeds.txt is archived into
+    /// feeds/ and listed in master.opml.
+    fn feed_lists(&self) -> Vec<FeedList> {
+        self.lists.clone().unwrap_or_else(|| vec![FeedList {
+            name: "master".to_string(),
+            source: "feeds.txt".to_string(),
+            output_dir: None,
+        }])
     }
+}
 
-    // Concurrently fetch and parse feeds
-    let mut all_items = Vec::new();
-    let mut feed_data_list = Vec::new();
-    let mut handles = Vec::new();
-    for url in feed_urls {
-        let url_owned = url.to_string();
-        let handle = tokio::spawn(async move { fetch_feed_data(url_owned).await });
-        handles.push(handle);
-    }
+// How the archived feeds are laid out in the directory of their list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+enum OutputDirStructure {
+    // All files directly in the list directory
+    #[default]
+    #[serde(rename = "flat")]
+    Flat,
+    // In the year/month directory of the feed's newest item, e.g. feeds/2024/11/
+    #[serde(rename = "year/month")]
+    YearMonth,
+}
 
-    // Collect results from tasks
-    for handle in handles {
-        match handle.await? {
-            Ok(feed_data) => {
-                all_items.extend(feed_data.items.clone());
-                feed_data_list.push(feed_data);
-            },
-            Err(e) => eprintln!("Error fetching feed: {}", e),
+// How the items of combined feeds name the member feed they come from, one style or several
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum SourceAnnotationSetting {
+    One(SourceAnnotationStyle),
+    Many(Vec<SourceAnnotationStyle>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SourceAnnotationStyle {
+    // Prefix the title with "[Feed Title] "
+    Title,
+    // Append a "via Feed Title" link to the description
+    Description,
+    // Point the <source> element at the member's own archive
+    SourceElement,
+    None,
+}
+
+// The source annotation styles applied to combined feeds
+#[derive(Debug, Clone, Copy, Default)]
+struct SourceAnnotation {
+    title: bool,
+    description: bool,
+    source_element: bool,
+}
+
+impl SourceAnnotation {
+    fn new(setting: Option<&SourceAnnotationSetting>) -> SourceAnnotation {
+        let styles = match setting {
+            Some(SourceAnnotationSetting::One(style)) => std::slice::from_ref(style),
+            Some(SourceAnnotationSetting::Many(styles)) => styles.as_slice(),
+            None => &[],
+        };
+        SourceAnnotation {
+            title: styles.contains(&SourceAnnotationStyle::Title),
+            description: styles.contains(&SourceAnnotationStyle::Description),
+            source_element: styles.contains(&SourceAnnotationStyle::SourceElement),
         }
     }
+}
 
-    // Sort items by publication date (newest first)
-    all_items.sort_by(|a, b| b.pub_date.cmp(&a.pub_date));
+// A named feed list: its source file produces feeds/{name}.opml and its archived feeds
+// are written to feeds/ or, if output_dir is set, to feeds/{output_dir}/
+#[derive(Debug, Clone, Deserialize)]
+struct FeedList {
+    name: String,
+    source: String,
+    output_dir: Option<String>,
+}
 
-    // Limit the list to the maximum number of items specified (0 means unlimited)
-    if max_items > 0 && all_items.len() > max_items {
-        all_items.truncate(max_items);
+impl FeedList {
+    /// Directory the archived feeds of this list are written to.
+    fn feed_dir(&self) -> String {
+        match &self.output_dir {
+            Some(dir) => format!("feeds/{}", dir.trim_matches('/')),
+            None => "feeds".to_string(),
+        }
     }
+}
 
-    // Generate OPML feed list instead of master RSS feed
-    let opml_content = build_opml_feed_list(&feed_data_list, &repo_name)?;
-    
-    // Write the generated OPML file
-    if let Err(e) = fs::write("feeds/master.opml", opml_content) {
-        eprintln!("Error writing master OPML file: {}", e);
-        return Err(e.into());
-    }
-    println!("Master OPML feed list generated with {} feeds", feed_data_list.len());
+/// Whether `segment` can be used as a file or directory name inside the output directory.
+fn is_safe_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment != "."
+        && segment != ".."
+        && !segment.contains('\\')
+        && !segment.ends_with(['.', ' '])
+        && !is_reserved_windows_name(segment)
+}
 
-    // Remove the old master.xml file if it exists
-    let master_xml_path = "feeds/master.xml";
-    if std::path::Path::new(master_xml_path).exists() {
-        if let Err(e) = fs::remove_file(master_xml_path) {
-            eprintln!("Warning: Could not remove old master.xml: {}", e);
-        } else {
-            println!("Removed old master.xml file");
+/// Checks that list names are unique and that output directories stay inside feeds/.
+fn validate_feed_lists(lists: &[FeedList]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if lists.is_empty() {
+        return Err("config.toml defines an empty `lists` array".into());
+    }
+    let mut names = HashSet::new();
+    for list in lists {
+        if !is_safe_segment(&list.name) || list.name.contains('/') {
+            return Err(format!("Invalid feed list name: {:?}", list.name).into());
+        }
+        if !names.insert(list.name.as_str()) {
+            return Err(format!("Duplicate feed list name: {}", list.name).into());
+        }
+        if let Some(dir) = &list.output_dir {
+            if !dir.trim_matches('/').split('/').all(is_safe_segment) {
+                return Err(format!("Invalid output_dir for feed list {}: {:?}", list.name, dir).into());
+            }
         }
     }
+    Ok(())
+}
 
-    // Clean up old individual feed files
-    cleanup_old_feeds(&feed_data_list)?;
+// A further repository the archive is published to: the OPML and archived feed files are
+// rendered again with the raw URLs of `name` and written to {output_prefix}/feeds/, the
+// prefix defaulting to the repository name
+#[derive(Debug, Clone, Deserialize)]
+struct RepoTarget {
+    name: String,
+    output_prefix: Option<String>,
+}
 
-    // Generate individual feed files - one unique file per feed URL
-    for feed_data in feed_data_list {
-        // Apply max_items limit to individual feeds too
-        let limited_feed_data = if max_items > 0 && feed_data.items.len() > max_items {
-            FeedData {
-                title: feed_data.title.clone(),
-                url: feed_data.url.clone(),
-                items: feed_data.items.into_iter().take(max_items).collect(),
-            }
-        } else {
-            feed_data
-        };
+impl RepoTarget {
+    fn output_prefix(&self) -> &str {
+        self.output_prefix.as_deref().unwrap_or(&self.name).trim_matches('/')
+    }
+}
 
-        // Generate unique filename based on URL and title to ensure one file per feed
-        let unique_filename = generate_unique_filename_for_feed(&limited_feed_data.url, &limited_feed_data.title);
-        let filepath = format!("feeds/{}.xml", unique_filename);
-        
-        let individual_channel = build_individual_feed(&limited_feed_data, &repo_name, &unique_filename);
-        
-        if let Err(e) = fs::write(&filepath, individual_channel.to_string()) {
-            eprintln!("Error writing individual feed {}: {}", filepath, e);
-            continue; // Continue with other feeds instead of failing completely
-        }
-        
-        println!("Generated individual feed: {} ({} items)", filepath, limited_feed_data.items.len());
+// A repo target resolved for writing
+#[derive(Debug, Clone)]
+struct OutputTarget {
+    repo_name: String,
+    base_url: String,
+    prefix: String,
+}
+
+impl OutputTarget {
+    /// Path of the copy of the primary output file at `path`.
+    fn path(&self, path: &str) -> String {
+        format!("{}/{}", self.prefix, path)
     }
+}
 
+/// Checks that repo targets name distinct repositories and that their output prefixes are
+/// distinct directories outside feeds/.
+fn validate_repo_targets(targets: &[RepoTarget]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut names = HashSet::new();
+    let mut prefixes: Vec<&str> = Vec::new();
+    for target in targets {
+        if target.name.split('/').count() != 2 || !target.name.split('/').all(is_safe_segment) {
+            return Err(format!("Invalid repo target name: {:?}", target.name).into());
+        }
+        if !names.insert(target.name.as_str()) {
+            return Err(format!("Duplicate repo target: {}", target.name).into());
+        }
+        let prefix = target.output_prefix();
+        if !prefix.split('/').all(is_safe_segment) || prefix.split('/').next() == Some("feeds") {
+            return Err(format!("Invalid output_prefix for repo target {}: {:?}", target.name, prefix).into());
+        }
+        // One target's files must not end up in another's directory, where cleanup would remove them
+        let nested = |a: &str, b: &str| a == b || a.starts_with(&format!("{}/", b));
+        if let Some(other) = prefixes.iter().find(|other| nested(prefix, other) || nested(other, prefix)) {
+            return Err(format!("output_prefix {:?} of repo target {} overlaps {:?}", prefix, target.name, other).into());
+        }
+        prefixes.push(prefix);
+    }
     Ok(())
 }
 
-// A simple struct to hold the feed item data
-#[derive(Debug, Clone)]
-struct FeedItem {
-    title: String,
-    link: String,
-    description: Option<String>,
-    pub_date: DateTime<FixedOffset>,
+// Command line options
+#[derive(Debug, Default)]
+struct CliArgs {
+    command: Option<Command>,
+    merge_opml: Option<String>,
+    ignore_hints: bool,
+    force_refresh: bool,
+    retry_quarantined: bool,
+    truncate: bool,
+    remote: bool,
+    // Print the changes to the archived feeds instead of writing them
+    diff: bool,
+    json: bool,
+    verbose: bool,
+    // Only fetch and rewrite the feeds of this URL, keeping the archived copies of all others
+    reprocess: Option<String>,
+    // Only fetch and rewrite the feeds added to the feed lists since this commit
+    since_commit: Option<String>,
+    // Keep running, archiving the feeds every interval_secs
+    daemon: bool,
+    // Treat the search query as a regular expression
+    regex: bool,
+    // Check config.toml, the feed lists and the output directories without fetching anything
+    config_check: bool,
+    // After the run, check that a sample of the already published files can be downloaded
+    check_published: bool,
+    // Archive feeds listed under several URLs once, as merge_duplicates does
+    fix_duplicates: bool,
+    // Stop at feed list lines with an invalid URL instead of skipping them
+    strict: bool,
+    // With import, add feeds that don't answer a HEAD request commented out
+    verify_imported: bool,
+    // With import, append the feeds to the feed list instead of printing them
+    write: bool,
 }
 
-// Struct to hold both feed metadata and items
-#[derive(Debug)]
-struct FeedData {
-    title: String,
-    url: String,
-    items: Vec<FeedItem>,
+// Subcommands run instead of archiving the feeds
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    // Print the publishing activity recorded in feeds/.stats.json
+    Stats,
+    // Check that every feed listed in the OPML files can be read and parsed
+    Verify,
+    // Print the archived items matching a query, read from the archived feed files
+    Search(String),
+    // Add the subscriptions of a reader export to the first feed list
+    Import(String),
 }
 
-/// Fetches a feed from the given URL and parses its items and metadata.
-async fn fetch_feed_data(url: String) -> Result<FeedData, Box<dyn Error + Send + Sync>> {
-    let response = reqwest::get(&url).await?;
-    let bytes = response.bytes().await?;
-    let feed = parser::parse(bytes.as_ref())?;
-
-    // Extract feed title
-    let feed_title = feed.title
-        .map(|t| t.content)
-        .unwrap_or_else(|| url.clone());
-
-    // Create a FixedOffset with zero offset.
-    let offset = FixedOffset::east_opt(0).unwrap();
-
-    let mut items = Vec::new();
-    for entry in feed.entries {
-        // Convert published/updated dates to DateTime<FixedOffset>
-        let pub_date = entry
-            .published
-            .map(|d| d.with_timezone(&offset))
-            .or(entry.updated.map(|d| d.with_timezone(&offset)))
-            .unwrap_or_else(|| Utc::now().with_timezone::<FixedOffset>(&offset));
-
-        // Use the first available link (if any)
-        let link = if !entry.links.is_empty() {
-            entry.links[0].href.clone()
+/// Parses the command line arguments passed to the archiver.
+fn parse_args() -> Result<CliArgs, Box<dyn Error + Send + Sync>> {
+    let mut cli = CliArgs::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--merge-opml" {
+            let file = args.next().ok_or("--merge-opml requires a file argument")?;
+            cli.merge_opml = Some(file);
+        } else if let Some(file) = arg.strip_prefix("--merge-opml=") {
+            cli.merge_opml = Some(file.to_string());
+        } else if arg == "--reprocess" {
+            let url = args.next().ok_or("--reprocess requires a feed URL")?;
+            cli.reprocess = Some(url);
+        } else if let Some(url) = arg.strip_prefix("--reprocess=") {
+            cli.reprocess = Some(url.to_string());
+        } else if arg == "--since-commit" {
+            let commit = args.next().ok_or("--since-commit requires a commit")?;
+            cli.since_commit = Some(commit);
+        } else if let Some(commit) = arg.strip_prefix("--since-commit=") {
+            cli.since_commit = Some(commit.to_string());
+        } else if arg == "--daemon" {
+            cli.daemon = true;
+        } else if arg == "--ignore-hints" {
+            cli.ignore_hints = true;
+        } else if arg == "--force-refresh" {
+            cli.force_refresh = true;
+        } else if arg == "--retry-quarantined" {
+            cli.retry_quarantined = true;
+        } else if arg == "--truncate" {
+            cli.truncate = true;
+        } else if arg == "--diff" {
+            cli.diff = true;
+        } else if arg == "--json" {
+            cli.json = true;
+        } else if arg == "--verbose" {
+            cli.verbose = true;
+        } else if arg == "--remote" {
+            cli.remote = true;
+        } else if arg == "--regex" {
+            cli.regex = true;
+        } else if arg == "--config-check" {
+            cli.config_check = true;
+        } else if arg == "--check-published" {
+            cli.check_published = true;
+        } else if arg == "--fix-duplicates" {
+            cli.fix_duplicates = true;
+        } else if arg == "--strict" {
+            cli.strict = true;
+        } else if arg == "--verify" {
+            cli.verify_imported = true;
+        } else if arg == "--write" {
+            cli.write = true;
+        } else if arg == "stats" && cli.command.is_none() {
+            cli.command = Some(Command::Stats);
+        } else if arg == "verify" && cli.command.is_none() {
+            cli.command = Some(Command::Verify);
+        } else if arg == "search" && cli.command.is_none() {
+            cli.command = Some(Command::Search(String::new()));
+        } else if arg == "import" && cli.command.is_none() {
+            cli.command = Some(Command::Import(String::new()));
+        } else if matches!(&cli.command, Some(Command::Search(query)) if query.is_empty()) {
+            cli.command = Some(Command::Search(arg));
+        } else if matches!(&cli.command, Some(Command::Import(file)) if file.is_empty()) {
+            cli.command = Some(Command::Import(arg));
         } else {
-            String::new()
-        };
-
-        let title = entry
-            .title
-            .map(|t| t.content)
-            .unwrap_or_else(|| String::from("No title"));
+            return Err(format!("Unknown argument: {}", arg).into());
+        }
+    }
+    if cli.command == Some(Command::Search(String::new())) {
+        return Err("search requires a query".into());
+    }
+    if cli.command == Some(Command::Import(String::new())) {
+        return Err("import requires an OPML or JSON file".into());
+    }
+    let import = matches!(cli.command, Some(Command::Import(_)));
+    if (cli.verify_imported || cli.write) && !import {
+        return Err("--verify and --write can only be used with import".into());
+    }
+    let verify = cli.command == Some(Command::Verify);
+    if cli.json && !cli.diff && !verify {
+        return Err("--json can only be used together with --diff or verify".into());
+    }
+    if cli.remote && !verify {
+        return Err("--remote can only be used with verify".into());
+    }
+    if cli.regex && !matches!(cli.command, Some(Command::Search(_))) {
+        return Err("--regex can only be used with search".into());
+    }
+    if cli.reprocess.is_some() && cli.command.is_some() {
+        return Err("--reprocess can't be used with stats, verify, search or import".into());
+    }
+    if cli.since_commit.is_some() && (cli.command.is_some() || cli.reprocess.is_some()) {
+        return Err("--since-commit can't be used with stats, verify, search, import or --reprocess".into());
+    }
+    if cli.config_check && (cli.command.is_some() || cli.diff || cli.daemon || cli.reprocess.is_some() || cli.since_commit.is_some()) {
+        return Err("--config-check can't be used with stats, verify, search, import, --diff, --daemon, --reprocess or --since-commit".into());
+    }
+    if cli.fix_duplicates && cli.command.is_some() {
+        return Err("--fix-duplicates can't be used with stats, verify, search or import".into());
+    }
+    if cli.strict && cli.command.is_some() {
+        return Err("--strict can't be used with stats, verify, search or import".into());
+    }
+    if cli.check_published && (cli.command.is_some() || cli.diff || cli.config_check) {
+        return Err("--check-published can't be used with stats, verify, search, import, --diff or --config-check".into());
+    }
+    if cli.daemon && (cli.command.is_some() || cli.diff || cli.reprocess.is_some() || cli.since_commit.is_some()) {
+        return Err("--daemon can't be used with stats, verify, search, import, --diff, --reprocess or --since-commit".into());
+    }
+    Ok(cli)
+}
 
-        let description = entry.summary.map(|s| s.content);
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let cli = parse_args()?;
 
-        items.push(FeedItem {
-            title,
-            link,
-            description,
-            pub_date,
-        });
+    if cli.config_check {
+        let errors = config_check::check_config();
+        for error in &errors {
+            eprintln!("Error: {}", error);
+        }
+        if !errors.is_empty() {
+            eprintln!("Found {} {}", errors.len(), if errors.len() == 1 { "problem" } else { "problems" });
+            std::process::exit(1);
+        }
+        println!("config.toml and the feed lists are valid");
+        return Ok(());
     }
 
-    Ok(FeedData {
-        title: feed_title,
-        url,
-        items,
-    })
+    let shutdown = spawn_shutdown_handler();
+    let config = load_config()?;
+    let client: Arc<dyn HttpClient> = Arc::new(RedirectPolicyClient::new(config.max_redirects.unwrap_or(10), config.ip_family.unwrap_or_default())?);
+    if cli.daemon {
+        return run_daemon(&cli, config, client, shutdown).await;
+    }
+    if run(&cli, config, client, shutdown).await? {
+        std::process::exit(130);
+    }
+    Ok(())
 }
 
-/// Builds an OPML document listing all the feeds.
-fn build_opml_feed_list(feeds: &[FeedData], repo_name: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let mut writer = Writer::new(Cursor::new(Vec::new()));
-    
-    // XML declaration
-    writer.write_event(Event::Decl(quick_xml::events::BytesDecl::new("1.0", Some("UTF-8"), None)))?;
-    
-    // Root opml element
-    let mut opml_elem = BytesStart::new("opml");
-    opml_elem.push_attribute(("version", "2.0"));
-    writer.write_event(Event::Start(opml_elem))?;
-    
-    // Head element
-    writer.write_event(Event::Start(BytesStart::new("head")))?;
-    
-    writer.write_event(Event::Start(BytesStart::new("title")))?;
-    writer.write_event(Event::Text(BytesText::new("RSS Feed Collection")))?;
-    writer.write_event(Event::End(BytesEnd::new("title")))?;
-    
-    let now = Utc::now();
-    let date_created = now.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
-    
-    writer.write_event(Event::Start(BytesStart::new("dateCreated")))?;
-    writer.write_event(Event::Text(BytesText::new(&date_created)))?;
-    writer.write_event(Event::End(BytesEnd::new("dateCreated")))?;
-    
-    writer.write_event(Event::Start(BytesStart::new("dateModified")))?;
-    writer.write_event(Event::Text(BytesText::new(&date_created)))?;
-    writer.write_event(Event::End(BytesEnd::new("dateModified")))?;
-    
-    writer.write_event(Event::End(BytesEnd::new("head")))?;
-    
-    // Body element
-    writer.write_event(Event::Start(BytesStart::new("body")))?;
-    
-    // Add each feed as an outline element
-    for feed in feeds {
-        let mut outline_elem = BytesStart::new("outline");
-        outline_elem.push_attribute(("text", feed.title.as_str()));
-        outline_elem.push_attribute(("title", feed.title.as_str()));
-        outline_elem.push_attribute(("type", "rss"));
-        
-        // Generate the individual feed URL for xmlUrl (RSS readers will fetch from our archive)
-        let unique_filename = generate_unique_filename_for_feed(&feed.url, &feed.title);
-        let archived_feed_url = format!("https://raw.githubusercontent.com/{}/refs/heads/main/feeds/{}.xml", repo_name, unique_filename);
-        outline_elem.push_attribute(("xmlUrl", archived_feed_url.as_str()));
-        
-        // Use original feed URL for htmlUrl (for human browsing to original site)
-        outline_elem.push_attribute(("htmlUrl", feed.url.as_str()));
-        
-        writer.write_event(Event::Empty(outline_elem))?;
+/// Reads configuration from config.toml, using the defaults if it is missing. Contents
+/// that don't match config.schema.json, such as a misspelled key, are an error.
+fn load_config() -> Result<Config, Box<dyn Error + Send + Sync>> {
+    let contents = match fs::read_to_string("config.toml") {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(format!("Could not read config.toml: {}", e).into()),
+    };
+    if let Err(errors) = config_schema::validate_config_with_schema(&contents) {
+        return Err(format!("Invalid config.toml: {}", errors.join("; ")).into());
     }
-    
-    writer.write_event(Event::End(BytesEnd::new("body")))?;
-    writer.write_event(Event::End(BytesEnd::new("opml")))?;
-    
-    let result = writer.into_inner().into_inner();
-    Ok(String::from_utf8(result)?)
+    Ok(toml::from_str(&contents).map_err(|e| format!("Invalid config.toml: {}", e))?)
 }
 
-/// Builds an RSS channel for an individual feed.
-fn build_individual_feed(feed_data: &FeedData, repo_name: &str, filename: &str) -> Channel {
-    let rss_items: Vec<Item> = feed_data.items
-        .iter()
-        .map(|fi| {
-            let mut builder = ItemBuilder::default();
-            builder.title(fi.title.clone());
-            builder.link(fi.link.clone());
-            if let Some(desc) = &fi.description {
-                builder.description(desc.clone());
-            }
-            // Format the publication date as RFC 2822 for RSS
-            builder.pub_date(fi.pub_date.to_rfc2822());
-            builder.build()
-        })
-        .collect();
+/// Sends `true` on the returned channel on Ctrl-C or, on Unix, SIGTERM, so that the crawl
+/// stops and whatever was fetched so far is written. A second Ctrl-C exits immediately.
+fn spawn_shutdown_handler() -> watch::Receiver<bool> {
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        eprintln!("Shutting down, finishing in-flight fetches (press Ctrl-C again to abort)");
+        let _ = shutdown_sender.send(true);
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    shutdown_receiver
+}
 
-    let github_link = format!("https://raw.githubusercontent.com/{}/refs/heads/main/feeds/{}.xml", repo_name, filename);
+/// Waits for Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    if let Ok(mut terminate) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = terminate.recv() => {}
+        }
+        return;
+    }
+    ctrl_c.await;
+}
 
-    ChannelBuilder::default()
-        .title(feed_data.title.clone())
-        .link(github_link)
-        .description(format!("Archived feed from {}", feed_data.url))
-        .items(rss_items)
-        .build()
+/// Archives the feeds every `interval_secs` until shut down, reusing the HTTP client (and
+/// its open connections) across runs. config.toml and the feed lists are read again before
+/// every run, so that edits apply without a restart. A failed run is reported and retried
+/// at the next interval.
+async fn run_daemon(
+    cli: &CliArgs,
+    config: Config,
+    client: Arc<dyn HttpClient>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let interval_secs = config.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS);
+    if interval_secs == 0 {
+        return Err("config.toml: interval_secs must be at least 1".into());
+    }
+    progress!(cli.json, "Running every {} seconds, press Ctrl-C or send SIGTERM to stop", interval_secs);
+    let mut config = config;
+    loop {
+        if let Err(e) = run(cli, config.clone(), Arc::clone(&client), shutdown.clone()).await {
+            eprintln!("Error: {}", e);
+        }
+        if *shutdown.borrow() {
+            return Ok(());
+        }
+
+        let delay = jittered_interval(interval_secs);
+        progress!(cli.json, "Next run in {} seconds", delay.as_secs());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.changed() => return Ok(()),
+        }
+        match load_config() {
+            Ok(reloaded) => config = reloaded,
+            Err(e) => eprintln!("Warning: {}, keeping the previous configuration", e),
+        }
+    }
 }
 
-/// Converts a string to kebab-case for use as a filename.
-fn to_kebab_case(input: &str) -> String {
-    let re = Regex::new(r"[^a-zA-Z0-9]+").unwrap();
-    re.replace_all(input.to_lowercase().as_str(), "-")
-        .trim_matches('-')
-        .to_string()
+/// Adds up to `DAEMON_JITTER_PERCENT` of the interval, so that several archivers started
+/// together don't keep hitting the same servers at the same moment.
+fn jittered_interval(interval_secs: u64) -> Duration {
+    let interval = Duration::from_secs(interval_secs);
+    let max_jitter_ms = interval.as_millis() as u64 * DAEMON_JITTER_PERCENT / 100;
+    let random = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.subsec_nanos() as u64)
+        .unwrap_or_default();
+    interval + Duration::from_millis(random % (max_jitter_ms + 1))
 }
 
-/// Generates a unique filename for a feed based on URL and title.
-/// This ensures one file per feed URL, preventing collisions.
-fn generate_unique_filename_for_feed(url: &str, title: &str) -> String {
-    // Use a combination of title and URL hash to create unique filenames
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    // Create a hash of the URL to ensure uniqueness
-    let mut hasher = DefaultHasher::new();
-    url.hash(&mut hasher);
-    let url_hash = hasher.finish();
-    
-    // Use title as base, but add URL hash for uniqueness
-    let base_title = to_kebab_case(title);
-    
-    // If title is too generic or empty, use domain from URL
-    let filename_base = if base_title.is_empty() || base_title.len() < 3 {
-        extract_domain_from_url(url).unwrap_or_else(|| "feed".to_string())
-    } else {
-        base_title
+/// Runs the archiver once, or the subcommand given on the command line. Returns whether
+/// the crawl was interrupted by a shutdown request.
+async fn run(
+    cli: &CliArgs,
+    config: Config,
+    client: Arc<dyn HttpClient>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // All output (and the state of previous runs) goes through the storage backend
+    let storage: Box<dyn Storage> = match &config.storage {
+        Some(storage_config) => storage_config.build()?,
+        None => Box::new(LocalFs::new(".")),
     };
-    
-    // Combine title with short hash of URL for uniqueness
-    format!("{}-{:08x}", filename_base, (url_hash & 0xFFFFFFFF) as u32)
-}
 
-/// Extracts domain name from URL for use in filename.
-fn extract_domain_from_url(url: &str) -> Option<String> {
-    if let Some(start) = url.find("://") {
-        let after_scheme = &url[start + 3..];
-        if let Some(end) = after_scheme.find('/') {
-            let domain = &after_scheme[..end];
-            // Remove www. prefix and convert to kebab case
-            let clean_domain = domain.strip_prefix("www.").unwrap_or(domain);
-            Some(to_kebab_case(clean_domain))
+    if cli.command == Some(Command::Stats) {
+        let min_items_per_week = config.min_items_per_week.unwrap_or(0.25);
+        FeedStats::load(storage.as_ref(), FEED_STATS_PATH).await?.print_table(min_items_per_week, Utc::now());
+        let state = FeedState::load(storage.as_ref(), STATE_PATH).await?;
+        state.print_quarantined();
+        state.print_latency();
+        return Ok(false);
+    }
+
+    if let Some(Command::Import(file)) = &cli.command {
+        let lists = config.feed_lists();
+        validate_feed_lists(&lists)?;
+        import::import_subscriptions(file, &lists, cli.verify_imported, cli.write, config.ip_family.unwrap_or_default()).await?;
+        return Ok(false);
+    }
+
+    let max_items = config.max_items.unwrap_or(300);
+    let layout = ArchiveLayout::new(&config);
+    // Archived Atom and JSON files are read back with the guid strategy they were written with
+    let parse_options = ParseOptions {
+        guid_strategy: config.guid_strategy.unwrap_or_default(),
+        verbose: cli.verbose,
+        progress_to_stderr: cli.json,
+    };
+    let file_limits = FileLimits {
+        max_items,
+        max_file_bytes: config.max_file_bytes.unwrap_or(0),
+        budget_strategy: config.budget_strategy.unwrap_or_default(),
+    };
+    let repo_name = config.repo_name.clone().unwrap_or_else(|| 
+        "xavwe/rss-aggregator".to_string()
+    );
+    // Archived feeds are served from the repository unless published elsewhere
+    let base_url = config
+        .base_url
+        .as_deref()
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| format!("https://raw.githubusercontent.com/{}/refs/heads/main", repo_name));
+    // A forced refresh rebuilds everything from freshly fetched feeds, ignoring what earlier runs cached
+    let force_refresh = cli.force_refresh || config.force_refresh.unwrap_or(false);
+    // A daemon fetches each feed only when due by default
+    let respect_feed_hints = config.respect_feed_hints.unwrap_or(cli.daemon) && !cli.ignore_hints && !force_refresh;
+    let quarantine = QuarantinePolicy {
+        after_failures: config.quarantine_after_failures.unwrap_or(20),
+        after_days: config.quarantine_after_days.unwrap_or(7),
+        retry_hours: config.quarantine_retry_hours.unwrap_or(24),
+    };
+    progress!(cli.json, "Using max_items = {}", max_items);
+
+    let lists = config.feed_lists();
+    validate_feed_lists(&lists)?;
+    if config.content_addressed.unwrap_or(false) && lists.iter().any(|list| list.feed_dir() == content_store::ITEMS_DIR) {
+        return Err(format!("{} holds the item content with content_addressed enabled and can't be a list's output_dir", content_store::ITEMS_DIR).into());
+    }
+    // Each repo target gets its own copy of the output, served from that repository
+    let repo_targets = config.repo_targets.clone().unwrap_or_default();
+    validate_repo_targets(&repo_targets)?;
+    let output_targets: Vec<OutputTarget> = repo_targets
+        .iter()
+        .map(|target| OutputTarget {
+            repo_name: target.name.clone(),
+            base_url: format!("https://raw.githubusercontent.com/{}/refs/heads/main", target.name),
+            prefix: target.output_prefix().to_string(),
+        })
+        .collect();
+
+    if let Some(Command::Search(query)) = &cli.command {
+        // The items of the feed of rejected items were filtered out of the archive
+        let rejects_dir = lists.first().filter(|_| config.generate_rejects_feed.unwrap_or(false)).map(FeedList::feed_dir);
+        let matches = search_archive(storage.as_ref(), &lists, query, cli.regex, rejects_dir.as_deref(), parse_options).await?;
+        for found in &matches {
+            println!("{}  {}  {}", found.date.format("%Y-%m-%d"), found.feed, found.title);
+            println!("            {}", found.link);
+        }
+        progress!(cli.json, "{} matching items", matches.len());
+        return Ok(false);
+    }
+
+    if cli.command == Some(Command::Verify) {
+        // The crawler's client, so that a stalled server times out as it does when fetching
+        let source = if cli.remote {
+            ArchiveSource::Remote(client.as_ref())
         } else {
-            let clean_domain = after_scheme.strip_prefix("www.").unwrap_or(after_scheme);
-            Some(to_kebab_case(clean_domain))
+            ArchiveSource::Storage(storage.as_ref())
+        };
+        let opml_paths: Vec<String> = lists.iter().map(|list| format!("feeds/{}.opml", list.name)).collect();
+        let max_age_days = config.verify_max_age_days.unwrap_or(30);
+        let report = verify_archive(&source, &base_url, &opml_paths, max_age_days, Utc::now()).await;
+        if cli.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            report.print();
         }
-    } else {
-        None
+        if report.broken() > 0 {
+            std::process::exit(1);
+        }
+        return Ok(false);
     }
-}
 
-/// Cleans up old individual feed files that are no longer in the feed list.
-fn cleanup_old_feeds(current_feeds: &[FeedData]) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Read current feeds directory
-    let feeds_dir = std::path::Path::new("feeds");
-    if !feeds_dir.exists() {
-        return Ok(());
+    // Create the output directories up front, so that a fresh checkout without feeds/ works
+    // and an output directory that can't be created is reported before anything is fetched
+    if !cli.diff {
+        for dir in std::iter::once("feeds".to_string()).chain(lists.iter().map(FeedList::feed_dir)) {
+            storage.create_dir(&dir).await.map_err(|e| format!("Could not create output directory {}: {}", dir, e))?;
+        }
     }
 
-    // Get current feed URLs as filenames
-    let mut current_filenames = HashSet::new();
-    
-    for feed_data in current_feeds {
-        let unique_filename = generate_unique_filename_for_feed(&feed_data.url, &feed_data.title);
-        current_filenames.insert(format!("{}.xml", unique_filename));
+    // Read the feeds of every list (one URL per line, optionally followed by per-feed options)
+    let mut list_specs: Vec<Vec<FeedSpec>> = Vec::new();
+    // The settings the archived copies are built from, to tell whether they are still current
+    let mut settings_files = vec![fs::read_to_string("config.toml").unwrap_or_default()];
+    // Directories of the lists with lines skipped for an invalid URL, whose archives are
+    // kept until the line is fixed
+    let mut skipped_line_dirs = HashSet::new();
+    for list in &lists {
+        let feeds_content = fs::read_to_string(&list.source)?;
+        settings_files.push(feeds_content.clone());
+        let (specs, invalid_urls) = parse_feed_list(&feeds_content, cli.strict).map_err(|e| format!("{}: {}", list.source, e))?;
+        for invalid in &invalid_urls {
+            eprintln!("Warning: {}: {}, skipping the line", list.source, invalid);
+        }
+        if !invalid_urls.is_empty() {
+            skipped_line_dirs.insert(list.feed_dir());
+        }
+        let mut specs = coalesce_duplicate_specs(specs, &list.source);
+        for spec in &mut specs {
+            spec.format = spec.format.or(config.output_format);
+        }
+        list_specs.push(specs);
     }
-    
-    // Always preserve master.opml and .gitkeep
-    current_filenames.insert("master.opml".to_string());
-    current_filenames.insert(".gitkeep".to_string());
 
-    // Read directory and remove files not in current set
-    for entry in fs::read_dir(feeds_dir)? {
-        let entry = entry?;
-        let filename = entry.file_name().to_string_lossy().to_string();
-        
-        // Only remove XML files that aren't in our current set, and remove old master.xml
-        if (filename.ends_with(".xml") && !current_filenames.contains(&filename)) || filename == "master.xml" {
-            if let Err(e) = fs::remove_file(entry.path()) {
-                eprintln!("Warning: Could not remove old feed file {}: {}", filename, e);
-            } else {
-                println!("Removed old feed file: {}", filename);
+    // Feeds published elsewhere need a URL subscribers can reach
+    for (list, specs) in lists.iter().zip(&list_specs) {
+        for spec in specs {
+            if let Some(feed_base_url) = &spec.base_url {
+                if !is_absolute_http_url(feed_base_url) {
+                    return Err(format!("{}: {}: base_url {:?} is not an absolute http(s) URL", list.source, spec.url, feed_base_url).into());
+                }
             }
         }
     }
 
+    if let Some(hub) = config.websub_hub.as_deref().filter(|hub| !is_absolute_http_url(hub)) {
+        return Err(format!("config.toml: websub_hub {:?} is not an absolute http(s) URL", hub).into());
+    }
+
+    // Catch invalid keyword patterns before fetching anything
+    KeywordFilter::for_feed(config.filters.as_ref(), None, None).map_err(|e| format!("config.toml [filters]: {}", e))?;
+    for (list, specs) in lists.iter().zip(&list_specs) {
+        for spec in specs {
+            KeywordFilter::for_feed(config.filters.as_ref(), spec.include.as_deref(), spec.exclude.as_deref())
+                .map_err(|e| format!("{}: {}: {}", list.source, spec.url, e))?;
+            AuthorFilter::for_feed(config.filters.as_ref(), spec.include_authors.as_deref(), spec.exclude_authors.as_deref(), false)
+                .map_err(|e| format!("{}: {}: {}", list.source, spec.url, e))?;
+        }
+    }
+
+    // A feed listed several times, in one or several lists, is only downloaded once
+    let mut unique_urls: Vec<String> = Vec::new();
+    let mut seen_urls = HashSet::new();
+    for spec in list_specs.iter().flatten() {
+        if seen_urls.insert(spec.url.clone()) {
+            unique_urls.push(spec.url.clone());
+        }
+    }
+
+    // Guard against a broken import pulling in far more feeds than intended
+    if let Some(max_feeds) = config.max_feeds.filter(|&max_feeds| unique_urls.len() > max_feeds) {
+        if !cli.truncate {
+            return Err(format!(
+                "Found {} feeds but max_feeds is {}; raise max_feeds in config.toml, review {}, or pass --truncate to only process the first {}",
+                unique_urls.len(),
+                max_feeds,
+                lists.iter().map(|l| l.source.as_str()).collect::<Vec<_>>().join(", "),
+                max_feeds
+            )
+            .into());
+        }
+        eprintln!("Warning: Found {} feeds, only processing the first {} (max_feeds)", unique_urls.len(), max_feeds);
+        unique_urls.truncate(max_feeds);
+        let kept: HashSet<&str> = unique_urls.iter().map(String::as_str).collect();
+        for specs in &mut list_specs {
+            specs.retain(|spec| kept.contains(spec.url.as_str()));
+        }
+    }
+
+    if let Some(url) = cli.reprocess.as_ref().filter(|url| !unique_urls.contains(url)) {
+        return Err(format!(
+            "--reprocess: {} is not listed in {}",
+            url,
+            lists.iter().map(|l| l.source.as_str()).collect::<Vec<_>>().join(", ")
+        )
+        .into());
+    }
+
+    if unique_urls.is_empty() {
+        eprintln!("No feed URLs found in {}", lists.iter().map(|l| l.source.as_str()).collect::<Vec<_>>().join(", "));
+        return Ok(false);
+    }
+
+    // With --reprocess or --since-commit only the selected feeds are fetched and their files
+    // rewritten, every other feed keeping its archived copies. The OPML files still list all.
+    let mut selected_urls: Option<HashSet<String>> = cli.reprocess.as_ref().map(|url| HashSet::from([url.clone()]));
+    if let Some(commit) = &cli.since_commit {
+        let sources: Vec<String> = lists.iter().map(|list| list.source.clone()).collect();
+        match added_feed_urls(commit, &sources) {
+            Ok(added) => {
+                let added: HashSet<String> = added.into_iter().filter(|url| unique_urls.contains(url)).collect();
+                progress!(cli.json, "{} feeds were added to {} since {}", added.len(), sources.join(", "), commit);
+                selected_urls = Some(added);
+            }
+            Err(e) => eprintln!("Warning: --since-commit: Could not compare the feed lists with {} ({}), processing all feeds", commit, e),
+        }
+    }
+
+    // Read pass-through subscriptions from an external OPML file, skipping feeds we already archive.
+    // They are only merged into the first (primary) list.
+    let external_feeds = match &cli.merge_opml {
+        Some(path) => {
+            let opml = fs::read_to_string(path)?;
+            let archived_urls: HashSet<&str> = list_specs[0].iter().map(|spec| spec.url.as_str()).collect();
+            let mut seen_urls = HashSet::new();
+            let external: Vec<ExternalFeed> = parse_opml_feeds(&opml)?
+                .into_iter()
+                .filter(|feed| !archived_urls.contains(feed.xml_url.as_str()))
+                .filter(|feed| seen_urls.insert(feed.xml_url.clone()))
+                .collect();
+            progress!(cli.json, "Merging {} external feeds from {} into {}.opml", external.len(), path, lists[0].name);
+            external
+        }
+        None => Vec::new(),
+    };
+
+    // Load per-feed state persisted by previous runs
+    let mut state = FeedState::load(storage.as_ref(), STATE_PATH).await?;
+    if force_refresh {
+        progress!(cli.json, "Forcing a full refresh of all feeds");
+        state.item_languages.clear();
+    }
+    let mut feed_stats = FeedStats::load(storage.as_ref(), FEED_STATS_PATH).await?;
+    let mut stale_detector = StaleDetector::load(storage.as_ref(), LAST_ITEM_DATES_PATH).await;
+    let mut run_changes = RunChanges::default();
+    let now = Utc::now();
+    // Where the archived copies of the feeds are, listed when first needed
+    let archive = ArchivedFiles::new(storage.as_ref(), layout);
+
+    // Feeds fetched by an interrupted run are taken from their archived copies instead of
+    // being fetched again. --diff, --reprocess and --since-commit neither use nor update the resume file.
+    let resume_file = config.resume_file.as_deref().filter(|_| !cli.diff && selected_urls.is_none());
+    // An age too large to be represented means the resume file never gets too old
+    let resume_max_age = i64::try_from(config.resume_max_age_minutes.unwrap_or(60)).ok().and_then(chrono::TimeDelta::try_minutes);
+    let resumed = match resume_file.filter(|_| !force_refresh) {
+        Some(path) => ResumeState::load(storage.as_ref(), path, resume_max_age, now, cli.json).await,
+        None => None,
+    };
+    if let Some(resumed) = &resumed {
+        progress!(cli.json, "Resuming the run interrupted at {}, {} feeds were already fetched", resumed.updated_at.to_rfc3339(), resumed.fetched.len());
+    }
+    // Feeds archived so far in this run, including those resumed and those unchanged
+    let fetch_progress: Arc<Mutex<BTreeSet<String>>> = Arc::default();
+
+    // A feed serving the same response as last time keeps its archived copies, without
+    // being parsed or written again. Forced refreshes, --reprocess and --since-commit always process it.
+    let use_fingerprints = config.skip_unchanged.unwrap_or(true) && !force_refresh && !cli.diff && selected_urls.is_none();
+    let mut fingerprints = FingerprintStore::load(storage.as_ref(), FINGERPRINTS_PATH, &settings_hash(GENERATOR_VERSION, &settings_files)).await;
+    // The fingerprints the crawler compares responses with, and the archived copies kept
+    // when they match
+    let mut known_fingerprints = FingerprintStore::default();
+    let mut unchanged_copies: HashMap<String, Vec<FeedData>> = HashMap::new();
+
+    // Reuse the archived copies while the feed's own TTL/skip hints say it has nothing new.
+    // Cached copies are keyed by URL and slug, as each variant of a feed has its own file.
+    let mut fetched_feeds: HashMap<String, FeedData> = HashMap::new();
+    let mut cached_feeds: HashMap<(String, Option<String>), FeedData> = HashMap::new();
+    let mut specs_to_fetch = Vec::new();
+    for url in &unique_urls {
+        // Feeds that are not selected keep their archived copies. With --since-commit, a
+        // feed without them is processed as well, so that it stays listed.
+        if selected_urls.as_ref().is_some_and(|selected| !selected.contains(url)) {
+            let cached = load_cached_variants(&archive, layout, url, &lists, &list_specs, &state, parse_options).await;
+            if cli.since_commit.is_some() && cached.iter().any(Option::is_none) {
+                progress!(cli.json, "No archived copy of {} found, processing it as well", url);
+                if let Some(selected) = &mut selected_urls {
+                    selected.insert(url.clone());
+                }
+            } else {
+                debug!(cli.verbose, "Keeping the archived copies of {}, which is not being reprocessed", url);
+                for feed_data in cached.into_iter().flatten() {
+                    cached_feeds.entry((url.clone(), feed_data.slug.clone())).or_insert(feed_data);
+                }
+                continue;
+            }
+        }
+        let reprocessing = selected_urls.is_some();
+
+        if resumed.as_ref().is_some_and(|resumed| resumed.fetched.contains(url)) {
+            // Only skip the fetch if every variant of the feed has been archived before
+            let cached: Option<Vec<FeedData>> = load_cached_variants(&archive, layout, url, &lists, &list_specs, &state, parse_options)
+                .await
+                .into_iter()
+                .collect();
+            if let Some(cached) = cached {
+                debug!(cli.verbose, "Skipping {}: fetched by the interrupted run", url);
+                for feed_data in cached {
+                    cached_feeds.entry((url.clone(), feed_data.slug.clone())).or_insert(feed_data);
+                }
+                fetch_progress.lock().unwrap().insert(url.clone());
+                continue;
+            }
+        }
+
+        // Quarantined feeds are only retried once in a while, keeping whatever was archived
+        if !cli.retry_quarantined && !force_refresh && !reprocessing {
+            if let Some(reason) = state.feeds.get(url).and_then(|status| status.quarantine_skip_reason(now, &quarantine)) {
+                progress!(cli.json, "Skipping {}: {}", url, reason);
+                for feed_data in load_cached_variants(&archive, layout, url, &lists, &list_specs, &state, parse_options).await.into_iter().flatten() {
+                    cached_feeds.entry((url.clone(), feed_data.slug.clone())).or_insert(feed_data);
+                }
+                continue;
+            }
+        }
+        // A feed is skipped while its hints, its last response's cache headers or its polling
+        // interval say so, so the longest of the waits applies
+        let interval_secs = list_specs
+            .iter()
+            .flatten()
+            .filter(|spec| &spec.url == url)
+            .find_map(|spec| spec.interval_secs)
+            .or(config.feed_interval_secs)
+            .filter(|&secs| secs > 0 && !force_refresh);
+        let skip_reason = state.feeds.get(url).and_then(|status| {
+            let hint = status.skip_reason(now).filter(|_| respect_feed_hints);
+            hint.or_else(|| status.freshness_skip_reason(now).filter(|_| !force_refresh))
+                .or_else(|| interval_secs.and_then(|secs| status.interval_skip_reason(url, secs, now)))
+        });
+        if !reprocessing {
+            if let Some(reason) = skip_reason {
+                // Only skip the fetch if every variant of the feed has been archived before
+                let cached: Option<Vec<FeedData>> = load_cached_variants(&archive, layout, url, &lists, &list_specs, &state, parse_options)
+                    .await
+                    .into_iter()
+                    .collect();
+                match cached {
+                    Some(cached) => {
+                        progress!(cli.json, "Skipping {}: {}", url, reason);
+                        for feed_data in cached {
+                            cached_feeds.entry((url.clone(), feed_data.slug.clone())).or_insert(feed_data);
+                        }
+                        continue;
+                    }
+                    None => progress!(cli.json, "No archived copy of {} found, fetching despite: {}", url, reason),
+                }
+            }
+        }
+        // Only feeds with an archived copy of every variant can be left as they are. Members
+        // of combined feeds have no archive of their own, so they are always processed.
+        let combined = list_specs.iter().flatten().any(|spec| &spec.url == url && spec.combined.is_some());
+        if let Some(fingerprint) = fingerprints.feeds.get(url).filter(|_| use_fingerprints && !combined) {
+            let cached: Option<Vec<FeedData>> = load_cached_variants(&archive, layout, url, &lists, &list_specs, &state, parse_options)
+                .await
+                .into_iter()
+                .collect();
+            if let Some(cached) = cached.filter(|cached| !cached.is_empty()) {
+                known_fingerprints.feeds.insert(url.clone(), fingerprint.clone());
+                unchanged_copies.insert(url.clone(), cached);
+            }
+        }
+        // Fetch options are set per URL; with several lines for a URL the first one setting them wins
+        let pseudo_spec = list_specs.iter().flatten().find(|spec| &spec.url == url && spec.pseudo_feed.is_some());
+        specs_to_fetch.push(FeedSpec {
+            url: url.clone(),
+            accept: list_specs.iter().flatten().filter(|spec| &spec.url == url).find_map(|spec| spec.accept.clone()),
+            follow_redirects: list_specs.iter().flatten().filter(|spec| &spec.url == url).find_map(|spec| spec.follow_redirects),
+            include: list_specs.iter().flatten().filter(|spec| &spec.url == url).find_map(|spec| spec.include.clone()),
+            exclude: list_specs.iter().flatten().filter(|spec| &spec.url == url).find_map(|spec| spec.exclude.clone()),
+            feed_type: list_specs.iter().flatten().filter(|spec| &spec.url == url).find_map(|spec| spec.feed_type),
+            pseudo_feed: pseudo_spec.and_then(|spec| spec.pseudo_feed),
+            sitemap_entries: pseudo_spec.and_then(|spec| spec.sitemap_entries),
+            scrape_selectors: pseudo_spec.map(|spec| spec.scrape_selectors.clone()).unwrap_or_default(),
+            include_authors: list_specs.iter().flatten().filter(|spec| &spec.url == url).find_map(|spec| spec.include_authors.clone()),
+            exclude_authors: list_specs.iter().flatten().filter(|spec| &spec.url == url).find_map(|spec| spec.exclude_authors.clone()),
+            drop_unattributed: list_specs.iter().flatten().filter(|spec| &spec.url == url).find_map(|spec| spec.drop_unattributed),
+            ..Default::default()
+        });
+    }
+
+    // The entries of sitemaps are titled after their page once
+    let sitemap_titles: HashMap<String, HashMap<String, String>> = list_specs
+        .iter()
+        .flatten()
+        .filter(|spec| spec.pseudo_feed == Some(PseudoFeedKind::Sitemap))
+        .filter_map(|spec| Some((spec.url.clone(), state.item_history.get(&spec.url)?.titles())))
+        .collect();
+
+    // Concurrently fetch and parse feeds, dropping items cross-posted to several feeds
+    let merge_duplicates = cli.fix_duplicates || config.merge_duplicates.unwrap_or(false);
+    let mut crawler = FeedCrawler::new()
+        .with_config(Config {
+            merge_duplicates: Some(merge_duplicates),
+            ..config.clone()
+        })
+        .with_client(client)
+        .with_dedup_cache(std::mem::take(&mut state.dedup))
+        .with_removed_items(std::mem::take(&mut state.removed_items))
+        .with_clamped_dates(std::mem::take(&mut state.clamped_dates))
+        .with_fingerprints(known_fingerprints)
+        .with_known_titles(sitemap_titles)
+        .with_shutdown(shutdown)
+        .with_progress(Arc::clone(&fetch_progress))
+        .with_verbose(cli.verbose)
+        .with_progress_to_stderr(cli.json);
+
+    // How often the archive changes, for readers polling it: the configured schedule or, as
+    // a daemon, its own interval. Without either, no update hints are announced.
+    let update_interval_minutes = config
+        .update_interval_minutes
+        .or_else(|| cli.daemon.then(|| config.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS).div_ceil(60)));
+
+    // Files written by earlier runs, the only ones that can already be published, and the
+    // URLs they are served from, checked once the run is done
+    let mut published_files = HashSet::new();
+    let mut published_urls = Vec::new();
+    if cli.check_published {
+        let mut dirs: Vec<String> = std::iter::once("feeds".to_string()).chain(lists.iter().map(FeedList::feed_dir)).collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            match list_archived_files(storage.as_ref(), &dir).await {
+                Ok(names) => published_files.extend(names.into_iter().map(|name| format!("{}/{}", dir, name))),
+                Err(e) => eprintln!("Warning: Could not list {}: {}", dir, e),
+            }
+        }
+    }
+
+    // How the items of combined feeds name their member feed
+    let source_annotation = SourceAnnotation::new(config.source_annotation.as_ref());
+
+    // Individual feed files are written as soon as their feed is fetched - one unique file
+    // per feed URL and list directory
+    let render_options = RenderOptions {
+        content_addressed: config.content_addressed.unwrap_or(false),
+        self_links: config.self_links.unwrap_or(false),
+        websub_hub: config.websub_hub.clone(),
+        channel_categories: config.channel_categories.unwrap_or(5),
+        comment_counts: config.comment_counts.unwrap_or(false),
+        dc_dates: config.dc_dates.unwrap_or(false),
+        update_interval_minutes,
+        copyright_rules: CopyrightRules::new(config.copyright_rules.as_ref(), config.default_copyright_template.as_deref()),
+        stylesheet_href: config.stylesheet_href.clone().filter(|href| !href.trim().is_empty()),
+    };
+    let stored_content = if render_options.content_addressed {
+        stored_hashes(storage.as_ref()).await.unwrap_or_else(|e| {
+            eprintln!("Warning: Could not list stored item content: {}", e);
+            HashSet::new()
+        })
+    } else {
+        HashSet::new()
+    };
+    // Reprocessing a single feed leaves the report of the last full run as it is, and so
+    // the index of the other feeds' items
+    let changes_report = config.changes_report.unwrap_or(false) && selected_urls.is_none();
+    let search_index = config.search_index.unwrap_or(false) && selected_urls.is_none();
+    let mut writer = FeedWriter {
+        storage: storage.as_ref(),
+        archive: &archive,
+        layout,
+        parse_options,
+        file_limits,
+        base_url: &base_url,
+        repo_name: &repo_name,
+        targets: &output_targets,
+        render_options,
+        stored_content,
+        changes_report,
+        changes_report_titles: config.changes_report_titles.unwrap_or(false),
+        search_index_max_items: search_index.then(|| config.search_index_max_items.unwrap_or(5000)),
+        published_files,
+        written_files: HashSet::new(),
+        archived_files: BTreeSet::new(),
+        output_files: Vec::new(),
+        published_urls: Vec::new(),
+        feed_diffs: BTreeMap::new(),
+        search_entries: BTreeMap::new(),
+        failed_urls: HashSet::new(),
+        verbose: cli.verbose,
+        progress_to_stderr: cli.json,
+    };
+
+    // A fetched feed is archived right away, while other feeds are still being fetched, and
+    // its items dropped. Feeds listed under several URLs can only be merged once all are
+    // fetched, and nothing is written with --diff, so then every feed waits for the others.
+    // So do the members of combined feeds.
+    let write_while_fetching = !merge_duplicates && !cli.diff;
+    let stable_item_order = config.stable_item_order.unwrap_or(false);
+    let generate_rejects_feed = config.generate_rejects_feed.unwrap_or(false);
+    let mut preparation = FeedPreparation::new(&config, &list_specs, cli.verbose, cli.json)?;
+    let combined_urls: HashSet<&str> = list_specs.iter().flatten().filter(|spec| spec.combined.is_some()).map(|spec| spec.url.as_str()).collect();
+    // The titles of the listed feeds as known so far, those of previous runs until fetched
+    let mut listed_titles = ListedTitles::default();
+    for specs in &list_specs {
+        let mut combined_names = HashSet::new();
+        for spec in specs {
+            match &spec.combined {
+                Some(name) if combined_names.insert(name.as_str()) => listed_titles.insert(&spec.url, name),
+                Some(_) => {}
+                None => {
+                    if let Some(status) = state.feeds.get(&spec.url).filter(|status| !status.title.is_empty()) {
+                        listed_titles.insert(&spec.url, &status.title);
+                    }
+                }
+            }
+        }
+    }
+    // Fetched feeds waiting for all the others
+    let mut fetched: Vec<FeedData> = Vec::new();
+    // Feeds written while fetching, without their items, the guids of their items and the
+    // copies written, by list and position in it, reduced to what the OPML files need
+    let mut written_early: HashMap<String, FeedData> = HashMap::new();
+    let mut written_early_guids: HashSet<String> = HashSet::new();
+    let mut written_copies: HashMap<(usize, usize), FeedData> = HashMap::new();
+    let mut republished_collapsed = 0;
+    // Items dropped by filters, for the feed of rejected items
+    let mut rejected_items = Vec::new();
+    let (ready_sender, mut ready) = mpsc::channel(READY_FEEDS_BUFFER);
+    let (crawl, ()) = tokio::join!(
+        crawl_with_progress_dumps(&mut crawler, &specs_to_fetch, storage.as_ref(), resume_file, &fetch_progress, ready_sender, cli.json),
+        async {
+            while let Some(mut feed_data) = ready.recv().await {
+                stale_detector.record(&feed_data, now);
+                if !write_while_fetching || combined_urls.contains(feed_data.url.as_str()) {
+                    fetched.push(feed_data);
+                    continue;
+                }
+                republished_collapsed += preparation.prepare(&mut feed_data, &mut state, &mut feed_stats, &mut run_changes, now);
+                if generate_rejects_feed {
+                    record_filtered_items(&feed_data, &list_specs, &mut rejected_items);
+                }
+                let previous_title = state.feeds.get(&feed_data.url).map(|status| status.title.as_str());
+                listed_titles.retitle(&feed_data.url, previous_title, &feed_data.title);
+                for (list_index, (list, specs)) in lists.iter().zip(&list_specs).enumerate() {
+                    for (position, spec) in specs.iter().enumerate().filter(|(_, spec)| spec.url == feed_data.url) {
+                        let mut variant = fetched_variant(&feed_data, spec);
+                        variant.display_title = listed_titles.display_title(&variant);
+                        if stable_item_order {
+                            sort_items_stably(&mut variant.items);
+                        }
+                        writer.write(&list.feed_dir(), &variant, false).await;
+                        written_copies.insert((list_index, position), newest_item_only(variant));
+                    }
+                }
+                // A feed is only skipped by the next run once its new items are archived
+                if !writer.failed_urls.contains(&feed_data.url) {
+                    fetch_progress.lock().unwrap().insert(feed_data.url.clone());
+                }
+                written_early_guids.extend(feed_data.items.drain(..).map(|item| item.guid));
+                written_early.insert(feed_data.url.clone(), feed_data);
+            }
+        }
+    );
+    state.removed_items = crawler.take_removed_items();
+    state.clamped_dates = crawler.take_clamped_dates();
+    rejected_items.extend(crawler.take_rejected_items());
+    state.dedup = crawler.into_dedup_cache().unwrap_or_default();
+
+    for (spec, e) in &crawl.failures {
+        eprintln!("Error fetching feed {}: {}", spec.url, e);
+        let status = state.feeds.entry(spec.url.clone()).or_default();
+        let title = if status.title.is_empty() { spec.url.clone() } else { status.title.clone() };
+        run_changes.failed_feeds.insert(title, e.to_string());
+        let was_quarantined = status.quarantined_since.is_some();
+        status.record_failure(now, &quarantine);
+        if status.quarantined_since.is_some() && !was_quarantined {
+            eprintln!(
+                "Warning: Quarantined {} after {} consecutive failures, retrying it every {} hours",
+                spec.url, status.consecutive_failures, quarantine.retry_hours
+            );
+        }
+        // A failed fetch keeps the feed listed with its archived copies
+        for feed_data in load_cached_variants(&archive, layout, &spec.url, &lists, &list_specs, &state, parse_options).await.into_iter().flatten() {
+            cached_feeds.entry((spec.url.clone(), feed_data.slug.clone())).or_insert(feed_data);
+        }
+    }
+    // Unchanged feeds are recorded as fetched, their archived copies staying as they are
+    let mut unchanged_urls = HashSet::new();
+    for spec in &crawl.unchanged {
+        debug!(cli.verbose, "{} is unchanged, keeping its archived copies", spec.url);
+        for feed_data in unchanged_copies.remove(&spec.url).unwrap_or_default() {
+            cached_feeds.entry((spec.url.clone(), feed_data.slug.clone())).or_insert(feed_data);
+        }
+        state.record_unchanged(&spec.url, crawl.freshness.get(&spec.url).copied(), now);
+        if let Some(history) = state.item_history.get_mut(&spec.url) {
+            history.mark_unchanged(now);
+        }
+        if let Some(timing) = crawl.timings.get(&spec.url) {
+            state.record_timing(&spec.url, timing, now);
+        }
+        unchanged_urls.insert(spec.url.clone());
+    }
+    fingerprints.feeds.extend(crawl.fingerprints.clone());
+    fingerprints.feeds.retain(|url, _| unique_urls.contains(url));
+    progress!(
+        cli.json,
+        "Fetched {}/{} feeds in {:.1}s ({} unchanged, {} failed, {} items, {} duplicates removed, {} excluded by link domain, {} by keyword, {} by author, {} blocked)",
+        crawl.stats.succeeded,
+        crawl.stats.attempted,
+        crawl.stats.elapsed.as_secs_f64(),
+        crawl.stats.unchanged,
+        crawl.stats.failed,
+        crawl.stats.items,
+        crawl.stats.duplicates_removed,
+        crawl.stats.excluded_by_link_domain,
+        crawl.stats.excluded_by_keyword,
+        crawl.stats.excluded_by_author,
+        crawl.stats.excluded_by_blocklist
+    );
+    if !written_early.is_empty() {
+        progress!(cli.json, "Archived {} feeds while fetching the others", written_early.len());
+    }
+    let mut slowest_feeds: Vec<SlowFeed> = crawl
+        .timings
+        .iter()
+        .map(|(url, timing)| SlowFeed {
+            url: url.clone(),
+            fetch_ms: timing.fetch.as_millis() as u64,
+            parse_ms: timing.parse.as_millis() as u64,
+        })
+        .collect();
+    slowest_feeds.sort_by(|a, b| (b.fetch_ms + b.parse_ms).cmp(&(a.fetch_ms + a.parse_ms)).then_with(|| a.url.cmp(&b.url)));
+    slowest_feeds.truncate(SLOWEST_FEEDS_LISTED);
+    if !slowest_feeds.is_empty() {
+        progress!(cli.json, "Slowest feeds:");
+        for feed in &slowest_feeds {
+            progress!(cli.json, "  {:>6} ms  {} (fetch {} ms, parse {} ms)", feed.fetch_ms + feed.parse_ms, feed.url, feed.fetch_ms, feed.parse_ms);
+        }
+    }
+
+    // Feeds skipped by an interruption keep their archived copies, so that they stay
+    // listed and are not removed by the cleanup below
+    if !crawl.interrupted.is_empty() {
+        progress!(cli.json, "{} feeds were not fetched due to the interruption, keeping their archived copies", crawl.stats.interrupted);
+        for spec in &crawl.interrupted {
+            for feed_data in load_cached_variants(&archive, layout, &spec.url, &lists, &list_specs, &state, parse_options).await.into_iter().flatten() {
+                cached_feeds.entry((spec.url.clone(), feed_data.slug.clone())).or_insert(feed_data);
+            }
+        }
+    }
+
+    // A feed listed under several URLs is archived once under its canonical URL when
+    // merging duplicates, and archived under each of them with a warning otherwise
+    for duplicate in &crawl.duplicates {
+        let listed_as = |url: &str| {
+            lists
+                .iter()
+                .zip(&list_specs)
+                .filter(|(_, specs)| specs.iter().any(|spec| spec.url == url))
+                .map(|(list, _)| format!("{}: {}", list.source, url))
+                .collect::<Vec<_>>()
+        };
+        let mut sources = listed_as(&duplicate.canonical);
+        for url in &duplicate.duplicates {
+            sources.extend(listed_as(url));
+        }
+        if !merge_duplicates {
+            eprintln!(
+                "Warning: {} are the same feed ({}) and are archived separately; pass --fix-duplicates or set merge_duplicates = true to archive it once as {}",
+                sources.join(" and "),
+                duplicate.reason,
+                duplicate.canonical
+            );
+            continue;
+        }
+        eprintln!(
+            "Warning: {} are the same feed ({}), archiving it once as {}; remove the other lines from the feed list",
+            sources.join(" and "),
+            duplicate.reason,
+            duplicate.canonical
+        );
+        // An unchanged canonical feed is known by its archived copies
+        let canonical = fetched
+            .iter()
+            .find(|feed| feed.url == duplicate.canonical)
+            .or_else(|| cached_feeds.iter().find(|((url, _), _)| *url == duplicate.canonical).map(|(_, feed)| feed));
+        let Some(canonical) = canonical else {
+            continue;
+        };
+        for url in &duplicate.duplicates {
+            if let Some(previous_title) = state.feeds.get(url).map(|status| status.title.clone()) {
+                for (from, to) in move_duplicate_archives(&archive, layout, &lists, &list_specs, url, &previous_title, canonical).await {
+                    progress!(cli.json, "Moved {} to {}", from, to);
+                }
+            }
+            for specs in &mut list_specs {
+                *specs = redirect_specs(std::mem::take(specs), url, &duplicate.canonical);
+            }
+            state.merge_feed(url, &duplicate.canonical);
+            fingerprints.feeds.remove(url);
+            if let Some(activity) = feed_stats.feeds.remove(url) {
+                feed_stats.feeds.entry(duplicate.canonical.clone()).or_insert(activity);
+            }
+        }
+    }
+
+    // Merging moved the feed list lines of duplicates, and with them their options, to the canonical URLs
+    if merge_duplicates {
+        preparation = FeedPreparation::new(&config, &list_specs, cli.verbose, cli.json)?;
+    }
+    for mut feed_data in fetched {
+        republished_collapsed += preparation.prepare(&mut feed_data, &mut state, &mut feed_stats, &mut run_changes, now);
+        if generate_rejects_feed {
+            record_filtered_items(&feed_data, &list_specs, &mut rejected_items);
+        }
+        fetched_feeds.insert(feed_data.url.clone(), feed_data);
+    }
+    for spec in &specs_to_fetch {
+        let Some(feed_data) = fetched_feeds.get(&spec.url).or_else(|| written_early.get(&spec.url)) else {
+            continue;
+        };
+        if state.feeds.get(&feed_data.url).is_some_and(|status| status.quarantined_since.is_some()) {
+            progress!(cli.json, "{} is reachable again, lifting its quarantine", feed_data.url);
+        }
+        for change in state.record_fetch(feed_data, crawl.freshness.get(&feed_data.url).copied(), now) {
+            progress!(cli.json, "{}", change);
+        }
+        if let Some(timing) = crawl.timings.get(&feed_data.url) {
+            state.record_timing(&feed_data.url, timing, now);
+        }
+    }
+
+    // Resolve the feeds of every list in the order of its source file. Each entry gets its
+    // own copy of the fetched feed, with the slug and filters of its feed list line applied.
+    // The members of a combined feed are merged where the combined feed is first listed.
+    let mut list_feeds: Vec<Vec<FeedData>> = Vec::new();
+    // The entries written while fetching: their list, position in it and feed list line,
+    // and the title they were written with
+    let mut written_entries = Vec::new();
+    // Files of the feeds built from the selected URLs, the only ones rewritten then
+    let mut reprocessed_files = HashSet::new();
+    for (list_index, (list, specs)) in lists.iter().zip(&list_specs).enumerate() {
+        let mut feeds = Vec::new();
+        let mut combined_names = HashSet::new();
+        // Positions of the combined feeds in feeds
+        let mut combined_feeds = Vec::new();
+        for (position, spec) in specs.iter().enumerate() {
+            let written = written_copies.remove(&(list_index, position));
+            if let Some(written) = &written {
+                written_entries.push((list_index, feeds.len(), position, written.display_title.clone()));
+            }
+            let feed_data = match &spec.combined {
+                None => written.or_else(|| feed_variant(spec, &fetched_feeds, &cached_feeds)),
+                Some(name) if combined_names.insert(name.as_str()) => {
+                    let members: Vec<&FeedSpec> = specs.iter().filter(|member| member.combined.as_ref() == Some(name)).collect();
+                    let archived = match members.first() {
+                        Some(first) if members.iter().any(|member| !fetched_feeds.contains_key(&member.url)) => {
+                            load_cached_feed(&archive, layout, list, first, &state, parse_options).await
+                        }
+                        _ => None,
+                    };
+                    let combined = combine_feeds(&members, &fetched_feeds, archived, source_annotation, max_items);
+                    combined_feeds.extend(combined.as_ref().map(|_| feeds.len()));
+                    combined
+                }
+                Some(_) => None,
+            };
+            if let (Some(selected), Some(feed_data)) = (&selected_urls, &feed_data) {
+                let uses_url = match &spec.combined {
+                    None => selected.contains(&spec.url),
+                    Some(name) => specs.iter().any(|member| member.combined.as_ref() == Some(name) && selected.contains(&member.url)),
+                };
+                if uses_url {
+                    reprocessed_files.insert(format!("{}/{}", list.feed_dir(), layout.feed_filename(feed_data)));
+                }
+            }
+            feeds.extend(feed_data);
+        }
+        if source_annotation.source_element {
+            let feed_dir = list.feed_dir();
+            link_sources_to_archives(&mut feeds, &combined_feeds, &ArchiveLinks { layout, base_url: &base_url, feed_dir: &feed_dir });
+        }
+        list_feeds.push(feeds);
+    }
+
+    disambiguate_titles(&mut list_feeds);
+
+    // A feed written while fetching is written again if a feed fetched later turned out to
+    // share its title, or to be listed before it with some of its items
+    for (list_index, index, position, written_title) in written_entries {
+        let written = &list_feeds[list_index][index];
+        let given_up = crawl.given_up.get(&written.url);
+        if written.display_title == written_title && given_up.is_none() {
+            continue;
+        }
+        let list = &lists[list_index];
+        let written_path = format!("{}/{}", list.feed_dir(), layout.feed_filename(written));
+        let Some(mut feed_data) = load_cached_feed(&archive, layout, list, &list_specs[list_index][position], &state, parse_options).await else {
+            eprintln!("Warning: Could not read {} back to write it again", written_path);
+            continue;
+        };
+        if let Some(guids) = given_up {
+            feed_data.items.retain(|item| !guids.contains(&item.guid));
+        }
+        feed_data.display_title = written.display_title.clone();
+        if selected_urls.is_some() {
+            reprocessed_files.insert(format!("{}/{}", list.feed_dir(), layout.feed_filename(&feed_data)));
+        }
+        writer.forget(&written_path);
+        list_feeds[list_index][index] = feed_data;
+    }
+
+    // Feeds reorder their items now and then; a fixed order keeps the archive diffs small
+    if stable_item_order {
+        for feed_data in list_feeds.iter_mut().flatten() {
+            sort_items_stably(&mut feed_data.items);
+        }
+    }
+
+    if cli.diff {
+        print_feed_diffs(&writer, &lists, &list_feeds, cli.json).await?;
+        return Ok(false);
+    }
+
+    // The items dropped by filters go next to the feeds of the first list, unless a listed
+    // feed already has the file
+    let rejects = match (generate_rejects_feed, lists.first()) {
+        (true, Some(list)) => {
+            let feed_dir = list.feed_dir();
+            let feed_data = build_rejects_feed(&archive, &feed_dir, &base_url, rejected_items).await;
+            let filename = layout.feed_filename(&feed_data);
+            let taken = lists
+                .iter()
+                .zip(&list_feeds)
+                .filter(|(other, _)| other.feed_dir() == feed_dir)
+                .flat_map(|(_, feeds)| feeds.iter())
+                .any(|feed| layout.feed_filename(feed) == filename);
+            if taken {
+                eprintln!("Warning: Not writing the rejected items to {}/{}, which is the file of a listed feed", feed_dir, filename);
+                None
+            } else {
+                Some(feed_data)
+            }
+        }
+        _ => None,
+    };
+    let rejects_feed_in_opml = config.rejects_feed_in_opml.unwrap_or(false);
+
+    // Quarantined feeds keep their archived files but are marked in (or left out of) the OPML files
+    let quarantined: HashSet<String> = state
+        .feeds
+        .iter()
+        .filter(|(_, status)| status.quarantined_since.is_some())
+        .map(|(url, _)| url.clone())
+        .collect();
+    if !quarantined.is_empty() {
+        progress!(cli.json, "{} feeds are quarantined, run `stats` for details", quarantined.len());
+    }
+    let exclude_quarantined = config.exclude_quarantined.unwrap_or(false);
+
+    // Every file written, with its size, for the run statistics
+    let mut output_files = Vec::new();
+    for (index, (list, feeds)) in lists.iter().zip(&list_feeds).enumerate() {
+        let feed_dir = list.feed_dir();
+
+        // Generate OPML feed list instead of master RSS feed
+        let external = if index == 0 { external_feeds.as_slice() } else { &[] };
+        let mut listed_feeds: Vec<FeedData> = feeds
+            .iter()
+            .filter(|feed| !(exclude_quarantined && quarantined.contains(&feed.url)))
+            .cloned()
+            .collect();
+        if index == 0 && rejects_feed_in_opml {
+            listed_feeds.extend(rejects.clone());
+        }
+        // The OPML file is written as it is built, so that large lists are never held in memory
+        let opml_path = format!("feeds/{}.opml", list.name);
+        let opml_content = |writer: &mut dyn Write| {
+            let links = ArchiveLinks { layout, base_url: &base_url, feed_dir: &feed_dir };
+            build_opml_feed_list(&listed_feeds, external, &links, &quarantined, update_interval_minutes, writer)
+        };
+        // A malformed OPML file would break every reader importing it, so it never replaces the last good one
+        let opml_bytes = match write_and_validate_opml(storage.as_ref(), &opml_path, &opml_content).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Error writing OPML file {}: {}", opml_path, e);
+                return Err(e.into());
+            }
+        };
+        output_files.push(OutputFile { path: opml_path.clone(), bytes: opml_bytes });
+        if writer.published_files.contains(&opml_path) {
+            published_urls.push(format!("{}/{}", base_url, opml_path));
+        }
+        println!("OPML feed list {} generated with {} feeds", opml_path, listed_feeds.len());
+
+        // The copies link to the target's own files
+        for feed in &mut listed_feeds {
+            feed.base_url = None;
+        }
+        for target in &output_targets {
+            let target_opml_path = target.path(&opml_path);
+            let opml_content = |writer: &mut dyn Write| {
+                let links = ArchiveLinks { layout, base_url: &target.base_url, feed_dir: &feed_dir };
+                build_opml_feed_list(&listed_feeds, external, &links, &quarantined, update_interval_minutes, writer)
+            };
+            match write_and_validate_opml(storage.as_ref(), &target_opml_path, &opml_content).await {
+                Ok(bytes) => output_files.push(OutputFile { path: target_opml_path, bytes }),
+                Err(e) => eprintln!("Error writing OPML file {}: {}", target_opml_path, e),
+            }
+        }
+    }
+
+    // Remove the old master.xml file if it exists
+    let master_xml_path = "feeds/master.xml";
+    if storage.list("feeds").await?.iter().any(|name| name == "master.xml") {
+        if let Err(e) = storage.remove(master_xml_path).await {
+            eprintln!("Warning: Could not remove old master.xml: {}", e);
+        } else {
+            println!("Removed old master.xml file");
+        }
+    }
+
+    // Clean up old individual feed files. Lists sharing a directory are cleaned up together
+    // so that one list never removes the files of another. Reprocessing a single feed
+    // leaves all other files alone, even if their archived copy could not be read.
+    let mut cleaned_dirs = HashSet::new();
+    for list in lists.iter().filter(|_| selected_urls.is_none()) {
+        let feed_dir = list.feed_dir();
+        if !cleaned_dirs.insert(feed_dir.clone()) {
+            continue;
+        }
+        // The file of a skipped line can't be told from that of a removed feed
+        if skipped_line_dirs.contains(&feed_dir) {
+            eprintln!("Warning: Not cleaning up {}, as lines of its feed lists were skipped for an invalid URL", feed_dir);
+            continue;
+        }
+        let dir_feeds: Vec<&FeedData> = lists
+            .iter()
+            .zip(&list_feeds)
+            .filter(|(other, _)| other.feed_dir() == feed_dir)
+            .flat_map(|(_, feeds)| feeds.iter())
+            .chain(rejects.iter().filter(|_| feed_dir == lists[0].feed_dir()))
+            .collect();
+        for filename in cleanup_old_feeds(storage.as_ref(), layout, &feed_dir, &dir_feeds).await? {
+            run_changes.removed_files.insert(format!("{}/{}", feed_dir, filename));
+        }
+        for target in &output_targets {
+            let target_dir = target.path(&feed_dir);
+            if let Err(e) = cleanup_old_feeds(storage.as_ref(), layout, &target_dir, &dir_feeds).await {
+                eprintln!("Warning: Could not clean up {}: {}", target_dir, e);
+            }
+        }
+    }
+
+    // Generate the individual feed files not written while fetching
+    for (list, feeds) in lists.iter().zip(&list_feeds) {
+        let feed_dir = list.feed_dir();
+        for feed_data in feeds {
+            if selected_urls.is_some() && !reprocessed_files.contains(&format!("{}/{}", feed_dir, layout.feed_filename(feed_data))) {
+                continue;
+            }
+            // The archived copy of an unchanged feed is what would be written
+            writer.write(&feed_dir, feed_data, unchanged_urls.contains(&feed_data.url)).await;
+        }
+    }
+    {
+        let mut archived = fetch_progress.lock().unwrap();
+        for feed_data in list_feeds.iter().flatten().filter(|feed_data| !writer.failed_urls.contains(&feed_data.url)) {
+            archived.insert(feed_data.url.clone());
+        }
+    }
+    if let Some(rejects) = &rejects {
+        writer.write_rejects(&lists[0].feed_dir(), rejects).await;
+    }
+    // Processed again next run, even if the feed doesn't change
+    for url in &writer.failed_urls {
+        fingerprints.feeds.remove(url);
+    }
+
+    if changes_report {
+        let changes_report_titles = writer.changes_report_titles;
+        let feed_changes: Vec<FeedChanges> = std::mem::take(&mut writer.feed_diffs)
+            .into_iter()
+            .filter(|(_, (_, diff))| !diff.is_empty())
+            .map(|(path, (title, diff))| FeedChanges::new(path, title, diff, changes_report_titles))
+            .collect();
+        for changes in &feed_changes {
+            progress!(cli.json, "{}: {} added, {} removed, {} modified", changes.path, changes.added, changes.removed, changes.modified);
+        }
+        progress!(cli.json, "{} of {} archived feeds changed since the last run", feed_changes.len(), writer.archived_files.len());
+        if let Err(e) = storage.write(CHANGES_REPORT_PATH, serde_json::to_string_pretty(&feed_changes)?.as_bytes()).await {
+            eprintln!("Warning: Could not write changes report {}: {}", CHANGES_REPORT_PATH, e);
+        }
+    }
+
+    if let Some(max_items) = writer.search_index_max_items {
+        let entries = merge_search_entries(std::mem::take(&mut writer.search_entries).into_values().flatten().collect(), max_items);
+        match write_search_index(storage.as_ref(), &entries).await {
+            Ok(true) => progress!(cli.json, "Wrote search index {} with {} items", SEARCH_INDEX_PATH, entries.len()),
+            Ok(false) => debug!(cli.verbose, "Search index {} is unchanged", SEARCH_INDEX_PATH),
+            Err(e) => eprintln!("Warning: Could not write search index {}: {}", SEARCH_INDEX_PATH, e),
+        }
+    }
+    output_files.append(&mut writer.output_files);
+    published_urls.append(&mut writer.published_urls);
+
+    if config.commit_message.unwrap_or(false) {
+        if let Err(e) = storage.write(COMMIT_MESSAGE_PATH, run_changes.commit_message().as_bytes()).await {
+            eprintln!("Warning: Could not write commit message {}: {}", COMMIT_MESSAGE_PATH, e);
+        }
+    }
+
+    // Only keep detection results for items that are still around
+    let current_guids: HashSet<&str> = fetched_feeds
+        .values()
+        .chain(list_feeds.iter().flatten())
+        .flat_map(|feed| feed.items.iter().map(|item| item.guid.as_str()))
+        .chain(written_early_guids.iter().map(String::as_str))
+        .collect();
+    state.item_languages.retain(|guid, _| current_guids.contains(guid.as_str()));
+
+    if let Err(e) = state.save(storage.as_ref(), STATE_PATH).await {
+        eprintln!("Warning: Could not write feed state {}: {}", STATE_PATH, e);
+    }
+
+    if let Err(e) = fingerprints.save(storage.as_ref(), FINGERPRINTS_PATH).await {
+        eprintln!("Warning: Could not write feed fingerprints {}: {}", FINGERPRINTS_PATH, e);
+    }
+
+    if let Err(e) = feed_stats.save(storage.as_ref(), FEED_STATS_PATH).await {
+        eprintln!("Warning: Could not write feed statistics {}: {}", FEED_STATS_PATH, e);
+    }
+
+    // A feed whose newest item got older than the threshold may have stopped updating
+    let stale_feed_threshold_days = config.stale_feed_threshold_days.unwrap_or(30);
+    if stale_feed_threshold_days > 0 {
+        let listed_urls: HashSet<&str> = list_specs.iter().flatten().map(|spec| spec.url.as_str()).collect();
+        // A threshold beyond the dates chrono handles is never reached
+        let threshold = i64::try_from(stale_feed_threshold_days).ok().and_then(chrono::TimeDelta::try_days).unwrap_or(chrono::TimeDelta::MAX);
+        for stale in stale_detector.check(&listed_urls, threshold, now) {
+            eprintln!(
+                "Warning: {} has published nothing since {}, more than {} days ago; it may have stopped updating",
+                stale.url,
+                stale.newest_item.format("%Y-%m-%d"),
+                stale_feed_threshold_days
+            );
+        }
+    }
+    if let Err(e) = stale_detector.save(storage.as_ref(), LAST_ITEM_DATES_PATH).await {
+        eprintln!("Warning: Could not write item dates {}: {}", LAST_ITEM_DATES_PATH, e);
+    }
+
+    // An interrupted run leaves the feeds it archived for the next one to skip
+    if let Some(path) = resume_file {
+        if crawl.interrupted.is_empty() {
+            ResumeState::remove(storage.as_ref(), path).await;
+        } else {
+            let fetched = fetch_progress.lock().unwrap().clone();
+            match ResumeState::save(storage.as_ref(), path, fetched, Utc::now()).await {
+                Ok(()) => progress!(cli.json, "Wrote the feeds archived so far to {}, the next run continues from there", path),
+                Err(e) => eprintln!("Warning: Could not write resume file {}: {}", path, e),
+            }
+        }
+    }
+
+    let mut run_stats = RunStats::new(&crawl.stats, now);
+    run_stats.republished_collapsed = republished_collapsed;
+    run_stats.feeds_quarantined = quarantined.len();
+    run_stats.output_bytes = output_files.iter().map(|file| file.bytes).sum();
+    run_stats.output_files = output_files;
+    run_stats.slowest_feeds = slowest_feeds;
+    if stale_feed_threshold_days > 0 {
+        run_stats.stale_feeds = stale_detector.stale_feeds();
+    }
+    progress!(cli.json, "Wrote {} files, {} bytes in total", run_stats.output_files.len(), run_stats.output_bytes);
+    if let Err(e) = storage.write(STATS_PATH, serde_json::to_string_pretty(&run_stats)?.as_bytes()).await {
+        eprintln!("Warning: Could not write run statistics {}: {}", STATS_PATH, e);
+    }
+
+    if cli.check_published {
+        check_published_urls(&published_urls, config.published_check_sample.unwrap_or(10), config.ip_family.unwrap_or_default(), cli.json).await;
+    }
+
+    Ok(!crawl.interrupted.is_empty())
+}
+
+/// Requests a sample of the archived URLs and warns about every one that can't be
+/// downloaded, such as files missing from the published copy of the archive.
+async fn check_published_urls(urls: &[String], sample_size: usize, ip_family: IpFamily, progress_to_stderr: bool) {
+    if urls.is_empty() {
+        progress!(progress_to_stderr, "No published files to check, none of the files written existed before this run");
+        return;
+    }
+    let sample = sample_evenly(urls, sample_size);
+    match check_reachable(&sample, ip_family).await {
+        Ok(unreachable) => {
+            for (url, error) in &unreachable {
+                eprintln!("Warning: Published file {} is unreachable: {}", url, error);
+            }
+            progress!(progress_to_stderr, "{} of {} sampled archived URLs are reachable", sample.len() - unreachable.len(), sample.len());
+        }
+        Err(e) => eprintln!("Warning: Could not check the published files: {}", e),
+    }
+}
+
+/// Runs the crawl, sending each feed to `ready` once fetched. On Unix, SIGUSR1 writes the
+/// feeds archived so far to the resume file, so that a run about to be killed can still be continued.
+async fn crawl_with_progress_dumps(
+    crawler: &mut FeedCrawler,
+    specs: &[FeedSpec],
+    storage: &dyn Storage,
+    resume_file: Option<&str>,
+    fetch_progress: &Mutex<BTreeSet<String>>,
+    ready: mpsc::Sender<FeedData>,
+    progress_to_stderr: bool,
+) -> CrawlResult {
+    #[cfg(unix)]
+    if let Some(path) = resume_file {
+        if let Ok(mut user_signal) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()) {
+            let crawl = crawler.crawl_all(specs, ready);
+            tokio::pin!(crawl);
+            loop {
+                tokio::select! {
+                    crawl = &mut crawl => return crawl,
+                    _ = user_signal.recv() => {
+                        let fetched = fetch_progress.lock().unwrap().clone();
+                        let count = fetched.len();
+                        match ResumeState::save(storage, path, fetched, Utc::now()).await {
+                            Ok(()) => progress!(progress_to_stderr, "Wrote the {} feeds archived so far to {}", count, path),
+                            Err(e) => eprintln!("Warning: Could not write resume file {}: {}", path, e),
+                        }
+                    }
+                }
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (storage, resume_file, fetch_progress, progress_to_stderr);
+    crawler.crawl_all(specs, ready).await
+}
+
+// The steps a fetched feed goes through before it is archived
+struct FeedPreparation {
+    // Republication detection is set per URL; with several lines for a URL the first one wins
+    republish_detection: HashMap<String, RepublishDetection>,
+    // So are the languages kept of a feed publishing its posts in several
+    preferred_languages: HashMap<String, Vec<String>>,
+    translation_grouping: TranslationGrouping,
+    detect_language: bool,
+    language_confidence: f64,
+    html_rewriter: Option<HtmlRewriter>,
+    image_extractor: Option<ImageExtractor>,
+    max_items: usize,
+    // Print debug messages, see `--verbose`
+    verbose: bool,
+    // Print progress messages to stderr, see `--json`
+    progress_to_stderr: bool,
+}
+
+impl FeedPreparation {
+    fn new(config: &Config, list_specs: &[Vec<FeedSpec>], verbose: bool, progress_to_stderr: bool) -> Result<FeedPreparation, Box<dyn Error + Send + Sync>> {
+        let mut republish_detection = HashMap::new();
+        let mut preferred_languages = HashMap::new();
+        for spec in list_specs.iter().flatten() {
+            if let Some(detection) = spec.republish_detection {
+                republish_detection.entry(spec.url.clone()).or_insert(detection);
+            }
+            if let Some(languages) = &spec.keep_languages {
+                preferred_languages.entry(spec.url.clone()).or_insert_with(|| languages.clone());
+            }
+        }
+        let window = i64::try_from(config.translation_window_minutes.unwrap_or(60))
+            .ok()
+            .and_then(chrono::TimeDelta::try_minutes)
+            .ok_or("config.toml: translation_window_minutes is too large")?;
+        Ok(FeedPreparation {
+            republish_detection,
+            preferred_languages,
+            translation_grouping: TranslationGrouping {
+                window,
+                min_link_similarity: config.translation_link_similarity.unwrap_or(1.0),
+            },
+            detect_language: config.detect_language.unwrap_or(false),
+            language_confidence: config.language_confidence.unwrap_or(0.8),
+            html_rewriter: config.html_rewrite.as_ref().map(HtmlRewriter::new),
+            image_extractor: config.extract_images.unwrap_or(false).then(ImageExtractor::new),
+            max_items: config.max_items.unwrap_or(300),
+            verbose,
+            progress_to_stderr,
+        })
+    }
+
+    /// Collapses the republished items of a fetched feed, keeps its preferred languages,
+    /// detects the language of its items and rewrites their HTML, and records its new items.
+    /// Returns the number of republished items collapsed.
+    fn prepare(&self, feed_data: &mut FeedData, state: &mut FeedState, feed_stats: &mut FeedStats, run_changes: &mut RunChanges, now: DateTime<Utc>) -> usize {
+        let detection = self.republish_detection.get(&feed_data.url).copied().unwrap_or_default();
+        let collapsed = state
+            .item_history
+            .entry(feed_data.url.clone())
+            .or_default()
+            .collapse_republished(&mut feed_data.items, detection, now);
+        if collapsed > 0 {
+            progress!(self.progress_to_stderr, "Collapsed {} republished items in {}", collapsed, feed_data.url);
+        }
+        if let Some(keep) = self.preferred_languages.get(&feed_data.url) {
+            // The items' own language or else the detected one, even if the feed declares one
+            let languages: Vec<Option<String>> = feed_data
+                .items
+                .iter()
+                .map(|item| item.language.clone().or_else(|| detected_item_language(item, &mut state.item_languages, self.language_confidence)))
+                .collect();
+            let selection = keep_languages(&mut feed_data.items, &languages, keep, &self.translation_grouping);
+            if selection.groups > 0 {
+                progress!(
+                    self.progress_to_stderr,
+                    "Found {} posts published in several languages in {}, dropped {} copies not in {}",
+                    selection.groups,
+                    feed_data.url,
+                    selection.dropped,
+                    keep.join(", ")
+                );
+            }
+            for group in &selection.ambiguous {
+                progress!(self.progress_to_stderr, "Keeping all translations of {} in {}", group, feed_data.url);
+            }
+        }
+        if self.detect_language {
+            detect_item_languages(feed_data, &mut state.item_languages, self.language_confidence);
+        }
+        // Rewritten first, so that thumbnails taken from the HTML are upgraded too
+        if let Some(rewriter) = &self.html_rewriter {
+            let rewritten = rewriter.rewrite_feed(feed_data);
+            if rewritten > 0 {
+                debug!(self.verbose, "Rewrote the HTML of {} items in {}", rewritten, feed_data.url);
+            }
+        }
+        if let Some(extractor) = &self.image_extractor {
+            extractor.extract_item_images(feed_data);
+        }
+        let archived_items = if self.max_items > 0 { feed_data.items.len().min(self.max_items) } else { feed_data.items.len() };
+        let (new_items, added) = feed_stats.record_fetch(feed_data, archived_items, now);
+        run_changes.record_new_items(&feed_data.title, new_items, added);
+        collapsed
+    }
+}
+
+// Writes the individual feed files, collecting what the run reports about them
+struct FeedWriter<'a> {
+    storage: &'a dyn Storage,
+    // Told about every file written, so that it is the copy read back for the feed
+    archive: &'a ArchivedFiles<'a>,
+    layout: ArchiveLayout,
+    // How archived Atom and JSON files are read back
+    parse_options: ParseOptions,
+    file_limits: FileLimits,
+    base_url: &'a str,
+    repo_name: &'a str,
+    // Repo targets getting a copy of every file written
+    targets: &'a [OutputTarget],
+    render_options: RenderOptions,
+    // Hashes of the item content files written, with content_addressed
+    stored_content: HashSet<String>,
+    // Compare every file with its previous copy for the changes report
+    changes_report: bool,
+    changes_report_titles: bool,
+    // Collect the search index entries of the files, up to this many (0 means all)
+    search_index_max_items: Option<usize>,
+    // Files written by earlier runs, see check_published_urls
+    published_files: HashSet<String>,
+    // Files written, or failed to be written, in this run
+    written_files: HashSet<String>,
+    // Files written or left unchanged
+    archived_files: BTreeSet<String>,
+    output_files: Vec<OutputFile>,
+    published_urls: Vec<String>,
+    // Title and changes since the previous run of every file, by path
+    feed_diffs: BTreeMap<String, (String, FeedDiff)>,
+    search_entries: BTreeMap<String, Vec<SearchEntry>>,
+    // Feeds with a file that could not be written
+    failed_urls: HashSet<String>,
+    // Print debug messages, see `--verbose`
+    verbose: bool,
+    // Print progress messages to stderr, see `--json`
+    progress_to_stderr: bool,
+}
+
+impl FeedWriter<'_> {
+    /// Writes the file of a feed in `feed_dir`, limited to max_items and trimmed to its size
+    /// budget, unless another list sharing the directory already wrote it. The file of an
+    /// `unchanged` feed is left as it is.
+    async fn write(&mut self, feed_dir: &str, feed_data: &FeedData, unchanged: bool) {
+        self.write_file(feed_dir, feed_data, unchanged, true).await;
+    }
+
+    /// Writes the feed of rejected items in `feed_dir`. Its items were filtered out of the
+    /// archive, so they are kept out of the search index, the changes report and the copies
+    /// of the repo targets.
+    async fn write_rejects(&mut self, feed_dir: &str, feed_data: &FeedData) {
+        self.write_file(feed_dir, feed_data, false, false).await;
+    }
+
+    /// Writes the file of a feed, see `write`. Only the files of `archived` feeds are
+    /// compared for the changes report, indexed and copied to the repo targets.
+    async fn write_file(&mut self, feed_dir: &str, feed_data: &FeedData, unchanged: bool, archived: bool) {
+        // Generate unique filename based on URL and title (or the slug) to ensure one file per feed
+        let unique_filename = self.layout.feed_filename(feed_data);
+        let filepath = format!("{}/{}", feed_dir, unique_filename);
+        if !self.written_files.insert(filepath.clone()) {
+            return; // Already written for another list sharing this directory
+        }
+
+        // Apply max_items limit to individual feeds too, and trim feeds exceeding their
+        // size budget, so that a few heavy feeds can't bloat the archive. Rendering large
+        // feeds takes a while, during which other tasks are moved off this thread.
+        let rendered = tokio::task::block_in_place(|| {
+            self.file_limits.apply(feed_data, |feed| {
+                render_feed(feed, self.base_url, self.repo_name, feed_dir, &unique_filename, &self.render_options)
+            })
+        });
+        let (limited_feed_data, individual_feed, trimmed) = match rendered {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                eprintln!("Error building individual feed {}: {}", filepath, e);
+                return;
+            }
+        };
+        if !trimmed.is_empty() {
+            let max_file_bytes = self.file_limits.max_file_bytes(feed_data);
+            progress!(
+                self.progress_to_stderr,
+                "Trimmed {} to fit max_file_bytes = {}: dropped {} oldest items, shortened {} descriptions",
+                filepath,
+                max_file_bytes,
+                trimmed.items_dropped,
+                trimmed.items_truncated
+            );
+            if individual_feed.len() as u64 > max_file_bytes {
+                eprintln!("Warning: {} is {} bytes even without items, over max_file_bytes = {}", filepath, individual_feed.len(), max_file_bytes);
+            }
+        }
+
+        // The content files must exist before a feed referencing them is published
+        if self.render_options.content_addressed && limited_feed_data.format == OutputFormat::Rss {
+            match store_items(self.storage, &limited_feed_data.items, &mut self.stored_content).await {
+                Ok(0) => {}
+                Ok(written) => progress!(self.progress_to_stderr, "Stored the content of {} items of {}", written, filepath),
+                Err(e) => {
+                    eprintln!("Error storing item content of {}: {}", filepath, e);
+                    return;
+                }
+            }
+        }
+
+        // The archived copy is compared with the new items before it is replaced
+        let previous = if self.changes_report && archived {
+            match read_archived_feed(self.storage, &filepath, self.parse_options).await {
+                Some(Ok(previous)) => Some(previous),
+                Some(Err(e)) => {
+                    debug!(self.verbose, "No previous copy of {} to compare with: {}", filepath, e);
+                    None
+                }
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        if !unchanged {
+            if let Err(e) = self.storage.write_atomic(&filepath, individual_feed.as_bytes()).await {
+                eprintln!("Error writing individual feed {}: {}", filepath, e);
+                self.failed_urls.insert(feed_data.url.clone());
+                return; // Continue with other feeds instead of failing completely
+            }
+            self.archive.record(feed_dir, &filepath).await;
+            if limited_feed_data.format == OutputFormat::Rss {
+                self.check_written_rss(&filepath, &individual_feed);
+            }
+        }
+        if self.changes_report && archived {
+            let diff = diff_feed_data(previous.as_ref(), &limited_feed_data);
+            let title = limited_feed_data.display_title().to_string();
+            match self.feed_diffs.get_mut(&filepath) {
+                // Written again in this run, so it changed by both writes
+                Some((written_title, written)) => {
+                    *written_title = title;
+                    written.then(diff);
+                }
+                None => {
+                    self.feed_diffs.insert(filepath.clone(), (title, diff));
+                }
+            }
+        }
+        if !unchanged {
+            self.output_files.push(OutputFile { path: filepath.clone(), bytes: individual_feed.len() as u64 });
+            for target in self.targets.iter().filter(|_| archived) {
+                self.write_target_copy(target, feed_dir, &unique_filename, feed_data).await;
+            }
+        }
+        if self.published_files.contains(&filepath) {
+            self.published_urls.push(archived_feed_url(feed_base_url(feed_data, self.base_url), feed_dir, &unique_filename));
+        }
+
+        println!(
+            "{} individual feed: {} ({} items, {} filtered, {} bytes)",
+            if unchanged { "Kept unchanged" } else { "Generated" },
+            filepath,
+            limited_feed_data.items.len(),
+            limited_feed_data.filtered_count,
+            individual_feed.len()
+        );
+        if let Some(max_items) = self.search_index_max_items.filter(|_| archived) {
+            self.search_entries.insert(filepath.clone(), build_search_index(std::slice::from_ref(&limited_feed_data), max_items));
+        }
+        self.archived_files.insert(filepath);
+    }
+
+    /// Checks the RSS file just written to `filepath` for serialization bugs, streaming it
+    /// back from disk with local storage and checking the rendered `xml` otherwise.
+    /// Violations are reported as warnings, the file is kept.
+    fn check_written_rss(&self, filepath: &str, xml: &str) {
+        let report = match self.storage.local_path(filepath) {
+            Some(local_path) => match tokio::task::block_in_place(|| validate_rss_file(&local_path)) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("Warning: Could not validate {}: {}", filepath, e);
+                    return;
+                }
+            },
+            None => validate_rss(xml.as_bytes()),
+        };
+        if report.is_valid() {
+            debug!(self.verbose, "Validated {} ({} items)", filepath, report.items);
+        }
+        for violation in &report.violations {
+            eprintln!("Warning: {} is not valid RSS 2.0: {}", filepath, violation);
+        }
+    }
+
+    /// Writes the copy of a feed file for a repo target, rendered with the target's URLs.
+    /// Item content is kept inline, as the content files are only stored for the primary output.
+    async fn write_target_copy(&mut self, target: &OutputTarget, feed_dir: &str, filename: &str, feed_data: &FeedData) {
+        let filepath = target.path(&format!("{}/{}", feed_dir, filename));
+        // A base_url of the feed points at where the primary output is published
+        let feed_data = FeedData { base_url: None, ..feed_data.clone() };
+        let render_options = RenderOptions { content_addressed: false, ..self.render_options.clone() };
+        let rendered = tokio::task::block_in_place(|| {
+            self.file_limits.apply(&feed_data, |feed| {
+                render_feed(feed, &target.base_url, &target.repo_name, feed_dir, filename, &render_options)
+            })
+        });
+        let individual_feed = match rendered {
+            Ok((_, individual_feed, _)) => individual_feed,
+            Err(e) => {
+                eprintln!("Error building individual feed {}: {}", filepath, e);
+                return;
+            }
+        };
+        if let Err(e) = self.storage.write_atomic(&filepath, individual_feed.as_bytes()).await {
+            eprintln!("Error writing individual feed {}: {}", filepath, e);
+            return;
+        }
+        debug!(self.verbose, "Generated individual feed: {} ({} bytes)", filepath, individual_feed.len());
+        self.output_files.push(OutputFile { path: filepath, bytes: individual_feed.len() as u64 });
+    }
+
+    /// Lets the file at `path` be written again, forgetting its size and search index
+    /// entries. Its changes so far are kept, to be combined with those of the next write.
+    fn forget(&mut self, path: &str) {
+        self.written_files.remove(path);
+        self.archived_files.remove(path);
+        let copies: Vec<String> = self.targets.iter().map(|target| target.path(path)).collect();
+        self.output_files.retain(|file| file.path != path && !copies.contains(&file.path));
+        let suffix = format!("/{}", path);
+        self.published_urls.retain(|url| !url.ends_with(&suffix));
+        self.search_entries.remove(path);
+    }
+}
+
+/// Prints which items each archived feed would gain or lose if `writer` wrote it, without
+/// writing anything.
+async fn print_feed_diffs(writer: &FeedWriter<'_>, lists: &[FeedList], list_feeds: &[Vec<FeedData>], json: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let FeedWriter { storage, layout, file_limits, base_url, repo_name, parse_options, .. } = *writer;
+    #[derive(Serialize)]
+    struct FileDiff {
+        path: String,
+        title: String,
+        #[serde(flatten)]
+        diff: FeedDiff,
+    }
+
+    let mut diffs = Vec::new();
+    let mut seen_files = HashSet::new();
+    for (list, feeds) in lists.iter().zip(list_feeds) {
+        let feed_dir = list.feed_dir();
+        for feed_data in feeds {
+            let filename = layout.feed_filename(feed_data);
+            let path = format!("{}/{}", feed_dir, filename);
+            if !seen_files.insert(path.clone()) {
+                continue;
+            }
+
+            // Compare against what would be written, limited and trimmed the same way
+            let limited_feed_data = match file_limits.apply(feed_data, |feed| render_feed(feed, base_url, repo_name, &feed_dir, &filename, &RenderOptions::default())) {
+                Ok((limited_feed_data, _, _)) => limited_feed_data,
+                Err(e) => {
+                    eprintln!("Error building individual feed {}: {}", path, e);
+                    continue;
+                }
+            };
+            // Items are compared by guid and link, which the render options leave alone
+            let channel = build_individual_feed(&limited_feed_data, base_url, repo_name, &feed_dir, &filename, &RenderOptions::default());
+            // A feed that hasn't been written yet compares against an empty one. Atom and
+            // JSON files are converted to RSS for the comparison.
+            let old_xml = match storage.read(&path).await {
+                Ok(bytes) if feed_data.format == OutputFormat::Rss => String::from_utf8_lossy(&bytes).into_owned(),
+                Ok(bytes) => parse_feed(&feed_data.url, &bytes, parse_options)
+                    .map(|old_feed| build_individual_feed(&old_feed, base_url, repo_name, &feed_dir, &filename, &RenderOptions::default()).to_string())
+                    .unwrap_or_default(),
+                Err(_) => String::new(),
+            };
+            let diff = diff_feed_items(&old_xml, &channel);
+            if !diff.is_empty() {
+                diffs.push(FileDiff { path, title: feed_data.title.clone(), diff });
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diffs)?);
+        return Ok(());
+    }
+    if diffs.is_empty() {
+        println!("No changes in {} feeds", seen_files.len());
+    }
+    for file in &diffs {
+        println!(
+            "{} ({}): {} new, {} removed",
+            file.path,
+            file.title,
+            file.diff.added.len(),
+            file.diff.removed.len()
+        );
+        for item in &file.diff.added {
+            println!("  + {} <{}>", item.title, item.link);
+        }
+        for item in &file.diff.removed {
+            println!("  - {} <{}>", item.title, item.link);
+        }
+    }
+    Ok(())
+}
+
+// A simple struct to hold the feed item data
+#[derive(Debug, Clone)]
+struct FeedItem {
+    guid: String,
+    // Set when the source had no id and the guid was made up, see GuidStrategy
+    guid_synthesized: bool,
+    title: String,
+    link: String,
+    description: Option<String>,
+    content: Option<String>,
+    pub_date: DateTime<FixedOffset>,
+    // When the source last updated the item, if it says so and it differs from pub_date,
+    // emitted as atom:updated or dcterms:modified. Items are still sorted by pub_date.
+    updated_date: Option<DateTime<FixedOffset>>,
+    read_time_minutes: Option<u8>,
+    language: Option<String>,
+    enclosure: Option<Enclosure>,
+    // Preview image URL, emitted as media:thumbnail
+    thumbnail: Option<String>,
+    // Discussion page of the item, e.g. on Hacker News, when it differs from the link
+    comments: Option<String>,
+    // Number of comments (slash:comments) and the feed of the comments (wfw:commentRss)
+    comment_count: Option<u64>,
+    comments_feed: Option<String>,
+    // Number of the item in a podcast or serial, from itunes:episode or its title (see
+    // number_episodes), emitted as itunes:episode
+    episode_number: Option<u32>,
+    categories: Vec<String>,
+    // Names of the item's authors
+    authors: Vec<String>,
+    // URL and title of the live feed the item was published in, emitted as <source> so
+    // that readers can navigate back to the publisher's feed
+    original_feed_url: Option<String>,
+    original_feed_title: Option<String>,
+}
+
+// An audio/video file attached to an item
+#[derive(Debug, Clone)]
+struct Enclosure {
+    url: String,
+    mime_type: String,
+    length: u64,
+}
+
+// Struct to hold both feed metadata and items
+#[derive(Debug, Clone)]
+struct FeedData {
+    title: String,
+    // Tagline of the source feed, written as the subtitle of archived Atom feeds and the
+    // description of archived JSON feeds
+    subtitle: Option<String>,
+    url: String,
+    // URL the feed was served from after redirects, the same as url if it wasn't redirected
+    resolved_url: String,
+    items: Vec<FeedItem>,
+    language: Option<String>,
+    // URL of the image or logo the source feed declares for itself
+    icon: Option<String>,
+    copyright: Option<String>,
+    // Set when the feed list gives this variant of the feed its own filename
+    slug: Option<String>,
+    // Priority from the feed list, used when truncating combined outputs
+    priority: i32,
+    // Size budget of the archived file from the feed list, overriding the configured one
+    max_file_bytes: Option<u64>,
+    // Where the archived file is published, from the feed list, overriding the configured base_url
+    base_url: Option<String>,
+    // Primary channel categories from the feed list
+    categories: Vec<String>,
+    // Title shown to readers when several feeds share the same title, see disambiguate_titles
+    display_title: Option<String>,
+    // Format the archived feed is written in
+    format: OutputFormat,
+    // Items dropped by per-feed filters during this run
+    filtered_count: usize,
+    // Publisher hints on how often the feed should be polled
+    ttl: Option<u32>,
+    skip_hours: Vec<u32>,
+    skip_days: Vec<String>,
+}
+
+impl FeedData {
+    /// Title shown in the OPML files and archived feeds.
+    fn display_title(&self) -> &str {
+        self.display_title.as_deref().unwrap_or(&self.title)
+    }
+}
+
+// Summary of a run, written to feeds/stats.json
+#[derive(Debug, Serialize)]
+struct RunStats {
+    started_at: DateTime<Utc>,
+    elapsed_seconds: f64,
+    feeds_attempted: usize,
+    feeds_succeeded: usize,
+    feeds_failed: usize,
+    feeds_interrupted: usize,
+    items: usize,
+    duplicates_removed: usize,
+    excluded_by_link_domain: usize,
+    excluded_by_keyword: usize,
+    excluded_by_author: usize,
+    excluded_by_blocklist: usize,
+    republished_collapsed: usize,
+    feeds_quarantined: usize,
+    // Total size of the files written by the run
+    output_bytes: u64,
+    output_files: Vec<OutputFile>,
+    slowest_feeds: Vec<SlowFeed>,
+    // Feeds whose newest item is older than stale_feed_threshold_days
+    stale_feeds: Vec<StaleFeed>,
+}
+
+#[derive(Debug, Serialize)]
+struct OutputFile {
+    path: String,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct SlowFeed {
+    url: String,
+    fetch_ms: u64,
+    parse_ms: u64,
+}
+
+impl RunStats {
+    fn new(stats: &CrawlStats, started_at: DateTime<Utc>) -> RunStats {
+        RunStats {
+            started_at,
+            elapsed_seconds: stats.elapsed.as_secs_f64(),
+            feeds_attempted: stats.attempted,
+            feeds_succeeded: stats.succeeded,
+            feeds_failed: stats.failed,
+            feeds_interrupted: stats.interrupted,
+            items: stats.items,
+            duplicates_removed: stats.duplicates_removed,
+            excluded_by_link_domain: stats.excluded_by_link_domain,
+            excluded_by_keyword: stats.excluded_by_keyword,
+            excluded_by_author: stats.excluded_by_author,
+            excluded_by_blocklist: stats.excluded_by_blocklist,
+            republished_collapsed: 0,
+            feeds_quarantined: 0,
+            output_bytes: 0,
+            output_files: Vec::new(),
+            slowest_feeds: Vec::new(),
+            stale_feeds: Vec::new(),
+        }
+    }
+}
+
+// State persisted between runs in feeds/.state.json, keyed by feed URL
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FeedState {
+    feeds: BTreeMap<String, FeedStatus>,
+    // Detected item languages keyed by guid, None when detection was inconclusive
+    #[serde(default)]
+    item_languages: BTreeMap<String, Option<String>>,
+    #[serde(default)]
+    dedup: DedupCache,
+    // Items seen per feed URL, to recognize republished items
+    #[serde(default)]
+    item_history: BTreeMap<String, ItemHistory>,
+    // Items removed with blocked_items, which are never archived again
+    #[serde(default)]
+    removed_items: RemovedItems,
+    // Items dated in the future, with the date they were clamped to
+    #[serde(default)]
+    clamped_dates: ClampedDates,
+}
+
+// What we know about a feed from its last successful fetch
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FeedStatus {
+    title: String,
+    last_fetched: Option<DateTime<Utc>>,
+    ttl: Option<u32>,
+    #[serde(default)]
+    skip_hours: Vec<u32>,
+    #[serde(default)]
+    skip_days: Vec<String>,
+    // Failures since the last successful fetch
+    #[serde(default)]
+    consecutive_failures: u32,
+    #[serde(default)]
+    failing_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    last_attempt: Option<DateTime<Utc>>,
+    // Set while the feed is only retried every `quarantine_retry_hours`
+    #[serde(default)]
+    quarantined_since: Option<DateTime<Utc>>,
+    // Timings of the last successful fetches, oldest first
+    #[serde(default)]
+    timings: Vec<TimingSample>,
+    // Seconds the last response stays fresh after last_fetched, from its Cache-Control or
+    // Expires header
+    #[serde(default)]
+    freshness_secs: Option<u64>,
+    // URL the last changed response was served from, if the feed redirects elsewhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resolved_url: Option<String>,
+    // Channel metadata of the last changed response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<ChannelMetadata>,
+}
+
+// Channel metadata of a feed as its source publishes it. Feeds reused from their archived
+// copy get it back from here, as the channel of an archived copy describes the archive.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct ChannelMetadata {
+    title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subtitle: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    copyright: Option<String>,
+}
+
+impl ChannelMetadata {
+    fn of(feed_data: &FeedData) -> ChannelMetadata {
+        ChannelMetadata {
+            title: feed_data.title.clone(),
+            subtitle: feed_data.subtitle.clone(),
+            language: feed_data.language.clone(),
+            icon: feed_data.icon.clone(),
+            copyright: feed_data.copyright.clone(),
+        }
+    }
+
+    /// Describes what changed between `self` and the metadata `new` of the feed at `url`,
+    /// one message per field.
+    fn changes(&self, new: &ChannelMetadata, url: &str) -> Vec<String> {
+        let feed = format!("Feed {:?} ({})", self.title, url);
+        let mut changes = Vec::new();
+        if new.title != self.title {
+            changes.push(format!("{} renamed to {:?}", feed, new.title));
+        }
+        if new.subtitle != self.subtitle {
+            changes.push(format!("{} changed its description", feed));
+        }
+        if new.language != self.language {
+            let language = |language: &Option<String>| language.clone().unwrap_or_else(|| "none".to_string());
+            changes.push(format!("{} changed its language from {} to {}", feed, language(&self.language), language(&new.language)));
+        }
+        if new.icon != self.icon {
+            changes.push(format!("{} changed its icon", feed));
+        }
+        if new.copyright != self.copyright {
+            changes.push(format!("{} changed its copyright notice", feed));
+        }
+        changes
+    }
+
+    /// Restores the metadata that the archived copy of a feed doesn't carry back. The
+    /// language is left as archived, as it may have been detected from the items.
+    fn restore(&self, feed_data: &mut FeedData) {
+        feed_data.subtitle = self.subtitle.clone();
+        feed_data.icon = self.icon.clone();
+        feed_data.copyright = self.copyright.clone();
+    }
+}
+
+// How long a successful fetch took, in milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TimingSample {
+    at: DateTime<Utc>,
+    // Time until the response headers arrived
+    headers_ms: Option<u64>,
+    fetch_ms: u64,
+    parse_ms: u64,
+}
+
+impl TimingSample {
+    fn total_ms(&self) -> u64 {
+        self.fetch_ms + self.parse_ms
+    }
+}
+
+// When feeds that keep failing are quarantined. A threshold of 0 disables that criterion.
+struct QuarantinePolicy {
+    after_failures: u32,
+    after_days: u64,
+    retry_hours: u64,
+}
+
+impl FeedState {
+    /// Loads the state file, starting from an empty state if it is missing or invalid. Fails
+    /// if the storage can't read it, rather than losing the state over a transient error.
+    async fn load(storage: &dyn Storage, path: &str) -> Result<FeedState, Box<dyn Error + Send + Sync>> {
+        match storage.read(path).await {
+            Ok(contents) => Ok(serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                eprintln!("Warning: Ignoring unreadable feed state {}: {}", path, e);
+                FeedState::default()
+            })),
+            Err(e) if is_not_found(e.as_ref()) => Ok(FeedState::default()),
+            Err(e) => Err(format!("Could not read feed state {}: {}", path, e).into()),
+        }
+    }
+
+    /// Writes the state file.
+    async fn save(&self, storage: &dyn Storage, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        storage.write(path, serde_json::to_string_pretty(self)?.as_bytes()).await
+    }
+
+    /// Moves the state of the feed `from` to the feed `to`, unless `to` has its own.
+    fn merge_feed(&mut self, from: &str, to: &str) {
+        if let Some(status) = self.feeds.remove(from) {
+            self.feeds.entry(to.to_string()).or_insert(status);
+        }
+        if let Some(history) = self.item_history.remove(from) {
+            self.item_history.entry(to.to_string()).or_insert(history);
+        }
+    }
+
+    /// Records a successful fetch along with the feed's polling hints, channel metadata and
+    /// the freshness lifetime of the response. Returns the changes of the metadata to report.
+    fn record_fetch(&mut self, feed_data: &FeedData, freshness: Option<Duration>, fetched_at: DateTime<Utc>) -> Vec<String> {
+        let status = self.feeds.entry(feed_data.url.clone()).or_default();
+        let metadata = ChannelMetadata::of(feed_data);
+        let changes = status.metadata.iter().flat_map(|previous| previous.changes(&metadata, &feed_data.url)).collect();
+        status.metadata = Some(metadata);
+        status.title = feed_data.title.clone();
+        status.last_fetched = Some(fetched_at);
+        status.freshness_secs = freshness.map(|freshness| freshness.as_secs());
+        status.ttl = feed_data.ttl;
+        status.skip_hours = feed_data.skip_hours.clone();
+        status.skip_days = feed_data.skip_days.clone();
+        status.resolved_url = Some(feed_data.resolved_url.clone()).filter(|resolved_url| *resolved_url != feed_data.url);
+        status.last_attempt = Some(fetched_at);
+        status.consecutive_failures = 0;
+        status.failing_since = None;
+        status.quarantined_since = None;
+        changes
+    }
+
+    /// Records a fetch whose response is the same as last time, leaving the feed's hints
+    /// as they were read from its last changed response.
+    fn record_unchanged(&mut self, url: &str, freshness: Option<Duration>, fetched_at: DateTime<Utc>) {
+        let status = self.feeds.entry(url.to_string()).or_default();
+        status.last_fetched = Some(fetched_at);
+        status.freshness_secs = freshness.map(|freshness| freshness.as_secs());
+        status.last_attempt = Some(fetched_at);
+        status.consecutive_failures = 0;
+        status.failing_since = None;
+        status.quarantined_since = None;
+    }
+
+    /// Adds the timing of a fetch to the feed's history, keeping the last `TIMING_HISTORY`.
+    fn record_timing(&mut self, url: &str, timing: &FetchTiming, fetched_at: DateTime<Utc>) {
+        let timings = &mut self.feeds.entry(url.to_string()).or_default().timings;
+        timings.push(TimingSample {
+            at: fetched_at,
+            headers_ms: timing.headers.map(|headers| headers.as_millis() as u64),
+            fetch_ms: timing.fetch.as_millis() as u64,
+            parse_ms: timing.parse.as_millis() as u64,
+        });
+        let excess = timings.len().saturating_sub(TIMING_HISTORY);
+        timings.drain(..excess);
+    }
+
+    /// Prints the fetch latency of every feed with recorded timings, slowest (by median) first.
+    fn print_latency(&self) {
+        let mut rows: Vec<(&String, &FeedStatus, u64)> = self
+            .feeds
+            .iter()
+            .filter_map(|(url, status)| {
+                let mut totals: Vec<u64> = status.timings.iter().map(TimingSample::total_ms).collect();
+                totals.sort_unstable();
+                totals.get(totals.len() / 2).map(|&median| (url, status, median))
+            })
+            .collect();
+        if rows.is_empty() {
+            return;
+        }
+        rows.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+
+        println!();
+        println!("{:>8}  {:>8}  {:>8}  {:>8}  {:>7}  Feed", "Median", "Last", "Max", "Headers", "Samples");
+        for (url, status, median) in rows {
+            let last = status.timings.last();
+            let max = status.timings.iter().map(TimingSample::total_ms).max().unwrap_or_default();
+            let headers = last
+                .and_then(|sample| sample.headers_ms)
+                .map(|headers| format!("{} ms", headers))
+                .unwrap_or_else(|| "-".to_string());
+            println!(
+                "{:>8}  {:>8}  {:>8}  {:>8}  {:>7}  {} ({})",
+                format!("{} ms", median),
+                format!("{} ms", last.map(TimingSample::total_ms).unwrap_or_default()),
+                format!("{} ms", max),
+                headers,
+                status.timings.len(),
+                status.title,
+                url
+            );
+        }
+    }
+
+    /// Prints the quarantined feeds, if any.
+    fn print_quarantined(&self) {
+        let quarantined: Vec<(&String, &FeedStatus)> =
+            self.feeds.iter().filter(|(_, status)| status.quarantined_since.is_some()).collect();
+        if quarantined.is_empty() {
+            return;
+        }
+        println!();
+        println!("Quarantined feeds (retried once in a while, --retry-quarantined fetches them now):");
+        for (url, status) in quarantined {
+            let failing_since = status.failing_since.map(|since| since.format("%Y-%m-%d").to_string()).unwrap_or_default();
+            println!("  {}: {} consecutive failures since {}", url, status.consecutive_failures, failing_since);
+        }
+    }
+}
+
+impl FeedStatus {
+    /// Counts a failed fetch and quarantines the feed once it crosses a threshold of the policy.
+    fn record_failure(&mut self, now: DateTime<Utc>, policy: &QuarantinePolicy) {
+        self.consecutive_failures += 1;
+        self.last_attempt = Some(now);
+        let failing_since = *self.failing_since.get_or_insert(now);
+        let too_many = policy.after_failures > 0 && self.consecutive_failures >= policy.after_failures;
+        // A number of days too large to be represented is never reached
+        let too_long = policy.after_days > 0
+            && i64::try_from(policy.after_days)
+                .ok()
+                .and_then(chrono::TimeDelta::try_days)
+                .is_some_and(|after| now - failing_since >= after);
+        if self.quarantined_since.is_none() && (too_many || too_long) {
+            self.quarantined_since = Some(now);
+        }
+    }
+
+    /// Returns why a quarantined feed should not be retried at `now`.
+    fn quarantine_skip_reason(&self, now: DateTime<Utc>, policy: &QuarantinePolicy) -> Option<String> {
+        self.quarantined_since?;
+        if policy.after_failures == 0 && policy.after_days == 0 {
+            return None;
+        }
+        let last_attempt = self.last_attempt?;
+        // A retry interval too long to be added to a date never elapses
+        let next_attempt = i64::try_from(policy.retry_hours)
+            .ok()
+            .and_then(chrono::TimeDelta::try_hours)
+            .and_then(|retry| last_attempt.checked_add_signed(retry));
+        next_attempt.is_none_or(|next_attempt| now < next_attempt).then(|| format!("quarantined after {} consecutive failures", self.consecutive_failures))
+    }
+
+    /// Returns why the feed should not be fetched at `now` because its last response, going
+    /// by its Cache-Control or Expires header, is still fresh.
+    fn freshness_skip_reason(&self, now: DateTime<Utc>) -> Option<String> {
+        let lifetime = chrono::TimeDelta::try_seconds(i64::try_from(self.freshness_secs?).ok()?)?;
+        let fresh_until = self.last_fetched?.checked_add_signed(lifetime)?;
+        (now < fresh_until).then(|| format!("fresh for another {}", format_remaining(fresh_until - now)))
+    }
+
+    /// Returns why the feed should not be fetched at `now` because its polling interval has
+    /// not elapsed since its last successful fetch. Each feed is due early by up to
+    /// `FEED_INTERVAL_STAGGER_PERCENT` of the interval, an amount fixed per URL, so that feeds
+    /// added at the same time drift apart instead of all being fetched by the same runs.
+    fn interval_skip_reason(&self, url: &str, interval_secs: u64, now: DateTime<Utc>) -> Option<String> {
+        let last_fetched = self.last_fetched?;
+        let wait_secs = interval_secs - interval_stagger_secs(url, interval_secs);
+        // An interval too long to be added to a date never elapses
+        let Some(due) = i64::try_from(wait_secs)
+            .ok()
+            .and_then(chrono::TimeDelta::try_seconds)
+            .and_then(|wait| last_fetched.checked_add_signed(wait))
+        else {
+            return Some(format!("interval of {}s has not elapsed", interval_secs));
+        };
+        (now < due).then(|| format!("interval of {}s has not elapsed, due in {}", interval_secs, format_remaining(due - now)))
+    }
+
+    /// Returns why the feed should not be fetched at `now`, based on its TTL and
+    /// skipHours/skipDays. skipHours are defined in GMT by the RSS spec.
+    fn skip_reason(&self, now: DateTime<Utc>) -> Option<String> {
+        let last_fetched = self.last_fetched?;
+
+        if let Some(ttl) = self.ttl {
+            let next_fetch = last_fetched + chrono::Duration::minutes(ttl as i64);
+            if now < next_fetch {
+                return Some(format!("TTL of {} minutes has not elapsed", ttl));
+            }
+        }
+        if self.skip_hours.contains(&now.hour()) {
+            return Some(format!("hour {} GMT is listed in skipHours", now.hour()));
+        }
+        let weekday = weekday_name(now.weekday());
+        if self.skip_days.iter().any(|day| day.eq_ignore_ascii_case(weekday)) {
+            return Some(format!("{} is listed in skipDays", weekday));
+        }
+        None
+    }
+}
+
+/// How early a feed polled every `interval_secs` is due, at most
+/// `FEED_INTERVAL_STAGGER_PERCENT` of the interval. It is taken from a SHA-256 hash of the
+/// URL, so that it stays the same across runs and toolchains.
+fn interval_stagger_secs(url: &str, interval_secs: u64) -> u64 {
+    let hash = Sha256::digest(url.as_bytes());
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&hash[..8]);
+    u64::from_be_bytes(prefix) % (interval_secs.saturating_mul(FEED_INTERVAL_STAGGER_PERCENT) / 100 + 1)
+}
+
+/// Formats a positive duration as `45s`, `7m` or `1h05m`, rounded to the minute from
+/// a minute on.
+fn format_remaining(remaining: chrono::Duration) -> String {
+    let secs = remaining.num_seconds().max(1);
+    if secs < 60 {
+        return format!("{}s", secs);
+    }
+    let minutes = (secs + 30) / 60;
+    if minutes < 60 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h{:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// Returns the RSS skipDays spelling of a weekday.
+fn weekday_name(weekday: chrono::Weekday) -> &'static str {
+    match weekday {
+        chrono::Weekday::Mon => "Monday",
+        chrono::Weekday::Tue => "Tuesday",
+        chrono::Weekday::Wed => "Wednesday",
+        chrono::Weekday::Thu => "Thursday",
+        chrono::Weekday::Fri => "Friday",
+        chrono::Weekday::Sat => "Saturday",
+        chrono::Weekday::Sun => "Sunday",
+    }
+}
+
+/// Loads the previously archived copies of every feed list entry for `url`, one per entry.
+async fn load_cached_variants(
+    archive: &ArchivedFiles<'_>,
+    layout: ArchiveLayout,
+    url: &str,
+    lists: &[FeedList],
+    list_specs: &[Vec<FeedSpec>],
+    state: &FeedState,
+    parse_options: ParseOptions,
+) -> Vec<Option<FeedData>> {
+    let mut cached = Vec::new();
+    for (list, specs) in lists.iter().zip(list_specs) {
+        // Members of combined feeds have no archive of their own
+        for spec in specs.iter().filter(|spec| spec.url == url && spec.combined.is_none()) {
+            cached.push(load_cached_feed(archive, layout, list, spec, state, parse_options).await);
+        }
+    }
+    cached
+}
+
+/// Renames the archived files of a duplicate URL to the names of the feed at its canonical
+/// URL, so that the archive keeps its history instead of the cleanup removing it. Files the
+/// canonical URL already has are left alone. Returns the files moved, as (from, to) paths.
+async fn move_duplicate_archives(
+    archive: &ArchivedFiles<'_>,
+    layout: ArchiveLayout,
+    lists: &[FeedList],
+    list_specs: &[Vec<FeedSpec>],
+    duplicate: &str,
+    duplicate_title: &str,
+    canonical: &FeedData,
+) -> Vec<(String, String)> {
+    let mut moved = Vec::new();
+    for (list, specs) in lists.iter().zip(list_specs) {
+        let feed_dir = list.feed_dir();
+        // Members of combined feeds have no archive of their own
+        for spec in specs.iter().filter(|spec| spec.url == duplicate && spec.combined.is_none()) {
+            let format = spec.format.unwrap_or_default();
+            let from = layout.archive_filename(duplicate, duplicate_title, spec.slug.as_deref(), format);
+            let to = layout.archive_filename(&canonical.url, &canonical.title, spec.slug.as_deref(), format);
+            let Some(from) = archive.find(&feed_dir, &from).await else {
+                continue;
+            };
+            // Both serve the same items, so both belong in the same year/month directory
+            let dir = from.rsplit_once('/').map_or(feed_dir.as_str(), |(dir, _)| dir);
+            if archive.storage.list(dir).await.unwrap_or_default().contains(&to) {
+                continue;
+            }
+            let to = format!("{}/{}", dir, to);
+            if from == to {
+                continue;
+            }
+            match archive.storage.rename(&from, &to).await {
+                Ok(()) => {
+                    archive.record_move(&feed_dir, &from, &to).await;
+                    moved.push((from, to));
+                }
+                Err(e) => eprintln!("Warning: Could not move {} to {}: {}", from, to, e),
+            }
+        }
+    }
+    moved
+}
+
+/// Returns the copy of a fetched or cached feed for a feed list entry.
+fn feed_variant(
+    spec: &FeedSpec,
+    fetched_feeds: &HashMap<String, FeedData>,
+    cached_feeds: &HashMap<(String, Option<String>), FeedData>,
+) -> Option<FeedData> {
+    match fetched_feeds.get(&spec.url) {
+        Some(feed_data) => Some(fetched_variant(feed_data, spec)),
+        None => cached_feeds.get(&(spec.url.clone(), spec.slug.clone())).cloned(),
+    }
+}
+
+/// Returns the copy of a fetched feed for a feed list entry, with the slug and filters of
+/// its line applied.
+fn fetched_variant(feed_data: &FeedData, spec: &FeedSpec) -> FeedData {
+    let mut variant = feed_data.clone();
+    variant.slug = spec.slug.clone();
+    variant.priority = spec.priority.unwrap_or_default();
+    variant.max_file_bytes = spec.max_file_bytes;
+    variant.base_url = spec.base_url.clone();
+    variant.categories = spec.categories.clone();
+    variant.format = spec.format.unwrap_or_default();
+    apply_feed_filters(&mut variant, spec);
+    if spec.prefer_comments_link {
+        prefer_comments_links(&mut variant);
+    }
+    variant
+}
+
+/// Drops all but the newest item of a written feed, which is enough to find its file and
+/// list it in the OPML files.
+fn newest_item_only(mut feed_data: FeedData) -> FeedData {
+    if let Some(newest) = feed_data.items.iter().enumerate().max_by_key(|(_, item)| item.pub_date).map(|(index, _)| index) {
+        feed_data.items.swap(0, newest);
+        feed_data.items.truncate(1);
+    }
+    feed_data
+}
+
+/// Merges the members of a combined feed into one feed named after their `combined` option,
+/// newest items first. Items listed by several members are kept once. The items of the
+/// `archived` combined feed, loaded when a member wasn't fetched in this run, are kept as
+/// well. The fetched items are annotated with their member as `annotation` says; archived
+/// ones already were.
+fn combine_feeds(
+    members: &[&FeedSpec],
+    fetched_feeds: &HashMap<String, FeedData>,
+    archived: Option<FeedData>,
+    annotation: SourceAnnotation,
+    max_items: usize,
+) -> Option<FeedData> {
+    let fresh: Vec<FeedData> = members
+        .iter()
+        .filter_map(|spec| {
+            let mut member = fetched_feeds.get(&spec.url)?.clone();
+            member.priority = spec.priority.unwrap_or_default();
+            apply_feed_filters(&mut member, spec);
+            if spec.prefer_comments_link {
+                prefer_comments_links(&mut member);
+            }
+            annotate_source(&mut member, annotation);
+            Some(member)
+        })
+        .collect();
+    merge_members(members, fresh, archived, max_items)
+}
+
+/// Merges the `fresh` member feeds of a combined feed, fetched in this run, and the
+/// `archived` combined feed, limited to `max_items` by member priority. Archived items get
+/// the priority of the member they came from, or the default one if it isn't listed anymore.
+fn merge_members(members: &[&FeedSpec], fresh: Vec<FeedData>, archived: Option<FeedData>, max_items: usize) -> Option<FeedData> {
+    let first = members.first()?;
+    if fresh.is_empty() && archived.is_none() {
+        return None;
+    }
+
+    // Only declare a language if all members agree on it
+    let language = match fresh.first() {
+        Some(feed_data) => feed_data
+            .language
+            .clone()
+            .filter(|language| fresh.iter().all(|member| member.language.as_ref() == Some(language))),
+        None => archived.as_ref().and_then(|feed_data| feed_data.language.clone()),
+    };
+    let archived_priority = |item: &FeedItem| {
+        members
+            .iter()
+            .find(|member| item.original_feed_url.as_ref() == Some(&member.url))
+            .and_then(|member| member.priority)
+            .unwrap_or_default()
+    };
+
+    let mut seen_guids = HashSet::new();
+    let items: Vec<(i32, FeedItem)> = fresh
+        .iter()
+        .flat_map(|member| member.items.iter().map(|item| (member.priority, item.clone())))
+        .chain(archived.into_iter().flat_map(|feed_data| feed_data.items).map(|item| (archived_priority(&item), item)))
+        .filter(|(_, item)| seen_guids.insert(item.guid.clone()))
+        .collect();
+
+    Some(FeedData {
+        title: first.combined.clone().unwrap_or_default(),
+        subtitle: None,
+        url: first.url.clone(),
+        resolved_url: fresh
+            .iter()
+            .find(|member| member.url == first.url)
+            .map_or_else(|| first.url.clone(), |member| member.resolved_url.clone()),
+        items: truncate_by_priority(items, max_items),
+        language,
+        icon: None,
+        copyright: None,
+        slug: first.slug.clone(),
+        priority: first.priority.unwrap_or_default(),
+        max_file_bytes: first.max_file_bytes,
+        base_url: first.base_url.clone(),
+        categories: first.categories.clone(),
+        display_title: None,
+        format: first.format.unwrap_or_default(),
+        filtered_count: fresh.iter().map(|member| member.filtered_count).sum(),
+        ttl: fresh.iter().filter_map(|member| member.ttl).min(),
+        skip_hours: Vec::new(),
+        skip_days: Vec::new(),
+    })
+}
+
+/// Loads the previously archived copy of a feed list entry from the directory of its list.
+async fn load_cached_feed(
+    archive: &ArchivedFiles<'_>,
+    layout: ArchiveLayout,
+    list: &FeedList,
+    spec: &FeedSpec,
+    state: &FeedState,
+    parse_options: ParseOptions,
+) -> Option<FeedData> {
+    let status = state.feeds.get(&spec.url)?;
+    let format = spec.format.unwrap_or_default();
+    let filename = layout.archive_filename(&spec.url, &status.title, spec.slug.as_deref(), format);
+    // A missing file simply means there is no archived copy yet
+    let path = archive.find(&list.feed_dir(), &filename).await?;
+    let storage = archive.storage;
+    let bytes = storage.read(&path).await.ok()?;
+    let loaded = match format {
+        OutputFormat::Rss => match load_feed_data_from_xml(&bytes, &spec.url) {
+            Ok(mut feed_data) => {
+                resolve_references(storage, &mut feed_data).await;
+                Ok(feed_data)
+            }
+            Err(e) => Err(e),
+        },
+        // Atom and JSON archives don't carry the archiver's extensions, so a plain parse will do
+        OutputFormat::Atom | OutputFormat::Json => parse_feed(&spec.url, &bytes, parse_options).map_err(|e| e.into()),
+    };
+    match loaded {
+        Ok(mut feed_data) => {
+            // The archived channel title may have been disambiguated, the filename uses the feed's own
+            feed_data.title = status.title.clone();
+            feed_data.slug = spec.slug.clone();
+            feed_data.priority = spec.priority.unwrap_or_default();
+            feed_data.max_file_bytes = spec.max_file_bytes;
+            feed_data.base_url = spec.base_url.clone();
+            feed_data.categories = spec.categories.clone();
+            feed_data.format = format;
+            feed_data.ttl = status.ttl;
+            feed_data.skip_hours = status.skip_hours.clone();
+            feed_data.skip_days = status.skip_days.clone();
+            feed_data.resolved_url = status.resolved_url.clone().unwrap_or_else(|| spec.url.clone());
+            if let Some(metadata) = &status.metadata {
+                metadata.restore(&mut feed_data);
+            }
+            Some(feed_data)
+        }
+        Err(e) => {
+            eprintln!("Warning: Could not read archived feed {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Builds the feed of the items rejected by filters archived in `feed_dir`, from the items
+/// rejected in this run and those of its archived copy.
+async fn build_rejects_feed(archive: &ArchivedFiles<'_>, feed_dir: &str, base_url: &str, rejected: Vec<RejectedItem>) -> FeedData {
+    let storage = archive.storage;
+    let filename = format!("{}.{}", REJECTS_SLUG, OutputFormat::Rss.extension());
+    let url = archived_feed_url(base_url, feed_dir, &filename);
+    let mut archived = Vec::new();
+    if let Some(path) = archive.find(feed_dir, &filename).await {
+        let loaded = match storage.read(&path).await {
+            Ok(bytes) => load_feed_data_from_xml(&bytes, &url),
+            Err(e) => Err(e),
+        };
+        match loaded {
+            Ok(mut feed_data) => {
+                resolve_references(storage, &mut feed_data).await;
+                archived = feed_data.items;
+            }
+            Err(e) => eprintln!("Warning: Could not read archived feed {}: {}", path, e),
+        }
+    }
+    rejects_feed(rejected, archived, &url)
+}
+
+/// Reads an archived feed file written by `build_individual_feed` back into `FeedData`.
+fn load_feed_data_from_xml(bytes: &[u8], url: &str) -> Result<FeedData, Box<dyn Error + Send + Sync>> {
+    let channel = Channel::read_from(bytes)?;
+
+    let items = channel
+        .items
+        .iter()
+        .map(|item| {
+            let pub_date = item
+                .pub_date
+                .as_deref()
+                .and_then(|d| DateTime::parse_from_rfc2822(d).ok())
+                .unwrap_or_else(|| Utc::now().fixed_offset());
+            let read_time_minutes = item
+                .extensions
+                .get(AGGREGATOR_NS_PREFIX)
+                .and_then(|ext| ext.get("readTime"))
+                .and_then(|values| values.first())
+                .and_then(|value| value.value.as_deref())
+                .and_then(|value| value.parse().ok());
+            let link = item.link.clone().unwrap_or_default();
+            FeedItem {
+                guid: item.guid.as_ref().map(|g| g.value.clone()).unwrap_or_else(|| link.clone()),
+                // Only a link used as guid tells, by not being marked as a permalink
+                guid_synthesized: item.guid.as_ref().is_some_and(|guid| !guid.permalink && guid.value == link),
+                title: item.title.clone().unwrap_or_else(|| String::from("No title")),
+                link,
+                description: item.description.clone(),
+                content: item.content.clone(),
+                updated_date: rss_extension_value(&channel, item, DCTERMS_NS, DCTERMS_NS_PREFIX, "modified")
+                    .and_then(|date| DateTime::parse_from_rfc3339(date.trim()).ok())
+                    .filter(|date| *date != pub_date),
+                pub_date,
+                read_time_minutes,
+                language: item.dublin_core_ext.as_ref().and_then(|dc| dc.languages.first().cloned()),
+                thumbnail: item
+                    .extensions
+                    .get(MEDIA_NS_PREFIX)
+                    .and_then(|ext| ext.get("thumbnail"))
+                    .and_then(|values| values.first())
+                    .and_then(|value| value.attrs.get("url").cloned()),
+                enclosure: item.enclosure.as_ref().map(|enclosure| Enclosure {
+                    url: enclosure.url.clone(),
+                    mime_type: enclosure.mime_type.clone(),
+                    length: enclosure.length.parse().unwrap_or(0),
+                }),
+                comments: item.comments.clone(),
+                comment_count: rss_extension_value(&channel, item, SLASH_NS, SLASH_NS_PREFIX, "comments")
+                    .and_then(|count| count.trim().parse().ok()),
+                comments_feed: rss_extension_value(&channel, item, WFW_NS, WFW_NS_PREFIX, "commentRss").map(|url| url.trim().to_string()),
+                episode_number: item.itunes_ext.as_ref().and_then(|itunes| itunes.episode.as_deref()).and_then(|episode| episode.trim().parse().ok()),
+                categories: item.categories.iter().map(|category| category.name.clone()).collect(),
+                authors: item.dublin_core_ext.as_ref().map(|dc| dc.creators.clone()).unwrap_or_default(),
+                original_feed_url: item.source.as_ref().map(|source| source.url.clone()),
+                original_feed_title: item.source.as_ref().and_then(|source| source.title.clone()),
+            }
+        })
+        .collect();
+
+    Ok(FeedData {
+        title: channel.title.clone(),
+        // The channel description of an archived RSS feed describes the archive
+        subtitle: None,
+        url: url.to_string(),
+        resolved_url: url.to_string(),
+        items,
+        language: channel.language.clone(),
+        icon: channel.image.as_ref().map(|image| image.url.clone()),
+        copyright: channel.copyright.clone(),
+        slug: None,
+        priority: 0,
+        max_file_bytes: None,
+        base_url: None,
+        categories: Vec::new(),
+        display_title: None,
+        format: OutputFormat::Rss,
+        filtered_count: 0,
+        ttl: channel.ttl.as_deref().and_then(|ttl| ttl.trim().parse().ok()),
+        skip_hours: Vec::new(),
+        skip_days: Vec::new(),
+    })
+}
+
+/// Text of the first `name` extension element of an RSS item in `namespace`, whether
+/// the feed bound the namespace to its usual prefix `default_prefix` or another one.
+fn rss_extension_value<'a>(channel: &Channel, item: &'a Item, namespace: &str, default_prefix: &str, name: &str) -> Option<&'a str> {
+    let declared = channel.namespaces.iter().filter(|(_, uri)| uri.as_str() == namespace).map(|(prefix, _)| prefix.as_str());
+    std::iter::once(default_prefix)
+        .chain(declared)
+        .filter_map(|prefix| item.extensions.get(prefix)?.get(name)?.first()?.value.as_deref())
+        .find(|value| !value.trim().is_empty())
+}
+
+// A subscription taken from an external OPML file that is listed but not archived
+#[derive(Debug, Clone)]
+struct ExternalFeed {
+    title: String,
+    xml_url: String,
+    html_url: Option<String>,
+}
+
+/// Extracts all `type="rss"` outlines from an OPML document.
+fn parse_opml_feeds(opml: &str) -> Result<Vec<ExternalFeed>, Box<dyn Error + Send + Sync>> {
+    let mut reader = Reader::from_str(opml);
+    reader.config_mut().trim_text(true);
+
+    let mut feeds = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"outline" => {
+                let mut outline_type = None;
+                let mut text = None;
+                let mut title = None;
+                let mut xml_url = None;
+                let mut html_url = None;
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    let value = attr.unescape_value()?.to_string();
+                    match attr.key.as_ref() {
+                        b"type" => outline_type = Some(value),
+                        b"text" => text = Some(value),
+                        b"title" => title = Some(value),
+                        b"xmlUrl" => xml_url = Some(value),
+                        b"htmlUrl" => html_url = Some(value),
+                        _ => {}
+                    }
+                }
+
+                let is_rss = outline_type.is_some_and(|t| t.eq_ignore_ascii_case("rss"));
+                if let (true, Some(xml_url)) = (is_rss, xml_url) {
+                    let xml_url = xml_url.trim().to_string();
+                    if xml_url.is_empty() {
+                        continue;
+                    }
+                    feeds.push(ExternalFeed {
+                        title: title.or(text).unwrap_or_else(|| xml_url.clone()),
+                        xml_url,
+                        html_url,
+                    });
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(feeds)
+}
+
+/// Prepares text for an XML attribute value. Characters that XML 1.0 doesn't allow
+/// at all are dropped, and tabs and line breaks are replaced by spaces since parsers
+/// normalize them to spaces anyway.
+fn xml_attribute_value(value: &str) -> String {
+    value
+        .chars()
+        .filter_map(|c| match c {
+            '\t' | '\n' | '\r' => Some(' '),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// Text of an OPML outline, falling back to the feed URL for feeds without a title, as
+/// outlines must have a text.
+fn outline_text(title: &str, url: &str) -> String {
+    let text = xml_attribute_value(title);
+    if text.trim().is_empty() {
+        xml_attribute_value(url)
+    } else {
+        text
+    }
+}
+
+/// The OPML outline of an archived feed. Its categories from the feed list, and the
+/// quarantine marker, are listed in the `category` attribute as slash-delimited paths.
+fn feed_outline(feed: &FeedData, links: &ArchiveLinks, quarantined: &HashSet<String>, update_hint: Option<&(String, SyndicationExtension)>) -> BytesStart<'static> {
+    // push_attribute escapes &, <, >, " and ', but control characters must be dropped
+    let title = outline_text(feed.display_title(), &feed.url);
+    let mut outline_elem = BytesStart::new("outline");
+    outline_elem.push_attribute(("text", title.as_str()));
+    outline_elem.push_attribute(("title", title.as_str()));
+    outline_elem.push_attribute(("type", feed.format.opml_type()));
+
+    // Generate the individual feed URL for xmlUrl (RSS readers will fetch from our archive)
+    let archived_feed_url = links.feed_url(feed);
+    outline_elem.push_attribute(("xmlUrl", archived_feed_url.as_str()));
+
+    // Point htmlUrl to the site the feed is served from now, for human browsing
+    let html_url = site_url(&feed.resolved_url).unwrap_or_else(|| feed.url.clone());
+    outline_elem.push_attribute(("htmlUrl", xml_attribute_value(&html_url).as_str()));
+    let mut categories: Vec<String> = feed.categories.iter().map(|category| format!("/{}", xml_attribute_value(category.trim_matches('/')))).collect();
+    if quarantined.contains(&feed.url) {
+        categories.push("/quarantined".to_string());
+    }
+    if !categories.is_empty() {
+        outline_elem.push_attribute(("category", categories.join(",").as_str()));
+    }
+    // Non-standard attributes, mirroring the update hints of the archived feed
+    if let Some((ttl, hint)) = update_hint {
+        outline_elem.push_attribute(("ttl", ttl.as_str()));
+        outline_elem.push_attribute(("updatePeriod", hint.period.to_string().as_str()));
+        outline_elem.push_attribute(("updateFrequency", hint.frequency.to_string().as_str()));
+    }
+    outline_elem
+}
+
+/// Writes an OPML document listing all the feeds to `output`, one outline at a time.
+fn build_opml_feed_list<W: Write>(
+    feeds: &[FeedData],
+    external_feeds: &[ExternalFeed],
+    links: &ArchiveLinks,
+    quarantined: &HashSet<String>,
+    update_interval_minutes: Option<u64>,
+    output: W,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let update_hint = update_interval_minutes.map(|minutes| (minutes.to_string(), syndication_hint(minutes)));
+    let mut writer = Writer::new(output);
+    
+    // XML declaration
+    writer.write_event(Event::Decl(quick_xml::events::BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    
+    // Root opml element
+    let mut opml_elem = BytesStart::new("opml");
+    opml_elem.push_attribute(("version", "2.0"));
+    writer.write_event(Event::Start(opml_elem))?;
+    
+    // Head element
+    writer.write_event(Event::Start(BytesStart::new("head")))?;
+    
+    writer.write_event(Event::Start(BytesStart::new("title")))?;
+    writer.write_event(Event::Text(BytesText::new("RSS Feed Collection")))?;
+    writer.write_event(Event::End(BytesEnd::new("title")))?;
+    
+    let now = Utc::now();
+    let date_created = now.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    
+    writer.write_event(Event::Start(BytesStart::new("dateCreated")))?;
+    writer.write_event(Event::Text(BytesText::new(&date_created)))?;
+    writer.write_event(Event::End(BytesEnd::new("dateCreated")))?;
+    
+    writer.write_event(Event::Start(BytesStart::new("dateModified")))?;
+    writer.write_event(Event::Text(BytesText::new(&date_created)))?;
+    writer.write_event(Event::End(BytesEnd::new("dateModified")))?;
+    
+    writer.write_event(Event::End(BytesEnd::new("head")))?;
+    
+    // Body element
+    writer.write_event(Event::Start(BytesStart::new("body")))?;
+    
+    // Add each feed as an outline element. Feeds with categories are grouped under the
+    // first one, in the order the groups first appear, after the feeds without any.
+    let mut groups: Vec<(&str, Vec<&FeedData>)> = Vec::new();
+    for feed in feeds {
+        match feed.categories.first() {
+            Some(primary) => match groups.iter_mut().find(|(name, _)| name == primary) {
+                Some((_, members)) => members.push(feed),
+                None => groups.push((primary, vec![feed])),
+            },
+            None => writer.write_event(Event::Empty(feed_outline(feed, links, quarantined, update_hint.as_ref())))?,
+        }
+    }
+    for (name, members) in groups {
+        let mut category_elem = BytesStart::new("outline");
+        category_elem.push_attribute(("type", "category"));
+        category_elem.push_attribute(("text", outline_text(name, "Uncategorized").as_str()));
+        writer.write_event(Event::Start(category_elem))?;
+        for feed in members {
+            writer.write_event(Event::Empty(feed_outline(feed, links, quarantined, update_hint.as_ref())))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("outline")))?;
+    }
+
+    // Group pass-through subscriptions from a merged OPML, pointing at their original feeds
+    if !external_feeds.is_empty() {
+        let mut category_elem = BytesStart::new("outline");
+        category_elem.push_attribute(("type", "category"));
+        category_elem.push_attribute(("text", "External Feeds"));
+        writer.write_event(Event::Start(category_elem))?;
+
+        for feed in external_feeds {
+            let title = outline_text(&feed.title, &feed.xml_url);
+            let mut outline_elem = BytesStart::new("outline");
+            outline_elem.push_attribute(("text", title.as_str()));
+            outline_elem.push_attribute(("title", title.as_str()));
+            outline_elem.push_attribute(("type", "rss"));
+            outline_elem.push_attribute(("xmlUrl", xml_attribute_value(&feed.xml_url).as_str()));
+            if let Some(html_url) = &feed.html_url {
+                outline_elem.push_attribute(("htmlUrl", xml_attribute_value(html_url).as_str()));
+            }
+            writer.write_event(Event::Empty(outline_elem))?;
+        }
+
+        writer.write_event(Event::End(BytesEnd::new("outline")))?;
+    }
+    
+    writer.write_event(Event::End(BytesEnd::new("body")))?;
+    writer.write_event(Event::End(BytesEnd::new("opml")))?;
     Ok(())
 }
+
+// Settings affecting how archived feeds are rendered
+#[derive(Debug, Clone, Default)]
+struct RenderOptions {
+    // Replace item descriptions and content with links to their content files, see `content_store`
+    content_addressed: bool,
+    // Add an atom:link rel="self" to RSS channels
+    self_links: bool,
+    // WebSub hub announced in every archived feed
+    websub_hub: Option<String>,
+    // Number of the most frequent item categories listed as channel categories
+    channel_categories: usize,
+    // Emit slash:comments and wfw:commentRss on items that have them
+    comment_counts: bool,
+    // Emit the publication date of items as a dc:date in ISO 8601 as well
+    dc_dates: bool,
+    // Minutes between updates of the archive, announced with <ttl> and sy:updatePeriod
+    update_interval_minutes: Option<u64>,
+    copyright_rules: CopyrightRules,
+    // Referenced by an xml-stylesheet processing instruction in RSS and Atom files
+    stylesheet_href: Option<String>,
+}
+
+// Copyright notice templates of archived RSS channels, `{year}` standing for the current
+// year: by domain suffix from `copyright_rules`, longest first, and for other domains
+// `default_copyright_template`
+#[derive(Debug, Clone, Default)]
+struct CopyrightRules {
+    by_domain: Vec<(String, String)>,
+    default_template: Option<String>,
+}
+
+impl CopyrightRules {
+    fn new(rules: Option<&BTreeMap<String, String>>, default_template: Option<&str>) -> CopyrightRules {
+        let mut by_domain: Vec<(String, String)> = rules
+            .into_iter()
+            .flatten()
+            .map(|(domain, template)| (domain.trim().trim_matches('.').to_ascii_lowercase(), template.clone()))
+            .filter(|(domain, _)| !domain.is_empty())
+            .collect();
+        by_domain.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        CopyrightRules { by_domain, default_template: default_template.map(str::to_string) }
+    }
+}
+
+/// The copyright notice of the first rule in `rules` whose domain is the host of `feed_url`
+/// or one of its parent domains, with `{year}` replaced by the current year.
+fn select_copyright(feed_url: &str, rules: &CopyrightRules) -> Option<String> {
+    let url = reqwest::Url::parse(feed_url).ok()?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    rules
+        .by_domain
+        .iter()
+        .find(|(domain, _)| host == *domain || host.strip_suffix(domain.as_str()).is_some_and(|rest| rest.ends_with('.')))
+        .map(|(_, template)| fill_copyright_template(template))
+}
+
+fn fill_copyright_template(template: &str) -> String {
+    template.replace("{year}", &Utc::now().year().to_string())
+}
+
+/// Builds an RSS channel for an individual feed.
+fn build_individual_feed(
+    feed_data: &FeedData,
+    base_url: &str,
+    repo_name: &str,
+    feed_dir: &str,
+    filename: &str,
+    options: &RenderOptions,
+) -> Channel {
+    // A declared feed language wins; otherwise use the item language if all items agree
+    let channel_language = feed_data.language.clone().or_else(|| {
+        let first = feed_data.items.first()?.language.clone()?;
+        feed_data
+            .items
+            .iter()
+            .all(|fi| fi.language.as_deref() == Some(first.as_str()))
+            .then_some(first)
+    });
+
+    let rss_items: Vec<Item> = listing_order(feed_data)
+        .into_iter()
+        .map(|fi| {
+            let mut builder = ItemBuilder::default();
+            builder.guid(Guid {
+                value: fi.guid.clone(),
+                permalink: !fi.guid_synthesized && fi.guid == fi.link,
+            });
+            builder.title(fi.title.clone());
+            builder.link(fi.link.clone());
+            builder.comments(fi.comments.clone());
+            // Point back to the live feed rather than this archive; items of a combined feed
+            // keep the feed of their member
+            builder.source(rss::Source {
+                url: fi.original_feed_url.clone().unwrap_or_else(|| feed_data.url.clone()),
+                title: fi.original_feed_title.clone().or_else(|| Some(feed_data.title.clone())),
+            });
+            builder.categories(fi.categories.iter().map(|name| Category { name: name.clone(), domain: None }).collect::<Vec<_>>());
+            builder.itunes_ext(fi.episode_number.map(|episode| ITunesItemExtension {
+                episode: Some(episode.to_string()),
+                ..ITunesItemExtension::default()
+            }));
+            if let Some(desc) = &fi.description {
+                builder.description(desc.clone());
+            }
+            if let Some(content) = &fi.content {
+                builder.content(content.clone());
+            }
+            // Format the publication date as RFC 2822 for RSS
+            builder.pub_date(fi.pub_date.to_rfc2822());
+            if let Some(enclosure) = &fi.enclosure {
+                builder.enclosure(rss::Enclosure {
+                    url: enclosure.url.clone(),
+                    length: enclosure.length.to_string(),
+                    mime_type: enclosure.mime_type.clone(),
+                });
+            }
+            // Authors become dc:creator, as <author> must be an email address. Items whose
+            // language differs from the channel's get their own dc:language. The dc:date is
+            // the pubDate in ISO 8601, for consumers that don't parse RFC 2822.
+            let languages: Vec<String> = fi.language.iter().filter(|_| fi.language != channel_language).cloned().collect();
+            let dates: Vec<String> = if options.dc_dates { vec![fi.pub_date.to_rfc3339()] } else { Vec::new() };
+            if !fi.authors.is_empty() || !languages.is_empty() || !dates.is_empty() {
+                builder.dublin_core_ext(DublinCoreExtension {
+                    creators: fi.authors.clone(),
+                    languages,
+                    dates,
+                    ..Default::default()
+                });
+            }
+            let mut extensions = ExtensionMap::new();
+            if let Some(updated) = fi.updated_date {
+                let modified = ExtensionBuilder::default()
+                    .name(format!("{}:modified", DCTERMS_NS_PREFIX))
+                    .value(Some(updated.to_rfc3339()))
+                    .build();
+                extensions
+                    .entry(DCTERMS_NS_PREFIX.to_string())
+                    .or_default()
+                    .insert("modified".to_string(), vec![modified]);
+            }
+            if let Some(minutes) = fi.read_time_minutes {
+                let read_time = ExtensionBuilder::default()
+                    .name(format!("{}:readTime", AGGREGATOR_NS_PREFIX))
+                    .value(Some(minutes.to_string()))
+                    .build();
+                extensions
+                    .entry(AGGREGATOR_NS_PREFIX.to_string())
+                    .or_default()
+                    .insert("readTime".to_string(), vec![read_time]);
+            }
+            if let Some(thumbnail) = &fi.thumbnail {
+                let thumbnail = ExtensionBuilder::default()
+                    .name(format!("{}:thumbnail", MEDIA_NS_PREFIX))
+                    .attrs(BTreeMap::from([("url".to_string(), thumbnail.clone())]))
+                    .build();
+                extensions
+                    .entry(MEDIA_NS_PREFIX.to_string())
+                    .or_default()
+                    .insert("thumbnail".to_string(), vec![thumbnail]);
+            }
+            if options.comment_counts {
+                if let Some(count) = fi.comment_count {
+                    let count = ExtensionBuilder::default()
+                        .name(format!("{}:comments", SLASH_NS_PREFIX))
+                        .value(Some(count.to_string()))
+                        .build();
+                    extensions
+                        .entry(SLASH_NS_PREFIX.to_string())
+                        .or_default()
+                        .insert("comments".to_string(), vec![count]);
+                }
+                if let Some(comments_feed) = &fi.comments_feed {
+                    let comments_feed = ExtensionBuilder::default()
+                        .name(format!("{}:commentRss", WFW_NS_PREFIX))
+                        .value(Some(comments_feed.clone()))
+                        .build();
+                    extensions
+                        .entry(WFW_NS_PREFIX.to_string())
+                        .or_default()
+                        .insert("commentRss".to_string(), vec![comments_feed]);
+                }
+            }
+            if !extensions.is_empty() {
+                builder.extensions(extensions);
+            }
+            let item = builder.build();
+            if options.content_addressed {
+                let reference_url = format!("{}/{}", base_url, content_path(&StoredContent::of(fi).hash()));
+                return build_content_store_reference_item(&item, &reference_url);
+            }
+            item
+        })
+        .collect();
+
+    let github_link = archived_feed_url(feed_base_url(feed_data, base_url), feed_dir, filename);
+
+    // Only declare the archiver, dcterms, media, slash and wfw namespaces when an item actually uses them
+    let mut namespaces = BTreeMap::new();
+    if feed_data.items.iter().any(|fi| fi.updated_date.is_some()) {
+        namespaces.insert(DCTERMS_NS_PREFIX.to_string(), DCTERMS_NS.to_string());
+    }
+    if feed_data.items.iter().any(|fi| fi.read_time_minutes.is_some()) {
+        namespaces.insert(AGGREGATOR_NS_PREFIX.to_string(), AGGREGATOR_NS.to_string());
+    }
+    if feed_data.items.iter().any(|fi| fi.thumbnail.is_some()) {
+        namespaces.insert(MEDIA_NS_PREFIX.to_string(), MEDIA_NS.to_string());
+    }
+    if options.comment_counts && feed_data.items.iter().any(|fi| fi.comment_count.is_some()) {
+        namespaces.insert(SLASH_NS_PREFIX.to_string(), SLASH_NS.to_string());
+    }
+    if options.comment_counts && feed_data.items.iter().any(|fi| fi.comments_feed.is_some()) {
+        namespaces.insert(WFW_NS_PREFIX.to_string(), WFW_NS.to_string());
+    }
+    if options.update_interval_minutes.is_some() {
+        namespaces.insert(SYNDICATION_NS_PREFIX.to_string(), SYNDICATION_NS.to_string());
+    }
+
+    // WebSub requires a self link next to the hub link
+    let mut atom_links = Vec::new();
+    if options.self_links || options.websub_hub.is_some() {
+        atom_links.push(
+            LinkBuilder::default()
+                .href(github_link.clone())
+                .rel("self")
+                .mime_type(Some("application/rss+xml".to_string()))
+                .build(),
+        );
+    }
+    if let Some(hub) = &options.websub_hub {
+        atom_links.push(LinkBuilder::default().href(hub.clone()).rel("hub").build());
+    }
+
+    ChannelBuilder::default()
+        .namespaces(namespaces)
+        .atom_ext((!atom_links.is_empty()).then_some(AtomExtension { links: atom_links }))
+        .categories(
+            channel_categories(feed_data, options.channel_categories)
+                .into_iter()
+                .map(|name| Category { name, domain: None })
+                .collect::<Vec<_>>(),
+        )
+        .title(feed_data.display_title())
+        .image(feed_data.icon.as_ref().map(|icon| Image {
+            url: icon.clone(),
+            title: feed_data.display_title().to_string(),
+            link: github_link.clone(),
+            ..Image::default()
+        }))
+        .link(github_link)
+        .description(format!("Archived feed from {}", feed_data.resolved_url))
+        .language(channel_language)
+        // A rule for the feed's domain wins over the feed's own notice, which wins over the default
+        .copyright(
+            select_copyright(&feed_data.url, &options.copyright_rules)
+                .or_else(|| feed_data.copyright.clone())
+                .or_else(|| options.copyright_rules.default_template.as_deref().map(fill_copyright_template)),
+        )
+        .generator(Some(format!("{} {} (https://github.com/{})", GENERATOR_NAME, GENERATOR_VERSION, repo_name)))
+        // The archive's own schedule wins over the source feed's TTL, which says how often
+        // the source changes rather than the archive
+        .ttl(options.update_interval_minutes.map(|minutes| minutes.to_string()).or_else(|| feed_data.ttl.map(|ttl| ttl.to_string())))
+        .syndication_ext(options.update_interval_minutes.map(syndication_hint))
+        .items(rss_items)
+        .build()
+}
+
+/// The syndication module's hint closest to an update every `minutes`: the shortest of
+/// hourly, daily, weekly, monthly and yearly that is at least that long, and how many
+/// times per period the archive is updated.
+fn syndication_hint(minutes: u64) -> SyndicationExtension {
+    let periods = [
+        (UpdatePeriod::Hourly, 60),
+        (UpdatePeriod::Daily, 24 * 60),
+        (UpdatePeriod::Weekly, 7 * 24 * 60),
+        (UpdatePeriod::Monthly, 30 * 24 * 60),
+        (UpdatePeriod::Yearly, 365 * 24 * 60),
+    ];
+    let minutes = minutes.max(1);
+    let (period, period_minutes) = periods
+        .iter()
+        .find(|(_, period_minutes)| minutes <= *period_minutes)
+        .cloned()
+        .unwrap_or((UpdatePeriod::Yearly, minutes));
+    SyndicationExtension {
+        period,
+        frequency: (period_minutes / minutes).max(1) as u32,
+        ..Default::default()
+    }
+}
+
+// Limits applied to every archived feed file
+#[derive(Debug, Clone, Copy)]
+struct FileLimits {
+    // Maximum number of items, 0 means unlimited
+    max_items: usize,
+    // Default size budget in bytes, 0 means unlimited
+    max_file_bytes: u64,
+    budget_strategy: BudgetStrategy,
+}
+
+impl FileLimits {
+    /// Size budget of a feed's file: its own from the feed list, or the configured one.
+    fn max_file_bytes(&self, feed_data: &FeedData) -> u64 {
+        feed_data.max_file_bytes.unwrap_or(self.max_file_bytes)
+    }
+
+    /// Truncates a feed to `max_items`, renders it with `render` and trims it to its size
+    /// budget. Returns the feed as written, its rendering and what was trimmed.
+    fn apply<F>(&self, feed_data: &FeedData, render: F) -> Result<(FeedData, String, Trimmed), Box<dyn Error + Send + Sync>>
+    where
+        F: Fn(&FeedData) -> Result<String, Box<dyn Error + Send + Sync>>,
+    {
+        let mut limited_feed_data = feed_data.clone();
+        if self.max_items > 0 {
+            limited_feed_data.items.truncate(self.max_items);
+        }
+        fit_to_budget(&limited_feed_data, self.max_file_bytes(feed_data), self.budget_strategy, render)
+    }
+}
+
+/// Applies the per-feed filters of a feed list entry, recording how many items were dropped.
+fn apply_feed_filters(feed_data: &mut FeedData, spec: &FeedSpec) {
+    let before = feed_data.items.len();
+    feed_data.items.retain(|item| enclosure_rule(spec, item).is_none());
+    feed_data.filtered_count = before - feed_data.items.len();
+}
+
+/// Returns the option of a feed list entry dropping `item` for its enclosure, if any.
+fn enclosure_rule(spec: &FeedSpec, item: &FeedItem) -> Option<&'static str> {
+    if spec.require_enclosure && item.enclosure.is_none() {
+        Some("require_enclosure")
+    } else if spec.exclude_enclosures && item.enclosure.is_some() {
+        Some("exclude_enclosures")
+    } else {
+        None
+    }
+}
+
+/// Adds the items of a fetched feed that the per-feed filters of its feed list lines drop
+/// to `rejects`, as `apply_feed_filters` will.
+fn record_filtered_items(feed_data: &FeedData, list_specs: &[Vec<FeedSpec>], rejects: &mut Vec<RejectedItem>) {
+    for spec in list_specs.iter().flatten().filter(|spec| spec.url == feed_data.url) {
+        for item in &feed_data.items {
+            if let Some(rule) = enclosure_rule(spec, item) {
+                rejects.push(RejectedItem {
+                    item: item.clone(),
+                    rule,
+                    feed_url: feed_data.url.clone(),
+                    feed_title: feed_data.title.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Links items to their discussion page instead of the article, for aggregator feeds such
+/// as Hacker News. The article URL is added to the description. Guids are left alone, so
+/// the item is still recognized as the same item by deduplication and other variants of
+/// the feed. Items without a comments link are unchanged.
+fn prefer_comments_links(feed_data: &mut FeedData) {
+    for item in &mut feed_data.items {
+        let Some(comments) = item.comments.clone().filter(|comments| *comments != item.link) else {
+            continue;
+        };
+        let article = std::mem::replace(&mut item.link, comments);
+        if !article.is_empty() {
+            let article = quick_xml::escape::escape(article.as_str());
+            let article_link = format!("<p>Article: <a href=\"{}\">{}</a></p>", article, article);
+            item.description = Some(match item.description.take() {
+                Some(description) => format!("{}\n{}", description, article_link),
+                None => article_link,
+            });
+        }
+    }
+}
+
+/// Names the member feed in the items fetched from it for a combined feed: prefixes their
+/// titles with `[Feed Title] ` and appends a "via" link to the site of the feed to their
+/// descriptions, as `annotation` says. Items republished from another feed keep their own
+/// source.
+fn annotate_source(member: &mut FeedData, annotation: SourceAnnotation) {
+    let site = site_url(&member.resolved_url).unwrap_or_else(|| member.resolved_url.clone());
+    let via = format!(
+        "<p>via <a href=\"{}\">{}</a></p>",
+        quick_xml::escape::escape(site.as_str()),
+        quick_xml::escape::escape(member.title.as_str())
+    );
+    for item in &mut member.items {
+        if annotation.title {
+            item.title = format!("[{}] {}", member.title, item.title);
+        }
+        if annotation.description {
+            item.description = Some(match item.description.take() {
+                Some(description) => format!("{}\n{}", description, via),
+                None => via.clone(),
+            });
+        }
+    }
+}
+
+/// Points the `<source>` of the items of combined feeds at the archive of their member,
+/// for members that are also archived on their own in the same list. `combined` holds the
+/// positions of the combined feeds in `feeds`.
+fn link_sources_to_archives(feeds: &mut [FeedData], combined: &[usize], links: &ArchiveLinks) {
+    let mut archives: HashMap<String, String> = HashMap::new();
+    for (_, feed_data) in feeds.iter().enumerate().filter(|(index, _)| !combined.contains(index)) {
+        let url = links.feed_url(feed_data);
+        archives.entry(feed_data.url.clone()).or_insert(url);
+    }
+    for &index in combined {
+        for item in &mut feeds[index].items {
+            if let Some(archive) = item.original_feed_url.as_ref().and_then(|url| archives.get(url)) {
+                item.original_feed_url = Some(archive.clone());
+            }
+        }
+    }
+}
+
+/// Limits a combined list of `(feed priority, item)` pairs to `max_items` (0 means
+/// unlimited) and returns the items newest first.
+///
+/// Items are dropped from the lowest priority feeds first, oldest first, and higher
+/// priorities are only cut into once all lower priority items are gone. This is the
+/// same as keeping the first `max_items` items ordered by priority (highest first)
+/// and then by date (newest first).
+fn truncate_by_priority(mut items: Vec<(i32, FeedItem)>, max_items: usize) -> Vec<FeedItem> {
+    if max_items > 0 && items.len() > max_items {
+        items.sort_by(|(a_priority, a), (b_priority, b)| b_priority.cmp(a_priority).then(b.pub_date.cmp(&a.pub_date)));
+        items.truncate(max_items);
+    }
+    let mut items: Vec<FeedItem> = items.into_iter().map(|(_, item)| item).collect();
+    items.sort_by_key(|item| std::cmp::Reverse(item.pub_date));
+    items
+}
+
+/// Sorts items newest first, breaking ties by guid, so that the order only depends on the
+/// items themselves and new items end up in front of the known ones.
+fn sort_items_stably(items: &mut [FeedItem]) {
+    items.sort_by(|a, b| b.pub_date.cmp(&a.pub_date).then_with(|| a.guid.cmp(&b.guid)));
+}
+
+/// Categories describing a feed as a whole: the categories given in the feed list, followed
+/// by the `count` most frequent item categories, ties broken alphabetically.
+fn channel_categories(feed_data: &FeedData, count: usize) -> Vec<String> {
+    let mut frequencies: BTreeMap<&str, usize> = BTreeMap::new();
+    for name in feed_data.items.iter().flat_map(|item| item.categories.iter()) {
+        *frequencies.entry(name.as_str()).or_default() += 1;
+    }
+    let mut by_frequency: Vec<(&str, usize)> = frequencies.into_iter().collect();
+    // Stable sort, so equally frequent categories stay in alphabetical order
+    by_frequency.sort_by_key(|&(_, frequency)| std::cmp::Reverse(frequency));
+
+    let mut categories: Vec<String> = feed_data.categories.clone();
+    for (name, _) in by_frequency.into_iter().take(count) {
+        if !categories.iter().any(|category| category == name) {
+            categories.push(name.to_string());
+        }
+    }
+    categories
+}
+
+/// Renders an individual feed in its output format.
+fn render_feed(
+    feed_data: &FeedData,
+    base_url: &str,
+    repo_name: &str,
+    feed_dir: &str,
+    filename: &str,
+    options: &RenderOptions,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let self_url = archived_feed_url(feed_base_url(feed_data, base_url), feed_dir, filename);
+    let xml = match feed_data.format {
+        OutputFormat::Rss => build_individual_feed(feed_data, base_url, repo_name, feed_dir, filename, options).to_string(),
+        OutputFormat::Atom => {
+            let categories = channel_categories(feed_data, options.channel_categories);
+            build_atom_feed(feed_data, &self_url, repo_name, options.websub_hub.as_deref(), &categories).to_string()
+        }
+        OutputFormat::Json => return Ok(build_json_feed(feed_data, &self_url, options.websub_hub.as_deref())?),
+    };
+    Ok(match &options.stylesheet_href {
+        Some(href) => with_stylesheet(xml, href),
+        None => xml,
+    })
+}
+
+/// Adds an xml-stylesheet processing instruction referencing `href` after the XML
+/// declaration of `xml`, so that browsers opening the file render it with the stylesheet.
+/// XSLT stylesheets are recognized by their extension, anything else is taken as CSS.
+fn with_stylesheet(xml: String, href: &str) -> String {
+    let path = href.split(['?', '#']).next().unwrap_or(href).to_ascii_lowercase();
+    let media_type = if path.ends_with(".xsl") || path.ends_with(".xslt") { "text/xsl" } else { "text/css" };
+    let instruction = format!("<?xml-stylesheet type=\"{}\" href=\"{}\"?>", media_type, quick_xml::escape::escape(href));
+    let declaration_end = if xml.starts_with("<?xml ") { xml.find("?>").map_or(0, |end| end + 2) } else { 0 };
+    format!("{}{}{}", &xml[..declaration_end], instruction, &xml[declaration_end..])
+}
+
+/// Fills in the language of items that declare none, using cached results where possible.
+/// Feeds declaring a language are left untouched.
+fn detect_item_languages(feed_data: &mut FeedData, cache: &mut BTreeMap<String, Option<String>>, min_confidence: f64) {
+    if feed_data.language.is_some() {
+        return;
+    }
+    for item in feed_data.items.iter_mut().filter(|item| item.language.is_none()) {
+        item.language = detected_item_language(item, cache, min_confidence);
+    }
+}
+
+/// Detects the language of an item from its title and description, looking it up in and
+/// adding it to `cache`, keyed by guid.
+fn detected_item_language(item: &FeedItem, cache: &mut BTreeMap<String, Option<String>>, min_confidence: f64) -> Option<String> {
+    cache
+        .entry(item.guid.clone())
+        .or_insert_with(|| {
+            let text = format!(
+                "{} {}",
+                item.title,
+                item.description.as_deref().map(html_to_plain_text).unwrap_or_default()
+            );
+            detect_text_language(&text, min_confidence)
+        })
+        .clone()
+}
+
+/// Detects the ISO 639-1 language code of a text. Returns None rather than a guess
+/// when the detection confidence is below `min_confidence`.
+fn detect_text_language(text: &str, min_confidence: f64) -> Option<String> {
+    let info = whatlang::detect(text)?;
+    if info.confidence() < min_confidence {
+        return None;
+    }
+    isolang::Language::from_639_3(info.lang().code())
+        .and_then(|language| language.to_639_1())
+        .map(|code| code.to_string())
+}
+
+// Finds the images of items without any media, compiled once per run
+struct ImageExtractor {
+    img_re: Regex,
+    attr_re: Regex,
+}
+
+impl ImageExtractor {
+    fn new() -> ImageExtractor {
+        ImageExtractor {
+            img_re: Regex::new(r"(?is)<img\b([^>]*)>").unwrap(),
+            attr_re: Regex::new(r#"(?is)([a-z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap(),
+        }
+    }
+
+    /// Uses the first image in the HTML of items without any media as their thumbnail.
+    fn extract_item_images(&self, feed_data: &mut FeedData) {
+        for item in feed_data.items.iter_mut() {
+            if item.thumbnail.is_some() || item.enclosure.is_some() {
+                continue;
+            }
+            // Relative image URLs are resolved against the item link, or the feed URL without one
+            let base = if item.link.is_empty() { &feed_data.url } else { &item.link };
+            item.thumbnail = [&item.description, &item.content]
+                .into_iter()
+                .flatten()
+                .find_map(|html| self.first_image_url(html, base));
+        }
+    }
+
+    /// Returns the absolute URL of the first `<img>` in the HTML that isn't a tracking pixel.
+    fn first_image_url(&self, html: &str, base: &str) -> Option<String> {
+        // Images declared at most this many pixels wide or high are tracking pixels or spacers
+        let is_tiny = |size: &str| {
+            let digits: String = size.trim().chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse::<u32>().is_ok_and(|pixels| pixels <= 2)
+        };
+
+        self.img_re.captures_iter(html).find_map(|tag| {
+            let mut src = None;
+            let mut tiny = false;
+            for attr in self.attr_re.captures_iter(&tag[1]) {
+                let value = attr.get(2).or(attr.get(3)).or(attr.get(4)).map_or("", |m| m.as_str());
+                match attr[1].to_ascii_lowercase().as_str() {
+                    "src" => src = Some(value.trim().replace("&amp;", "&")),
+                    "width" | "height" => tiny |= is_tiny(value),
+                    _ => {}
+                }
+            }
+            let src = src.filter(|src| !src.is_empty() && !src.starts_with("data:"))?;
+            if tiny {
+                return None;
+            }
+            let url = match reqwest::Url::parse(base) {
+                Ok(base) => base.join(&src).ok()?,
+                Err(_) => reqwest::Url::parse(&src).ok()?,
+            };
+            matches!(url.scheme(), "http" | "https").then(|| url.to_string())
+        })
+    }
+}
+
+/// Strips HTML tags from a string, leaving the text content.
+fn html_to_plain_text(html: &str) -> String {
+    static TAG_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+    TAG_RE.replace_all(html, " ").to_string()
+}
+
+/// The text of an HTML fragment with its whitespace collapsed, cut to its first words so
+/// that it is at most `max_chars` characters long (plus an ellipsis when cut).
+fn plain_text_excerpt(html: &str, max_chars: usize) -> String {
+    let text = html_to_plain_text(html).split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+    let cut: String = text.chars().take(max_chars).collect();
+    let cut = match cut.rfind(' ') {
+        Some(space) => &cut[..space],
+        None => cut.as_str(),
+    };
+    format!("{}…", cut)
+}
+
+/// Estimates the reading time of an item in minutes at 250 words per minute,
+/// clamped to the range 1 to 99.
+fn estimate_read_time(content: &str) -> u8 {
+    let words = html_to_plain_text(content).split_whitespace().count();
+    words.div_ceil(WORDS_PER_MINUTE).clamp(1, 99) as u8
+}
+
+/// Checks that a URL starts with http:// or https:// followed by a host.
+fn is_absolute_http_url(url: &str) -> bool {
+    ["http://", "https://"]
+        .iter()
+        .filter_map(|scheme| url.strip_prefix(scheme))
+        .any(|rest| rest.split(['/', '?', '#']).next().is_some_and(|host| !host.is_empty()))
+}
+
+// Where the archived feeds of a directory are published
+struct ArchiveLinks<'a> {
+    layout: ArchiveLayout,
+    base_url: &'a str,
+    feed_dir: &'a str,
+}
+
+impl ArchiveLinks<'_> {
+    /// The URL of a feed's archived file, under its own base URL if it has one.
+    fn feed_url(&self, feed_data: &FeedData) -> String {
+        archived_feed_url(feed_base_url(feed_data, self.base_url), self.feed_dir, &self.layout.feed_filename(feed_data))
+    }
+}
+
+/// Returns the base URL a feed is published under: its own from the feed list, or `base_url`.
+fn feed_base_url<'a>(feed_data: &'a FeedData, base_url: &'a str) -> &'a str {
+    feed_data.base_url.as_deref().unwrap_or(base_url)
+}
+
+/// Builds the public URL of an archived feed file.
+fn archived_feed_url(base_url: &str, feed_dir: &str, filename: &str) -> String {
+    format!("{}/{}/{}", base_url, feed_dir, filename)
+}
+
+/// Converts a string to kebab-case for use as a filename, transliterating non-ASCII
+/// characters. Results longer than `max_length` (0 means unlimited) are cut at the last
+/// word boundary that fits, or mid-word if the first word alone is too long.
+fn to_kebab_case(input: &str, max_length: usize) -> String {
+    // Decompose characters (é into e and an accent, ﬁ into fi), drop the accents and
+    // transliterate what is left (CJK, Cyrillic, ...) so that names stay plain ASCII
+    let decomposed: String = input.nfkd().filter(|&c| !is_combining_mark(c)).collect();
+    let lowercase = deunicode(&decomposed).to_lowercase();
+    let re = Regex::new(r"[^a-zA-Z0-9]+").unwrap();
+    let kebab = re.replace_all(&lowercase, "-");
+    let kebab = kebab.trim_matches('-');
+    if max_length == 0 || kebab.len() <= max_length {
+        return kebab.to_string();
+    }
+    // Only ASCII letters, digits and hyphens are left, so any byte index is a char boundary
+    let cut = match kebab[..=max_length].rfind('-') {
+        Some(boundary) if boundary > 0 => &kebab[..boundary],
+        _ => &kebab[..max_length],
+    };
+    cut.trim_end_matches('-').to_string()
+}
+
+// How the archived feeds are named within the directory of their list
+#[derive(Debug, Clone, Copy)]
+struct ArchiveLayout {
+    // Maximum length of the name part of filenames: `max_filename_length`, with 0 meaning
+    // FILENAME_LENGTH_LIMIT, which also caps larger values
+    max_filename_length: usize,
+    // Whether archived feeds go into year/month directories, from `output_dir_structure`
+    dated_dirs: bool,
+}
+
+impl Default for ArchiveLayout {
+    fn default() -> Self {
+        ArchiveLayout::new(&Config::default())
+    }
+}
+
+impl ArchiveLayout {
+    fn new(config: &Config) -> ArchiveLayout {
+        let max_filename_length = match config.max_filename_length.unwrap_or(DEFAULT_MAX_FILENAME_LENGTH) {
+            0 => FILENAME_LENGTH_LIMIT,
+            length => length.min(FILENAME_LENGTH_LIMIT),
+        };
+        ArchiveLayout {
+            max_filename_length,
+            dated_dirs: config.output_dir_structure == Some(OutputDirStructure::YearMonth),
+        }
+    }
+
+    /// Returns the path of a feed's archive within the directory of its list: its filename,
+    /// including the extension of its format, in the year/month directory of its newest
+    /// item with `output_dir_structure = "year/month"`.
+    /// Every path in the archive directories is built here, so that cleanup_old_feeds keeps
+    /// and removes exactly the files written.
+    fn feed_filename(&self, feed_data: &FeedData) -> String {
+        let filename = self.archive_filename(&feed_data.url, &feed_data.title, feed_data.slug.as_deref(), feed_data.format);
+        let newest = feed_data.items.iter().map(|item| item.pub_date).max();
+        let path = match newest.filter(|_| self.dated_dirs) {
+            Some(newest) => format!("{}/{}", newest.with_timezone(&Utc).format("%Y/%m"), filename),
+            // Feeds without items have no month to go into
+            None => filename,
+        };
+        // The path is joined to the list's directory and must stay inside it
+        if path.split('/').all(is_safe_segment) {
+            path
+        } else {
+            format!("{}.{}", generate_unique_filename_for_feed(&feed_data.url, "", self.max_filename_length), feed_data.format.extension())
+        }
+    }
+
+    /// Returns the filename of a feed archived in `format`, extension included. Uses the slug
+    /// given in the feed list when there is one, otherwise generates a unique name from the
+    /// feed's URL and title. The name only ever consists of `[a-z0-9-]`; anything else falls
+    /// back to the name generated from the URL alone. A filename that is one of the
+    /// RESERVED_FILES gets a `-feed` suffix.
+    fn archive_filename(&self, url: &str, title: &str, slug: Option<&str>, format: OutputFormat) -> String {
+        let name = match slug.map(|slug| to_kebab_case(slug, self.max_filename_length)).filter(|slug| !slug.is_empty()) {
+            Some(slug) => slug,
+            None => generate_unique_filename_for_feed(url, title, self.max_filename_length),
+        };
+        let name = sanitize_filename(&name);
+        let name = if name.is_empty() || !name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-') {
+            generate_unique_filename_for_feed(url, "", self.max_filename_length)
+        } else {
+            name
+        };
+        let filename = format!("{}.{}", name, format.extension());
+        if RESERVED_FILES.contains(&filename.as_str()) {
+            format!("{}-feed.{}", name, format.extension())
+        } else {
+            filename
+        }
+    }
+}
+
+/// Returns true for the `2024/11` directories of `output_dir_structure = "year/month"`.
+fn is_dated_dir(year: &str, month: &str) -> bool {
+    year.len() == 4 && year.bytes().all(|b| b.is_ascii_digit()) && month.len() == 2 && matches!(month.parse::<u32>(), Ok(1..=12))
+}
+
+/// Lists the files in `feed_dir` and in its year/month directories, as paths relative
+/// to `feed_dir`. The dated directories are listed whatever `output_dir_structure` is,
+/// so that changing it leaves no files behind.
+async fn list_archived_files(storage: &dyn Storage, feed_dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let mut files = storage.list(feed_dir).await?;
+    for year in storage.list_dirs(feed_dir).await? {
+        for month in storage.list_dirs(&format!("{}/{}", feed_dir, year)).await? {
+            if !is_dated_dir(&year, &month) {
+                continue;
+            }
+            let dir = format!("{}/{}/{}", feed_dir, year, month);
+            files.extend(storage.list(&dir).await?.into_iter().map(|name| format!("{}/{}/{}", year, month, name)));
+        }
+    }
+    Ok(files)
+}
+
+// The archived files of the list directories, each listed once per run rather than once
+// per lookup, which would take a listing per year/month directory and feed
+struct ArchivedFiles<'a> {
+    storage: &'a dyn Storage,
+    dated_dirs: bool,
+    // Path of every archived file by filename, for each directory listed so far
+    dirs: Mutex<HashMap<String, HashMap<String, String>>>,
+}
+
+impl<'a> ArchivedFiles<'a> {
+    fn new(storage: &'a dyn Storage, layout: ArchiveLayout) -> ArchivedFiles<'a> {
+        ArchivedFiles {
+            storage,
+            dated_dirs: layout.dated_dirs,
+            dirs: Mutex::default(),
+        }
+    }
+
+    /// Finds the archived file named `filename` in `feed_dir` or, with year/month
+    /// directories, in the newest of them holding it. Returns its path, or None if there
+    /// is no archived copy.
+    async fn find(&self, feed_dir: &str, filename: &str) -> Option<String> {
+        self.list(feed_dir).await;
+        self.dirs.lock().unwrap().get(feed_dir)?.get(filename).cloned()
+    }
+
+    /// Records that the file at `path` was written in `feed_dir`, so that it is found
+    /// instead of the copies archived before.
+    async fn record(&self, feed_dir: &str, path: &str) {
+        self.list(feed_dir).await;
+        let filename = path.rsplit('/').next().unwrap_or(path);
+        if let Some(files) = self.dirs.lock().unwrap().get_mut(feed_dir) {
+            files.insert(filename.to_string(), path.to_string());
+        }
+    }
+
+    /// Records that the file at `from` in `feed_dir` was moved to `to`.
+    async fn record_move(&self, feed_dir: &str, from: &str, to: &str) {
+        self.record(feed_dir, to).await;
+        let filename = from.rsplit('/').next().unwrap_or(from);
+        if let Some(files) = self.dirs.lock().unwrap().get_mut(feed_dir) {
+            if files.get(filename).is_some_and(|path| path == from) {
+                files.remove(filename);
+            }
+        }
+    }
+
+    /// Lists the files in `feed_dir` and, with year/month directories, in those, unless it
+    /// was listed before. Directories that can't be listed are left out with a warning, the
+    /// files of the others are still found.
+    async fn list(&self, feed_dir: &str) {
+        if self.dirs.lock().unwrap().contains_key(feed_dir) {
+            return;
+        }
+        let list_files = |dir: String| async move {
+            self.storage.list(&dir).await.unwrap_or_else(|e| {
+                eprintln!("Warning: Could not list archived feeds in {}: {}", dir, e);
+                Vec::new()
+            })
+        };
+        let list_dirs = |dir: String| async move {
+            self.storage.list_dirs(&dir).await.unwrap_or_else(|e| {
+                eprintln!("Warning: Could not list archived feeds in {}: {}", dir, e);
+                Vec::new()
+            })
+        };
+
+        // Files archived before switching to year/month directories are still found
+        let mut files: HashMap<String, String> = list_files(feed_dir.to_string())
+            .await
+            .into_iter()
+            .map(|name| (name.clone(), format!("{}/{}", feed_dir, name)))
+            .collect();
+        if self.dated_dirs {
+            // Listed oldest first, so that the newest directory holding a file wins
+            for year in list_dirs(feed_dir.to_string()).await {
+                for month in list_dirs(format!("{}/{}", feed_dir, year)).await {
+                    if !is_dated_dir(&year, &month) {
+                        continue;
+                    }
+                    let dir = format!("{}/{}/{}", feed_dir, year, month);
+                    for name in list_files(dir.clone()).await {
+                        files.insert(name.clone(), format!("{}/{}", dir, name));
+                    }
+                }
+            }
+        }
+        self.dirs.lock().unwrap().entry(feed_dir.to_string()).or_insert(files);
+    }
+}
+
+/// Makes a filename usable on Windows as well: drops trailing dots and spaces, which
+/// Windows strips silently, and renames reserved device names such as `con` or
+/// `PRN.xml`, which can't be used as filenames whatever their extension.
+fn sanitize_filename(name: &str) -> String {
+    let name = name.trim_end_matches(['.', ' ']);
+    if is_reserved_windows_name(name) {
+        format!("{}-feed", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Returns true if the part of `name` before the first dot is a Windows device name.
+fn is_reserved_windows_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end().to_ascii_uppercase();
+    matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit())
+}
+
+/// Generates a unique filename for a feed based on URL and title, its title part at most
+/// `max_length` long. This ensures one file per feed URL, preventing collisions.
+fn generate_unique_filename_for_feed(url: &str, title: &str, max_length: usize) -> String {
+    // Use a combination of title and URL hash to create unique filenames
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    
+    // Create a hash of the URL to ensure uniqueness
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    let url_hash = hasher.finish();
+    
+    // Use title as base, but add URL hash for uniqueness
+    let base_title = to_kebab_case(title, max_length);
+    
+    // If title is too generic or empty, use domain from URL
+    let filename_base = if base_title.is_empty() || base_title.len() < 3 {
+        extract_domain_from_url(url, max_length).filter(|domain| !domain.is_empty()).unwrap_or_else(|| "feed".to_string())
+    } else {
+        base_title
+    };
+    
+    // Combine title with short hash of URL for uniqueness
+    format!("{}-{:08x}", filename_base, (url_hash & 0xFFFFFFFF) as u32)
+}
+
+/// Extracts domain name from URL for use in filename.
+fn extract_domain_from_url(url: &str, max_length: usize) -> Option<String> {
+    url_domain(url).map(|domain| to_kebab_case(domain, max_length))
+}
+
+/// Returns the domain of a URL without its `www.` prefix, e.g. `example.com`.
+fn url_domain(url: &str) -> Option<&str> {
+    let start = url.find("://")?;
+    let after_scheme = &url[start + 3..];
+    let domain = match after_scheme.find('/') {
+        Some(end) => &after_scheme[..end],
+        None => after_scheme,
+    };
+    Some(domain.strip_prefix("www.").unwrap_or(domain))
+}
+
+/// The home page of the site serving `url`, e.g. `https://example.com/` for
+/// `https://example.com/blog/feed.xml`.
+fn site_url(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url.trim()).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}://{}:{}/", url.scheme(), host, port),
+        None => format!("{}://{}/", url.scheme(), host),
+    })
+}
+
+/// Appends the domain to the display title of feeds whose title is shared by a feed with
+/// another URL, e.g. "Blog (example.com)", so that they can be told apart in the OPML files
+/// and feed readers. Titles are compared case-insensitively and ignoring whitespace. The
+/// feed's own title, and thus its filename, is not changed.
+fn disambiguate_titles(list_feeds: &mut [Vec<FeedData>]) {
+    let mut titles = ListedTitles::default();
+    for feed_data in list_feeds.iter().flatten() {
+        titles.insert(&feed_data.url, &feed_data.title);
+    }
+    for feed_data in list_feeds.iter_mut().flatten() {
+        feed_data.display_title = titles.display_title(feed_data);
+    }
+}
+
+// The URLs of the listed feeds by title, ignoring case and whitespace
+#[derive(Debug, Default)]
+struct ListedTitles {
+    urls: HashMap<String, HashSet<String>>,
+}
+
+impl ListedTitles {
+    fn normalized(title: &str) -> String {
+        title.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+    }
+
+    fn insert(&mut self, url: &str, title: &str) {
+        self.urls.entry(ListedTitles::normalized(title)).or_default().insert(url.to_string());
+    }
+
+    /// Records that the feed at `url` is now titled `title` instead of `previous`.
+    fn retitle(&mut self, url: &str, previous: Option<&str>, title: &str) {
+        if let Some(urls) = previous.and_then(|previous| self.urls.get_mut(&ListedTitles::normalized(previous))) {
+            urls.remove(url);
+        }
+        self.insert(url, title);
+    }
+
+    /// The title of a feed followed by its domain if another feed has the same title, or
+    /// None to show its own. If that feed is on the same domain, the rest of the URL is
+    /// added as well, e.g. `Blog (example.com/en/feed.xml)`.
+    fn display_title(&self, feed_data: &FeedData) -> Option<String> {
+        let urls = self.urls.get(&ListedTitles::normalized(&feed_data.title))?;
+        let others: Vec<&String> = urls.iter().filter(|url| **url != feed_data.url).collect();
+        if others.is_empty() {
+            return None;
+        }
+        let domain = url_domain(&feed_data.url)?;
+        let label = if others.iter().any(|url| url_domain(url) == Some(domain)) {
+            let (_, rest) = feed_data.url.split_once("://")?;
+            rest.strip_prefix("www.").unwrap_or(rest).trim_end_matches('/')
+        } else {
+            domain
+        };
+        Some(format!("{} ({})", feed_data.title, label))
+    }
+}
+
+// What cleanup_old_feeds does with a file found in a feed directory
+#[derive(Debug, PartialEq, Eq)]
+enum ArchivedFileAction {
+    Keep,
+    // The file of a current feed, differing from its name in case only
+    Rename(String),
+    Remove,
+}
+
+/// Decides what becomes of `filename`, a path relative to a feed directory, given the
+/// `current` files of that directory. Feed files not in `current` are removed, as is an
+/// old master.xml. On case-insensitive filesystems, a file differing from a current name
+/// in case only (FOO.XML, Master.xml) is that file: a current feed may already have been
+/// written to it, so it is kept and renamed to the feed's own name rather than removed.
+fn archived_file_action(filename: &str, current: &HashSet<String>, case_insensitive: bool) -> ArchivedFileAction {
+    let folded = if case_insensitive { filename.to_lowercase() } else { filename.to_string() };
+    let basename = folded.rsplit('/').next().unwrap_or(&folded);
+    let is_feed_file = !basename.starts_with('.') && [".xml", ".atom", ".json"].iter().any(|ext| basename.ends_with(ext));
+    if current.contains(filename) {
+        return ArchivedFileAction::Keep;
+    }
+    if case_insensitive && is_feed_file {
+        if let Some(name) = current.iter().find(|name| name.to_lowercase() == folded) {
+            return ArchivedFileAction::Rename(name.clone());
+        }
+    }
+    if is_feed_file || folded == "master.xml" {
+        ArchivedFileAction::Remove
+    } else {
+        ArchivedFileAction::Keep
+    }
+}
+
+/// Cleans up old individual feed files that are no longer in the feed list.
+/// Returns the names of the removed files.
+async fn cleanup_old_feeds(storage: &dyn Storage, layout: ArchiveLayout, feeds_dir: &str, current_feeds: &[&FeedData]) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    // Get current feed URLs as filenames
+    let mut current_filenames = HashSet::new();
+    
+    for feed_data in current_feeds {
+        current_filenames.insert(layout.feed_filename(feed_data));
+    }
+    
+    // Always preserve master.opml, .gitkeep and the run statistics
+    current_filenames.insert("master.opml".to_string());
+    current_filenames.insert(".gitkeep".to_string());
+    current_filenames.insert("stats.json".to_string());
+    current_filenames.insert("search-index.json".to_string());
+    current_filenames.insert("changes.json".to_string());
+
+    // List the feeds directory and its year/month directories and remove files not in current set
+    let case_insensitive = storage.case_insensitive();
+    let mut removed = Vec::new();
+    for filename in list_archived_files(storage, feeds_dir).await? {
+        match archived_file_action(&filename, &current_filenames, case_insensitive) {
+            ArchivedFileAction::Keep => {}
+            ArchivedFileAction::Rename(current) => match storage.rename(&format!("{}/{}", feeds_dir, filename), &format!("{}/{}", feeds_dir, current)).await {
+                Ok(()) => println!("Renamed feed file {} to {}", filename, current),
+                Err(e) => eprintln!("Warning: Could not rename feed file {} to {}: {}", filename, current, e),
+            },
+            ArchivedFileAction::Remove => {
+                if let Err(e) = storage.remove(&format!("{}/{}", feeds_dir, filename)).await {
+                    eprintln!("Warning: Could not remove old feed file {}: {}", filename, e);
+                } else {
+                    println!("Removed old feed file: {}", filename);
+                    removed.push(filename);
+                }
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{date_time, feed, item, rss, MemoryStorage, MockClient};
+
+    #[test]
+    fn feeds_sharing_a_title_are_told_apart_by_domain_then_path() {
+        let mut list_feeds = vec![vec![
+            feed("https://www.example.com/en/feed.xml", "Blog", Vec::new()),
+            feed("https://example.com/de/feed.xml", "Blog", Vec::new()),
+            feed("https://other.example/feed", "BLOG", Vec::new()),
+            feed("https://other.example/news", "News", Vec::new()),
+        ]];
+        disambiguate_titles(&mut list_feeds);
+        let titles: Vec<Option<&str>> = list_feeds[0].iter().map(|feed_data| feed_data.display_title.as_deref()).collect();
+        assert_eq!(
+            titles,
+            [Some("Blog (example.com/en/feed.xml)"), Some("Blog (example.com/de/feed.xml)"), Some("BLOG (other.example)"), None]
+        );
+    }
+
+    #[test]
+    fn comment_counts_are_archived_only_when_enabled_and_read_back() {
+        let mut feed_data = feed("https://example.com/feed.xml", "Example", vec![item("a", "2024-01-01T00:00:00Z"), item("b", "2024-01-02T00:00:00Z")]);
+        feed_data.items[0].comment_count = Some(42);
+        feed_data.items[0].comments_feed = Some("https://example.com/a/comments.xml".to_string());
+        let render = |comment_counts| {
+            let options = RenderOptions { comment_counts, ..Default::default() };
+            build_individual_feed(&feed_data, "https://example.org", "owner/repo", "feeds", "example.xml", &options).to_string()
+        };
+
+        assert!(!render(false).contains("slash:comments"));
+        let xml = render(true);
+        assert!(xml.contains("<slash:comments>42</slash:comments>"), "{}", xml);
+        assert!(xml.contains(&format!("xmlns:slash=\"{}\"", SLASH_NS)), "{}", xml);
+        let archived = load_feed_data_from_xml(xml.as_bytes(), &feed_data.url).unwrap();
+        let counts: Vec<(Option<u64>, Option<&str>)> = archived.items.iter().map(|item| (item.comment_count, item.comments_feed.as_deref())).collect();
+        assert!(counts.contains(&(Some(42), Some("https://example.com/a/comments.xml"))));
+        assert!(counts.contains(&(None, None)));
+    }
+
+    #[test]
+    fn filenames_are_usable_on_windows() {
+        assert_eq!(sanitize_filename("con"), "con-feed");
+        assert_eq!(sanitize_filename("PRN.xml"), "PRN.xml-feed");
+        assert_eq!(sanitize_filename("com1"), "com1-feed");
+        assert_eq!(sanitize_filename("News. "), "News");
+        assert_eq!(sanitize_filename("console"), "console");
+    }
+
+    #[test]
+    fn case_variants_of_current_feeds_are_renamed_rather_than_removed() {
+        let current: HashSet<String> = ["example.xml".to_string(), "master.opml".to_string()].into();
+        assert_eq!(archived_file_action("example.xml", &current, true), ArchivedFileAction::Keep);
+        assert_eq!(archived_file_action("Example.XML", &current, true), ArchivedFileAction::Rename("example.xml".to_string()));
+        assert_eq!(archived_file_action("Example.xml", &current, false), ArchivedFileAction::Remove);
+        assert_eq!(archived_file_action("Master.xml", &current, true), ArchivedFileAction::Remove);
+        assert_eq!(archived_file_action("other.xml", &current, true), ArchivedFileAction::Remove);
+        assert_eq!(archived_file_action("notes.txt", &current, true), ArchivedFileAction::Keep);
+    }
+
+    #[test]
+    fn huge_quarantine_thresholds_never_quarantine_nor_retry() {
+        let now = Utc::now();
+        let policy = |after_days, retry_hours| QuarantinePolicy { after_failures: 0, after_days, retry_hours };
+        let failing = || FeedStatus { failing_since: Some(now - chrono::Duration::days(30)), ..Default::default() };
+
+        let mut status = failing();
+        status.record_failure(now, &policy(7, 24));
+        assert_eq!(status.quarantined_since, Some(now));
+        assert!(status.quarantine_skip_reason(now + chrono::Duration::hours(23), &policy(7, 24)).is_some());
+        assert_eq!(status.quarantine_skip_reason(now + chrono::Duration::hours(24), &policy(7, 24)), None);
+        // Retried never rather than overflowing
+        assert!(status.quarantine_skip_reason(now + chrono::Duration::days(3650), &policy(7, u64::MAX)).is_some());
+        assert!(status.quarantine_skip_reason(now + chrono::Duration::days(3650), &policy(7, i64::MAX as u64)).is_some());
+
+        for after_days in [u64::MAX, i64::MAX as u64] {
+            let mut status = failing();
+            status.record_failure(now, &policy(after_days, 24));
+            assert_eq!(status.quarantined_since, None);
+        }
+    }
+
+    #[test]
+    fn huge_freshness_lifetimes_expire_instead_of_overflowing() {
+        let now = Utc::now();
+        let status = |freshness_secs| FeedStatus {
+            last_fetched: Some(now),
+            freshness_secs: Some(freshness_secs),
+            ..Default::default()
+        };
+        assert!(status(600).freshness_skip_reason(now).is_some());
+        assert_eq!(status(600).freshness_skip_reason(now + chrono::Duration::minutes(11)), None);
+        // Written by older versions, which didn't cap the lifetime
+        assert_eq!(status(u64::MAX).freshness_skip_reason(now), None);
+        assert_eq!(status(i64::MAX as u64).freshness_skip_reason(now), None);
+    }
+
+    #[test]
+    fn feeds_are_not_fetched_again_before_their_interval_elapses() {
+        let fetched = Utc::now();
+        let status = FeedStatus {
+            last_fetched: Some(fetched),
+            ..Default::default()
+        };
+        let url = "https://example.com/feed.xml";
+        let stagger = interval_stagger_secs(url, 3600);
+        assert!(stagger <= 360);
+        assert_eq!(interval_stagger_secs(url, 3600), stagger);
+        // Staggered by URL, so that feeds added together drift apart
+        assert!((0..20).any(|n| interval_stagger_secs(&format!("https://example.com/{}", n), 3600) != stagger));
+
+        let due = fetched + chrono::Duration::seconds(3600 - stagger as i64);
+        assert!(status.interval_skip_reason(url, 3600, fetched).is_some());
+        assert!(status.interval_skip_reason(url, 3600, due - chrono::Duration::seconds(1)).is_some());
+        assert_eq!(status.interval_skip_reason(url, 3600, due), None);
+        assert_eq!(status.interval_skip_reason(url, 0, fetched), None);
+        assert!(status.interval_skip_reason(url, u64::MAX, fetched + chrono::Duration::days(10_000)).is_some());
+        assert_eq!(FeedStatus::default().interval_skip_reason(url, 3600, fetched), None);
+    }
+
+    #[tokio::test]
+    async fn archived_copies_are_found_in_the_newest_dated_directory() {
+        let storage = MemoryStorage::default();
+        for path in ["feeds/blog.xml", "feeds/old.xml", "feeds/2024/01/blog.xml", "feeds/2024/11/blog.xml", "feeds/2023/12/news.xml", "feeds/misc/x.xml"] {
+            storage.write(path, b"").await.unwrap();
+        }
+        let dated = ArchiveLayout {
+            dated_dirs: true,
+            ..ArchiveLayout::default()
+        };
+        let archive = ArchivedFiles::new(&storage, dated);
+        assert_eq!(archive.find("feeds", "blog.xml").await.as_deref(), Some("feeds/2024/11/blog.xml"));
+        assert_eq!(archive.find("feeds", "news.xml").await.as_deref(), Some("feeds/2023/12/news.xml"));
+        assert_eq!(archive.find("feeds", "old.xml").await.as_deref(), Some("feeds/old.xml"));
+        assert_eq!(archive.find("feeds", "x.xml").await, None);
+
+        // Listed once, then kept up to date with what the run writes and moves
+        storage.write("feeds/2024/12/unrecorded.xml", b"").await.unwrap();
+        assert_eq!(archive.find("feeds", "unrecorded.xml").await, None);
+        archive.record("feeds", "feeds/2024/10/blog.xml").await;
+        assert_eq!(archive.find("feeds", "blog.xml").await.as_deref(), Some("feeds/2024/10/blog.xml"));
+        archive.record_move("feeds", "feeds/2023/12/news.xml", "feeds/2023/12/headlines.xml").await;
+        assert_eq!(archive.find("feeds", "news.xml").await, None);
+        assert_eq!(archive.find("feeds", "headlines.xml").await.as_deref(), Some("feeds/2023/12/headlines.xml"));
+
+        let flat = ArchivedFiles::new(&storage, ArchiveLayout::default());
+        assert_eq!(flat.find("feeds", "blog.xml").await.as_deref(), Some("feeds/blog.xml"));
+        assert_eq!(flat.find("feeds", "news.xml").await, None);
+    }
+
+    #[tokio::test]
+    async fn missing_state_starts_empty_but_read_errors_fail() {
+        let storage = MemoryStorage::default();
+        let state = FeedState::load(&storage, STATE_PATH).await.unwrap();
+        assert!(state.feeds.is_empty());
+
+        state.save(&storage, STATE_PATH).await.unwrap();
+        storage.set_unavailable(true);
+        assert!(FeedState::load(&storage, STATE_PATH).await.is_err());
+    }
+
+    #[test]
+    fn image_extraction_skips_tracking_pixels_and_resolves_relative_urls() {
+        let mut with_image = item("a", "2024-01-01T00:00:00Z");
+        with_image.description = Some(
+            r#"<p><img src="https://tracker.example/pixel.gif" width="1" height="1"> Text <IMG alt='Cover' SRC='/images/cover.jpg?size=large&amp;v=2'></p>"#.to_string(),
+        );
+        let mut in_content = item("b", "2024-01-01T00:00:00Z");
+        in_content.content = Some(r#"<img src="data:image/png;base64,AAAA"><img src=photo.png>"#.to_string());
+        let mut with_enclosure = item("c", "2024-01-01T00:00:00Z");
+        with_enclosure.description = Some(r#"<img src="https://example.com/ignored.jpg">"#.to_string());
+        with_enclosure.enclosure = Some(Enclosure {
+            url: "https://example.com/episode.mp3".to_string(),
+            mime_type: "audio/mpeg".to_string(),
+            length: 0,
+        });
+        let mut feed_data = feed("https://example.com/feed.xml", "Example", vec![with_image, in_content, with_enclosure]);
+
+        ImageExtractor::new().extract_item_images(&mut feed_data);
+        let thumbnails: Vec<Option<&str>> = feed_data.items.iter().map(|item| item.thumbnail.as_deref()).collect();
+        assert_eq!(
+            thumbnails,
+            [Some("https://example.com/images/cover.jpg?size=large&v=2"), Some("https://example.com/photo.png"), None]
+        );
+    }
+
+    #[test]
+    fn firehose_does_not_evict_items_of_higher_priority_members() {
+        let member = |url: &str, priority: Option<i32>| FeedSpec {
+            url: url.to_string(),
+            combined: Some("News".to_string()),
+            priority,
+            ..Default::default()
+        };
+        let members = [member("https://firehose.example/feed", None), member("https://must-read.example/feed", Some(1))];
+        let firehose_items = (0..10).map(|hour| item(&format!("firehose-{}", hour), &format!("2024-03-02T{:02}:00:00Z", hour))).collect();
+        let mut firehose = feed("https://firehose.example/feed", "Firehose", firehose_items);
+        firehose.priority = 0;
+        let mut must_read = feed(
+            "https://must-read.example/feed",
+            "Must read",
+            vec![item("old", "2024-02-01T00:00:00Z"), item("older", "2024-01-01T00:00:00Z")],
+        );
+        must_read.priority = 1;
+
+        let combined = merge_members(&members.iter().collect::<Vec<_>>(), vec![firehose, must_read], None, 5).unwrap();
+        let guids: Vec<&str> = combined.items.iter().map(|item| item.guid.as_str()).collect();
+        assert_eq!(guids, ["firehose-9", "firehose-8", "firehose-7", "old", "older"]);
+        assert_eq!(combined.title, "News");
+    }
+
+    #[test]
+    fn filenames_are_cut_to_the_configured_length() {
+        let url = "https://example.com/feed.xml";
+        let slug = Some("a-rather-long-slug-for-a-feed");
+        let layout = |max_filename_length| ArchiveLayout::new(&Config { max_filename_length: Some(max_filename_length), ..Default::default() });
+        assert_eq!(layout(10).archive_filename(url, "Example", slug, OutputFormat::Rss), "a-rather.xml");
+        assert_eq!(layout(0).archive_filename(url, "Example", slug, OutputFormat::Rss), "a-rather-long-slug-for-a-feed.xml");
+        assert_eq!(layout(0).max_filename_length, FILENAME_LENGTH_LIMIT);
+        assert_eq!(layout(10_000).max_filename_length, FILENAME_LENGTH_LIMIT);
+        assert_eq!(ArchiveLayout::default().max_filename_length, DEFAULT_MAX_FILENAME_LENGTH);
+    }
+
+    #[test]
+    fn too_large_translation_windows_are_config_errors() {
+        let preparation = |minutes| FeedPreparation::new(&Config { translation_window_minutes: Some(minutes), ..Default::default() }, &[], false, false);
+        assert_eq!(preparation(90).unwrap().translation_grouping.window, chrono::Duration::minutes(90));
+        assert!(preparation(u64::MAX).is_err());
+    }
+
+    #[test]
+    fn shared_titles_follow_the_feeds_written_so_far() {
+        let blog = |url: &str| feed(url, "Blog", Vec::new());
+        let mut titles = ListedTitles::default();
+        titles.insert("https://a.example/feed.xml", "Blog");
+        assert_eq!(titles.display_title(&blog("https://a.example/feed.xml")), None);
+
+        titles.insert("https://b.example/feed.xml", " blog ");
+        titles.insert("https://a.example/en/feed.xml", "Blog");
+        assert_eq!(titles.display_title(&blog("https://b.example/feed.xml")).as_deref(), Some("Blog (b.example)"));
+        assert_eq!(titles.display_title(&blog("https://a.example/feed.xml")).as_deref(), Some("Blog (a.example/feed.xml)"));
+
+        // Once fetched under another title, the feeds no longer share theirs
+        titles.retitle("https://a.example/en/feed.xml", Some("Blog"), "Blog in English");
+        titles.retitle("https://b.example/feed.xml", Some("Blog"), "B's Blog");
+        assert_eq!(titles.display_title(&blog("https://a.example/feed.xml")), None);
+    }
+
+    #[test]
+    fn feed_files_never_take_the_run_summary() {
+        let layout = ArchiveLayout::default();
+        let url = "https://example.com/feed.xml";
+        assert_eq!(layout.archive_filename(url, "Example", Some("stats"), OutputFormat::Json), "stats-feed.json");
+        assert_eq!(layout.archive_filename(url, "Example", Some("stats"), OutputFormat::Rss), "stats.xml");
+        assert_eq!(layout.archive_filename(url, "Example", Some("stats"), OutputFormat::Atom), "stats.atom");
+        assert_eq!(layout.archive_filename(url, "Example", Some("news"), OutputFormat::Json), "news.json");
+        assert_eq!(layout.archive_filename(url, "Example", Some("changes"), OutputFormat::Json), "changes-feed.json");
+        assert_eq!(layout.archive_filename(url, "Example", Some("search-index"), OutputFormat::Json), "search-index-feed.json");
+        // Only JSON feeds could overwrite the archiver's files, so RSS slugs keep their names
+        assert_eq!(layout.archive_filename(url, "Example", Some("changes"), OutputFormat::Rss), "changes.xml");
+        assert_eq!(layout.archive_filename(url, "Example", Some("index"), OutputFormat::Rss), "index.xml");
+        assert_eq!(layout.archive_filename(url, "Example", Some("new"), OutputFormat::Rss), "new.xml");
+    }
+
+    #[test]
+    fn hostile_titles_and_slugs_give_safe_filenames() {
+        let layout = ArchiveLayout::default();
+        let url = "https://example.com/feed.xml";
+        let safe = |filename: &str| {
+            let name = filename.strip_suffix(".xml").unwrap();
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        };
+
+        let url_hash = generate_unique_filename_for_feed(url, "", DEFAULT_MAX_FILENAME_LENGTH).rsplit('-').next().unwrap().to_string();
+        for title in ["word ".repeat(1000), "x".repeat(5000)] {
+            let long = layout.archive_filename(url, &title, None, OutputFormat::Rss);
+            assert!(long.len() <= DEFAULT_MAX_FILENAME_LENGTH + "-".len() + url_hash.len() + ".xml".len(), "{}", long);
+            assert!(safe(&long), "{}", long);
+            // The name is cut, never the URL hash keeping it unique
+            assert!(long.ends_with(&format!("-{}.xml", url_hash)), "{}", long);
+        }
+
+        for traversal in ["../../etc/passwd", "..", "a/../../b"] {
+            let by_title = layout.archive_filename(url, traversal, None, OutputFormat::Rss);
+            let by_slug = layout.archive_filename(url, "Example", Some(traversal), OutputFormat::Rss);
+            for filename in [by_title, by_slug] {
+                assert!(safe(&filename) && !filename.contains(".."), "{}", filename);
+            }
+        }
+        assert_eq!(layout.archive_filename(url, "../../etc/passwd", None, OutputFormat::Rss), format!("{}.xml", generate_unique_filename_for_feed(url, "etc passwd", DEFAULT_MAX_FILENAME_LENGTH)));
+
+        let master = layout.archive_filename(url, "master", None, OutputFormat::Rss);
+        assert_eq!(master, format!("{}.xml", generate_unique_filename_for_feed(url, "master", DEFAULT_MAX_FILENAME_LENGTH)));
+        assert_ne!(master, "master.xml");
+        assert_eq!(layout.archive_filename(url, "Example", Some("master"), OutputFormat::Rss), "master.xml");
+        assert_eq!(layout.archive_filename(url, "Example", Some("master"), OutputFormat::Json), "master.json");
+    }
+
+    #[test]
+    fn feeds_of_one_run_are_rendered_and_listed_in_their_own_formats() {
+        let in_format = |url: &str, slug: &str, format: OutputFormat| FeedData {
+            slug: Some(slug.to_string()),
+            format,
+            ..feed(url, slug, vec![item(slug, "2024-01-01T00:00:00Z")])
+        };
+        let feeds = [
+            in_format("https://a.example/feed", "a", OutputFormat::Rss),
+            in_format("https://b.example/feed", "b", OutputFormat::Atom),
+            in_format("https://c.example/feed", "c", OutputFormat::Json),
+        ];
+        let base_url = "https://example.org/archive";
+
+        let mut opml = Vec::new();
+        let links = ArchiveLinks { layout: ArchiveLayout::default(), base_url, feed_dir: "feeds" };
+        build_opml_feed_list(&feeds, &[], &links, &HashSet::new(), None, &mut opml).unwrap();
+        let opml = String::from_utf8(opml).unwrap();
+        for (filename, opml_type) in [("a.xml", "rss"), ("b.atom", "atom"), ("c.json", "json")] {
+            let outline = format!("type=\"{}\" xmlUrl=\"{}/feeds/{}\"", opml_type, base_url, filename);
+            assert!(opml.contains(&outline), "{}", opml);
+        }
+
+        let rendered: Vec<String> = feeds
+            .iter()
+            .map(|feed_data| render_feed(feed_data, base_url, "owner/repo", "feeds", &links.layout.feed_filename(feed_data), &RenderOptions::default()).unwrap())
+            .collect();
+        assert!(rendered[0].contains("<rss"), "{}", rendered[0]);
+        assert!(rendered[1].contains("<feed"), "{}", rendered[1]);
+        assert!(rendered[2].trim_start().starts_with('{'), "{}", rendered[2]);
+    }
+
+    #[test]
+    fn feeds_in_two_categories_are_grouped_under_the_first_and_tagged_with_both() {
+        let (specs, _) = parse_feed_list("https://a.example/feed category=Rust,Programming\nhttps://b.example/feed category=Programming\n", true).unwrap();
+        let feeds: Vec<FeedData> = specs
+            .iter()
+            .zip(["a", "b"])
+            .map(|(spec, slug)| FeedData {
+                slug: Some(slug.to_string()),
+                categories: spec.categories.clone(),
+                ..feed(&spec.url, slug, Vec::new())
+            })
+            .collect();
+        assert_eq!(feeds[0].categories, ["Rust", "Programming"]);
+
+        let mut opml = Vec::new();
+        let links = ArchiveLinks { layout: ArchiveLayout::default(), base_url: "https://example.org/archive", feed_dir: "feeds" };
+        build_opml_feed_list(&feeds, &[], &links, &HashSet::new(), None, &mut opml).unwrap();
+        let opml = String::from_utf8(opml).unwrap();
+        let rust = opml.find("<outline type=\"category\" text=\"Rust\">").expect(&opml);
+        let programming = opml.find("<outline type=\"category\" text=\"Programming\">").expect(&opml);
+        let a = opml.find("xmlUrl=\"https://example.org/archive/feeds/a.xml\"").expect(&opml);
+        let b = opml.find("xmlUrl=\"https://example.org/archive/feeds/b.xml\"").expect(&opml);
+        assert!(rust < a && a < programming && programming < b, "{}", opml);
+        assert_eq!(opml.matches("feeds/a.xml").count(), 1, "{}", opml);
+        assert!(opml.contains("category=\"/Rust,/Programming\""), "{}", opml);
+        assert!(opml.contains("category=\"/Programming\""), "{}", opml);
+    }
+
+    #[test]
+    fn opml_attributes_round_trip_escaped_titles_and_urls() {
+        let titled = FeedData {
+            slug: Some("titled".to_string()),
+            ..feed("https://a.example/feed?a=1&utm_source=rss&utm_medium=feed", "Tom & \"Jerry\" <Café> 日本語\u{1}\u{7}\tnews\r\n", Vec::new())
+        };
+        // A title of only control characters falls back to the feed URL
+        let untitled = FeedData {
+            slug: Some("untitled".to_string()),
+            ..feed("https://b.example/feed?a=1&utm_source=rss&utm_medium=feed", "\u{1}\u{2}", Vec::new())
+        };
+        let mut opml = Vec::new();
+        let links = ArchiveLinks { layout: ArchiveLayout::default(), base_url: "https://example.org/archive", feed_dir: "feeds" };
+        build_opml_feed_list(&[titled, untitled], &[], &links, &HashSet::new(), None, &mut opml).unwrap();
+        let opml = String::from_utf8(opml).unwrap();
+
+        let mut reader = Reader::from_str(&opml);
+        let mut outlines = Vec::new();
+        loop {
+            match reader.read_event().unwrap_or_else(|e| panic!("{}: {}", e, opml)) {
+                Event::Eof => break,
+                Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"outline" => {
+                    let attributes: HashMap<String, String> = e
+                        .attributes()
+                        .map(|attr| {
+                            let attr = attr.unwrap();
+                            (String::from_utf8(attr.key.as_ref().to_vec()).unwrap(), attr.unescape_value().unwrap().to_string())
+                        })
+                        .collect();
+                    if attributes.contains_key("xmlUrl") {
+                        outlines.push(attributes);
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(outlines.len(), 2, "{}", opml);
+        assert_eq!(outlines[0]["text"], "Tom & \"Jerry\" <Café> 日本語 news  ");
+        assert_eq!(outlines[0]["title"], outlines[0]["text"]);
+        assert_eq!(outlines[0]["xmlUrl"], "https://example.org/archive/feeds/titled.xml");
+        assert_eq!(outlines[1]["text"], "https://b.example/feed?a=1&utm_source=rss&utm_medium=feed");
+        assert_eq!(outlines[1]["xmlUrl"], "https://example.org/archive/feeds/untitled.xml");
+        assert!(opml.contains("feed?a=1&amp;utm_source=rss&amp;utm_medium=feed"), "{}", opml);
+    }
+
+    #[test]
+    fn episodes_are_listed_newest_first_in_every_format_whatever_their_dates() {
+        let episode = |guid: &str, date: &str, number: Option<u32>| FeedItem { episode_number: number, ..item(guid, date) };
+        let podcast = |trailer: Option<FeedItem>| FeedData {
+            items: [
+                episode("two", "2024-01-01T00:00:00Z", Some(2)),
+                episode("three", "2024-01-03T00:00:00Z", Some(3)),
+                episode("one", "2024-01-02T00:00:00Z", Some(1)),
+            ]
+            .into_iter()
+            .chain(trailer)
+            .collect(),
+            ..feed("https://example.com/podcast.xml", "Podcast", Vec::new())
+        };
+        let order = |feed_data: &FeedData, format| {
+            let feed_data = FeedData { format, ..feed_data.clone() };
+            let rendered = render_feed(&feed_data, "https://example.org/archive", "owner/repo", "feeds", "podcast.xml", &RenderOptions::default()).unwrap();
+            let mut guids: Vec<(usize, &str)> = ["one", "two", "three", "trailer"]
+                .into_iter()
+                .filter_map(|guid| rendered.find(&format!("https://example.com/{}", guid)).map(|at| (at, guid)))
+                .collect();
+            guids.sort();
+            guids.into_iter().map(|(_, guid)| guid).collect::<Vec<_>>()
+        };
+
+        for format in [OutputFormat::Rss, OutputFormat::Atom, OutputFormat::Json] {
+            assert_eq!(order(&podcast(None), format), ["three", "two", "one"], "{:?}", format);
+            // Unless every item has a number, the feed keeps its order
+            let trailer = podcast(Some(episode("trailer", "2023-12-31T00:00:00Z", None)));
+            assert_eq!(order(&trailer, format), ["two", "three", "one", "trailer"], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn copyright_rules_match_the_longest_domain_suffix() {
+        let rules: BTreeMap<String, String> = [
+            ("nytimes.com", "© {year} The New York Times Company"),
+            ("cooking.NYTimes.com.", "© NYT Cooking"),
+            ("github.com", "© {year} GitHub, Inc."),
+        ]
+        .into_iter()
+        .map(|(domain, template)| (domain.to_string(), template.to_string()))
+        .collect();
+        let rules = CopyrightRules::new(Some(&rules), Some("Archived from the original"));
+        let year = Utc::now().year();
+
+        assert_eq!(select_copyright("https://www.nytimes.com/feed.xml", &rules), Some(format!("© {} The New York Times Company", year)));
+        assert_eq!(select_copyright("https://nytimes.com/feed.xml", &rules), Some(format!("© {} The New York Times Company", year)));
+        assert_eq!(select_copyright("https://cooking.nytimes.com/rss", &rules).as_deref(), Some("© NYT Cooking"));
+        assert_eq!(select_copyright("https://notnytimes.com/feed.xml", &rules), None);
+        assert_eq!(select_copyright("not a url", &rules), None);
+
+        // A matching rule wins over the feed's own notice, which wins over the default
+        let copyright = |url: &str, own: Option<&str>| {
+            let feed_data = FeedData { copyright: own.map(str::to_string), ..feed(url, "Example", Vec::new()) };
+            let options = RenderOptions { copyright_rules: rules.clone(), ..Default::default() };
+            build_individual_feed(&feed_data, "https://example.org/archive", "owner/repo", "feeds", "example.xml", &options).copyright().map(str::to_string)
+        };
+        assert_eq!(copyright("https://github.com/owner/repo/releases.atom", Some("Own")), Some(format!("© {} GitHub, Inc.", year)));
+        assert_eq!(copyright("https://example.com/feed.xml", Some("Own")).as_deref(), Some("Own"));
+        assert_eq!(copyright("https://example.com/feed.xml", None).as_deref(), Some("Archived from the original"));
+    }
+
+    #[test]
+    fn stylesheets_are_referenced_after_the_xml_declaration() {
+        let xml = "<?xml version=\"1.0\" encoding=\"utf-8\"?><rss version=\"2.0\"/>".to_string();
+        assert_eq!(
+            with_stylesheet(xml.clone(), "/feeds/style.xsl"),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><?xml-stylesheet type=\"text/xsl\" href=\"/feeds/style.xsl\"?><rss version=\"2.0\"/>"
+        );
+        assert!(with_stylesheet(xml.clone(), "https://example.org/feed.XSLT?v=2&x=1").contains(
+            "<?xml-stylesheet type=\"text/xsl\" href=\"https://example.org/feed.XSLT?v=2&amp;x=1\"?>"
+        ));
+        assert!(with_stylesheet(xml, "feed.css#dark").contains("<?xml-stylesheet type=\"text/css\" href=\"feed.css#dark\"?>"));
+        // Without a declaration the instruction comes first
+        assert_eq!(with_stylesheet("<feed/>".to_string(), "a.css"), "<?xml-stylesheet type=\"text/css\" href=\"a.css\"?><feed/>");
+
+        let feed_data = FeedData { format: OutputFormat::Atom, ..feed("https://example.com/feed.xml", "Example", Vec::new()) };
+        let options = RenderOptions { stylesheet_href: Some("style.xsl".to_string()), ..Default::default() };
+        let atom = render_feed(&feed_data, "https://example.org/archive", "owner/repo", "feeds", "example.atom", &options).unwrap();
+        assert!(atom.starts_with("<?xml version=\"1.0\"?><?xml-stylesheet type=\"text/xsl\" href=\"style.xsl\"?>"), "{}", atom);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejected_items_stay_out_of_the_search_index_changes_and_targets() {
+        let storage = MemoryStorage::default();
+        let archive = ArchivedFiles::new(&storage, ArchiveLayout::default());
+        let targets = [OutputTarget {
+            repo_name: "owner/mirror".to_string(),
+            base_url: "https://example.org/mirror".to_string(),
+            prefix: "mirror".to_string(),
+        }];
+        let mut writer = FeedWriter {
+            storage: &storage,
+            archive: &archive,
+            layout: ArchiveLayout::default(),
+            parse_options: ParseOptions::default(),
+            file_limits: FileLimits { max_items: 0, max_file_bytes: 0, budget_strategy: BudgetStrategy::default() },
+            base_url: "https://example.org/archive",
+            repo_name: "owner/repo",
+            targets: &targets,
+            render_options: RenderOptions::default(),
+            stored_content: HashSet::new(),
+            changes_report: true,
+            changes_report_titles: false,
+            search_index_max_items: Some(0),
+            published_files: HashSet::new(),
+            written_files: HashSet::new(),
+            archived_files: BTreeSet::new(),
+            output_files: Vec::new(),
+            published_urls: Vec::new(),
+            feed_diffs: BTreeMap::new(),
+            search_entries: BTreeMap::new(),
+            failed_urls: HashSet::new(),
+            verbose: false,
+            progress_to_stderr: false,
+        };
+
+        let listed = FeedData { slug: Some("news".to_string()), ..feed("https://example.com/feed.xml", "News", vec![item("kept", "2024-01-02T00:00:00Z")]) };
+        let rejected = RejectedItem { item: item("dropped", "2024-01-01T00:00:00Z"), rule: "exclude keywords", feed_url: listed.url.clone(), feed_title: listed.title.clone() };
+        let rejects = rejects_feed(vec![rejected], Vec::new(), "https://example.org/archive/feeds/rejected.xml");
+        writer.write("feeds", &listed, false).await;
+        writer.write_rejects("feeds", &rejects).await;
+
+        let files = storage.files.lock().unwrap();
+        assert!(files.contains_key("feeds/news.xml") && files.contains_key("feeds/rejected.xml"), "{:?}", files.keys());
+        assert!(files.contains_key("mirror/feeds/news.xml"));
+        assert!(!files.contains_key("mirror/feeds/rejected.xml"));
+        assert_eq!(writer.search_entries.keys().collect::<Vec<_>>(), ["feeds/news.xml"]);
+        assert_eq!(writer.feed_diffs.keys().collect::<Vec<_>>(), ["feeds/news.xml"]);
+    }
+
+    #[test]
+    fn updated_dates_are_kept_apart_from_pub_dates_in_every_format() {
+        let updated = date_time("2024-03-05T08:00:00+01:00");
+        let feed_data = feed(
+            "https://example.com/feed.xml",
+            "Example",
+            vec![FeedItem { updated_date: Some(updated), ..item("a", "2024-03-01T10:30:00Z") }, item("b", "2024-02-01T00:00:00Z")],
+        );
+        for format in [OutputFormat::Rss, OutputFormat::Atom, OutputFormat::Json] {
+            let feed_data = FeedData { format, ..feed_data.clone() };
+            let filename = format!("example.{}", format.extension());
+            let rendered = render_feed(&feed_data, "https://example.org/archive", "owner/repo", "feeds", &filename, &RenderOptions::default()).unwrap();
+            let parsed = parse_feed("https://example.org/archive/feeds/example", rendered.as_bytes(), ParseOptions::default()).unwrap();
+            let dates: Vec<_> = parsed.items.iter().map(|item| (item.guid.as_str(), item.pub_date, item.updated_date)).collect();
+            assert_eq!(
+                dates,
+                [("a", date_time("2024-03-01T10:30:00Z"), Some(updated)), ("b", date_time("2024-02-01T00:00:00Z"), None)],
+                "{:?}: {}",
+                format,
+                rendered
+            );
+            if format == OutputFormat::Rss {
+                assert_eq!(rendered.matches("<dcterms:modified>").count(), 1, "{}", rendered);
+                let archived = load_feed_data_from_xml(rendered.as_bytes(), &feed_data.url).unwrap();
+                assert_eq!(archived.items[0].updated_date, Some(updated));
+                assert_eq!(archived.items[1].updated_date, None);
+            }
+        }
+    }
+
+    #[test]
+    fn dc_dates_match_the_pub_dates() {
+        let feed_data = feed("https://example.com/feed.xml", "Example", vec![item("a", "2024-03-01T10:30:00+02:00")]);
+        let render = |dc_dates| {
+            let options = RenderOptions { dc_dates, ..Default::default() };
+            build_individual_feed(&feed_data, "https://example.org/archive", "owner/repo", "feeds", "example.xml", &options)
+        };
+        let channel = render(true);
+        let item = &channel.items()[0];
+        let pub_date = DateTime::parse_from_rfc2822(item.pub_date().unwrap()).unwrap();
+        let dc_date = DateTime::parse_from_rfc3339(&item.dublin_core_ext().unwrap().dates()[0]).unwrap();
+        assert_eq!(pub_date, dc_date);
+        assert_eq!(dc_date, feed_data.items[0].pub_date);
+        assert!(channel.to_string().contains("<dc:date>2024-03-01T10:30:00+02:00</dc:date>"), "{}", channel.to_string());
+        assert!(render(false).items()[0].dublin_core_ext().is_none_or(|dc| dc.dates().is_empty()));
+    }
+
+    #[test]
+    fn a_second_stable_run_only_prepends_the_new_item() {
+        let run = |items: Vec<FeedItem>| {
+            let mut feed_data = feed("https://example.com/feed.xml", "Example", items);
+            sort_items_stably(&mut feed_data.items);
+            let rendered = render_feed(&feed_data, "https://example.org/archive", "owner/repo", "feeds", "example.xml", &RenderOptions::default()).unwrap();
+            rendered.split("<item>").skip(1).map(|item| item.split("</item>").next().unwrap().to_string()).collect::<Vec<String>>()
+        };
+        // Items sharing a date, listed in a different order by the second fetch
+        let first = run(vec![
+            item("b", "2024-01-02T00:00:00Z"),
+            item("c", "2024-01-01T00:00:00Z"),
+            item("a", "2024-01-02T00:00:00Z"),
+            item("d", "2024-01-01T00:00:00Z"),
+        ]);
+        let second = run(vec![
+            item("d", "2024-01-01T00:00:00Z"),
+            item("a", "2024-01-02T00:00:00Z"),
+            item("new", "2024-01-03T00:00:00Z"),
+            item("c", "2024-01-01T00:00:00Z"),
+            item("b", "2024-01-02T00:00:00Z"),
+        ]);
+        assert!(second[0].contains(">new</guid>"), "{}", second[0]);
+        assert_eq!(first.iter().map(|item| item.split("</guid>").next().unwrap().rsplit('>').next().unwrap()).collect::<Vec<_>>(), ["a", "b", "c", "d"]);
+        assert_eq!(second[1..], first[..]);
+    }
+
+    #[test]
+    fn channel_categories_are_the_distinct_item_categories_most_frequent_first() {
+        let categorized = |guid: &str, categories: &[&str]| FeedItem {
+            categories: categories.iter().map(|category| category.to_string()).collect(),
+            ..item(guid, "2024-01-01T00:00:00Z")
+        };
+        let items = vec![
+            categorized("1", &["rust"]),
+            categorized("2", &["go", "rust"]),
+            categorized("3", &["c"]),
+            categorized("4", &["rust"]),
+            categorized("5", &["go"]),
+            categorized("6", &["c", "go"]),
+            categorized("7", &["rust"]),
+            categorized("8", &[]),
+            categorized("9", &["c"]),
+            categorized("10", &["rust", "rust"]),
+        ];
+        let mut feed_data = feed("https://example.com/feed.xml", "Example", items);
+        assert_eq!(channel_categories(&feed_data, 10), ["rust", "c", "go"]);
+        assert_eq!(channel_categories(&feed_data, 10), channel_categories(&feed_data, 3));
+        assert_eq!(channel_categories(&feed_data, 2), ["rust", "c"]);
+        let options = RenderOptions { channel_categories: 10, ..Default::default() };
+        let channel = build_individual_feed(&feed_data, "https://example.org/archive", "owner/repo", "feeds", "example.xml", &options);
+        assert_eq!(channel.categories().iter().map(|category| category.name()).collect::<Vec<_>>(), ["rust", "c", "go"]);
+
+        // Categories from the feed list come first and aren't repeated
+        feed_data.categories = vec!["go".to_string()];
+        assert_eq!(channel_categories(&feed_data, 10), ["go", "rust", "c"]);
+    }
+
+    #[test]
+    fn self_links_point_at_the_archived_file_and_hubs_are_only_linked_when_configured() {
+        let base_url = "https://example.org/archive";
+        let links = ArchiveLinks { layout: ArchiveLayout::default(), base_url, feed_dir: "feeds" };
+        let rss = FeedData { slug: Some("news".to_string()), ..feed("https://example.com/feed.xml", "News", vec![item("a", "2024-01-01T00:00:00Z")]) };
+        let atom = FeedData { slug: Some("news".to_string()), format: OutputFormat::Atom, ..rss.clone() };
+        let archived_url = links.feed_url(&rss);
+        assert_eq!(archived_url, "https://example.org/archive/feeds/news.xml");
+
+        let rss_links = |websub_hub: Option<&str>| {
+            let options = RenderOptions { self_links: true, websub_hub: websub_hub.map(str::to_string), ..Default::default() };
+            let channel = build_individual_feed(&rss, base_url, "owner/repo", "feeds", &links.layout.feed_filename(&rss), &options);
+            channel.atom_ext().unwrap().links().iter().map(|link| (link.rel().to_string(), link.href().to_string())).collect::<Vec<_>>()
+        };
+        assert_eq!(rss_links(None), [("self".to_string(), archived_url.clone())]);
+        assert_eq!(
+            rss_links(Some("https://hub.example/")),
+            [("self".to_string(), archived_url.clone()), ("hub".to_string(), "https://hub.example/".to_string())]
+        );
+        let channel = build_individual_feed(&rss, base_url, "owner/repo", "feeds", &links.layout.feed_filename(&rss), &RenderOptions::default());
+        assert!(channel.atom_ext().is_none());
+
+        let atom_links = |websub_hub: Option<&str>| {
+            let options = RenderOptions { websub_hub: websub_hub.map(str::to_string), ..Default::default() };
+            let rendered = render_feed(&atom, base_url, "owner/repo", "feeds", &links.layout.feed_filename(&atom), &options).unwrap();
+            let parsed: atom_syndication::Feed = rendered.parse().unwrap();
+            parsed.links().iter().filter(|link| matches!(link.rel(), "self" | "hub")).map(|link| (link.rel().to_string(), link.href().to_string())).collect::<Vec<_>>()
+        };
+        let archived_url = links.feed_url(&atom);
+        assert_eq!(atom_links(None), [("self".to_string(), archived_url.clone())]);
+        assert_eq!(
+            atom_links(Some("https://hub.example/")),
+            [("self".to_string(), archived_url), ("hub".to_string(), "https://hub.example/".to_string())]
+        );
+    }
+
+    #[test]
+    fn kebab_case_transliterates_and_cuts_multi_byte_titles() {
+        assert_eq!(to_kebab_case("Café Déjà Vu", 0), "cafe-deja-vu");
+        assert_eq!(to_kebab_case("東京タワー", 0), "dong-jing-tawa");
+        // Lengths count the bytes of the result, so cuts that would fall inside a multi-byte
+        // character of the title don't split it
+        assert_eq!(to_kebab_case("Café Déjà Vu", 4), "cafe");
+        assert_eq!(to_kebab_case("Café Déjà Vu", 9), "cafe-deja");
+        assert_eq!(to_kebab_case("Café Déjà Vu", 6), "cafe");
+        assert_eq!(to_kebab_case("東京タワー", 5), "dong");
+        assert_eq!(to_kebab_case("東京タワー", 3), "don");
+        assert_eq!(to_kebab_case("éééééééé", 3), "eee");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_run_creates_the_missing_output_directories() {
+        let root = std::env::temp_dir().join(format!("output-dirs-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let source = root.join("tech.txt");
+        fs::write(&source, "https://example.com/feed.xml slug=example\n").unwrap();
+        let config = Config {
+            storage: Some(StorageConfig::Local { root: Some(root.to_string_lossy().into_owned()) }),
+            lists: Some(vec![FeedList { name: "tech".to_string(), source: source.to_string_lossy().into_owned(), output_dir: Some("tech".to_string()) }]),
+            ..Default::default()
+        };
+        let client = MockClient::new(&[("https://example.com/feed.xml", rss("Example", &[("a", "2024-01-01T00:00:00Z")]))]);
+        let (_, shutdown) = watch::channel(false);
+        assert!(!root.join("feeds").exists());
+
+        assert!(!run(&CliArgs::default(), config, Arc::new(client), shutdown).await.unwrap());
+        assert!(root.join("feeds").is_dir());
+        assert!(root.join("feeds/tech").is_dir());
+        assert!(root.join("feeds/tech/example.xml").is_file());
+        assert!(root.join("feeds/tech.opml").is_file());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn read_times_count_the_words_outside_of_tags() {
+        let words = |count: usize| vec!["word"; count].join(" ");
+        assert_eq!(estimate_read_time(&words(WORDS_PER_MINUTE)), 1);
+        assert_eq!(estimate_read_time(&words(WORDS_PER_MINUTE + 1)), 2);
+        // Tags separate words without counting as words themselves
+        let html = format!("<p>{}</p><img src=\"a.png\" alt=\"an image\"><p><b>bold</b>word</p>", words(WORDS_PER_MINUTE * 2 - 2));
+        assert_eq!(html_to_plain_text(&html).split_whitespace().count(), WORDS_PER_MINUTE * 2);
+        assert_eq!(estimate_read_time(&html), 2);
+        assert_eq!(estimate_read_time(""), 1);
+        assert_eq!(estimate_read_time(&words(WORDS_PER_MINUTE * 500)), 99);
+    }
+}
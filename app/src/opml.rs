@@ -0,0 +1,182 @@
+// opml.rs
+use crate::storage::{Storage, WriteContents};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Why an OPML file was not written.
+#[derive(Debug)]
+pub enum OpmlError {
+    /// The storage backend failed to write, read back or rename the file
+    Storage(String),
+    /// The file is not well-formed XML or not an OPML document
+    Malformed(String),
+    /// An outline lacks a required attribute; outlines are numbered from 1 in document order
+    InvalidOutline {
+        index: usize,
+        text: Option<String>,
+        missing: &'static str,
+    },
+}
+
+impl fmt::Display for OpmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpmlError::Storage(e) => write!(f, "could not write file: {}", e),
+            OpmlError::Malformed(e) => write!(f, "malformed OPML: {}", e),
+            OpmlError::InvalidOutline { index, text: Some(text), missing } => {
+                write!(f, "outline {} ({:?}) has no {} attribute", index, text, missing)
+            }
+            OpmlError::InvalidOutline { index, text: None, missing } => write!(f, "outline {} has no {} attribute", index, missing),
+        }
+    }
+}
+
+impl Error for OpmlError {}
+
+/// Writes an OPML file so that a malformed one never replaces the previous file: the
+/// content is streamed to `{path}.tmp`, read back and validated with `validate_opml`, and
+/// only then renamed to `path`. With local storage the file is validated as it is read
+/// from disk, without holding it in memory. A file that fails validation is removed
+/// again. Returns the size of the file.
+pub async fn write_and_validate_opml(storage: &dyn Storage, path: &str, content: &WriteContents<'_>) -> Result<u64, OpmlError> {
+    let tmp_path = format!("{}.tmp", path);
+    let size = storage.write_streaming(&tmp_path, content).await.map_err(|e| OpmlError::Storage(e.to_string()))?;
+
+    let validated = match storage.local_path(&tmp_path) {
+        Some(local_path) => tokio::task::block_in_place(|| match File::open(&local_path) {
+            Ok(file) => validate_opml(BufReader::new(file)),
+            Err(e) => Err(OpmlError::Storage(e.to_string())),
+        }),
+        None => match storage.read(&tmp_path).await {
+            Ok(written) => validate_opml(written.as_slice()),
+            Err(e) => Err(OpmlError::Storage(e.to_string())),
+        },
+    };
+    if let Err(e) = validated {
+        if let Err(remove_error) = storage.remove(&tmp_path).await {
+            eprintln!("Warning: Could not remove {}: {}", tmp_path, remove_error);
+        }
+        return Err(e);
+    }
+
+    storage.rename(&tmp_path, path).await.map_err(|e| OpmlError::Storage(e.to_string()))?;
+    Ok(size)
+}
+
+/// Checks an OPML document read from `source` event by event: it is well-formed XML with
+/// an `<opml>` root element, and every outline has a `text` and, unless it is a category
+/// grouping other outlines, an `xmlUrl`.
+pub fn validate_opml<R: BufRead>(source: R) -> Result<(), OpmlError> {
+    let mut reader = Reader::from_reader(source);
+    let mut buf = Vec::new();
+    let mut depth = 0;
+    let mut outlines = 0;
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| OpmlError::Malformed(format!("{} at byte {}", e, reader.error_position())))?;
+        match event {
+            Event::Start(e) | Event::Empty(e) if depth == 0 && e.name().as_ref() != b"opml" => {
+                return Err(OpmlError::Malformed(format!(
+                    "root element is <{}>, expected <opml>",
+                    String::from_utf8_lossy(e.name().as_ref())
+                )));
+            }
+            Event::Start(e) => {
+                if e.name().as_ref() == b"outline" {
+                    outlines += 1;
+                    check_outline(&e, outlines)?;
+                }
+                depth += 1;
+            }
+            Event::Empty(e) if e.name().as_ref() == b"outline" => {
+                outlines += 1;
+                check_outline(&e, outlines)?;
+            }
+            Event::End(_) => depth -= 1,
+            Event::Eof if depth > 0 => return Err(OpmlError::Malformed("unexpected end of file".to_string())),
+            Event::Eof => return Ok(()),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Checks the attributes of the `index`th outline.
+fn check_outline(outline: &BytesStart, index: usize) -> Result<(), OpmlError> {
+    let mut text = None;
+    let mut xml_url = None;
+    let mut is_category = false;
+    for attr in outline.attributes() {
+        let attr = attr.map_err(|e| OpmlError::Malformed(format!("outline {}: {}", index, e)))?;
+        let value = attr
+            .unescape_value()
+            .map_err(|e| OpmlError::Malformed(format!("outline {}: {}", index, e)))?
+            .to_string();
+        match attr.key.as_ref() {
+            b"text" => text = Some(value),
+            b"xmlUrl" => xml_url = Some(value),
+            b"type" => is_category = value == "category",
+            _ => {}
+        }
+    }
+    if text.as_deref().is_none_or(str::is_empty) {
+        return Err(OpmlError::InvalidOutline { index, text: None, missing: "text" });
+    }
+    if !is_category && xml_url.as_deref().is_none_or(str::is_empty) {
+        return Err(OpmlError::InvalidOutline { index, text, missing: "xmlUrl" });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalFs;
+    use crate::test_support::MemoryStorage;
+    use std::io::Write;
+
+    const VALID: &str = r#"<?xml version="1.0"?><opml version="2.0"><head><title>Feeds</title></head><body>
+        <outline text="News" type="category"><outline text="Example" xmlUrl="https://example.com/feed.xml"/></outline>
+        </body></opml>"#;
+
+    fn writing(opml: &'static str) -> impl Fn(&mut dyn Write) -> Result<(), Box<dyn Error + Send + Sync>> + Send + Sync {
+        move |writer| Ok(writer.write_all(opml.as_bytes())?)
+    }
+
+    #[test]
+    fn validates_outlines() {
+        assert!(validate_opml(VALID.as_bytes()).is_ok());
+        let missing_url = r#"<opml><body><outline text="News" type="category"/><outline text="Example"/></body></opml>"#;
+        assert!(matches!(validate_opml(missing_url.as_bytes()), Err(OpmlError::InvalidOutline { index: 2, missing: "xmlUrl", .. })));
+        let missing_text = r#"<opml><body><outline xmlUrl="https://example.com/feed.xml"/></body></opml>"#;
+        assert!(matches!(validate_opml(missing_text.as_bytes()), Err(OpmlError::InvalidOutline { index: 1, missing: "text", .. })));
+        assert!(matches!(validate_opml(r#"<rss version="2.0"/>"#.as_bytes()), Err(OpmlError::Malformed(_))));
+        assert!(matches!(validate_opml("<opml><body>".as_bytes()), Err(OpmlError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn invalid_opml_keeps_the_previous_file() {
+        let storage = MemoryStorage::default();
+        assert_eq!(write_and_validate_opml(&storage, "feeds/master.opml", &writing(VALID)).await.unwrap(), VALID.len() as u64);
+        assert!(write_and_validate_opml(&storage, "feeds/master.opml", &writing("<opml><body>")).await.is_err());
+        let files = storage.files.lock().unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["feeds/master.opml"]);
+        assert_eq!(files["feeds/master.opml"], VALID.as_bytes());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn local_files_are_validated_from_disk() {
+        let root = std::env::temp_dir().join(format!("opml-test-{}", std::process::id()));
+        let storage = LocalFs::new(&root);
+        write_and_validate_opml(&storage, "feeds/master.opml", &writing(VALID)).await.unwrap();
+        assert!(write_and_validate_opml(&storage, "feeds/master.opml", &writing("<opml><body>")).await.is_err());
+        assert_eq!(std::fs::read_to_string(root.join("feeds/master.opml")).unwrap(), VALID);
+        assert!(!root.join("feeds/master.opml.tmp").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+// output.rs
+use crate::{FeedData, FeedItem, GENERATOR_NAME, GENERATOR_VERSION};
+use atom_syndication::{
+    Category, CategoryBuilder, ContentBuilder, EntryBuilder, FeedBuilder, Generator, LinkBuilder, PersonBuilder, Text,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Format an archived feed is written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Rss,
+    Atom,
+    /// JSON Feed 1.1
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "rss" => Ok(OutputFormat::Rss),
+            "atom" => Ok(OutputFormat::Atom),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("invalid format {:?}, expected rss, atom or json", value)),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// File extension of archived feeds in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Rss => "xml",
+            OutputFormat::Atom => "atom",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    /// Value of the `type` attribute of the feed's OPML outline.
+    pub fn opml_type(self) -> &'static str {
+        match self {
+            OutputFormat::Rss => "rss",
+            OutputFormat::Atom => "atom",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+/// The items of `feed_data` in the order they are archived in every format. Podcasts and
+/// serials, whose items all have an episode number, are listed by episode, newest first,
+/// whatever their dates say; other feeds keep their order.
+pub fn listing_order(feed_data: &FeedData) -> Vec<&FeedItem> {
+    let mut items: Vec<&FeedItem> = feed_data.items.iter().collect();
+    if items.iter().all(|fi| fi.episode_number.is_some()) {
+        items.sort_by_key(|fi| std::cmp::Reverse(fi.episode_number));
+    }
+    items
+}
+
+/// Builds an Atom feed for an individual feed, published at `self_url` and announcing the
+/// WebSub `hub` if given. `categories` describe the feed as a whole.
+pub fn build_atom_feed(
+    feed_data: &FeedData,
+    self_url: &str,
+    repo_name: &str,
+    hub: Option<&str>,
+    categories: &[String],
+) -> atom_syndication::Feed {
+    let entries: Vec<atom_syndication::Entry> = listing_order(feed_data)
+        .into_iter()
+        .map(|fi| {
+            let mut links = vec![LinkBuilder::default().href(fi.link.clone()).rel("alternate").build()];
+            if let Some(comments) = &fi.comments {
+                links.push(
+                    LinkBuilder::default()
+                        .href(comments.clone())
+                        .rel("replies")
+                        .mime_type(Some("text/html".to_string()))
+                        .build(),
+                );
+            }
+            if let Some(enclosure) = &fi.enclosure {
+                links.push(
+                    LinkBuilder::default()
+                        .href(enclosure.url.clone())
+                        .rel("enclosure")
+                        .mime_type(Some(enclosure.mime_type.clone()))
+                        .length(Some(enclosure.length.to_string()))
+                        .build(),
+                );
+            }
+            EntryBuilder::default()
+                .id(fi.guid.clone())
+                .title(Text::plain(fi.title.clone()))
+                .links(links)
+                .published(Some(fi.pub_date))
+                .updated(fi.updated_date.unwrap_or(fi.pub_date))
+                .summary(fi.description.clone().map(Text::html))
+                .categories(fi.categories.iter().map(|term| atom_category(term)).collect::<Vec<_>>())
+                .authors(fi.authors.iter().map(|name| PersonBuilder::default().name(name.clone()).build()).collect::<Vec<_>>())
+                .content(fi.content.clone().map(|content| {
+                    ContentBuilder::default()
+                        .value(Some(content))
+                        .content_type(Some("html".to_string()))
+                        .build()
+                }))
+                .build()
+        })
+        .collect();
+
+    // Atom requires an updated date; use the latest item update, or now for an empty feed
+    let updated = feed_data
+        .items
+        .iter()
+        .map(|fi| fi.updated_date.unwrap_or(fi.pub_date))
+        .max()
+        .unwrap_or_else(|| Utc::now().fixed_offset());
+
+    let mut links = vec![
+        LinkBuilder::default().href(self_url).rel("self").build(),
+        LinkBuilder::default().href(feed_data.resolved_url.clone()).rel("via").build(),
+    ];
+    if let Some(hub) = hub {
+        links.push(LinkBuilder::default().href(hub).rel("hub").build());
+    }
+
+    FeedBuilder::default()
+        .id(self_url)
+        .title(Text::plain(feed_data.display_title()))
+        .subtitle(Some(Text::plain(archive_description(feed_data))))
+        .links(links)
+        .updated(updated)
+        .generator(Some(Generator {
+            value: GENERATOR_NAME.to_string(),
+            uri: Some(format!("https://github.com/{}", repo_name)),
+            version: Some(GENERATOR_VERSION.to_string()),
+        }))
+        .lang(feed_data.language.clone())
+        .logo(feed_data.icon.clone())
+        .rights(feed_data.copyright.as_deref().map(Text::plain))
+        .categories(categories.iter().map(|term| atom_category(term)).collect::<Vec<_>>())
+        .entries(entries)
+        .build()
+}
+
+/// The tagline of the source feed, or where the feed was archived from if it has none.
+fn archive_description(feed_data: &FeedData) -> String {
+    feed_data.subtitle.clone().unwrap_or_else(|| format!("Archived feed from {}", feed_data.resolved_url))
+}
+
+fn atom_category(term: &str) -> Category {
+    CategoryBuilder::default().term(term).build()
+}
+
+// JSON Feed 1.1 document, see https://www.jsonfeed.org/version/1.1/
+#[derive(Debug, Serialize)]
+struct JsonFeed {
+    version: &'static str,
+    title: String,
+    feed_url: String,
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hubs: Vec<JsonFeedHub>,
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonFeedHub {
+    #[serde(rename = "type")]
+    hub_type: &'static str,
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonFeedItem {
+    id: String,
+    url: String,
+    title: String,
+    content_html: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    date_published: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<JsonFeedAuthor>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<JsonFeedAttachment>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonFeedAuthor {
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size_in_bytes: Option<u64>,
+}
+
+/// Builds a JSON Feed document for an individual feed, published at `self_url` and
+/// announcing the WebSub `hub` if given.
+pub fn build_json_feed(feed_data: &FeedData, self_url: &str, hub: Option<&str>) -> Result<String, serde_json::Error> {
+    let items = listing_order(feed_data)
+        .into_iter()
+        .map(|fi| JsonFeedItem {
+            id: fi.guid.clone(),
+            url: fi.link.clone(),
+            title: fi.title.clone(),
+            // Items need some content; fall back to the description when there is no full content
+            content_html: fi.content.clone().or_else(|| fi.description.clone()).unwrap_or_default(),
+            summary: fi.content.as_ref().and(fi.description.clone()),
+            date_published: fi.pub_date.to_rfc3339(),
+            date_modified: fi.updated_date.map(|date| date.to_rfc3339()),
+            image: fi.thumbnail.clone(),
+            language: fi.language.clone(),
+            tags: fi.categories.clone(),
+            authors: fi.authors.iter().map(|name| JsonFeedAuthor { name: name.clone() }).collect(),
+            attachments: fi
+                .enclosure
+                .iter()
+                .map(|enclosure| JsonFeedAttachment {
+                    url: enclosure.url.clone(),
+                    mime_type: enclosure.mime_type.clone(),
+                    size_in_bytes: (enclosure.length > 0).then_some(enclosure.length),
+                })
+                .collect(),
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: feed_data.display_title().to_string(),
+        feed_url: self_url.to_string(),
+        description: archive_description(feed_data),
+        language: feed_data.language.clone(),
+        icon: feed_data.icon.clone(),
+        hubs: hub
+            .map(|hub| JsonFeedHub { hub_type: "WebSub", url: hub.to_string() })
+            .into_iter()
+            .collect(),
+        items,
+    })
+}
@@ -0,0 +1,984 @@
+// pseudo_feed.rs
+use crate::crawler::{decompress_sniffed, FeedError, FetchRequest, HttpClient};
+use crate::dates::parse_fallback_date;
+use crate::html_rewrite::{split_raw_text, Tag, RAW_TEXT_ELEMENTS};
+use crate::output::OutputFormat;
+use crate::{FeedData, FeedItem};
+use chrono::{DateTime, FixedOffset, Utc};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::{Chars, FromStr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+
+// Entries of a sitemap taken as items unless the feed list sets `entries`
+pub const DEFAULT_SITEMAP_ENTRIES: usize = 20;
+
+// Pages fetched per run for the titles of sitemap entries, unless `sitemap_title_fetches` is set
+pub const DEFAULT_SITEMAP_TITLE_FETCHES: usize = 20;
+
+// Sitemaps of a sitemap index that are read, the most recently modified first
+const MAX_CHILD_SITEMAPS: usize = 5;
+
+const SITEMAP_ACCEPT: &str = "application/xml, text/xml;q=0.9, */*;q=0.5";
+const HTML_ACCEPT: &str = "text/html, application/xhtml+xml;q=0.9, */*;q=0.5";
+
+// Elements that have no end tag
+const VOID_ELEMENTS: &[&str] = &["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+// Elements whose start tag ends an open element of the same name, e.g. <li>one<li>two
+const SELF_ENDING_ELEMENTS: &[&str] = &["dd", "dt", "li", "option", "p", "td", "th", "tr"];
+
+/// How a page that isn't a feed is turned into one, from the `type` option of its feed
+/// list line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PseudoFeedKind {
+    /// The newest `<url>` entries of a sitemap or sitemap index
+    Sitemap,
+    /// The elements of an HTML listing page picked by CSS selectors
+    Scrape,
+}
+
+impl PseudoFeedKind {
+    /// The value of the `type` option naming this kind, None for other values.
+    pub fn from_type(value: &str) -> Option<PseudoFeedKind> {
+        match value {
+            "sitemap" => Some(PseudoFeedKind::Sitemap),
+            "scrape" => Some(PseudoFeedKind::Scrape),
+            _ => None,
+        }
+    }
+
+    /// Accept header sent for the page unless the feed list sets one.
+    pub fn accept(self) -> &'static str {
+        match self {
+            PseudoFeedKind::Sitemap => SITEMAP_ACCEPT,
+            PseudoFeedKind::Scrape => HTML_ACCEPT,
+        }
+    }
+}
+
+/// The CSS selectors of a `type=scrape` feed, from the `*_selector` options of its feed
+/// list line. Only the item selector is required.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrapeSelectors {
+    /// The element of each item on the listing page
+    pub item: Option<Selector>,
+    /// The link of an item within its element, by default the first `a[href]`
+    pub link: Option<Selector>,
+    /// The title of an item within its element, by default the text of its link
+    pub title: Option<Selector>,
+    /// The date of an item within its element, by default the first `time`
+    pub date: Option<Selector>,
+}
+
+impl ScrapeSelectors {
+    pub fn is_empty(&self) -> bool {
+        *self == ScrapeSelectors::default()
+    }
+}
+
+/// A pseudo-feed to build from the body of its page.
+#[derive(Debug, Clone)]
+pub enum PseudoFeed {
+    Sitemap {
+        /// Number of entries taken as items
+        entries: usize,
+        /// Page fetches left in this run for the titles of entries, shared by all sitemaps
+        title_fetches: Arc<AtomicUsize>,
+        /// Titles of the entries archived by earlier runs, by URL, which aren't fetched again
+        known_titles: Arc<HashMap<String, String>>,
+    },
+    Scrape(ScrapeSelectors),
+}
+
+impl PseudoFeed {
+    /// Human readable name of the kind of page, for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PseudoFeed::Sitemap { .. } => "sitemap",
+            PseudoFeed::Scrape(_) => "scraped listing page",
+        }
+    }
+}
+
+/// Builds the feed of the sitemap or listing page fetched for `request` and served from
+/// `page_url`. Sitemaps of a sitemap index and the pages of entries without a title are
+/// fetched with `client`. This is best-effort: pages that can't be read are reported as
+/// warnings and skipped, and the feed only fails if nothing usable is left. With `verbose`,
+/// the number of entries titled after their URL is printed as well.
+pub async fn build_pseudo_feed(client: &dyn HttpClient, request: &FetchRequest, page_url: &str, body: &[u8], pseudo_feed: &PseudoFeed, verbose: bool) -> Result<FeedData, FeedError> {
+    let decompressed = decompress_sniffed(body).map(|(_, body)| body);
+    let body = decompressed.as_deref().unwrap_or(body);
+    match pseudo_feed {
+        PseudoFeed::Sitemap { entries, title_fetches, known_titles } => sitemap_feed(client, request, body, *entries, title_fetches, known_titles, verbose).await,
+        PseudoFeed::Scrape(selectors) => scrape_feed(&request.url, page_url, body, selectors),
+    }
+}
+
+// A <url> of a sitemap, or a <sitemap> of a sitemap index
+#[derive(Debug, Default)]
+struct SitemapEntry {
+    loc: String,
+    lastmod: Option<DateTime<FixedOffset>>,
+    // <news:title> and <news:publication_date> of news sitemaps
+    title: Option<String>,
+    published: Option<DateTime<FixedOffset>>,
+}
+
+impl SitemapEntry {
+    fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.published.or(self.lastmod)
+    }
+}
+
+enum Sitemap {
+    Urls(Vec<SitemapEntry>),
+    Index(Vec<SitemapEntry>),
+}
+
+/// Reads the entries of a `<urlset>` or a `<sitemapindex>`, skipping those without a `<loc>`.
+fn parse_sitemap(body: &[u8]) -> Result<Sitemap, String> {
+    let mut reader = Reader::from_reader(body);
+    reader.config_mut().trim_text(true);
+    let mut buf = Vec::new();
+    let mut root: Option<Vec<u8>> = None;
+    // Local names of the open elements below the root
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let mut entry: Option<SitemapEntry> = None;
+    let mut entries = Vec::new();
+    let mut text = String::new();
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(|e| e.to_string())?;
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let empty = matches!(event, Event::Empty(_));
+                let name = e.local_name().as_ref().to_vec();
+                if root.is_none() {
+                    if name != b"urlset" && name != b"sitemapindex" {
+                        return Err(format!("expected a sitemap, found a <{}> document", String::from_utf8_lossy(&name)));
+                    }
+                    root = Some(name);
+                    if empty {
+                        break;
+                    }
+                } else if !empty {
+                    if path.is_empty() && matches!(name.as_slice(), b"url" | b"sitemap") {
+                        entry = Some(SitemapEntry::default());
+                    }
+                    path.push(name);
+                    text.clear();
+                }
+            }
+            Event::Text(e) => text.push_str(&e.unescape().map_err(|e| e.to_string())?),
+            Event::CData(e) => text.push_str(&String::from_utf8_lossy(e)),
+            Event::End(_) => {
+                let Some(name) = path.pop() else {
+                    continue;
+                };
+                if path.is_empty() {
+                    entries.extend(entry.take().filter(|entry| !entry.loc.is_empty()));
+                } else if let Some(entry) = entry.as_mut() {
+                    let value = text.trim();
+                    let in_news = path.last().is_some_and(|parent| parent == b"news");
+                    match name.as_slice() {
+                        b"loc" if path.len() == 1 => entry.loc = value.to_string(),
+                        b"lastmod" if path.len() == 1 => entry.lastmod = parse_fallback_date(value),
+                        b"title" if in_news => entry.title = Some(value.to_string()).filter(|title| !title.is_empty()),
+                        b"publication_date" if in_news => entry.published = parse_fallback_date(value),
+                        _ => {}
+                    }
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    match root.as_deref() {
+        Some(b"sitemapindex") => Ok(Sitemap::Index(entries)),
+        Some(_) => Ok(Sitemap::Urls(entries)),
+        None => Err("no <urlset> or <sitemapindex> found".to_string()),
+    }
+}
+
+/// Whether `body` is a sitemap index, whose sitemaps can change while it stays the same.
+pub fn is_sitemap_index(body: &[u8]) -> bool {
+    let decompressed = decompress_sniffed(body).map(|(_, body)| body);
+    let mut reader = Reader::from_reader(decompressed.as_deref().unwrap_or(body));
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e) | Event::Empty(e)) => return e.local_name().as_ref() == b"sitemapindex",
+            Ok(Event::Eof) | Err(_) => return false,
+            Ok(_) => buf.clear(),
+        }
+    }
+}
+
+/// The feed of a sitemap: its newest `entries` URLs, dated by their `<lastmod>` (or news
+/// publication date) and titled by their `<news:title>`, the title `known_titles` has for
+/// them or else the `<title>` of their page.
+async fn sitemap_feed(
+    client: &dyn HttpClient,
+    request: &FetchRequest,
+    body: &[u8],
+    entries: usize,
+    title_fetches: &AtomicUsize,
+    known_titles: &HashMap<String, String>,
+    verbose: bool,
+) -> Result<FeedData, FeedError> {
+    let mut listed = match parse_sitemap(body).map_err(FeedError::Parse)? {
+        Sitemap::Urls(listed) => listed,
+        Sitemap::Index(sitemaps) => read_sitemap_index(client, request, sitemaps).await?,
+    };
+    // Entries without a date go last, in the order listed
+    listed.sort_by_key(|entry| std::cmp::Reverse(entry.date()));
+    let mut seen = HashSet::new();
+    listed.retain(|entry| seen.insert(entry.loc.clone()));
+    listed.truncate(entries);
+
+    let feed_title = host_title(&request.url);
+    let now = Utc::now().fixed_offset();
+    let mut items = Vec::new();
+    let mut untitled = 0;
+    for entry in listed {
+        let date = entry.date();
+        // A title made from the URL is no title found, and fetching it is tried again
+        let known = known_titles.get(&entry.loc).filter(|title| **title != title_from_url(&entry.loc));
+        let title = match entry.title.or_else(|| known.cloned()) {
+            Some(title) => title,
+            None => match page_title(client, request, &entry.loc, title_fetches).await {
+                Some(title) => title,
+                None => {
+                    untitled += 1;
+                    title_from_url(&entry.loc)
+                }
+            },
+        };
+        items.push(pseudo_item(entry.loc, title, date.unwrap_or(now), &request.url, &feed_title));
+    }
+    if untitled > 0 {
+        debug!(verbose, "{} entries of the sitemap {} are titled after their URL", untitled, request.url);
+    }
+    Ok(pseudo_feed_data(&request.url, feed_title, None, items))
+}
+
+/// The entries of the most recently modified sitemaps of a sitemap index. Sitemaps that
+/// can't be fetched or read are skipped with a warning.
+async fn read_sitemap_index(client: &dyn HttpClient, request: &FetchRequest, mut sitemaps: Vec<SitemapEntry>) -> Result<Vec<SitemapEntry>, FeedError> {
+    sitemaps.sort_by_key(|sitemap| std::cmp::Reverse(sitemap.lastmod));
+    sitemaps.truncate(MAX_CHILD_SITEMAPS);
+    let mut entries = Vec::new();
+    let mut read = 0;
+    for sitemap in &sitemaps {
+        let child = FetchRequest {
+            url: sitemap.loc.clone(),
+            accept: SITEMAP_ACCEPT.to_string(),
+            follow_redirects: request.follow_redirects,
+            if_none_match: None,
+            if_modified_since: None,
+        };
+        let body = match client.fetch(&child).await {
+            Ok(response) => response.body,
+            Err(e) => {
+                eprintln!("Warning: Skipping the sitemap {} listed in {}: {}", sitemap.loc, request.url, e);
+                continue;
+            }
+        };
+        let decompressed = decompress_sniffed(&body).map(|(_, body)| body);
+        match parse_sitemap(decompressed.as_deref().unwrap_or(&body)) {
+            Ok(Sitemap::Urls(urls)) => {
+                entries.extend(urls);
+                read += 1;
+            }
+            Ok(Sitemap::Index(_)) => eprintln!("Warning: Skipping the sitemap index {} nested in {}", sitemap.loc, request.url),
+            Err(e) => eprintln!("Warning: Skipping the sitemap {} listed in {}: {}", sitemap.loc, request.url, e),
+        }
+    }
+    if read == 0 && !sitemaps.is_empty() {
+        return Err(FeedError::Parse(format!("none of the {} sitemaps of the sitemap index could be read", sitemaps.len())));
+    }
+    Ok(entries)
+}
+
+/// The `<title>` of the page at `url`, if the page fetches left in this run allow fetching
+/// it. A page that can't be fetched is reported as a warning.
+async fn page_title(client: &dyn HttpClient, request: &FetchRequest, url: &str, title_fetches: &AtomicUsize) -> Option<String> {
+    title_fetches.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1)).ok()?;
+    let page = FetchRequest {
+        url: url.to_string(),
+        accept: HTML_ACCEPT.to_string(),
+        follow_redirects: request.follow_redirects,
+        if_none_match: None,
+        if_modified_since: None,
+    };
+    match client.fetch(&page).await {
+        Ok(response) => HtmlDocument::parse(&String::from_utf8_lossy(&response.body)).title(),
+        Err(e) => {
+            eprintln!("Warning: Could not fetch the title of {} listed in {}: {}", url, request.url, e);
+            None
+        }
+    }
+}
+
+/// A title made from the last segment of a URL's path, e.g. "Release notes" for
+/// https://example.com/blog/release-notes.html, or its host for the home page.
+fn title_from_url(url: &str) -> String {
+    let Ok(parsed) = reqwest::Url::parse(url) else {
+        return url.to_string();
+    };
+    let segment = parsed.path_segments().and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()));
+    let Some(segment) = segment else {
+        return host_title(url);
+    };
+    let stem = segment.rsplit_once('.').filter(|(_, extension)| matches!(*extension, "html" | "htm" | "php" | "aspx")).map_or(segment, |(stem, _)| stem);
+    let words = stem.replace(['-', '_', '+'], " ").split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => host_title(url),
+    }
+}
+
+/// The host of a URL, used as the title of pseudo-feeds that have none.
+fn host_title(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// The feed of a listing page: an item for every element matched by the item selector
+/// that contains a link. Fails if the item selector matches nothing, which usually means
+/// the layout of the page changed.
+fn scrape_feed(url: &str, page_url: &str, body: &[u8], selectors: &ScrapeSelectors) -> Result<FeedData, FeedError> {
+    let item_selector = selectors.item.as_ref().ok_or_else(|| FeedError::Parse("type=scrape needs an item_selector".to_string()))?;
+    let default_link = Selector::from_str("a[href]").unwrap();
+    let default_date = Selector::from_str("time").unwrap();
+    let link_selector = selectors.link.as_ref().unwrap_or(&default_link);
+    let date_selector = selectors.date.as_ref().unwrap_or(&default_date);
+
+    let html = String::from_utf8_lossy(body);
+    let document = HtmlDocument::parse(&html);
+    let containers: Vec<usize> = (0..document.elements.len()).filter(|&index| item_selector.matches(&document, index)).collect();
+    if containers.is_empty() {
+        return Err(FeedError::Parse(format!(
+            "the item selector {} matched nothing on the page; scraping is best-effort and the page layout may have changed",
+            item_selector
+        )));
+    }
+    let feed_title = document.title().unwrap_or_else(|| host_title(url));
+    let language = document.elements.iter().find(|element| element.name == "html").and_then(|html| html.attr("lang")).map(str::to_string);
+    let base = reqwest::Url::parse(page_url).ok();
+    let now = Utc::now().fixed_offset();
+
+    let mut items: Vec<FeedItem> = Vec::new();
+    let mut without_link = 0;
+    for container in containers {
+        let link_element = document.select_within(container, link_selector);
+        let link = link_element
+            .and_then(|index| document.elements[index].attr("href"))
+            .filter(|href| !href.starts_with('#'))
+            .and_then(|href| base.as_ref().map_or_else(|| reqwest::Url::parse(href), |base| base.join(href)).ok())
+            .filter(|link| matches!(link.scheme(), "http" | "https"));
+        let (Some(link_element), Some(link)) = (link_element, link) else {
+            without_link += 1;
+            continue;
+        };
+        let link = link.to_string();
+        if items.iter().any(|item| item.link == link) {
+            continue;
+        }
+        let title = match &selectors.title {
+            Some(selector) => document.select_within(container, selector).map(|index| document.text(index)),
+            None => Some(document.text(link_element)),
+        }
+        .filter(|title| !title.is_empty())
+        .or_else(|| document.elements[link_element].attr("title").map(str::to_string))
+        .unwrap_or_else(|| String::from("No title"));
+        let date = document.select_within(container, date_selector).and_then(|index| {
+            let element = &document.elements[index];
+            ["datetime", "content"]
+                .iter()
+                .find_map(|name| element.attr(name))
+                .and_then(parse_fallback_date)
+                .or_else(|| parse_fallback_date(&document.text(index)))
+        });
+        items.push(pseudo_item(link, title, date.unwrap_or(now), url, &feed_title));
+    }
+    if without_link > 0 {
+        eprintln!(
+            "Warning: Skipping {} elements matched by {} on {} without a link matched by {}",
+            without_link, item_selector, url, link_selector
+        );
+    }
+    if items.is_empty() {
+        return Err(FeedError::Parse(format!("none of the elements matched by {} contains a link matched by {}", item_selector, link_selector)));
+    }
+    Ok(pseudo_feed_data(url, feed_title, language, items))
+}
+
+/// An item of a pseudo-feed, identified by its link.
+fn pseudo_item(link: String, title: String, pub_date: DateTime<FixedOffset>, feed_url: &str, feed_title: &str) -> FeedItem {
+    FeedItem {
+        guid: link.clone(),
+        guid_synthesized: true,
+        title,
+        link,
+        description: None,
+        content: None,
+        pub_date,
+        updated_date: None,
+        read_time_minutes: None,
+        language: None,
+        enclosure: None,
+        thumbnail: None,
+        comments: None,
+        comment_count: None,
+        comments_feed: None,
+        episode_number: None,
+        categories: Vec::new(),
+        authors: Vec::new(),
+        original_feed_url: Some(feed_url.to_string()),
+        original_feed_title: Some(feed_title.to_string()),
+    }
+}
+
+fn pseudo_feed_data(url: &str, title: String, language: Option<String>, items: Vec<FeedItem>) -> FeedData {
+    FeedData {
+        title,
+        subtitle: None,
+        url: url.to_string(),
+        resolved_url: url.to_string(),
+        items,
+        language,
+        icon: None,
+        copyright: None,
+        slug: None,
+        priority: 0,
+        max_file_bytes: None,
+        base_url: None,
+        categories: Vec::new(),
+        display_title: None,
+        format: OutputFormat::default(),
+        filtered_count: 0,
+        ttl: None,
+        skip_hours: Vec::new(),
+        skip_days: Vec::new(),
+    }
+}
+
+// An HTML page as a list of its elements in document order. It is parsed leniently:
+// an end tag closes the elements left open inside its element, stray end tags are
+// ignored and elements still open at the end of the page end there.
+struct HtmlDocument<'a> {
+    html: &'a str,
+    // Byte ranges of the text of the page in document order, without the markup and the
+    // content of raw text elements such as scripts
+    texts: Vec<Range<usize>>,
+    elements: Vec<Element>,
+}
+
+struct Element {
+    // Lowercase tag name
+    name: String,
+    // Lowercase names, and values with their character references decoded
+    attributes: Vec<(String, String)>,
+    parent: Option<usize>,
+    // Index after the last element inside this one
+    end: usize,
+    // The document's texts inside the element
+    texts: Range<usize>,
+    // Byte range of the content of a raw text element, such as a <title>
+    raw_text: Option<Range<usize>>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(attribute, _)| attribute == name).map(|(_, value)| value.as_str())
+    }
+}
+
+impl<'a> HtmlDocument<'a> {
+    fn parse(html: &'a str) -> HtmlDocument<'a> {
+        let mut elements: Vec<Element> = Vec::new();
+        let mut texts: Vec<Range<usize>> = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        let offset = |rest: &str| html.len() - rest.len();
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            if start > 0 {
+                texts.push(offset(rest)..offset(rest) + start);
+            }
+            rest = &rest[start..];
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+                continue;
+            }
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+                continue;
+            }
+            let Some(tag) = Tag::parse(rest) else {
+                texts.push(offset(rest)..offset(rest) + 1);
+                rest = &rest[1..];
+                continue;
+            };
+            rest = &rest[tag.len..];
+            let name = tag.name.to_ascii_lowercase();
+            if tag.end {
+                if let Some(position) = open.iter().rposition(|&index| elements[index].name == name) {
+                    for index in open.drain(position..) {
+                        elements[index].end = elements.len();
+                        elements[index].texts.end = texts.len();
+                    }
+                }
+                continue;
+            }
+            if SELF_ENDING_ELEMENTS.contains(&name.as_str()) && open.last().is_some_and(|&index| elements[index].name == name) {
+                if let Some(index) = open.pop() {
+                    elements[index].end = elements.len();
+                    elements[index].texts.end = texts.len();
+                }
+            }
+            let index = elements.len();
+            elements.push(Element {
+                attributes: tag
+                    .attributes
+                    .iter()
+                    .map(|attribute| (attribute.name.to_ascii_lowercase(), attribute.value.map(decode_entities).unwrap_or_default()))
+                    .collect(),
+                parent: open.last().copied(),
+                end: index + 1,
+                texts: texts.len()..texts.len(),
+                raw_text: None,
+                name,
+            });
+            let name = elements[index].name.as_str();
+            if RAW_TEXT_ELEMENTS.contains(&name) {
+                // The text of a <title> is kept, that of scripts and styles isn't part of the page text
+                let (content, end_tag) = split_raw_text(rest, name);
+                elements[index].raw_text = Some(offset(rest)..offset(rest) + content.len());
+                rest = if end_tag.is_empty() { "" } else { &rest[content.len() + end_tag.len()..] };
+            } else if !tag.self_closing && !VOID_ELEMENTS.contains(&name) {
+                open.push(index);
+            }
+        }
+        if !rest.is_empty() {
+            texts.push(offset(rest)..html.len());
+        }
+        let count = elements.len();
+        for index in open {
+            elements[index].end = count;
+            elements[index].texts.end = texts.len();
+        }
+        HtmlDocument { html, texts, elements }
+    }
+
+    /// The text of the element at `index` with its whitespace collapsed.
+    fn text(&self, index: usize) -> String {
+        let element = &self.elements[index];
+        let text: String = match &element.raw_text {
+            Some(range) => self.html[range.clone()].to_string(),
+            None => self.texts[element.texts.clone()].iter().map(|range| &self.html[range.clone()]).collect(),
+        };
+        decode_entities(&text).split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// The text of the first `<title>`, if it isn't empty.
+    fn title(&self) -> Option<String> {
+        let index = self.elements.iter().position(|element| element.name == "title")?;
+        Some(self.text(index)).filter(|title| !title.is_empty())
+    }
+
+    /// The first element matched by `selector` among the element at `index` and those inside it.
+    fn select_within(&self, index: usize, selector: &Selector) -> Option<usize> {
+        (index..self.elements[index].end).find(|&inner| selector.matches(self, inner))
+    }
+}
+
+/// Decodes the character references of HTML text: the XML ones, `&nbsp;` and numeric ones.
+fn decode_entities(text: &str) -> String {
+    static ENTITY_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&(amp|lt|gt|quot|apos|nbsp|#[0-9]+|#[xX][0-9a-fA-F]+);").unwrap());
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    ENTITY_RE
+        .replace_all(text, |caps: &regex::Captures| {
+            let entity = &caps[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity[1..]
+                    .strip_prefix(['x', 'X'])
+                    .map_or_else(|| entity[1..].parse().ok(), |hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32),
+            };
+            decoded.map_or_else(|| caps[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+/// A CSS selector of the subset scrape mode understands: type and universal selectors,
+/// `#id`, `.class`, `[attr]` and `[attr=value]`, the descendant and child (`>`)
+/// combinators and comma-separated lists of selectors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    text: String,
+    // The comma-separated selectors, each a chain of compound selectors from the left
+    // with the combinator joining it to the previous one
+    alternatives: Vec<Vec<(Combinator, Compound)>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+// Conditions on a single element, e.g. a.title[href]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let alternatives = text
+            .split(',')
+            .map(|alternative| parse_chain(alternative.trim()))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("invalid selector {:?}: {}", text, e))?;
+        Ok(Selector {
+            text: text.trim().to_string(),
+            alternatives,
+        })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.text)
+    }
+}
+
+impl Selector {
+    /// Returns true if the element at `index` of `document` is matched.
+    fn matches(&self, document: &HtmlDocument, index: usize) -> bool {
+        self.alternatives.iter().any(|chain| chain_matches(document, chain, index))
+    }
+}
+
+/// Matches a chain of compound selectors from the right: the element must match the
+/// last one, and its parent (child combinator) or one of its ancestors (descendant
+/// combinator) the rest of the chain.
+fn chain_matches(document: &HtmlDocument, chain: &[(Combinator, Compound)], index: usize) -> bool {
+    let Some(((combinator, last), rest)) = chain.split_last() else {
+        return true;
+    };
+    if !last.matches(&document.elements[index]) {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+    let mut ancestor = document.elements[index].parent;
+    while let Some(candidate) = ancestor {
+        if chain_matches(document, rest, candidate) {
+            return true;
+        }
+        if *combinator == Combinator::Child {
+            return false;
+        }
+        ancestor = document.elements[candidate].parent;
+    }
+    false
+}
+
+impl Compound {
+    fn matches(&self, element: &Element) -> bool {
+        self.tag.as_ref().is_none_or(|tag| *tag == element.name)
+            && self.id.as_ref().is_none_or(|id| element.attr("id") == Some(id.as_str()))
+            && self
+                .classes
+                .iter()
+                .all(|class| element.attr("class").is_some_and(|classes| classes.split_whitespace().any(|known| known == class)))
+            && self.attributes.iter().all(|(name, value)| match value {
+                Some(value) => element.attr(name) == Some(value.as_str()),
+                None => element.attr(name).is_some(),
+            })
+    }
+}
+
+fn parse_chain(text: &str) -> Result<Vec<(Combinator, Compound)>, String> {
+    let mut chain = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut child = false;
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None => break,
+            Some('>') if child || chain.is_empty() => return Err("misplaced >".to_string()),
+            Some('>') => {
+                chars.next();
+                child = true;
+                continue;
+            }
+            Some(_) => {}
+        }
+        let combinator = if child { Combinator::Child } else { Combinator::Descendant };
+        chain.push((combinator, parse_compound(&mut chars)?));
+        child = false;
+    }
+    if child {
+        return Err("expected a selector after >".to_string());
+    }
+    if chain.is_empty() {
+        return Err("empty selector".to_string());
+    }
+    Ok(chain)
+}
+
+fn parse_compound(chars: &mut Peekable<Chars>) -> Result<Compound, String> {
+    let mut compound = Compound::default();
+    if chars.next_if_eq(&'*').is_none() && chars.peek().is_some_and(|&c| is_name_char(c)) {
+        compound.tag = Some(read_name(chars).to_ascii_lowercase());
+    }
+    loop {
+        match chars.peek().copied() {
+            Some('#') => {
+                chars.next();
+                compound.id = Some(read_required_name(chars, '#')?);
+            }
+            Some('.') => {
+                chars.next();
+                compound.classes.push(read_required_name(chars, '.')?);
+            }
+            Some('[') => {
+                chars.next();
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                let name = read_required_name(chars, '[')?.to_ascii_lowercase();
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                let value = match chars.next() {
+                    Some(']') => None,
+                    Some('=') => {
+                        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                        let value: String = match chars.next_if(|&c| c == '\'' || c == '"') {
+                            Some(quote) => {
+                                let value = chars.by_ref().take_while(|&c| c != quote).collect();
+                                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                                if chars.next() != Some(']') {
+                                    return Err("expected ] after the attribute value".to_string());
+                                }
+                                value
+                            }
+                            None => {
+                                let value: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                                value.trim().to_string()
+                            }
+                        };
+                        Some(value)
+                    }
+                    _ => return Err(format!("expected = or ] after [{}", name)),
+                };
+                compound.attributes.push((name, value));
+            }
+            Some(c) if c.is_whitespace() || c == '>' => break,
+            None => break,
+            Some(c) => return Err(format!("unsupported {:?}", c)),
+        }
+    }
+    Ok(compound)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+fn read_name(chars: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|&c| is_name_char(c)) {
+        name.push(c);
+    }
+    name
+}
+
+fn read_required_name(chars: &mut Peekable<Chars>, after: char) -> Result<String, String> {
+    let name = read_name(chars);
+    if name.is_empty() {
+        return Err(format!("expected a name after {}", after));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockClient;
+
+    const LISTING: &str = r##"<!DOCTYPE html><html lang="en"><head><title>The &amp; Blog</title><script>var x = "<a href='/fake'>";</script></head>
+        <body><main>
+        <article class="post featured"><h2><a href="/posts/one" title="First">One &amp; only</a></h2><time datetime="2024-03-01T10:00:00Z">March 1</time></article>
+        <article class="post"><h2><a href="https://example.com/posts/two">Two</a></h2><span class="date">2024-02-01</span></article>
+        <!-- <article class="post"><a href="/posts/commented">Commented out</a></article> -->
+        <article class="post"><p>No link</article>
+        <aside><article class="post"><a href="#top">Top</a></article></aside>
+        </main></body></html>"##;
+
+    fn selector(text: &str) -> Selector {
+        Selector::from_str(text).unwrap()
+    }
+
+    fn matched(html: &str, selector_text: &str) -> Vec<String> {
+        let document = HtmlDocument::parse(html);
+        let selector = selector(selector_text);
+        (0..document.elements.len()).filter(|&index| selector.matches(&document, index)).map(|index| document.text(index)).collect()
+    }
+
+    #[test]
+    fn selectors_are_parsed() {
+        let parsed = selector(" main > article.post.featured[data-id='7'] a[href], #top ");
+        assert_eq!(parsed.alternatives.len(), 2);
+        let chain = &parsed.alternatives[0];
+        assert_eq!(chain.iter().map(|(combinator, _)| *combinator).collect::<Vec<_>>(), [Combinator::Descendant, Combinator::Child, Combinator::Descendant]);
+        assert_eq!(chain[1].1.tag.as_deref(), Some("article"));
+        assert_eq!(chain[1].1.classes, ["post", "featured"]);
+        assert_eq!(chain[1].1.attributes, [("data-id".to_string(), Some("7".to_string()))]);
+        assert_eq!(chain[2].1.attributes, [("href".to_string(), None)]);
+        assert_eq!(parsed.alternatives[1][0].1.id.as_deref(), Some("top"));
+
+        for invalid in ["", "a,", "> a", "a >", "a > > b", "a:hover", "a[href", "a[href=\"x\"", ".", "a + b"] {
+            assert!(Selector::from_str(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn selectors_match_descendants_children_and_attributes() {
+        let html = r#"<div id="main" class="a b"><ul><li data-x="1">one <li>two <b>bold</b></ul> <p class="b">para</div><li data-x=2>three"#;
+        assert_eq!(matched(html, "li"), ["one", "two bold", "three"]);
+        assert_eq!(matched(html, "div li"), ["one", "two bold"]);
+        assert_eq!(matched(html, "div > li"), Vec::<String>::new());
+        assert_eq!(matched(html, "ul > li > b"), ["bold"]);
+        assert_eq!(matched(html, "[data-x]"), ["one", "three"]);
+        assert_eq!(matched(html, "li[data-x=2]"), ["three"]);
+        assert_eq!(matched(html, "#main > .b"), ["para"]);
+        assert_eq!(matched(html, ".a.b"), ["one two bold para"]);
+        assert_eq!(matched(html, "p, b"), ["bold", "para"]);
+        assert_eq!(matched(html, "DIV#main *.b"), ["para"]);
+    }
+
+    #[test]
+    fn html_is_parsed_leniently() {
+        let html = "<div><p>one <p>two</div> <span>stray</b> end</span><br><img src=x><em>unclosed <i>nested";
+        let document = HtmlDocument::parse(html);
+        let names: Vec<(&str, Option<usize>)> = document.elements.iter().map(|element| (element.name.as_str(), element.parent)).collect();
+        assert_eq!(names, [("div", None), ("p", Some(0)), ("p", Some(0)), ("span", None), ("br", None), ("img", None), ("em", None), ("i", Some(6))]);
+        assert_eq!(document.text(0), "one two");
+        assert_eq!(document.text(3), "stray end");
+        assert_eq!(document.text(6), "unclosed nested");
+        assert_eq!(document.elements[6].end, 8);
+
+        let document = HtmlDocument::parse(r#"<a href="/x?a=1&amp;b=2" TITLE='It&#39;s'>a < b &lt; c</a><style>p { color: red }</style>"#);
+        assert_eq!(document.elements[0].attr("href"), Some("/x?a=1&b=2"));
+        assert_eq!(document.elements[0].attr("title"), Some("It's"));
+        assert_eq!(document.text(0), "a < b < c");
+        assert_eq!(document.title(), None);
+    }
+
+    #[test]
+    fn listing_pages_are_scraped() {
+        let selectors = ScrapeSelectors {
+            item: Some(selector("main > article.post")),
+            date: Some(selector("time, .date")),
+            ..Default::default()
+        };
+        let feed_data = scrape_feed("https://example.com/blog/", "https://example.com/blog/", LISTING.as_bytes(), &selectors).unwrap();
+        assert_eq!(feed_data.title, "The & Blog");
+        assert_eq!(feed_data.language.as_deref(), Some("en"));
+        let items: Vec<(&str, &str, String)> = feed_data.items.iter().map(|item| (item.link.as_str(), item.title.as_str(), item.pub_date.to_rfc3339())).collect();
+        assert_eq!(
+            items,
+            [
+                ("https://example.com/posts/one", "One & only", "2024-03-01T10:00:00+00:00".to_string()),
+                ("https://example.com/posts/two", "Two", "2024-02-01T00:00:00+00:00".to_string()),
+            ]
+        );
+
+        let nothing = ScrapeSelectors { item: Some(selector("li.missing")), ..Default::default() };
+        assert!(matches!(scrape_feed("https://example.com/blog/", "https://example.com/blog/", LISTING.as_bytes(), &nothing), Err(FeedError::Parse(_))));
+    }
+
+    #[test]
+    fn sitemaps_and_indexes_are_read() {
+        let urlset = r#"<?xml version="1.0"?><urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" xmlns:news="http://www.google.com/schemas/sitemap-news/0.9">
+            <url><loc>https://example.com/a</loc><lastmod>2024-01-01</lastmod></url>
+            <url><loc> https://example.com/b </loc><news:news><news:title>B &amp; co</news:title><news:publication_date>2024-02-01T08:00:00Z</news:publication_date></news:news></url>
+            <url><lastmod>2024-03-01</lastmod></url>
+        </urlset>"#;
+        let Ok(Sitemap::Urls(entries)) = parse_sitemap(urlset.as_bytes()) else {
+            panic!("not read as a urlset");
+        };
+        assert_eq!(entries.iter().map(|entry| entry.loc.as_str()).collect::<Vec<_>>(), ["https://example.com/a", "https://example.com/b"]);
+        assert_eq!(entries[1].title.as_deref(), Some("B & co"));
+        assert_eq!(entries[1].date().map(|date| date.to_rfc3339()).as_deref(), Some("2024-02-01T08:00:00+00:00"));
+        assert!(!is_sitemap_index(urlset.as_bytes()));
+
+        let index = r#"<?xml version="1.0"?><sitemapindex><sitemap><loc>https://example.com/sitemap-1.xml</loc></sitemap></sitemapindex>"#;
+        assert!(matches!(parse_sitemap(index.as_bytes()), Ok(Sitemap::Index(entries)) if entries.len() == 1));
+        assert!(is_sitemap_index(index.as_bytes()));
+        assert!(parse_sitemap(b"<rss version=\"2.0\"/>").is_err());
+    }
+
+    #[tokio::test]
+    async fn known_titles_are_not_fetched_again() {
+        let sitemap = r#"<urlset><url><loc>https://example.com/known</loc><lastmod>2024-03-01</lastmod></url>
+            <url><loc>https://example.com/new-post</loc><lastmod>2024-02-01</lastmod></url>
+            <url><loc>https://example.com/untitled-post</loc><lastmod>2024-01-01</lastmod></url></urlset>"#;
+        let client = MockClient::new(&[
+            ("https://example.com/new-post", "<title>New post</title>".to_string()),
+            ("https://example.com/untitled-post", "<title>Untitled, now titled</title>".to_string()),
+        ]);
+        let known_titles = HashMap::from([
+            ("https://example.com/known".to_string(), "Known post".to_string()),
+            // Titled after its URL when its page was not fetched
+            ("https://example.com/untitled-post".to_string(), "Untitled post".to_string()),
+        ]);
+        let request = FetchRequest {
+            url: "https://example.com/sitemap.xml".to_string(),
+            accept: SITEMAP_ACCEPT.to_string(),
+            follow_redirects: true,
+            if_none_match: None,
+            if_modified_since: None,
+        };
+        let title_fetches = AtomicUsize::new(5);
+        let feed_data = sitemap_feed(&client, &request, sitemap.as_bytes(), 10, &title_fetches, &known_titles, false).await.unwrap();
+        let titles: Vec<&str> = feed_data.items.iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(titles, ["Known post", "New post", "Untitled, now titled"]);
+        let fetched: Vec<String> = client.requests.lock().unwrap().iter().map(|request| request.url.clone()).collect();
+        assert_eq!(fetched, ["https://example.com/new-post", "https://example.com/untitled-post"]);
+        assert_eq!(title_fetches.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn titles_from_urls() {
+        assert_eq!(title_from_url("https://example.com/blog/release-notes.html"), "Release notes");
+        assert_eq!(title_from_url("https://example.com/blog/2024_recap/"), "2024 recap");
+        assert_eq!(title_from_url("https://example.com/"), "example.com");
+    }
+}
@@ -0,0 +1,143 @@
+// rejects.rs
+use crate::output::OutputFormat;
+use crate::{FeedData, FeedItem};
+use std::collections::HashSet;
+
+// Slug, and so filename, of the feed of rejected items written with `generate_rejects_feed`
+pub const REJECTS_SLUG: &str = "rejected";
+
+const REJECTS_TITLE: &str = "Rejected items";
+
+// An item dropped by a filter, kept for the feed of rejected items
+#[derive(Debug, Clone)]
+pub struct RejectedItem {
+    pub item: FeedItem,
+    // The option that dropped it, such as "exclude keywords"
+    pub rule: &'static str,
+    pub feed_url: String,
+    pub feed_title: String,
+}
+
+/// Keeps the items of `feed_data` for which `keep` returns true and adds the others to
+/// `rejects`, if given, as dropped by `rule`. Returns how many items were dropped.
+pub fn retain_items(feed_data: &mut FeedData, rejects: Option<&mut Vec<RejectedItem>>, rule: &'static str, mut keep: impl FnMut(&mut FeedItem) -> bool) -> usize {
+    let before = feed_data.items.len();
+    let Some(rejects) = rejects else {
+        feed_data.items.retain_mut(keep);
+        return before - feed_data.items.len();
+    };
+    for mut item in std::mem::take(&mut feed_data.items) {
+        if keep(&mut item) {
+            feed_data.items.push(item);
+        } else {
+            rejects.push(RejectedItem {
+                item,
+                rule,
+                feed_url: feed_data.url.clone(),
+                feed_title: feed_data.title.clone(),
+            });
+        }
+    }
+    before - feed_data.items.len()
+}
+
+/// Builds the feed of rejected items archived at `url`: the items rejected in this run, each
+/// with the rule that dropped it and its feed on top of its description, followed by the
+/// `archived` items of the previous runs, newest first. An item is listed once, however
+/// many times it was rejected. The feed is limited to max_items when it is written.
+pub fn rejects_feed(rejects: Vec<RejectedItem>, archived: Vec<FeedItem>, url: &str) -> FeedData {
+    let mut seen_guids = HashSet::new();
+    let mut items: Vec<FeedItem> = rejects
+        .into_iter()
+        .map(|rejected| {
+            let note = format!(
+                "<p>Rejected by {} from <a href=\"{}\">{}</a></p>",
+                rejected.rule,
+                quick_xml::escape::escape(rejected.feed_url.as_str()),
+                quick_xml::escape::escape(rejected.feed_title.as_str())
+            );
+            let mut item = rejected.item;
+            item.description = Some(match item.description.take() {
+                Some(description) => format!("{}\n{}", note, description),
+                None => note,
+            });
+            item.original_feed_url = Some(rejected.feed_url);
+            item.original_feed_title = Some(rejected.feed_title);
+            item
+        })
+        .chain(archived)
+        .filter(|item| seen_guids.insert(item.guid.clone()))
+        .collect();
+    items.sort_by_key(|item| std::cmp::Reverse(item.pub_date));
+
+    FeedData {
+        title: REJECTS_TITLE.to_string(),
+        subtitle: Some("Items dropped by the filters of the archived feeds".to_string()),
+        url: url.to_string(),
+        resolved_url: url.to_string(),
+        items,
+        language: None,
+        icon: None,
+        copyright: None,
+        slug: Some(REJECTS_SLUG.to_string()),
+        priority: 0,
+        max_file_bytes: None,
+        base_url: None,
+        categories: Vec::new(),
+        display_title: None,
+        format: OutputFormat::Rss,
+        filtered_count: 0,
+        ttl: None,
+        skip_hours: Vec::new(),
+        skip_days: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{feed, item};
+
+    #[test]
+    fn dropped_items_are_kept_with_their_rule_and_feed() {
+        let mut feed_data = feed("https://example.com/feed.xml", "Example", vec![item("a", "2024-01-01T00:00:00Z"), item("b", "2024-01-02T00:00:00Z")]);
+        let mut rejects = Vec::new();
+        assert_eq!(retain_items(&mut feed_data, Some(&mut rejects), "exclude keywords", |item| item.guid == "a"), 1);
+        assert_eq!(feed_data.items.len(), 1);
+        assert_eq!((rejects[0].item.guid.as_str(), rejects[0].rule, rejects[0].feed_url.as_str()), ("b", "exclude keywords", "https://example.com/feed.xml"));
+
+        assert_eq!(retain_items(&mut feed_data, None, "blocked_items", |_| false), 1);
+        assert!(feed_data.items.is_empty());
+        assert_eq!(rejects.len(), 1);
+    }
+
+    #[test]
+    fn rejected_items_are_merged_with_the_archived_ones_newest_first() {
+        let rejected = |guid: &str, date: &str, rule| RejectedItem {
+            item: item(guid, date),
+            rule,
+            feed_url: "https://example.com/feed.xml".to_string(),
+            feed_title: "A & B".to_string(),
+        };
+        let mut archived_again = item("again", "2024-01-02T00:00:00Z");
+        archived_again.description = Some("<p>Rejected by blocked_items</p>".to_string());
+        let rejects = vec![
+            rejected("new", "2024-01-03T00:00:00Z", "exclude keywords"),
+            rejected("again", "2024-01-02T00:00:00Z", "exclude authors"),
+            rejected("new", "2024-01-03T00:00:00Z", "drop_future_dates"),
+        ];
+        let archived = vec![item("old", "2024-01-01T00:00:00Z"), archived_again];
+        let feed_data = rejects_feed(rejects, archived, "https://example.org/feeds/rejected.xml");
+
+        let guids: Vec<&str> = feed_data.items.iter().map(|item| item.guid.as_str()).collect();
+        assert_eq!(guids, ["new", "again", "old"]);
+        assert_eq!(
+            feed_data.items[0].description.as_deref(),
+            Some("<p>Rejected by exclude keywords from <a href=\"https://example.com/feed.xml\">A &amp; B</a></p>")
+        );
+        // Rejected again in this run, so described by this run's rule
+        assert!(feed_data.items[1].description.as_deref().unwrap().starts_with("<p>Rejected by exclude authors"));
+        assert_eq!(feed_data.items[2].description, None);
+        assert_eq!(feed_data.slug.as_deref(), Some(REJECTS_SLUG));
+    }
+}
@@ -0,0 +1,180 @@
+// republish.rs
+use crate::FeedItem;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+// Items not seen for this many days are forgotten
+const HISTORY_DAYS: i64 = 180;
+
+// Query parameters that only track where a click came from and don't identify the item
+const TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "mc_cid", "mc_eid", "ref", "source"];
+
+/// How loosely an incoming item may match a previously seen item of the same feed
+/// to be treated as a republication of it. Each level includes the stricter ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepublishDetection {
+    /// Same guid
+    Guid,
+    /// Same guid or same normalized link
+    #[default]
+    Link,
+    /// Same guid, normalized link or title
+    Title,
+}
+
+impl FromStr for RepublishDetection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "guid" => Ok(RepublishDetection::Guid),
+            "link" => Ok(RepublishDetection::Link),
+            "title" => Ok(RepublishDetection::Title),
+            _ => Err(format!("invalid republish_detection {:?}, expected guid, link or title", value)),
+        }
+    }
+}
+
+// Items previously seen in a feed, used to recognize republished items
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ItemHistory {
+    pub items: Vec<KnownItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownItem {
+    guid: String,
+    // Normalized link
+    link: String,
+    title: String,
+    // Publication date of the item when it was first seen
+    pub_date: DateTime<FixedOffset>,
+    last_seen: DateTime<Utc>,
+}
+
+impl KnownItem {
+    fn matches(&self, item: &FeedItem, link: &str, detection: RepublishDetection) -> bool {
+        let same_link = !link.is_empty() && self.link == link;
+        let same_title = !item.title.is_empty() && self.title == item.title;
+        self.guid == item.guid
+            || (detection != RepublishDetection::Guid && same_link)
+            || (detection == RepublishDetection::Title && same_title)
+    }
+}
+
+impl ItemHistory {
+    /// Gives republished items the guid and pub_date they were first seen with, so that
+    /// they replace the original item instead of showing up as new ones, then remembers
+    /// the feed's current items. Returns the number of republished items.
+    pub fn collapse_republished(&mut self, items: &mut [FeedItem], detection: RepublishDetection, now: DateTime<Utc>) -> usize {
+        let known_count = self.items.len();
+        let mut matched = HashSet::new();
+        let mut collapsed = 0;
+
+        for item in items.iter_mut() {
+            let link = normalize_link(&item.link);
+            // Each known item absorbs at most one incoming item per run
+            let found = (0..known_count)
+                .find(|index| !matched.contains(index) && self.items[*index].matches(item, &link, detection));
+            match found {
+                Some(index) => {
+                    matched.insert(index);
+                    let known = &mut self.items[index];
+                    if known.guid != item.guid || known.pub_date != item.pub_date {
+                        item.guid = known.guid.clone();
+                        item.pub_date = known.pub_date;
+                        collapsed += 1;
+                    }
+                    known.link = link;
+                    known.title = item.title.clone();
+                    known.last_seen = now;
+                }
+                None => self.items.push(KnownItem {
+                    guid: item.guid.clone(),
+                    link,
+                    title: item.title.clone(),
+                    pub_date: item.pub_date,
+                    last_seen: now,
+                }),
+            }
+        }
+
+        self.items
+            .retain(|known| now - known.last_seen <= chrono::Duration::days(HISTORY_DAYS));
+        collapsed
+    }
+
+    /// The titles of the known items, by guid.
+    pub fn titles(&self) -> HashMap<String, String> {
+        self.items.iter().map(|known| (known.guid.clone(), known.title.clone())).collect()
+    }
+
+    /// Marks the items of the last fetch as seen again, for a feed whose response has not
+    /// changed since, so that they are not forgotten while the feed is left unparsed.
+    pub fn mark_unchanged(&mut self, now: DateTime<Utc>) {
+        let Some(last_fetch) = self.items.iter().map(|known| known.last_seen).max() else {
+            return;
+        };
+        for known in self.items.iter_mut().filter(|known| known.last_seen == last_fetch) {
+            known.last_seen = now;
+        }
+    }
+}
+
+/// Normalizes a link for comparison: drops the fragment, tracking parameters
+/// (`utm_*` and the like) and a trailing slash, and sorts the remaining query.
+fn normalize_link(link: &str) -> String {
+    let Ok(mut url) = reqwest::Url::parse(link.trim()) else {
+        return link.trim().to_string();
+    };
+    url.set_fragment(None);
+
+    let mut params: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    params.sort();
+    if params.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(params);
+    }
+
+    url.to_string().trim_end_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::item;
+
+    fn day(day: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap().with_timezone(&Utc) + chrono::Duration::days(day)
+    }
+
+    fn seen(history: &ItemHistory) -> Vec<(&str, DateTime<Utc>)> {
+        history.items.iter().map(|known| (known.guid.as_str(), known.last_seen)).collect()
+    }
+
+    #[test]
+    fn unchanged_feeds_keep_their_last_items_remembered() {
+        let mut history = ItemHistory::default();
+        history.collapse_republished(&mut [item("a", "2024-01-01T00:00:00Z"), item("b", "2024-01-01T00:00:00Z")], RepublishDetection::Guid, day(0));
+        history.collapse_republished(&mut [item("b", "2024-01-01T00:00:00Z")], RepublishDetection::Guid, day(1));
+
+        // The same body on the following runs is not parsed again
+        history.mark_unchanged(day(100));
+        history.mark_unchanged(day(200));
+        assert_eq!(seen(&history), [("a", day(0)), ("b", day(200))]);
+
+        // The item is still recognized once the feed changes, long after it was last parsed
+        let mut republished = [item("b2", "2024-06-01T00:00:00Z")];
+        republished[0].link = item("b", "2024-01-01T00:00:00Z").link;
+        assert_eq!(history.collapse_republished(&mut republished, RepublishDetection::Link, day(250)), 1);
+        assert_eq!(republished[0].guid, "b");
+        assert_eq!(seen(&history), [("b", day(250))]);
+    }
+}
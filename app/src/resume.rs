@@ -0,0 +1,86 @@
+// resume.rs
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::error::Error;
+
+// Feeds fetched by a run that did not complete, persisted in the configured resume_file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResumeState {
+    pub updated_at: DateTime<Utc>,
+    // URLs of the feeds fetched and parsed successfully
+    pub fetched: BTreeSet<String>,
+}
+
+impl ResumeState {
+    /// Loads the resume file, returning None if it is missing, unreadable or was last
+    /// written more than `max_age` ago. Without a `max_age` it never gets too old. Ignoring
+    /// a file too old is reported on stderr if `progress_to_stderr`, else on stdout.
+    pub async fn load(storage: &dyn Storage, path: &str, max_age: Option<chrono::TimeDelta>, now: DateTime<Utc>, progress_to_stderr: bool) -> Option<ResumeState> {
+        let contents = storage.read(path).await.ok()?;
+        let resume: ResumeState = match serde_json::from_slice(&contents) {
+            Ok(resume) => resume,
+            Err(e) => {
+                eprintln!("Warning: Ignoring unreadable resume file {}: {}", path, e);
+                return None;
+            }
+        };
+        if max_age.is_some_and(|max_age| now - resume.updated_at > max_age) {
+            progress!(progress_to_stderr, "Ignoring resume file {} from {}, it is too old", path, resume.updated_at.to_rfc3339());
+            return None;
+        }
+        Some(resume)
+    }
+
+    /// Writes the resume file listing `fetched`.
+    pub async fn save(storage: &dyn Storage, path: &str, fetched: BTreeSet<String>, now: DateTime<Utc>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let resume = ResumeState { updated_at: now, fetched };
+        storage.write(path, serde_json::to_string_pretty(&resume)?.as_bytes()).await
+    }
+
+    /// Removes the resume file after a run that completed, if there is one.
+    pub async fn remove(storage: &dyn Storage, path: &str) {
+        if storage.read(path).await.is_err() {
+            return;
+        }
+        if let Err(e) = storage.remove(path).await {
+            eprintln!("Warning: Could not remove resume file {}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MemoryStorage;
+
+    const PATH: &str = "feeds/.resume.json";
+
+    #[tokio::test]
+    async fn only_fresh_resume_files_are_loaded() {
+        let storage = MemoryStorage::default();
+        let written = Utc::now();
+        let minutes = |minutes| chrono::TimeDelta::try_minutes(minutes);
+        assert!(ResumeState::load(&storage, PATH, minutes(60), written, false).await.is_none());
+
+        let fetched: BTreeSet<String> = ["https://example.com/feed.xml".to_string()].into();
+        ResumeState::save(&storage, PATH, fetched.clone(), written).await.unwrap();
+        let later = written + chrono::TimeDelta::minutes(61);
+        let resumed = ResumeState::load(&storage, PATH, minutes(60), written + chrono::TimeDelta::minutes(59), false).await.unwrap();
+        assert_eq!(resumed.fetched, fetched);
+        assert!(ResumeState::load(&storage, PATH, minutes(60), later, false).await.is_none());
+        // A max age too large to be represented never runs out
+        assert!(ResumeState::load(&storage, PATH, None, later, false).await.is_some());
+
+        ResumeState::remove(&storage, PATH).await;
+        assert!(ResumeState::load(&storage, PATH, None, later, false).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unreadable_resume_files_are_ignored() {
+        let storage = MemoryStorage::default();
+        storage.write(PATH, b"{not json").await.unwrap();
+        assert!(ResumeState::load(&storage, PATH, None, Utc::now(), false).await.is_none());
+    }
+}
@@ -0,0 +1,289 @@
+// rss_validation.rs
+use chrono::DateTime;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+// Elements every RSS 2.0 channel must have
+const REQUIRED_CHANNEL_ELEMENTS: [&str; 3] = ["title", "link", "description"];
+
+/// What is wrong with an RSS file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RssViolation {
+    /// The file is not well-formed XML; nothing after this point was checked
+    Malformed(String),
+    /// The root element is not `<rss>`; nothing else was checked
+    RootElement(String),
+    /// The `version` attribute of `<rss>` is missing or not 2.0
+    Version(Option<String>),
+    /// `<rss>` doesn't have exactly one `<channel>`
+    ChannelCount(usize),
+    /// A channel lacks a required element
+    MissingChannelElement(&'static str),
+    /// An item, numbered from 1 in document order, has neither a title nor a description
+    EmptyItem(usize),
+    /// A `<pubDate>` of the channel (item None) or of an item is not an RFC 2822 date
+    InvalidPubDate { item: Option<usize>, value: String },
+}
+
+impl fmt::Display for RssViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RssViolation::Malformed(e) => write!(f, "malformed XML: {}", e),
+            RssViolation::RootElement(name) => write!(f, "root element is <{}>, expected <rss>", name),
+            RssViolation::Version(Some(version)) => write!(f, "<rss> has version {:?}, expected \"2.0\"", version),
+            RssViolation::Version(None) => write!(f, "<rss> has no version attribute"),
+            RssViolation::ChannelCount(count) => write!(f, "<rss> has {} <channel> elements, expected one", count),
+            RssViolation::MissingChannelElement(name) => write!(f, "<channel> has no <{}>", name),
+            RssViolation::EmptyItem(index) => write!(f, "item {} has neither a <title> nor a <description>", index),
+            RssViolation::InvalidPubDate { item: Some(index), value } => write!(f, "item {} has an invalid <pubDate> {:?}", index, value),
+            RssViolation::InvalidPubDate { item: None, value } => write!(f, "<channel> has an invalid <pubDate> {:?}", value),
+        }
+    }
+}
+
+/// The result of checking an RSS file with `validate_rss_file`.
+#[derive(Debug, Default)]
+pub struct RssValidationReport {
+    /// Number of items checked
+    pub items: usize,
+    pub violations: Vec<RssViolation>,
+}
+
+impl RssValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Checks the RSS 2.0 file at `path` in a single streaming pass, see `validate_rss`.
+/// Fails only if the file can't be opened.
+pub fn validate_rss_file(path: &Path) -> Result<RssValidationReport, Box<dyn Error + Send + Sync>> {
+    let file = File::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+    Ok(validate_rss(BufReader::new(file)))
+}
+
+/// Checks an RSS 2.0 document read from `source` event by event, without holding it in
+/// memory: the root element is `<rss version="2.0">` with exactly one `<channel>`, which
+/// has a `<title>`, `<link>` and `<description>`, every item has a `<title>` or a
+/// `<description>`, and every `<pubDate>` is an RFC 2822 date. All violations are
+/// reported, up to the point where the XML becomes malformed.
+pub fn validate_rss<R: BufRead>(source: R) -> RssValidationReport {
+    let mut reader = Reader::from_reader(source);
+    let mut buf = Vec::new();
+    let mut validator = RssValidator::default();
+    loop {
+        let event = match reader.read_event_into(&mut buf) {
+            Ok(event) => event,
+            Err(e) => {
+                validator.report.violations.push(RssViolation::Malformed(format!("{} at byte {}", e, reader.error_position())));
+                return validator.report;
+            }
+        };
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                if validator.path.is_empty() && !validator.check_root(e) {
+                    return validator.report;
+                }
+                validator.start(e.name().as_ref());
+                if matches!(event, Event::Empty(_)) {
+                    validator.end();
+                }
+            }
+            Event::Text(e) if validator.in_pub_date() => validator.text.push_str(&String::from_utf8_lossy(e)),
+            Event::CData(e) if validator.in_pub_date() => validator.text.push_str(&String::from_utf8_lossy(e)),
+            Event::End(_) => validator.end(),
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    if !validator.path.is_empty() {
+        validator.report.violations.push(RssViolation::Malformed("unexpected end of file".to_string()));
+    } else if validator.channels != 1 {
+        validator.report.violations.push(RssViolation::ChannelCount(validator.channels));
+    }
+    validator.report
+}
+
+// State of a validation pass
+#[derive(Default)]
+struct RssValidator {
+    report: RssValidationReport,
+    // Names of the open elements, the root first
+    path: Vec<Vec<u8>>,
+    channels: usize,
+    // Required elements of the open channel found so far
+    channel_elements: Vec<&'static str>,
+    item_has_content: bool,
+    // Text of the open <pubDate>
+    text: String,
+}
+
+impl RssValidator {
+    /// Checks the root element, returning false if it is not `<rss>`.
+    fn check_root(&mut self, root: &BytesStart) -> bool {
+        if root.name().as_ref() != b"rss" {
+            let name = String::from_utf8_lossy(root.name().as_ref()).into_owned();
+            self.report.violations.push(RssViolation::RootElement(name));
+            return false;
+        }
+        let version = root
+            .try_get_attribute("version")
+            .ok()
+            .flatten()
+            .map(|attr| String::from_utf8_lossy(&attr.value).into_owned());
+        if version.as_deref() != Some("2.0") {
+            self.report.violations.push(RssViolation::Version(version));
+        }
+        true
+    }
+
+    fn parent_is(&self, name: &[u8]) -> bool {
+        self.path.last().is_some_and(|parent| parent == name)
+    }
+
+    fn in_pub_date(&self) -> bool {
+        self.parent_is(b"pubDate")
+    }
+
+    fn start(&mut self, name: &[u8]) {
+        match name {
+            b"channel" if self.path.len() == 1 => {
+                self.channels += 1;
+                self.channel_elements.clear();
+            }
+            b"item" if self.parent_is(b"channel") => {
+                self.report.items += 1;
+                self.item_has_content = false;
+            }
+            b"title" | b"description" if self.parent_is(b"item") => self.item_has_content = true,
+            _ if self.parent_is(b"channel") && self.path.len() == 2 => {
+                if let Some(required) = REQUIRED_CHANNEL_ELEMENTS.iter().find(|required| required.as_bytes() == name) {
+                    self.channel_elements.push(required);
+                }
+            }
+            _ => {}
+        }
+        self.path.push(name.to_vec());
+        self.text.clear();
+    }
+
+    fn end(&mut self) {
+        let Some(name) = self.path.pop() else {
+            return;
+        };
+        match name.as_slice() {
+            b"channel" if self.path.len() == 1 => {
+                for required in REQUIRED_CHANNEL_ELEMENTS {
+                    if !self.channel_elements.contains(&required) {
+                        self.report.violations.push(RssViolation::MissingChannelElement(required));
+                    }
+                }
+            }
+            b"item" if self.parent_is(b"channel") && !self.item_has_content => {
+                self.report.violations.push(RssViolation::EmptyItem(self.report.items));
+            }
+            b"pubDate" if self.parent_is(b"channel") || self.parent_is(b"item") => {
+                let value = self.text.trim();
+                if DateTime::parse_from_rfc2822(value).is_err() {
+                    self.report.violations.push(RssViolation::InvalidPubDate {
+                        item: self.parent_is(b"item").then_some(self.report.items),
+                        value: value.to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+        self.text.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNEL: &str = "<title>Feed</title><link>https://example.com/</link><description>A feed</description>";
+
+    fn violations(document: &str) -> Vec<RssViolation> {
+        validate_rss(document.as_bytes()).violations
+    }
+
+    fn rss(channel: &str) -> String {
+        format!("<?xml version=\"1.0\"?><rss version=\"2.0\"><channel>{}{}</channel></rss>", CHANNEL, channel)
+    }
+
+    #[test]
+    fn valid_feeds_have_no_violations() {
+        let report = validate_rss(
+            rss("<pubDate>Mon, 01 Jan 2024 00:00:00 +0000</pubDate><item><title>One</title></item><item><description>Two</description><pubDate>Tue, 02 Jan 2024 00:00:00 +0000</pubDate></item>")
+                .as_bytes(),
+        );
+        assert!(report.is_valid(), "{:?}", report.violations);
+        assert_eq!(report.items, 2);
+    }
+
+    #[test]
+    fn malformed_xml_stops_the_check() {
+        let found = violations("<rss version=\"2.0\"><channel></item></channel></rss>");
+        assert_eq!(found.len(), 1);
+        assert!(matches!(found[0], RssViolation::Malformed(_)));
+
+        let found = violations(&rss("<item><title>One</title></item>").replace("</channel></rss>", ""));
+        assert_eq!(found, vec![RssViolation::Malformed("unexpected end of file".to_string())]);
+    }
+
+    #[test]
+    fn other_root_elements_are_not_checked_further() {
+        let found = violations("<feed xmlns=\"http://www.w3.org/2005/Atom\"><entry/></feed>");
+        assert_eq!(found, vec![RssViolation::RootElement("feed".to_string())]);
+    }
+
+    #[test]
+    fn the_version_must_be_2_0() {
+        let found = violations(&rss("").replace("version=\"2.0\"", "version=\"0.91\""));
+        assert_eq!(found, vec![RssViolation::Version(Some("0.91".to_string()))]);
+        let found = violations(&rss("").replace(" version=\"2.0\"", ""));
+        assert_eq!(found, vec![RssViolation::Version(None)]);
+    }
+
+    #[test]
+    fn there_must_be_exactly_one_channel() {
+        assert_eq!(violations("<rss version=\"2.0\"></rss>"), vec![RssViolation::ChannelCount(0)]);
+        let two = format!("<rss version=\"2.0\"><channel>{0}</channel><channel>{0}</channel></rss>", CHANNEL);
+        assert_eq!(violations(&two), vec![RssViolation::ChannelCount(2)]);
+    }
+
+    #[test]
+    fn channels_need_a_title_link_and_description() {
+        let found = violations("<rss version=\"2.0\"><channel><link>https://example.com/</link></channel></rss>");
+        assert_eq!(
+            found,
+            vec![RssViolation::MissingChannelElement("title"), RssViolation::MissingChannelElement("description")]
+        );
+    }
+
+    #[test]
+    fn items_need_a_title_or_description() {
+        let found = violations(&rss("<item><title>One</title></item><item><link>https://example.com/2</link></item><item/>"));
+        assert_eq!(found, vec![RssViolation::EmptyItem(2), RssViolation::EmptyItem(3)]);
+    }
+
+    #[test]
+    fn pub_dates_must_be_rfc_2822() {
+        let found = violations(&rss(
+            "<pubDate>2024-01-01</pubDate><item><title>One</title><pubDate><![CDATA[yesterday]]></pubDate></item>",
+        ));
+        assert_eq!(
+            found,
+            vec![
+                RssViolation::InvalidPubDate { item: None, value: "2024-01-01".to_string() },
+                RssViolation::InvalidPubDate { item: Some(1), value: "yesterday".to_string() },
+            ]
+        );
+    }
+}
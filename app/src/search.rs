@@ -0,0 +1,160 @@
+// search.rs
+use crate::content_store::resolve_references;
+use crate::crawler::{parse_feed, ParseOptions};
+use crate::filters::SearchQuery;
+use crate::output::OutputFormat;
+use crate::rejects::REJECTS_SLUG;
+use crate::storage::Storage;
+use crate::diff::CHANGES_REPORT_PATH;
+use crate::{list_archived_files, load_feed_data_from_xml, plain_text_excerpt, FeedData, FeedList};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::error::Error;
+
+/// Where the search index is written when `search_index` is enabled.
+pub const SEARCH_INDEX_PATH: &str = "feeds/search-index.json";
+
+// Characters of an item's description kept in its search index entry
+const EXCERPT_CHARS: usize = 200;
+
+// An archived item as listed in feeds/search-index.json
+#[derive(Debug, Serialize)]
+pub struct SearchEntry {
+    pub title: String,
+    pub link: String,
+    // Title of the archived feed holding the item
+    pub feed: String,
+    pub date: DateTime<FixedOffset>,
+    pub excerpt: String,
+}
+
+/// Lists the items of the archived feeds, newest first and at most `max_items` of them
+/// (0 means unlimited). An item archived in several feeds is listed once.
+pub fn build_search_index(feeds: &[FeedData], max_items: usize) -> Vec<SearchEntry> {
+    let entries: Vec<SearchEntry> = feeds
+        .iter()
+        .flat_map(|feed_data| {
+            feed_data.items.iter().map(|item| SearchEntry {
+                title: item.title.clone(),
+                link: item.link.clone(),
+                feed: feed_data.display_title().to_string(),
+                date: item.pub_date,
+                excerpt: plain_text_excerpt(item.description.as_deref().or(item.content.as_deref()).unwrap_or_default(), EXCERPT_CHARS),
+            })
+        })
+        .collect();
+    merge_search_entries(entries, max_items)
+}
+
+/// Orders search index entries newest first, keeping one entry per link and at most
+/// `max_items` (0 means unlimited). The indexes of several sets of feeds merged this way
+/// are the index of all of them.
+pub fn merge_search_entries(mut entries: Vec<SearchEntry>, max_items: usize) -> Vec<SearchEntry> {
+    entries.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.link.cmp(&b.link)).then_with(|| a.feed.cmp(&b.feed)));
+    let mut seen_links = HashSet::new();
+    entries.retain(|entry| entry.link.is_empty() || seen_links.insert(entry.link.clone()));
+    if max_items > 0 {
+        entries.truncate(max_items);
+    }
+    entries
+}
+
+/// Writes the search index unless the file already holds the same entries. Returns
+/// whether the file was written.
+pub async fn write_search_index(storage: &dyn Storage, entries: &[SearchEntry]) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let json = serde_json::to_vec(entries)?;
+    if storage.read(SEARCH_INDEX_PATH).await.is_ok_and(|existing| existing == json) {
+        return Ok(false);
+    }
+    storage.write(SEARCH_INDEX_PATH, &json).await?;
+    Ok(true)
+}
+
+// An archived item matching a search
+#[derive(Debug)]
+pub struct SearchMatch {
+    pub title: String,
+    pub link: String,
+    pub feed: String,
+    pub date: DateTime<FixedOffset>,
+}
+
+/// Searches the titles, descriptions and content of the items in the archived feed files of
+/// `lists` for `query`, a case-insensitive substring or, with `use_regex`, a regular
+/// expression. The feed of rejected items in `rejects_dir` is skipped. Only local state is
+/// read. Matches are returned newest first.
+pub async fn search_archive(
+    storage: &dyn Storage,
+    lists: &[FeedList],
+    query: &str,
+    use_regex: bool,
+    rejects_dir: Option<&str>,
+    parse_options: ParseOptions,
+) -> Result<Vec<SearchMatch>, Box<dyn Error + Send + Sync>> {
+    let query = SearchQuery::new(query, use_regex)?;
+    let mut matches = Vec::new();
+    let mut searched_dirs = HashSet::new();
+    for list in lists {
+        let feed_dir = list.feed_dir();
+        if !searched_dirs.insert(feed_dir.clone()) {
+            continue;
+        }
+        let mut filenames = list_archived_files(storage, &feed_dir).await?;
+        filenames.sort();
+        let rejects_file = format!("{}.{}", REJECTS_SLUG, OutputFormat::Rss.extension());
+        for filename in filenames {
+            if rejects_dir == Some(feed_dir.as_str()) && filename.rsplit('/').next() == Some(rejects_file.as_str()) {
+                continue;
+            }
+            let path = format!("{}/{}", feed_dir, filename);
+            let feed_data = match read_archived_feed(storage, &path, parse_options).await {
+                Some(Ok(feed_data)) => feed_data,
+                Some(Err(e)) => {
+                    eprintln!("Warning: Could not read archived feed {}: {}", path, e);
+                    continue;
+                }
+                None => continue,
+            };
+            for item in feed_data.items.iter().filter(|item| query.matches(item)) {
+                matches.push(SearchMatch {
+                    title: item.title.clone(),
+                    link: item.link.clone(),
+                    feed: feed_data.title.clone(),
+                    date: item.pub_date,
+                });
+            }
+        }
+    }
+    matches.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.link.cmp(&b.link)));
+    Ok(matches)
+}
+
+/// Reads an archived feed file, or returns None if `path` is not a feed file. Atom and JSON
+/// files are parsed with `parse_options`.
+pub async fn read_archived_feed(storage: &dyn Storage, path: &str, parse_options: ParseOptions) -> Option<Result<FeedData, Box<dyn Error + Send + Sync>>> {
+    let filename = path.rsplit('/').next().unwrap_or(path);
+    if filename.starts_with('.') || filename == "stats.json" || path == SEARCH_INDEX_PATH || path == CHANGES_REPORT_PATH {
+        return None;
+    }
+    if filename.ends_with(".xml") {
+        let bytes = match storage.read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(match load_feed_data_from_xml(&bytes, path) {
+            Ok(mut feed_data) => {
+                resolve_references(storage, &mut feed_data).await;
+                Ok(feed_data)
+            }
+            Err(e) => Err(e),
+        })
+    } else if filename.ends_with(".atom") || filename.ends_with(".json") {
+        Some(match storage.read(path).await {
+            Ok(bytes) => parse_feed(path, &bytes, parse_options).map_err(|e| e.into()),
+            Err(e) => Err(e),
+        })
+    } else {
+        None
+    }
+}
@@ -0,0 +1,56 @@
+// since_commit.rs
+use crate::crawler::parse_feed_list_with_errors;
+use std::collections::BTreeSet;
+use std::process::Command;
+
+/// Returns the URLs of the feed list lines added or changed in `sources` since `commit`,
+/// comparing the commit with the working tree using `git diff`. Fails outside a git
+/// repository, without git, or if the commit is unknown, e.g. in a shallow clone, and for
+/// a commit starting with `-`, which git would take as an option.
+pub fn added_feed_urls(commit: &str, sources: &[String]) -> Result<BTreeSet<String>, String> {
+    if commit.starts_with('-') {
+        return Err(format!("{:?} is not a commit", commit));
+    }
+    let inside = git(&["rev-parse", "--is-inside-work-tree"])?;
+    if inside.trim() != "true" {
+        return Err("not inside a git work tree".to_string());
+    }
+    let mut args = vec!["diff", "--no-color", "--no-ext-diff", "--unified=0", commit, "--"];
+    args.extend(sources.iter().map(String::as_str));
+    let diff = git(&args)?;
+
+    // Added lines are parsed on their own: members of a combined feed are indented, and
+    // the header of the combined feed they belong to may not have changed
+    let added: String = diff
+        .lines()
+        .filter(|line| !line.starts_with("+++"))
+        .filter_map(|line| line.strip_prefix('+'))
+        .map(str::trim)
+        .filter(|line| !line.starts_with('@'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let (specs, _) = parse_feed_list_with_errors(&added);
+    Ok(specs.into_iter().map(|spec| spec.url).collect())
+}
+
+/// Runs git with `args`, returning its output or its error message.
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git").args(args).output().map_err(|e| format!("could not run git: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().next().unwrap_or("git failed").trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_are_not_commits() {
+        let sources = ["feeds.txt".to_string()];
+        assert_eq!(added_feed_urls("--output=/tmp/diff", &sources), Err("\"--output=/tmp/diff\" is not a commit".to_string()));
+        assert!(added_feed_urls("-p", &sources).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+// stale.rs
+use crate::storage::Storage;
+use crate::FeedData;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+// Date of the newest item of every feed
+pub const LAST_ITEM_DATES_PATH: &str = "feeds/.last_item_dates.json";
+
+// The newest item date of every feed, persisted in feeds/.last_item_dates.json and keyed
+// by feed URL, to notice feeds that stopped publishing altogether
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StaleDetector {
+    feeds: BTreeMap<String, LastItemDate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LastItemDate {
+    newest_item: DateTime<Utc>,
+    // Whether the newest item was ever within the threshold, so that feeds added long
+    // after their last post aren't reported
+    #[serde(default)]
+    active: bool,
+    // Run that found the newest item older than the threshold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stale_since: Option<DateTime<Utc>>,
+}
+
+// A feed that stopped publishing, listed in feeds/stats.json
+#[derive(Debug, Clone, Serialize)]
+pub struct StaleFeed {
+    pub url: String,
+    pub newest_item: DateTime<Utc>,
+    pub stale_since: DateTime<Utc>,
+}
+
+impl StaleDetector {
+    /// Reads the item dates file, starting over if it is missing or unreadable.
+    pub async fn load(storage: &dyn Storage, path: &str) -> StaleDetector {
+        match storage.read(path).await {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                eprintln!("Warning: Ignoring unreadable item dates {}: {}", path, e);
+                StaleDetector::default()
+            }),
+            Err(_) => StaleDetector::default(),
+        }
+    }
+
+    /// Writes the item dates file.
+    pub async fn save(&self, storage: &dyn Storage, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        storage.write(path, serde_json::to_string_pretty(self)?.as_bytes()).await
+    }
+
+    /// Records the date of the newest item of a fetched feed, if it is newer than the one
+    /// known. Items dated after `now` are left out, so that they don't keep a feed fresh.
+    pub fn record(&mut self, feed_data: &FeedData, now: DateTime<Utc>) {
+        let Some(newest) = feed_data.items.iter().map(|item| item.pub_date.with_timezone(&Utc)).filter(|date| *date <= now).max() else {
+            return;
+        };
+        let last = self.feeds.entry(feed_data.url.clone()).or_insert(LastItemDate {
+            newest_item: newest,
+            active: false,
+            stale_since: None,
+        });
+        last.newest_item = last.newest_item.max(newest);
+    }
+
+    /// Checks the feeds listed in `urls` against `threshold`, forgetting the others, and
+    /// returns those that became stale in this run: their newest item is now older than
+    /// the threshold, while an earlier run found it within.
+    pub fn check(&mut self, urls: &HashSet<&str>, threshold: Duration, now: DateTime<Utc>) -> Vec<StaleFeed> {
+        self.feeds.retain(|url, _| urls.contains(url.as_str()));
+        let cutoff = now.checked_sub_signed(threshold);
+        let mut became_stale = Vec::new();
+        for (url, last) in &mut self.feeds {
+            if cutoff.is_none_or(|cutoff| last.newest_item >= cutoff) {
+                last.active = true;
+                last.stale_since = None;
+            } else if last.active && last.stale_since.is_none() {
+                last.stale_since = Some(now);
+                became_stale.push(StaleFeed {
+                    url: url.clone(),
+                    newest_item: last.newest_item,
+                    stale_since: now,
+                });
+            }
+        }
+        became_stale
+    }
+
+    /// The feeds that stopped publishing and haven't published since, the longest silent first.
+    pub fn stale_feeds(&self) -> Vec<StaleFeed> {
+        let mut stale: Vec<StaleFeed> = self
+            .feeds
+            .iter()
+            .filter_map(|(url, last)| {
+                Some(StaleFeed {
+                    url: url.clone(),
+                    newest_item: last.newest_item,
+                    stale_since: last.stale_since?,
+                })
+            })
+            .collect();
+        stale.sort_by_key(|feed| feed.newest_item);
+        stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{feed, item};
+
+    const URL: &str = "https://example.com/feed.xml";
+
+    fn day(day: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap().with_timezone(&Utc) + Duration::days(day)
+    }
+
+    fn fetched(date: &str) -> FeedData {
+        feed(URL, "Example", vec![item("a", date)])
+    }
+
+    #[test]
+    fn feeds_become_stale_once() {
+        let urls = HashSet::from([URL]);
+        let mut detector = StaleDetector::default();
+        detector.record(&fetched("2024-01-01T00:00:00Z"), day(0));
+        assert!(detector.check(&urls, Duration::days(30), day(10)).is_empty());
+
+        let stale = detector.check(&urls, Duration::days(30), day(31));
+        assert_eq!(stale.iter().map(|feed| (feed.url.as_str(), feed.stale_since)).collect::<Vec<_>>(), [(URL, day(31))]);
+        assert!(detector.check(&urls, Duration::days(30), day(40)).is_empty());
+        assert_eq!(detector.stale_feeds().len(), 1);
+
+        // Publishing again makes it fresh, and it can become stale again later
+        detector.record(&fetched("2024-02-20T00:00:00Z"), day(50));
+        assert!(detector.check(&urls, Duration::days(30), day(50)).is_empty());
+        assert!(detector.stale_feeds().is_empty());
+        assert_eq!(detector.check(&urls, Duration::days(30), day(81)).len(), 1);
+    }
+
+    #[test]
+    fn feeds_added_after_their_last_post_and_unlisted_feeds_are_not_reported() {
+        let mut detector = StaleDetector::default();
+        detector.record(&fetched("2023-01-01T00:00:00Z"), day(0));
+        // Items dated in the future don't count
+        detector.record(&fetched("2025-01-01T00:00:00Z"), day(0));
+        assert!(detector.check(&HashSet::from([URL]), Duration::days(30), day(0)).is_empty());
+        assert!(detector.check(&HashSet::from([URL]), Duration::days(30), day(100)).is_empty());
+
+        detector.check(&HashSet::new(), Duration::days(30), day(100));
+        assert!(detector.feeds.is_empty());
+    }
+
+    #[test]
+    fn thresholds_beyond_the_supported_dates_are_never_reached() {
+        let urls = HashSet::from([URL]);
+        let mut detector = StaleDetector::default();
+        detector.record(&fetched("2024-01-01T00:00:00Z"), day(0));
+        assert!(detector.check(&urls, Duration::MAX, day(10_000)).is_empty());
+        assert!(detector.feeds[URL].active);
+    }
+}
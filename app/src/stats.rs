@@ -0,0 +1,229 @@
+// stats.rs
+use crate::storage::{is_not_found, Storage};
+use crate::FeedData;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+// Feeds tracked for less than this many days are never flagged as inactive
+const MIN_TRACKED_DAYS: i64 = 14;
+
+// Guids not fetched for this many days are forgotten, and count as new if they come back
+const KNOWN_GUID_DAYS: i64 = 365;
+
+// Publishing activity of every feed, persisted in feeds/.stats.json and keyed by feed URL
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FeedStats {
+    pub feeds: BTreeMap<String, FeedActivity>,
+}
+
+// Activity of a single feed. Rates are based on when items were first seen by the
+// archiver rather than on their pubDate, so republished old items count only once
+// and backdated or future-dated items don't skew the numbers.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeedActivity {
+    pub title: String,
+    /// First run that saw this feed. Items seen in that run are its backlog.
+    pub tracked_since: DateTime<Utc>,
+    /// Items seen since tracking started, including the initial backlog
+    pub total_items: usize,
+    /// Items first seen after the initial run
+    pub new_items: usize,
+    pub first_item_seen: Option<DateTime<Utc>>,
+    pub last_item_seen: Option<DateTime<Utc>>,
+    /// Items in the archived feed after the last run
+    pub archived_items: usize,
+    /// Every guid archived for this feed, with the last run that fetched it, used to tell
+    /// new items from known ones even after they rotated out of the feed and back
+    #[serde(default)]
+    pub archived_guids: BTreeMap<String, DateTime<Utc>>,
+    /// Guids of the latest fetch, as recorded by older versions. Only read, to seed
+    /// `archived_guids`.
+    #[serde(default, skip_serializing)]
+    pub known_guids: BTreeSet<String>,
+}
+
+impl FeedActivity {
+    /// Average number of new items per week since tracking started.
+    pub fn items_per_week(&self, now: DateTime<Utc>) -> f64 {
+        let tracked_days = ((now - self.tracked_since).num_seconds() as f64 / 86_400.0).max(1.0);
+        self.new_items as f64 * 7.0 / tracked_days
+    }
+
+    /// Average time between new items since tracking started, in days.
+    pub fn average_gap_days(&self) -> Option<f64> {
+        let last = self.last_item_seen.filter(|_| self.new_items > 0)?;
+        Some((last - self.tracked_since).num_seconds() as f64 / 86_400.0 / self.new_items as f64)
+    }
+}
+
+impl FeedStats {
+    /// Loads the stats file, starting from empty stats if it is missing or invalid. Fails
+    /// if the storage can't read it, rather than losing the stats over a transient error.
+    pub async fn load(storage: &dyn Storage, path: &str) -> Result<FeedStats, Box<dyn Error + Send + Sync>> {
+        match storage.read(path).await {
+            Ok(contents) => Ok(serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                eprintln!("Warning: Ignoring unreadable feed stats {}: {}", path, e);
+                FeedStats::default()
+            })),
+            Err(e) if is_not_found(e.as_ref()) => Ok(FeedStats::default()),
+            Err(e) => Err(format!("Could not read feed stats {}: {}", path, e).into()),
+        }
+    }
+
+    /// Writes the stats file.
+    pub async fn save(&self, storage: &dyn Storage, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        storage.write(path, serde_json::to_string_pretty(self)?.as_bytes()).await
+    }
+
+    /// Records the items of a fresh fetch, counting those not seen before as new. Returns
+    /// their number and whether this is the first fetch of the feed.
+    pub fn record_fetch(&mut self, feed_data: &FeedData, archived_items: usize, now: DateTime<Utc>) -> (usize, bool) {
+        let activity = self.feeds.entry(feed_data.url.clone()).or_insert_with(|| FeedActivity {
+            title: feed_data.title.clone(),
+            tracked_since: now,
+            total_items: 0,
+            new_items: 0,
+            first_item_seen: None,
+            last_item_seen: None,
+            archived_items: 0,
+            archived_guids: BTreeMap::new(),
+            known_guids: BTreeSet::new(),
+        });
+        let is_backlog = activity.tracked_since == now;
+        for guid in std::mem::take(&mut activity.known_guids) {
+            activity.archived_guids.entry(guid).or_insert(now);
+        }
+
+        let guids: BTreeSet<&str> = feed_data.items.iter().map(|item| item.guid.as_str()).collect();
+        let unseen = guids.iter().filter(|guid| !activity.archived_guids.contains_key(**guid)).count();
+        if unseen > 0 {
+            activity.total_items += unseen;
+            if !is_backlog {
+                activity.new_items += unseen;
+            }
+            activity.first_item_seen.get_or_insert(now);
+            activity.last_item_seen = Some(now);
+        }
+
+        activity.title = feed_data.title.clone();
+        activity.archived_items = archived_items;
+        for guid in guids {
+            activity.archived_guids.insert(guid.to_string(), now);
+        }
+        if let Some(cutoff) = now.checked_sub_signed(chrono::TimeDelta::days(KNOWN_GUID_DAYS)) {
+            activity.archived_guids.retain(|_, last_fetched| *last_fetched >= cutoff);
+        }
+        (unseen, is_backlog)
+    }
+
+    /// Prints all feeds from most to least active and flags those publishing less than
+    /// `min_items_per_week` as candidates for removal.
+    pub fn print_table(&self, min_items_per_week: f64, now: DateTime<Utc>) {
+        if self.feeds.is_empty() {
+            println!("No feed statistics recorded yet");
+            return;
+        }
+
+        let mut rows: Vec<(&String, &FeedActivity, f64)> = self
+            .feeds
+            .iter()
+            .map(|(url, activity)| (url, activity, activity.items_per_week(now)))
+            .collect();
+        rows.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.1.title.cmp(&b.1.title)));
+
+        println!(
+            "{:>10}  {:>8}  {:>8}  {:>10}  {:>10}  Feed",
+            "Items/week", "Avg gap", "Archived", "First item", "Last item"
+        );
+        let mut inactive = Vec::new();
+        for (url, activity, per_week) in &rows {
+            let gap = activity
+                .average_gap_days()
+                .map(|days| format!("{:.1}d", days))
+                .unwrap_or_else(|| "-".to_string());
+            let date = |seen: Option<DateTime<Utc>>| {
+                seen.map(|seen| seen.format("%Y-%m-%d").to_string())
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            println!(
+                "{:>10.2}  {:>8}  {:>8}  {:>10}  {:>10}  {} ({})",
+                per_week,
+                gap,
+                activity.archived_items,
+                date(activity.first_item_seen),
+                date(activity.last_item_seen),
+                activity.title,
+                url
+            );
+
+            let tracked_days = (now - activity.tracked_since).num_days();
+            if *per_week < min_items_per_week && tracked_days >= MIN_TRACKED_DAYS {
+                inactive.push((url, activity, per_week));
+            }
+        }
+
+        if !inactive.is_empty() {
+            println!();
+            println!("Candidates for removal (less than {} items per week):", min_items_per_week);
+            for (url, activity, per_week) in inactive {
+                println!("  {} ({}): {:.2} items per week", activity.title, url, per_week);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{feed, item, MemoryStorage};
+
+    fn fetch(guids: &[&str]) -> FeedData {
+        let items = guids.iter().map(|guid| item(guid, "2024-01-01T00:00:00Z")).collect();
+        feed("https://example.com/feed.xml", "Example", items)
+    }
+
+    fn day(day: u32) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&format!("2024-03-{:02}T00:00:00Z", day)).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn items_rotating_back_into_the_feed_are_not_new() {
+        let mut stats = FeedStats::default();
+        assert_eq!(stats.record_fetch(&fetch(&["a", "b"]), 2, day(1)), (2, true));
+        assert_eq!(stats.record_fetch(&fetch(&["b", "c"]), 2, day(2)), (1, false));
+        assert_eq!(stats.record_fetch(&fetch(&["a", "c"]), 2, day(3)), (0, false));
+
+        let activity = &stats.feeds["https://example.com/feed.xml"];
+        assert_eq!(activity.total_items, 3);
+        assert_eq!(activity.new_items, 1);
+    }
+
+    #[test]
+    fn guids_of_older_stats_are_known() {
+        let mut stats: FeedStats = serde_json::from_str(
+            r#"{"feeds": {"https://example.com/feed.xml": {
+                "title": "Example", "tracked_since": "2024-03-01T00:00:00Z", "total_items": 2,
+                "new_items": 0, "first_item_seen": "2024-03-01T00:00:00Z",
+                "last_item_seen": "2024-03-01T00:00:00Z", "archived_items": 2,
+                "known_guids": ["a", "b"]}}}"#,
+        )
+        .unwrap();
+        assert_eq!(stats.record_fetch(&fetch(&["b", "c"]), 2, day(2)), (1, false));
+        assert_eq!(stats.record_fetch(&fetch(&["a"]), 1, day(3)), (0, false));
+        assert!(!serde_json::to_string(&stats).unwrap().contains("known_guids"));
+    }
+
+    #[tokio::test]
+    async fn missing_stats_start_empty_but_read_errors_fail() {
+        let storage = MemoryStorage::default();
+        let mut stats = FeedStats::load(&storage, "feeds/.stats.json").await.unwrap();
+        assert!(stats.feeds.is_empty());
+
+        stats.record_fetch(&fetch(&["a"]), 1, day(1));
+        stats.save(&storage, "feeds/.stats.json").await.unwrap();
+        storage.set_unavailable(true);
+        assert!(FeedStats::load(&storage, "feeds/.stats.json").await.is_err());
+    }
+}
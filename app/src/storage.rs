@@ -0,0 +1,333 @@
+// storage.rs
+use async_trait::async_trait;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, ObjectStoreExt, PutPayload};
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Writes the contents of a file to the stream it is given.
+pub type WriteContents<'a> = dyn Fn(&mut dyn Write) -> Result<(), Box<dyn Error + Send + Sync>> + Send + Sync + 'a;
+
+/// The `[storage]` section of config.toml, selecting where output is written.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Local filesystem, relative to `root` (default: the working directory)
+    Local { root: Option<String> },
+    /// S3 bucket; credentials are read from the standard `AWS_*` environment variables.
+    /// `endpoint` selects an S3-compatible provider such as Cloudflare R2, MinIO or Backblaze B2.
+    S3 {
+        bucket: String,
+        prefix: Option<String>,
+        region: Option<String>,
+        endpoint: Option<String>,
+    },
+}
+
+impl StorageConfig {
+    /// Creates the configured storage backend.
+    pub fn build(&self) -> Result<Box<dyn Storage>, Box<dyn Error + Send + Sync>> {
+        match self {
+            StorageConfig::Local { root } => Ok(Box::new(LocalFs::new(root.as_deref().unwrap_or(".")))),
+            StorageConfig::S3 { bucket, prefix, region, endpoint } => {
+                let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+                if let Some(region) = region {
+                    builder = builder.with_region(region);
+                }
+                if let Some(endpoint) = endpoint {
+                    // Local MinIO setups are commonly served over plain HTTP
+                    builder = builder
+                        .with_endpoint(endpoint)
+                        .with_allow_http(endpoint.starts_with("http://"));
+                }
+                Ok(Box::new(S3Storage::new(builder.build()?, prefix.as_deref().unwrap_or(""))))
+            }
+        }
+    }
+}
+
+/// Destination for generated output files.
+///
+/// Paths are relative, `/`-separated keys such as `feeds/master.opml`, so that
+/// backends other than the local filesystem (e.g. object stores) can map them
+/// onto their own namespace.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Writes `bytes` to `path`, replacing any existing file.
+    async fn write(&self, path: &str, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Writes the file at `path` with `contents`, returning its size in bytes. By default
+    /// the contents are collected and passed to `write`; backends able to stream a file
+    /// write them as they are produced instead.
+    async fn write_streaming(&self, path: &str, contents: &WriteContents<'_>) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        contents(&mut bytes)?;
+        self.write(path, &bytes).await?;
+        Ok(bytes.len() as u64)
+    }
+
+    /// Writes `bytes` to `path` so that a crash midway leaves the previous file intact: by
+    /// default they are written to `{path}.tmp`, which is then renamed to `path`.
+    async fn write_atomic(&self, path: &str, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tmp_path = format!("{}.tmp", path);
+        self.write(&tmp_path, bytes).await?;
+        if let Err(e) = self.rename(&tmp_path, path).await {
+            if let Err(remove_error) = self.remove(&tmp_path).await {
+                eprintln!("Warning: Could not remove {}: {}", tmp_path, remove_error);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Reads the file at `path`.
+    async fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+    /// Removes the file at `path`.
+    async fn remove(&self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Moves the file at `from` to `to`, replacing any existing file.
+    async fn rename(&self, from: &str, to: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Creates the directory `dir` and its parents if they don't exist. Object stores
+    /// have no directories, so by default this does nothing.
+    async fn create_dir(&self, _dir: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+
+    /// Lists the names of the files directly inside `dir`.
+    /// A missing directory is treated as empty.
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>>;
+
+    /// Lists the names of the directories directly inside `dir`.
+    /// A missing directory is treated as empty.
+    async fn list_dirs(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>>;
+
+    /// Whether names differing only in case refer to the same file.
+    fn case_insensitive(&self) -> bool {
+        false
+    }
+
+    /// Path of the file at `path` on the local filesystem, for reading it without going
+    /// through the backend. None for backends that don't store files locally.
+    fn local_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Whether `error`, returned by a `Storage` method, means that the file doesn't exist, as
+/// opposed to the backend failing to read it.
+pub fn is_not_found(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    if let Some(error) = error.downcast_ref::<std::io::Error>() {
+        return error.kind() == std::io::ErrorKind::NotFound;
+    }
+    matches!(error.downcast_ref::<object_store::Error>(), Some(object_store::Error::NotFound { .. }))
+}
+
+/// Stores output on the local filesystem, relative to `root`.
+pub struct LocalFs {
+    root: PathBuf,
+}
+
+impl LocalFs {
+    pub fn new(root: impl Into<PathBuf>) -> LocalFs {
+        LocalFs { root: root.into() }
+    }
+
+    /// Maps a `/`-separated output path to a path below the root, using the
+    /// platform's separator.
+    fn full_path(&self, path: &str) -> PathBuf {
+        path.split('/').filter(|segment| !segment.is_empty()).fold(self.root.clone(), |full_path, segment| full_path.join(segment))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalFs {
+    async fn write(&self, path: &str, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let full_path = self.full_path(path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(full_path, bytes)?;
+        Ok(())
+    }
+
+    /// Rendering and writing both block, so they run off the async workers.
+    async fn write_streaming(&self, path: &str, contents: &WriteContents<'_>) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let full_path = self.full_path(path);
+        tokio::task::block_in_place(|| {
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut writer = BufWriter::new(fs::File::create(&full_path)?);
+            contents(&mut writer)?;
+            writer.flush()?;
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            Ok(file.metadata()?.len())
+        })
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(fs::read(self.full_path(path))?)
+    }
+
+    async fn remove(&self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        fs::remove_file(self.full_path(path))?;
+        Ok(())
+    }
+
+    /// Renames within a filesystem are atomic, so readers see either file but never a partial one.
+    async fn rename(&self, from: &str, to: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        fs::rename(self.full_path(from), self.full_path(to))?;
+        Ok(())
+    }
+
+    async fn create_dir(&self, dir: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        fs::create_dir_all(self.full_path(dir))?;
+        Ok(())
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let full_path = self.full_path(dir);
+        if !full_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(full_path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    async fn list_dirs(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let full_path = self.full_path(dir);
+        if !full_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(full_path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// The default filesystems of Windows (NTFS) and macOS (APFS) ignore case.
+    fn case_insensitive(&self) -> bool {
+        cfg!(any(windows, target_os = "macos"))
+    }
+
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        Some(self.full_path(path))
+    }
+}
+
+/// Stores output as objects in an S3 bucket, below an optional key prefix.
+pub struct S3Storage {
+    store: AmazonS3,
+    prefix: String,
+}
+
+impl S3Storage {
+    pub fn new(store: AmazonS3, prefix: &str) -> S3Storage {
+        S3Storage {
+            store,
+            prefix: prefix.trim_matches('/').to_string(),
+        }
+    }
+
+    /// Maps a relative output path to its object key.
+    fn key(&self, path: &str) -> ObjectPath {
+        let path = path.trim_matches('/');
+        if self.prefix.is_empty() {
+            ObjectPath::from(path)
+        } else {
+            ObjectPath::from(format!("{}/{}", self.prefix, path))
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn write(&self, path: &str, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.store.put(&self.key(path), PutPayload::from(bytes.to_vec())).await?;
+        Ok(())
+    }
+
+    /// An object only replaces the previous one once it is uploaded completely.
+    async fn write_atomic(&self, path: &str, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.write(path, bytes).await
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let object = self.store.get(&self.key(path)).await?;
+        Ok(object.bytes().await?.to_vec())
+    }
+
+    async fn remove(&self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.store.delete(&self.key(path)).await?;
+        Ok(())
+    }
+
+    /// S3 has no rename, so the object is copied and the original deleted.
+    async fn rename(&self, from: &str, to: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.store.rename(&self.key(from), &self.key(to)).await?;
+        Ok(())
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let listing = self.store.list_with_delimiter(Some(&self.key(dir))).await?;
+        let mut names: Vec<String> = listing
+            .objects
+            .iter()
+            .filter_map(|object| object.location.filename().map(|name| name.to_string()))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Object stores have no directories, the common prefixes of the keys below `dir` stand in for them.
+    async fn list_dirs(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let listing = self.store.list_with_delimiter(Some(&self.key(dir))).await?;
+        let mut names: Vec<String> = listing
+            .common_prefixes
+            .iter()
+            .filter_map(|prefix| prefix.filename().map(|name| name.to_string()))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failed_atomic_writes_keep_the_previous_file() {
+        let root = std::env::temp_dir().join(format!("storage-test-{}", std::process::id()));
+        let storage = LocalFs::new(&root);
+        storage.write_atomic("feeds/blog.xml", b"first").await.unwrap();
+        storage.write_atomic("feeds/blog.xml", b"second").await.unwrap();
+        assert_eq!(storage.list("feeds").await.unwrap(), ["blog.xml"]);
+
+        // The temporary file can't be written, the way a crash midway leaves it unfinished
+        fs::create_dir_all(root.join("feeds/blog.xml.tmp")).unwrap();
+        assert!(storage.write_atomic("feeds/blog.xml", b"third").await.is_err());
+        assert_eq!(storage.read("feeds/blog.xml").await.unwrap(), b"second");
+        fs::remove_dir_all(root).unwrap();
+    }
+}
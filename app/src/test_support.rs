@@ -0,0 +1,217 @@
+// test_support.rs
+use crate::crawler::{FeedError, FetchRequest, FetchResponse, HttpClient};
+use crate::output::OutputFormat;
+use crate::storage::Storage;
+use crate::{FeedData, FeedItem};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// An item with the given guid, linking to `https://example.com/<guid>` and published at
+/// `date` (RFC 3339).
+pub fn item(guid: &str, date: &str) -> FeedItem {
+    FeedItem {
+        guid: guid.to_string(),
+        guid_synthesized: false,
+        title: format!("Item {}", guid),
+        link: format!("https://example.com/{}", guid),
+        description: None,
+        content: None,
+        pub_date: date_time(date),
+        updated_date: None,
+        read_time_minutes: None,
+        language: None,
+        enclosure: None,
+        thumbnail: None,
+        comments: None,
+        comment_count: None,
+        comments_feed: None,
+        episode_number: None,
+        categories: Vec::new(),
+        authors: Vec::new(),
+        original_feed_url: None,
+        original_feed_title: None,
+    }
+}
+
+/// A feed at `url` titled `title` with `items`, written as RSS.
+pub fn feed(url: &str, title: &str, items: Vec<FeedItem>) -> FeedData {
+    FeedData {
+        title: title.to_string(),
+        subtitle: None,
+        url: url.to_string(),
+        resolved_url: url.to_string(),
+        items,
+        language: None,
+        icon: None,
+        copyright: None,
+        slug: None,
+        priority: 0,
+        max_file_bytes: None,
+        base_url: None,
+        categories: Vec::new(),
+        display_title: None,
+        format: OutputFormat::Rss,
+        filtered_count: 0,
+        ttl: None,
+        skip_hours: Vec::new(),
+        skip_days: Vec::new(),
+    }
+}
+
+pub fn date_time(date: &str) -> DateTime<FixedOffset> {
+    DateTime::parse_from_rfc3339(date).unwrap()
+}
+
+// Storage keeping its files in memory, keyed by path
+#[derive(Default)]
+pub struct MemoryStorage {
+    pub files: Mutex<BTreeMap<String, Vec<u8>>>,
+    // Fail every read of an existing file, like an unreachable bucket
+    unavailable: AtomicBool,
+}
+
+impl MemoryStorage {
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.unavailable.store(unavailable, Ordering::SeqCst);
+    }
+
+    /// Names of the entries directly inside `dir`, files or directories.
+    fn entries(&self, dir: &str, directories: bool) -> Vec<String> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let mut names: Vec<String> = self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|path| path.strip_prefix(&prefix))
+            .filter_map(|rest| match rest.split_once('/') {
+                Some((directory, _)) if directories => Some(directory.to_string()),
+                None if !directories => Some(rest.to_string()),
+                _ => None,
+            })
+            .collect();
+        names.dedup();
+        names
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn write(&self, path: &str, bytes: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.files.lock().unwrap().insert(path.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let bytes = self.files.lock().unwrap().get(path).cloned();
+        match bytes {
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path)).into()),
+            Some(_) if self.unavailable.load(Ordering::SeqCst) => Err("storage unavailable".into()),
+            Some(bytes) => Ok(bytes),
+        }
+    }
+
+    async fn remove(&self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self.files.lock().unwrap().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path)).into()),
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut files = self.files.lock().unwrap();
+        let bytes = files.remove(from).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found", from)))?;
+        files.insert(to.to_string(), bytes);
+        Ok(())
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        Ok(self.entries(dir, false))
+    }
+
+    async fn list_dirs(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        Ok(self.entries(dir, true))
+    }
+}
+
+/// An RSS document titled `title` with an item per `(guid, date)`, dates in RFC 3339.
+pub fn rss(title: &str, items: &[(&str, &str)]) -> String {
+    let items: String = items
+        .iter()
+        .map(|(guid, date)| {
+            format!(
+                "<item><title>Item {guid}</title><link>https://example.com/{guid}</link><guid>{guid}</guid><pubDate>{}</pubDate></item>",
+                date_time(date).to_rfc2822()
+            )
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\"?><rss version=\"2.0\"><channel><title>{}</title><link>https://example.com/</link><description>Test feed</description>{}</channel></rss>",
+        title, items
+    )
+}
+
+// Client serving the bodies in `feeds` by URL and recording the requests it gets
+#[derive(Default)]
+pub struct MockClient {
+    pub feeds: HashMap<String, String>,
+    pub requests: Mutex<Vec<FetchRequest>>,
+}
+
+impl MockClient {
+    pub fn new(feeds: &[(&str, String)]) -> MockClient {
+        MockClient {
+            feeds: feeds.iter().map(|(url, body)| (url.to_string(), body.clone())).collect(),
+            requests: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl HttpClient for MockClient {
+    async fn fetch(&self, request: &FetchRequest) -> Result<FetchResponse, FeedError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let body = self.feeds.get(&request.url).ok_or(FeedError::Status(404))?;
+        Ok(FetchResponse {
+            body: body.as_bytes().to_vec(),
+            headers_after: None,
+            freshness: None,
+            final_url: None,
+            etag: None,
+            last_modified: None,
+            not_modified: false,
+        })
+    }
+}
+
+/// Serves a single HTTP request on a local port with `response`, a full HTTP response.
+/// Returns the URL to request and a task resolving to the request as received.
+pub async fn serve_once(response: &str) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/feed.xml", listener.local_addr().unwrap());
+    let response = response.to_string();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+        stream.write_all(response.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        String::from_utf8_lossy(&request).to_string()
+    });
+    (url, server)
+}
@@ -0,0 +1,236 @@
+// translations.rs
+use crate::FeedItem;
+use chrono::Duration;
+use std::collections::HashMap;
+
+// Query parameters selecting the language of a page
+const LANGUAGE_PARAMS: &[&str] = &["hl", "lang", "language", "lng", "locale"];
+
+/// How items are recognized as translations of the same post.
+#[derive(Debug, Clone, Copy)]
+pub struct TranslationGrouping {
+    /// Most time between the publication of two translations
+    pub window: Duration,
+    /// Least share of path segments other than language markers two links must have in the
+    /// same place, 1.0 requiring the same path apart from the markers
+    pub min_link_similarity: f64,
+}
+
+// What keeping the preferred languages did to a feed
+#[derive(Debug, Default)]
+pub struct LanguageSelection {
+    // Items dropped as translations of a kept item
+    pub dropped: usize,
+    // Groups of translations found, kept entirely or not
+    pub groups: usize,
+    // Groups kept entirely because it is unclear which item to keep, described with the reason
+    pub ambiguous: Vec<String>,
+}
+
+/// Keeps one item of every group of translations: the one in the first of `keep` that the
+/// group has. Items are in a group when they were published within the grouping window and
+/// their links differ in a language marker at the same place, such as `/en/post` and
+/// `/de/post`, `en.example.com` and `de.example.com`, or `?lang=en` and `?lang=de`. `languages` holds
+/// the language of each item, if known. Items without translations are kept whatever
+/// their language, and so are groups where an item's language is unknown, two items share
+/// a language or none is in a preferred language.
+pub fn keep_languages(items: &mut Vec<FeedItem>, languages: &[Option<String>], keep: &[String], grouping: &TranslationGrouping) -> LanguageSelection {
+    let links: Vec<Option<LinkKey>> = items.iter().map(|item| LinkKey::new(&item.link)).collect();
+    // Each item points at an item of its group, the first one pointing at itself
+    let mut parent: Vec<usize> = (0..items.len()).collect();
+    for a in 0..items.len() {
+        for b in a + 1..items.len() {
+            let (Some(link_a), Some(link_b)) = (&links[a], &links[b]) else {
+                continue;
+            };
+            let close = (items[a].pub_date - items[b].pub_date).abs() <= grouping.window;
+            if close && link_a.similarity(link_b).is_some_and(|similarity| similarity >= grouping.min_link_similarity) {
+                let (root_a, root_b) = (find_root(&mut parent, a), find_root(&mut parent, b));
+                parent[root_a.max(root_b)] = root_a.min(root_b);
+            }
+        }
+    }
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..items.len() {
+        groups.entry(find_root(&mut parent, index)).or_default().push(index);
+    }
+    let mut groups: Vec<Vec<usize>> = groups.into_values().filter(|members| members.len() > 1).collect();
+    groups.sort();
+
+    let mut selection = LanguageSelection::default();
+    let mut dropped = vec![false; items.len()];
+    for members in groups {
+        selection.groups += 1;
+        let titles = || members.iter().map(|&index| format!("{:?}", items[index].title)).collect::<Vec<_>>().join(", ");
+        let member_languages: Vec<&str> = members.iter().filter_map(|&index| languages.get(index)?.as_deref()).collect();
+        if member_languages.len() < members.len() {
+            selection.ambiguous.push(format!("{}: language not detected for all of them", titles()));
+            continue;
+        }
+        if let Some(shared) = member_languages.iter().enumerate().find(|(position, language)| member_languages[..*position].contains(language)) {
+            selection.ambiguous.push(format!("{}: several are in {}", titles(), shared.1));
+            continue;
+        }
+        let Some(preferred) = keep.iter().find(|language| member_languages.contains(&language.as_str())) else {
+            selection.ambiguous.push(format!("{}: none is in {}", titles(), keep.join(", ")));
+            continue;
+        };
+        for (&index, &language) in members.iter().zip(&member_languages) {
+            if language != preferred {
+                dropped[index] = true;
+                selection.dropped += 1;
+            }
+        }
+    }
+    let mut index = 0;
+    items.retain(|_| {
+        index += 1;
+        !dropped[index - 1]
+    });
+    selection
+}
+
+/// Follows the `parent` links from an item to the first item of its group.
+fn find_root(parent: &mut [usize], mut index: usize) -> usize {
+    while parent[index] != index {
+        parent[index] = parent[parent[index]];
+        index = parent[index];
+    }
+    index
+}
+
+// A link split into the parts translations share and their language markers
+#[derive(Debug)]
+struct LinkKey {
+    host: String,
+    // Language subdomain, as in en.example.com, removed from `host`
+    host_language: Option<String>,
+    segments: Vec<String>,
+    query: Vec<(String, String)>,
+    // Value of a language query parameter, removed from `query`
+    query_language: Option<String>,
+}
+
+impl LinkKey {
+    fn new(link: &str) -> Option<LinkKey> {
+        let url = reqwest::Url::parse(link.trim()).ok()?;
+        let host = url.host_str()?.to_lowercase();
+        let host = host.strip_prefix("www.").unwrap_or(&host);
+        // A language subdomain, as in en.example.com, but not the site name of en.com
+        let (host, host_language) = match host.split_once('.') {
+            Some((label, rest)) if is_language_code(label) && rest.contains('.') => (rest.to_string(), Some(label.to_string())),
+            _ => (host.to_string(), None),
+        };
+        let segments = url.path_segments().into_iter().flatten().filter(|segment| !segment.is_empty()).map(str::to_string).collect();
+        let mut query_language = None;
+        let mut query: Vec<(String, String)> = Vec::new();
+        for (name, value) in url.query_pairs() {
+            if LANGUAGE_PARAMS.contains(&name.to_lowercase().as_str()) {
+                query_language.get_or_insert_with(|| value.to_lowercase());
+            } else {
+                query.push((name.into_owned(), value.into_owned()));
+            }
+        }
+        query.sort();
+        Some(LinkKey { host, host_language, segments, query, query_language })
+    }
+
+    /// The share of path segments other than language markers two links have in the same
+    /// place, if the links are to the same host and query and differ in a language marker. None for links that can't be translations of each other, such as
+    /// `/id/1` and `/id/2`, whose markers don't differ.
+    fn similarity(&self, other: &LinkKey) -> Option<f64> {
+        if self.host != other.host || self.query != other.query {
+            return None;
+        }
+        let differ = |a: &Option<String>, b: &Option<String>| matches!((a, b), (Some(a), Some(b)) if a != b);
+        let mut other_language = differ(&self.host_language, &other.host_language) || differ(&self.query_language, &other.query_language);
+        let (mut same, mut markers) = (0, 0);
+        for (a, b) in self.segments.iter().zip(&other.segments) {
+            if a == b {
+                same += 1;
+            } else if is_language_code(a) && is_language_code(b) {
+                markers += 1;
+                other_language = true;
+            }
+        }
+        if !other_language {
+            return None;
+        }
+        let longest = self.segments.len().max(other.segments.len()) - markers;
+        Some(if longest == 0 { 1.0 } else { same as f64 / longest as f64 })
+    }
+}
+
+/// Whether a path segment or subdomain names a language, e.g. `en`, `de-AT` or `pt_BR`.
+fn is_language_code(value: &str) -> bool {
+    let (language, region) = value.split_once(['-', '_']).unwrap_or((value, ""));
+    let region_valid = region.is_empty() || (region.len() == 2 && region.chars().all(|c| c.is_ascii_alphabetic()));
+    language.len() == 2 && region_valid && isolang::Language::from_639_1(&language.to_lowercase()).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::item;
+
+    fn linked(link: &str, date: &str) -> FeedItem {
+        FeedItem { link: link.to_string(), ..item(link, date) }
+    }
+
+    fn grouping(min_link_similarity: f64) -> TranslationGrouping {
+        TranslationGrouping { window: Duration::minutes(60), min_link_similarity }
+    }
+
+    fn keep_english(links: &[&str], languages: &[&str], grouping: &TranslationGrouping) -> (Vec<String>, LanguageSelection) {
+        let mut items: Vec<FeedItem> = links.iter().map(|link| linked(link, "2024-03-01T10:00:00Z")).collect();
+        let languages: Vec<Option<String>> = languages.iter().map(|language| Some(language.to_string())).collect();
+        let selection = keep_languages(&mut items, &languages, &["en".to_string()], grouping);
+        (items.into_iter().map(|item| item.link).collect(), selection)
+    }
+
+    #[test]
+    fn keeps_the_preferred_translation() {
+        let links = ["https://example.com/de/post", "https://example.com/en/post", "https://example.com/fr/post"];
+        let (kept, selection) = keep_english(&links, &["de", "en", "fr"], &grouping(1.0));
+        assert_eq!(kept, ["https://example.com/en/post"]);
+        assert_eq!((selection.groups, selection.dropped), (1, 2));
+
+        let links = ["https://de.example.com/post", "https://en.example.com/post", "https://example.com/post?lang=de&id=1", "https://example.com/post?id=1&lang=en"];
+        let (kept, _) = keep_english(&links, &["de", "en", "de", "en"], &grouping(1.0));
+        assert_eq!(kept, ["https://en.example.com/post", "https://example.com/post?id=1&lang=en"]);
+    }
+
+    #[test]
+    fn path_segments_that_are_language_codes_are_not_markers_unless_they_differ() {
+        let links = ["https://example.com/id/1", "https://example.com/id/2", "https://example.com/no/comments", "https://example.com/comments", "https://example.com/to/post"];
+        let (kept, selection) = keep_english(&links, &["de", "en", "de", "en", "fr"], &grouping(0.5));
+        assert_eq!(kept.len(), links.len());
+        assert_eq!(selection.groups, 0);
+    }
+
+    #[test]
+    fn no_link_similarity_does_not_group_a_whole_host() {
+        let links = ["https://example.com/blog/one", "https://example.com/blog/two", "https://example.com/en/release", "https://example.com/de/version"];
+        let (kept, selection) = keep_english(&links, &["de", "en", "en", "de"], &grouping(0.0));
+        assert_eq!(selection.groups, 1);
+        assert_eq!(kept, ["https://example.com/blog/one", "https://example.com/blog/two", "https://example.com/en/release"]);
+    }
+
+    #[test]
+    fn translations_published_apart_are_not_grouped() {
+        let mut items = vec![linked("https://example.com/de/post", "2024-03-01T10:00:00Z"), linked("https://example.com/en/post", "2024-03-01T12:00:00Z")];
+        let languages = [Some("de".to_string()), Some("en".to_string())];
+        let selection = keep_languages(&mut items, &languages, &["en".to_string()], &grouping(1.0));
+        assert_eq!((items.len(), selection.groups), (2, 0));
+    }
+
+    #[test]
+    fn language_codes() {
+        assert!(is_language_code("en"));
+        assert!(is_language_code("de-AT"));
+        assert!(is_language_code("pt_BR"));
+        assert!(!is_language_code("xx"));
+        assert!(!is_language_code("eng"));
+        assert!(!is_language_code("en-USA"));
+    }
+}
@@ -0,0 +1,286 @@
+// verify.rs
+use crate::crawler::{FetchRequest, HttpClient, IpFamily, DEFAULT_ACCEPT};
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+// How long a published file may take to answer the reachability check
+const REACHABILITY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the published archive is read from.
+pub enum ArchiveSource<'a> {
+    /// The output written by the last run, read through the storage backend
+    Storage(&'a dyn Storage),
+    /// The archive as served from `base_url`, the way subscribers see it
+    Remote(&'a dyn HttpClient),
+}
+
+impl ArchiveSource<'_> {
+    /// Reads a file of the archive given by its path relative to `base_url`.
+    async fn read(&self, base_url: &str, path: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        match self {
+            ArchiveSource::Storage(storage) => storage.read(path).await,
+            ArchiveSource::Remote(client) => {
+                let request = FetchRequest {
+                    url: format!("{}/{}", base_url, path),
+                    accept: DEFAULT_ACCEPT.to_string(),
+                    follow_redirects: true,
+                    if_none_match: None,
+                    if_modified_since: None,
+                };
+                Ok(client.fetch(&request).await?.body)
+            }
+        }
+    }
+}
+
+// Result of checking a feed referenced by an OPML file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedHealth {
+    Ok,
+    /// The file could not be read or downloaded
+    Missing,
+    /// The file is not a feed feed_rs can parse
+    Unparsable,
+    /// The feed has no items
+    Empty,
+    /// The newest item is older than the configured maximum age
+    Stale,
+}
+
+impl fmt::Display for FeedHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FeedHealth::Ok => "ok",
+            FeedHealth::Missing => "missing",
+            FeedHealth::Unparsable => "unparsable",
+            FeedHealth::Empty => "empty",
+            FeedHealth::Stale => "stale",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedCheck {
+    /// OPML file listing the feed
+    pub opml: String,
+    /// Path of the feed relative to `base_url`; for an unreadable OPML file, the OPML file itself
+    pub path: String,
+    pub title: String,
+    pub health: FeedHealth,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub items: usize,
+    pub newest_item: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub feeds: Vec<FeedCheck>,
+}
+
+impl VerifyReport {
+    pub fn broken(&self) -> usize {
+        self.feeds.iter().filter(|check| check.health != FeedHealth::Ok).count()
+    }
+
+    pub fn print(&self) {
+        for check in &self.feeds {
+            let detail = match (&check.error, check.newest_item) {
+                (Some(error), _) => error.clone(),
+                (None, Some(newest)) => format!("{} items, newest {}", check.items, newest.format("%Y-%m-%d")),
+                (None, None) => format!("{} items", check.items),
+            };
+            println!("{:<10}  {} ({}): {}", check.health, check.path, check.title, detail);
+        }
+        println!("Verified {} feeds, {} broken", self.feeds.len(), self.broken());
+    }
+}
+
+/// Reads every OPML file, then every archived feed it references, and checks that each
+/// feed parses, has items and, unless `max_age_days` is 0, has an item newer than that.
+/// Outlines pointing outside `base_url` (merged external subscriptions) are not checked.
+pub async fn verify_archive(
+    source: &ArchiveSource<'_>,
+    base_url: &str,
+    opml_paths: &[String],
+    max_age_days: u64,
+    now: DateTime<Utc>,
+) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    let prefix = format!("{}/", base_url);
+
+    for opml_path in opml_paths {
+        let outlines = match source.read(base_url, opml_path).await {
+            Ok(bytes) => parse_opml_outlines(&String::from_utf8_lossy(&bytes)).map_err(|e| (FeedHealth::Unparsable, e.to_string())),
+            Err(e) => Err((FeedHealth::Missing, e.to_string())),
+        };
+        let outlines = match outlines {
+            Ok(outlines) => outlines,
+            Err((health, error)) => {
+                report.feeds.push(FeedCheck {
+                    opml: opml_path.clone(),
+                    path: opml_path.clone(),
+                    title: "OPML feed list".to_string(),
+                    health,
+                    error: Some(error),
+                    items: 0,
+                    newest_item: None,
+                });
+                continue;
+            }
+        };
+
+        for (title, xml_url) in outlines {
+            let Some(path) = xml_url.strip_prefix(&prefix) else {
+                continue;
+            };
+            let mut check = FeedCheck {
+                opml: opml_path.clone(),
+                path: path.to_string(),
+                title,
+                health: FeedHealth::Ok,
+                error: None,
+                items: 0,
+                newest_item: None,
+            };
+
+            match source.read(base_url, path).await {
+                Err(e) => {
+                    check.health = FeedHealth::Missing;
+                    check.error = Some(e.to_string());
+                }
+                Ok(bytes) => match feed_rs::parser::parse(bytes.as_slice()) {
+                    Err(e) => {
+                        check.health = FeedHealth::Unparsable;
+                        check.error = Some(e.to_string());
+                    }
+                    Ok(feed) => {
+                        check.items = feed.entries.len();
+                        check.newest_item = feed.entries.iter().filter_map(|entry| entry.published.or(entry.updated)).max();
+                        if check.items == 0 {
+                            check.health = FeedHealth::Empty;
+                        } else if let Some(newest) = check.newest_item {
+                            let age_days = (now - newest).num_days();
+                            if max_age_days > 0 && age_days > max_age_days as i64 {
+                                check.health = FeedHealth::Stale;
+                                check.error = Some(format!("newest item is {} days old", age_days));
+                            }
+                        }
+                    }
+                },
+            }
+            report.feeds.push(check);
+        }
+    }
+
+    report
+}
+
+/// Extracts the title and xmlUrl of every outline of an OPML document, whatever its type.
+fn parse_opml_outlines(opml: &str) -> Result<Vec<(String, String)>, Box<dyn Error + Send + Sync>> {
+    let mut reader = Reader::from_str(opml);
+    let mut outlines = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"outline" => {
+                let mut title = None;
+                let mut xml_url = None;
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    match attr.key.as_ref() {
+                        b"title" => title = Some(attr.unescape_value()?.to_string()),
+                        b"xmlUrl" => xml_url = Some(attr.unescape_value()?.to_string()),
+                        _ => {}
+                    }
+                }
+                if let Some(xml_url) = xml_url {
+                    outlines.push((title.unwrap_or_else(|| xml_url.clone()), xml_url));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(outlines)
+}
+
+/// Picks up to `count` items spread evenly over `items`, or all of them if `count` is 0,
+/// so that the same archive is always checked through the same sample.
+pub fn sample_evenly<T: Clone>(items: &[T], count: usize) -> Vec<T> {
+    if count == 0 || items.len() <= count {
+        return items.to_vec();
+    }
+    (0..count).map(|index| items[index * items.len() / count].clone()).collect()
+}
+
+/// Sends a HEAD request to every URL, falling back to GET where the server doesn't allow
+/// HEAD, and returns the URLs that could not be reached with the reason, in the order given.
+pub async fn check_reachable(urls: &[String], ip_family: IpFamily) -> Result<Vec<(String, String)>, Box<dyn Error + Send + Sync>> {
+    let client = ip_family.client_builder().timeout(REACHABILITY_TIMEOUT).build()?;
+    let handles: Vec<_> = urls
+        .iter()
+        .map(|url| {
+            let client = client.clone();
+            let url = url.clone();
+            tokio::spawn(async move {
+                let mut response = client.head(&url).send().await;
+                if let Ok(head) = &response {
+                    let status = head.status();
+                    if status == reqwest::StatusCode::METHOD_NOT_ALLOWED || status == reqwest::StatusCode::NOT_IMPLEMENTED {
+                        response = client.get(&url).send().await;
+                    }
+                }
+                let error = match response {
+                    Ok(response) if response.status().is_success() => None,
+                    Ok(response) => Some(format!("HTTP {}", response.status())),
+                    Err(e) => Some(e.to_string()),
+                };
+                error.map(|error| (url, error))
+            })
+        })
+        .collect();
+
+    let mut unreachable = Vec::new();
+    for (url, handle) in urls.iter().zip(handles) {
+        match handle.await {
+            Ok(Some(failure)) => unreachable.push(failure),
+            Ok(None) => {}
+            Err(e) => unreachable.push((url.clone(), e.to_string())),
+        }
+    }
+    Ok(unreachable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{rss, MockClient};
+
+    #[tokio::test]
+    async fn remote_archive_is_read_through_the_given_client() {
+        let base_url = "https://example.org/archive";
+        let opml = format!(
+            r#"<opml version="2.0"><body><outline title="A" xmlUrl="{0}/feeds/a.xml"/><outline title="B" xmlUrl="{0}/feeds/b.xml"/></body></opml>"#,
+            base_url
+        );
+        let client = MockClient::new(&[
+            ("https://example.org/archive/feeds/master.opml", opml),
+            ("https://example.org/archive/feeds/a.xml", rss("A", &[("a", "2024-03-01T00:00:00Z")])),
+        ]);
+        let now = DateTime::parse_from_rfc3339("2024-03-02T00:00:00Z").unwrap().with_timezone(&Utc);
+
+        let report = verify_archive(&ArchiveSource::Remote(&client), base_url, &["feeds/master.opml".to_string()], 30, now).await;
+        let health: Vec<(&str, FeedHealth)> = report.feeds.iter().map(|check| (check.path.as_str(), check.health)).collect();
+        assert_eq!(health, [("feeds/a.xml", FeedHealth::Ok), ("feeds/b.xml", FeedHealth::Missing)]);
+        let requested: Vec<String> = client.requests.lock().unwrap().iter().map(|request| request.url.clone()).collect();
+        assert_eq!(requested.len(), 3);
+    }
+}